@@ -0,0 +1,69 @@
+//! Сведение действий за кадр к записи фиксированного размера.
+//!
+//! Настройки выбираются при обработке (`data/aggregation.json`, по умолчанию траектория
+//! мыши на все [`MOUSE_VECTOR_LENGTH`] отрезков), сохраняются в атрибуте файлов hdf5
+//! отдельно от формата кадров и в конфигурации модели: от них зависит ширина входа мыши.
+
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{MOUSE_VECTOR_LENGTH, session::write_atomic};
+
+/// Файл настроек сведения действий в каталоге данных
+pub const AGGREGATION_CONFIG_FILE: &str = "aggregation.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregationConfig {
+    /// Отрезков траектории мыши за кадр, от 1 до [`MOUSE_VECTOR_LENGTH`]
+    pub mouse_bins: usize,
+}
+
+impl Default for AggregationConfig {
+    fn default() -> Self {
+        Self {
+            mouse_bins: MOUSE_VECTOR_LENGTH,
+        }
+    }
+}
+
+impl AggregationConfig {
+    /// Длина траектории ограничена местом в записи действий
+    pub fn new(mouse_bins: usize) -> io::Result<Self> {
+        if !(1..=MOUSE_VECTOR_LENGTH).contains(&mouse_bins) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Отрезков траектории мыши {mouse_bins}, допустимо от 1 до {MOUSE_VECTOR_LENGTH}"
+                ),
+            ));
+        }
+
+        Ok(Self { mouse_bins })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        let config: Self = serde_json::from_reader(file)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Self::new(config.mouse_bins)
+    }
+
+    /// Настройки из `dir/aggregation.json`, если файла нет — по умолчанию
+    pub fn load_or_default(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(AGGREGATION_CONFIG_FILE);
+
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+
+        write_atomic(path, &json)
+    }
+}
@@ -0,0 +1,219 @@
+//! Размер и раскладка каналов кадров датасета.
+//!
+//! Формат выбирается при обработке (`data/frame_format.json`, по умолчанию
+//! [`WIDTH`]x[`HEIGHT`] RGBA), сохраняется в атрибутах файлов hdf5 и в конфигурации
+//! модели, поэтому другое разрешение не требует пересборки. Вместе с размером хранится
+//! способ уменьшения кадра ([`Resize`]), чтобы инференс готовил кадр так же, как обработка.
+//! Длина траектории мыши от формата кадров не зависит, см. [`crate::aggregation`].
+
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CHANNELS, HEIGHT, WIDTH, session::write_atomic};
+
+/// Файл формата кадров в каталоге данных
+pub const FRAME_FORMAT_FILE: &str = "frame_format.json";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelLayout {
+    Gray,
+    Rgb,
+    #[default]
+    Rgba,
+}
+
+impl ChannelLayout {
+    pub fn channels(self) -> usize {
+        match self {
+            Self::Gray => 1,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+
+    pub fn from_channels(channels: usize) -> Option<Self> {
+        match channels {
+            1 => Some(Self::Gray),
+            3 => Some(Self::Rgb),
+            4 => Some(Self::Rgba),
+            _ => None,
+        }
+    }
+}
+
+/// Как кадр экрана приводится к размеру формата
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum ResizeMode {
+    /// Растянуть весь кадр, пропорции не сохраняются
+    #[default]
+    Stretch,
+    /// Вырезать середину с пропорциями формата
+    Crop,
+    /// Вписать кадр целиком, поля залить цветом RGBA
+    Letterbox { color: [u8; 4] },
+    /// Растянуть прямоугольник кадра, например игровое поле без интерфейса
+    Region {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// Фильтр масштабирования, те же варианты, что у `image::imageops::FilterType`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resize {
+    #[serde(flatten)]
+    pub mode: ResizeMode,
+    #[serde(default)]
+    pub filter: ResizeFilter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameFormat {
+    pub width: usize,
+    pub height: usize,
+    pub layout: ChannelLayout,
+    /// Для файлов без этого поля — растягивание, как до его появления
+    #[serde(default)]
+    pub resize: Resize,
+}
+
+impl Default for FrameFormat {
+    fn default() -> Self {
+        Self {
+            width: WIDTH,
+            height: HEIGHT,
+            layout: ChannelLayout::from_channels(CHANNELS).unwrap_or_default(),
+            resize: Resize::default(),
+        }
+    }
+}
+
+impl FrameFormat {
+    pub fn new(width: usize, height: usize, layout: ChannelLayout) -> io::Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Пустой размер кадра {width}x{height}"),
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            layout,
+            resize: Resize::default(),
+        })
+    }
+
+    /// Размер захвата здесь неизвестен, поэтому проверяется только сама область:
+    /// она не пустая и не выходит за пределы `u32`. С кадром её сверяет [`FrameFormat::check_source`]
+    pub fn with_resize(self, resize: Resize) -> io::Result<Self> {
+        if let ResizeMode::Region {
+            x,
+            y,
+            width,
+            height,
+        } = resize.mode
+        {
+            if width == 0 || height == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Пустая область кадра {width}x{height}"),
+                ));
+            }
+            if x.checked_add(width).is_none() || y.checked_add(height).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Область кадра {x},{y} {width}x{height} слишком велика"),
+                ));
+            }
+        }
+
+        Ok(Self { resize, ..self })
+    }
+
+    /// Подходит ли исходный кадр размера `width`×`height`: область кадра
+    /// ([`ResizeMode::Region`]) должна хотя бы частично на него попадать.
+    /// Выступающая за край часть области отбрасывается.
+    pub fn check_source(&self, width: u32, height: u32) -> io::Result<()> {
+        if let ResizeMode::Region {
+            x,
+            y,
+            width: region_width,
+            height: region_height,
+        } = self.resize.mode
+            && (x >= width || y >= height)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Область кадра {x},{y} {region_width}x{region_height} за пределами кадра {width}x{height}"
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Формат по числу каналов, например из атрибутов файла или конфигурации модели
+    pub fn from_channels(width: usize, height: usize, channels: usize) -> io::Result<Self> {
+        let layout = ChannelLayout::from_channels(channels).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Неподдерживаемое число каналов: {channels}"),
+            )
+        })?;
+
+        Self::new(width, height, layout)
+    }
+
+    pub fn channels(&self) -> usize {
+        self.layout.channels()
+    }
+
+    /// Значений в одном кадре
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height * self.channels()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        let format: Self = serde_json::from_reader(file)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Self::new(format.width, format.height, format.layout)?.with_resize(format.resize)
+    }
+
+    /// Формат из `dir/frame_format.json`, если файла нет — формат по умолчанию
+    pub fn load_or_default(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(FRAME_FORMAT_FILE);
+
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+
+        write_atomic(path, &json)
+    }
+}
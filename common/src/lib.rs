@@ -1,5 +1,14 @@
+pub mod aggregation;
+pub mod frame;
+pub mod session;
+
+/// Формат кадров по умолчанию, см. [`frame::FrameFormat`]
 pub const WIDTH: usize = 40;
 pub const HEIGHT: usize = 40;
 pub const CHANNELS: usize = 4;
-pub const MOUSE_VECTOR_LENGTH: usize = 200;
+/// Место под траекторию мыши за кадр в записи действий: наибольшее число отрезков
+/// времени. Сколько из них используется, задаёт [`aggregation::AggregationConfig::mouse_bins`]
+pub const MOUSE_VECTOR_LENGTH: usize = 16;
+/// Кнопки мыши, которые записываются отдельным каналом: левая, правая, средняя
+pub const MOUSE_BUTTONS: usize = 3;
 pub const DATA_DIR: &str = "data/";
@@ -0,0 +1,130 @@
+//! Формат сессии записи, общий для `recorder` и `preprocessor`.
+//!
+//! Каждый запуск записи создаёт свою директорию `data/sessions/<session_id>/`:
+//! - `manifest.json` — описание сессии ([`SessionManifest`]);
+//! - `frames/` — исходные кадры;
+//! - `resized/` — кадры, уменьшенные до размера обучения;
+//! - `frames.csv` — индекс кадров с метками времени;
+//! - `events.bin` — двоичный журнал событий клавиатуры и мыши
+//!   (у сессий, записанных до него, — текстовый `events.csv`);
+//! - `capture_stats.json` — статистика захвата ([`CaptureStats`]);
+//! - `checkpoint.json` — метка незавершённой записи: создаётся при запуске, удаляется
+//!   при штатном завершении. Если файл остался, запись была прервана и сессию нужно
+//!   восстановить; что успело попасть на диск, определяется по самим файлам.
+//!
+//! Кадры и служебные файлы пишутся атомарно ([`write_atomic`]): после сбоя на диске
+//! остаётся либо целый файл, либо временный `*.tmp`.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const SESSIONS_DIR: &str = "sessions";
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const FRAMES_DIR: &str = "frames";
+pub const RESIZED_DIR: &str = "resized";
+pub const FRAMES_INDEX_FILE: &str = "frames.csv";
+pub const EVENTS_FILE: &str = "events.bin";
+pub const LEGACY_EVENTS_FILE: &str = "events.csv";
+pub const CAPTURE_STATS_FILE: &str = "capture_stats.json";
+pub const CHECKPOINT_FILE: &str = "checkpoint.json";
+/// Суффикс временных файлов, которые ещё не переименованы в итоговые
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// Версия словаря клавиш по умолчанию.
+/// В манифесте хранится версия словаря, которым кодировались клавиши при записи.
+pub const KEY_VOCABULARY_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionManifest {
+    pub session_id: String,
+    /// Время начала записи, миллисекунды Unix
+    pub started_at_unix_ms: u64,
+    pub monitor_name: String,
+    /// Цель захвата в строковой форме (`primary`, `monitor:1`, `region:x,y,wxh`, `window:title`);
+    /// `None`, если кадры пришли не с экрана
+    #[serde(default)]
+    pub capture_target: Option<String>,
+    /// Разрешение сохранённых кадров `[ширина, высота]` (после обрезки по цели захвата)
+    pub native_resolution: [u32; 2],
+    pub target_fps: f64,
+    pub key_vocabulary_version: u32,
+    pub frame_count: usize,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Датасет записан рекордером прямо во время записи (уменьшение при захвате)
+    #[serde(default)]
+    pub dataset_written: bool,
+}
+
+impl SessionManifest {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+
+        serde_json::from_reader(file).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+
+        write_atomic(path, &json)
+    }
+}
+
+/// Фактические параметры захвата кадров за сессию
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CaptureStats {
+    pub target_fps: f64,
+    /// Средняя частота захвата без учёта пауз
+    pub achieved_fps: f64,
+    /// Среднее отклонение интервала между кадрами от целевого, мс
+    pub jitter_ms: f64,
+    pub max_interval_ms: f64,
+    /// Сохранённые кадры
+    pub frames: usize,
+    /// Сумма всех потерь ниже
+    pub dropped_frames: usize,
+    /// Пропущенные такты: захват не успевал за целевой частотой
+    pub missed_deadlines: usize,
+    /// Очередь кодирования переполнена
+    pub queue_full: usize,
+    pub capture_errors: usize,
+    pub encode_errors: usize,
+}
+
+impl CaptureStats {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+
+        serde_json::from_reader(file).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+
+        write_atomic(path, &json)
+    }
+}
+
+/// Путь временного файла, в который пишется `path` до переименования
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(TEMP_SUFFIX);
+
+    path.with_file_name(name)
+}
+
+/// Запись файла целиком: сначала во временный файл рядом, затем переименование
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path);
+
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    fs::rename(&temp, path)
+}
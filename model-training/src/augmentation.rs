@@ -0,0 +1,278 @@
+//! Искажения обучающих примеров.
+//!
+//! Сдвиг, обрезка и цвет выбираются один раз на окно и одинаково применяются
+//! к кадрам контекста и следующему кадру, иначе модель училась бы предсказывать
+//! само искажение. Шум добавляется только к кадрам контекста.
+//!
+//! Случайные числа берутся из seed запуска, содержимого пакета и номера его
+//! повтора, а не из общего генератора: пакеты собираются в нескольких потоках,
+//! и порядок их сборки не должен влиять на результат. Повторы считаются по
+//! содержимому ([`BatchPasses`]), поэтому пакет, собранный в следующей эпохе
+//! из тех же окон, искажается заново, а весь запуск повторяется при том же seed.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use burn::config::Config;
+use common::frame::ChannelLayout;
+use preprocessor::{csv_processing::KeysRecordConst, images::MyImage};
+
+use crate::data::FrameWindow;
+
+/// Все искажения по умолчанию выключены
+#[derive(Config, Debug, PartialEq)]
+pub struct AugmentationConfig {
+    /// Яркость умножается на случайное число из `1 ± brightness`
+    #[config(default = 0.0)]
+    pub brightness: f32,
+    /// Отклонение от середины диапазона умножается на число из `1 ± contrast`
+    #[config(default = 0.0)]
+    pub contrast: f32,
+    /// Отклонение цвета от серого умножается на число из `1 ± saturation`
+    #[config(default = 0.0)]
+    pub saturation: f32,
+    /// Наибольшая доля стороны кадра, отрезаемая перед растяжением обратно
+    #[config(default = 0.0)]
+    pub crop: f32,
+    /// Наибольший сдвиг кадра в пикселях по каждой оси, края повторяются
+    #[config(default = 0)]
+    pub max_shift: usize,
+    /// СКО гауссова шума в долях от полного диапазона
+    #[config(default = 0.0)]
+    pub noise_std: f32,
+    /// Вероятность заменить действие пустым
+    #[config(default = 0.0)]
+    pub action_dropout: f32,
+}
+
+impl AugmentationConfig {
+    /// Ни одно искажение не включено
+    pub fn is_identity(&self) -> bool {
+        *self == Self::new()
+    }
+
+    /// Искажение окон пакета. Одинаковые seed, пакет и число его прошлых повторов
+    /// в `passes` дают одинаковый результат.
+    pub fn apply(
+        &self,
+        seed: u64,
+        passes: &BatchPasses,
+        mut windows: Vec<FrameWindow>,
+    ) -> Vec<FrameWindow> {
+        if self.is_identity() {
+            return windows;
+        }
+
+        let hash = batch_hash(seed, &windows);
+        let pass = passes.next(hash);
+        let mut rng = Rng(hash.wrapping_add(pass.wrapping_mul(0xd1b5_4a32_d192_ed03)));
+        for window in &mut windows {
+            self.apply_window(&mut rng, window);
+        }
+
+        windows
+    }
+
+    fn apply_window(&self, rng: &mut Rng, window: &mut FrameWindow) {
+        let format = window.next.format;
+
+        let scale = 1.0 - rng.uniform() * self.crop.clamp(0.0, 1.0);
+        let mut shift = |size: usize| {
+            let crop = rng.uniform() * size as f32 * (1.0 - scale);
+            let max_shift = self.max_shift as u64;
+            let shift = (rng.next_u64() % (2 * max_shift + 1)) as f32 - max_shift as f32;
+            crop + shift
+        };
+        let geometry = Geometry {
+            scale,
+            x0: shift(format.width),
+            y0: shift(format.height),
+        };
+        let colour = Colour {
+            brightness: 1.0 + rng.symmetric(self.brightness),
+            contrast: 1.0 + rng.symmetric(self.contrast),
+            saturation: 1.0 + rng.symmetric(self.saturation),
+        };
+
+        for image in window.context.iter_mut().chain([&mut window.next]) {
+            if !geometry.is_identity() {
+                *image = geometry.resample(image);
+            }
+            if !colour.is_identity() {
+                colour.apply(image);
+            }
+        }
+
+        if self.noise_std > 0.0 {
+            for image in &mut window.context {
+                add_noise(rng, image, self.noise_std * 255.0);
+            }
+        }
+
+        if rng.uniform() < self.action_dropout {
+            window.action = KeysRecordConst::from_slices(&[], &[]);
+        }
+    }
+}
+
+/// Сколько раз искажался пакет с тем же содержимым; общий для копий загрузчика
+#[derive(Clone, Debug, Default)]
+pub struct BatchPasses(Arc<Mutex<HashMap<u64, u64>>>);
+
+impl BatchPasses {
+    /// Номер очередного повтора пакета с хэшем `hash`, с нуля
+    fn next(&self, hash: u64) -> u64 {
+        let mut passes = self.0.lock().unwrap();
+        let pass = passes.entry(hash).or_default();
+        *pass += 1;
+
+        *pass - 1
+    }
+}
+
+/// Генератор splitmix64: воспроизводим на любой платформе
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Равномерно из [0, 1)
+    fn uniform(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Равномерно из [-range, range]
+    fn symmetric(&mut self, range: f32) -> f32 {
+        (self.uniform() * 2.0 - 1.0) * range
+    }
+
+    /// Стандартное нормальное распределение, преобразование Бокса — Мюллера
+    fn gaussian(&mut self) -> f32 {
+        let radius = (-2.0 * (1.0 - self.uniform()).ln()).sqrt();
+        radius * (std::f32::consts::TAU * self.uniform()).cos()
+    }
+}
+
+/// FNV-1a от seed, действий и следующих кадров пакета
+fn batch_hash(seed: u64, windows: &[FrameWindow]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325 ^ seed;
+    let bytes = windows
+        .iter()
+        .flat_map(|window| window.action.keys.iter().chain(&window.next.pixels));
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash
+}
+
+/// Пиксель `x` результата берётся из точки `x0 + (x + 0.5) * scale - 0.5` исходного кадра
+struct Geometry {
+    scale: f32,
+    x0: f32,
+    y0: f32,
+}
+
+impl Geometry {
+    fn is_identity(&self) -> bool {
+        self.scale == 1.0 && self.x0 == 0.0 && self.y0 == 0.0
+    }
+
+    /// Билинейная выборка, за краями кадра повторяются крайние пиксели
+    fn resample(&self, image: &MyImage) -> MyImage {
+        let format = image.format;
+        let (width, height) = (format.width, format.height);
+
+        // Соседние пиксели и вес дальнего из них по одной оси
+        let taps = |origin: f32, size: usize| -> Vec<(usize, usize, f32)> {
+            (0..size)
+                .map(|i| {
+                    let source = (origin + (i as f32 + 0.5) * self.scale - 0.5)
+                        .clamp(0.0, (size - 1) as f32);
+                    let near = source.floor() as usize;
+                    (near, (near + 1).min(size - 1), source - near as f32)
+                })
+                .collect()
+        };
+        let columns = taps(self.x0, width);
+        let rows = taps(self.y0, height);
+
+        let mut pixels = Vec::with_capacity(image.pixels.len());
+        for plane in image.pixels.chunks_exact(width * height) {
+            for &(top, bottom, fy) in &rows {
+                for &(left, right, fx) in &columns {
+                    let at = |y: usize, x: usize| plane[y * width + x] as f32;
+                    let upper = at(top, left) * (1.0 - fx) + at(top, right) * fx;
+                    let lower = at(bottom, left) * (1.0 - fx) + at(bottom, right) * fx;
+                    pixels.push((upper * (1.0 - fy) + lower * fy).round() as u8);
+                }
+            }
+        }
+
+        MyImage { format, pixels }
+    }
+}
+
+/// Множители цвета, 1 — без изменений
+struct Colour {
+    brightness: f32,
+    contrast: f32,
+    saturation: f32,
+}
+
+impl Colour {
+    fn is_identity(&self) -> bool {
+        self.brightness == 1.0 && self.contrast == 1.0 && self.saturation == 1.0
+    }
+
+    /// Альфа-канал не меняется, насыщенность серых кадров тоже
+    fn apply(&self, image: &mut MyImage) {
+        let plane = image.format.width * image.format.height;
+        let colour_channels = match image.format.layout {
+            ChannelLayout::Gray => 1,
+            ChannelLayout::Rgb | ChannelLayout::Rgba => 3,
+        };
+
+        for i in 0..plane {
+            let mut values = [0.0f32; 3];
+            for (c, value) in values.iter_mut().enumerate().take(colour_channels) {
+                *value = image.pixels[c * plane + i] as f32;
+            }
+
+            if colour_channels == 3 {
+                let gray = 0.299 * values[0] + 0.587 * values[1] + 0.114 * values[2];
+                for value in &mut values {
+                    *value = gray + (*value - gray) * self.saturation;
+                }
+            }
+
+            for (c, value) in values.iter().enumerate().take(colour_channels) {
+                let value = (value * self.brightness - 127.5) * self.contrast + 127.5;
+                image.pixels[c * plane + i] = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Шум по всем каналам, кроме альфа-канала
+fn add_noise(rng: &mut Rng, image: &mut MyImage, std: f32) {
+    let plane = image.format.width * image.format.height;
+    let colour_channels = match image.format.layout {
+        ChannelLayout::Rgba => 3,
+        layout => layout.channels(),
+    };
+
+    for value in &mut image.pixels[..colour_channels * plane] {
+        let noisy = *value as f32 + rng.gaussian() * std;
+        *value = noisy.round().clamp(0.0, 255.0) as u8;
+    }
+}
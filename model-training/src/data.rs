@@ -1,32 +1,212 @@
-use burn::{data::dataloader::batcher::Batcher, prelude::*};
-use common::{CHANNELS, HEIGHT, MOUSE_VECTOR_LENGTH, WIDTH};
-use preprocessor::types::MyConstData;
+use std::{io, marker::PhantomData, path::Path};
 
+use burn::{
+    data::{dataloader::batcher::Batcher, dataset::Dataset},
+    prelude::*,
+};
+use common::{
+    MOUSE_BUTTONS, MOUSE_VECTOR_LENGTH, aggregation::AggregationConfig, frame::FrameFormat,
+};
+use preprocessor::{
+    csv_processing::KeysRecordConst,
+    hdf5_processing::{CACHED_BLOCKS, Hdf5Reader},
+    images::MyImage,
+    types::MyConstData,
+    vocabulary::KEY_PADDING,
+};
+
+use crate::augmentation::{AugmentationConfig, BatchPasses};
+
+/// Размер вектора мыши одного кадра при `mouse_bins` отрезках траектории:
+/// траектория + положение курсора + суммарное смещение + кнопки + колёсико
+pub const fn mouse_features(mouse_bins: usize) -> usize {
+    2 * mouse_bins + 2 + 2 + MOUSE_BUTTONS + 2
+}
+
+/// Размер вектора мыши при формате по умолчанию
+pub const MOUSE_FEATURES: usize = mouse_features(MOUSE_VECTOR_LENGTH);
+
+/// Датасет из файлов hdf5, записи читаются с диска по мере обращения
+pub struct Hdf5Dataset {
+    reader: Hdf5Reader,
+}
+
+impl Hdf5Dataset {
+    /// Все файлы проверяются при открытии, см. [`Hdf5Reader::validate`]
+    pub fn open(data_path: &Path) -> io::Result<Self> {
+        let reader = Hdf5Reader::open(data_path, CACHED_BLOCKS)?;
+        reader.validate()?;
+
+        Ok(Self { reader })
+    }
+
+    /// Формат кадров, общий для всех файлов
+    pub fn format(&self) -> FrameFormat {
+        self.reader.format()
+    }
+
+    /// Сведение действий, общее для всех файлов
+    pub fn aggregation(&self) -> AggregationConfig {
+        self.reader.aggregation()
+    }
+}
+
+impl Dataset<MyConstData> for Hdf5Dataset {
+    /// Если запись не читается, сообщается файл, кадр и ошибка, и возвращается `None`
+    fn get(&self, index: usize) -> Option<MyConstData> {
+        match self.reader.read(index) {
+            Ok(record) => record,
+            Err(err) => {
+                let (file, frame) = self.reader.source(index).unwrap_or_default();
+                eprintln!("{file}: кадр {frame} не читается: {err}");
+                None
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.reader.len()
+    }
+}
+
+/// Пример для обучения: кадры подряд, действие на последнем из них и настоящий следующий кадр
+#[derive(Clone, Debug)]
+pub struct FrameWindow {
+    /// Предыдущие кадры по порядку, последний — тот, на котором совершено действие
+    pub context: Vec<MyImage>,
+    pub action: KeysRecordConst,
+    pub next: MyImage,
+}
+
+impl FrameWindow {
+    /// Окно из `context_frames + 1` записей подряд
+    pub fn from_records(mut records: Vec<MyConstData>) -> Self {
+        let next = records.pop().expect("Окно из одной записи").image;
+        let action = records.last().expect("Окно без кадров").keys_record.clone();
+
+        Self {
+            context: records.into_iter().map(|data| data.image).collect(),
+            action,
+            next,
+        }
+    }
+}
+
+/// Окна из `context_frames` кадров и следующего за ними кадра.
+/// Окно не пересекает границу сессии и пропуски в номерах кадров.
+pub struct WindowDataset {
+    records: Hdf5Dataset,
+    context_frames: usize,
+    /// Номер первой записи каждого окна
+    starts: Vec<usize>,
+}
+
+impl WindowDataset {
+    pub fn new(records: Hdf5Dataset, context_frames: usize) -> Self {
+        let context_frames = context_frames.max(1);
+        let starts = records.reader.windows(context_frames + 1);
+
+        Self {
+            records,
+            context_frames,
+            starts,
+        }
+    }
+
+    pub fn format(&self) -> FrameFormat {
+        self.records.format()
+    }
+
+    pub fn aggregation(&self) -> AggregationConfig {
+        self.records.aggregation()
+    }
+
+    /// Откуда окно: имя файла (сессии), номера первого и последнего кадра
+    pub fn source(&self, index: usize) -> Option<(&str, u64, u64)> {
+        let start = *self.starts.get(index)?;
+        let (name, first) = self.records.reader.source(start)?;
+        let (_, last) = self.records.reader.source(start + self.context_frames)?;
+
+        Some((name, first, last))
+    }
+}
+
+impl Dataset<FrameWindow> for WindowDataset {
+    fn get(&self, index: usize) -> Option<FrameWindow> {
+        let start = *self.starts.get(index)?;
+
+        let records = (start..=start + self.context_frames)
+            .map(|index| self.records.get(index))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(FrameWindow::from_records(records))
+    }
+
+    fn len(&self) -> usize {
+        self.starts.len()
+    }
+}
+
+/// Сборка пакетов на устройстве, которое передаёт загрузчик
 #[derive(Clone)]
 pub struct FrameBatcher<B: Backend> {
-    device: B::Device,
+    /// Размер словаря клавиш, см. [`preprocessor::vocabulary::KeyVocabulary::size`]
+    key_count: usize,
+    /// Отрезков траектории мыши, см. [`AggregationConfig::mouse_bins`]
+    mouse_bins: usize,
+    /// Искажения и seed запуска; только для обучающих пакетов
+    augmentation: Option<(AugmentationConfig, u64)>,
+    /// Повторы пакетов для искажений, общие для копий в потоках загрузчика
+    passes: BatchPasses,
+    backend: PhantomData<B>,
 }
 
 impl<B: Backend> FrameBatcher<B> {
-    pub fn new(device: B::Device) -> Self {
-        Self { device }
+    pub fn new(key_count: usize) -> Self {
+        Self {
+            key_count,
+            mouse_bins: MOUSE_VECTOR_LENGTH,
+            augmentation: None,
+            passes: BatchPasses::default(),
+            backend: PhantomData,
+        }
+    }
+
+    pub fn with_mouse_bins(mut self, mouse_bins: usize) -> Self {
+        self.mouse_bins = mouse_bins;
+        self
     }
 
-    fn extract_const_keys(&self, mydata: &Vec<MyConstData>) -> Tensor<B, 2> {
-        let keys = mydata
+    pub fn with_augmentation(mut self, config: AugmentationConfig, seed: u64) -> Self {
+        self.augmentation = Some((config, seed));
+        self
+    }
+
+    fn extract_const_keys(&self, windows: &[FrameWindow], device: &B::Device) -> Tensor<B, 2> {
+        let unknown_id = self.key_count - 1;
+
+        let keys = windows
             .iter()
-            .map(|data| {
-                let mut keys_vector = [0; 108];
+            .map(|window| {
+                let mut keys_vector = vec![0.0f32; self.key_count];
 
-                for i in &data.keys_record.keys {
-                    keys_vector[*i as usize] += 1;
+                let record = &window.action;
+
+                // Вес клавиши — доля кадра, в течение которой она удерживалась
+                for (id, hold) in record
+                    .keys
+                    .iter()
+                    .zip(record.hold)
+                    .filter(|(id, _)| **id != KEY_PADDING)
+                {
+                    // Номера за пределами словаря считаются неизвестными клавишами
+                    keys_vector[(*id as usize).min(unknown_id)] += hold;
                 }
 
                 keys_vector
             })
-            .map(|vector| TensorData::from(vector).convert::<B::IntElem>())
-            .map(|data| Tensor::<B, 1>::from_data(data, &self.device))
-            .map(|tensor| tensor.reshape([1 as usize, 108]))
+            .map(|vector| TensorData::new(vector, [1, self.key_count]))
+            .map(|data| Tensor::<B, 2>::from_data(data, device))
             // // Простая нормализация
             // .map(|tensor| tensor / 255)
             .collect();
@@ -34,22 +214,32 @@ impl<B: Backend> FrameBatcher<B> {
         Tensor::cat(keys, 0)
     }
 
-    fn extract_const_mouse(&self, mydata: &Vec<MyConstData>) -> Tensor<B, 3> {
-        let mouse = mydata
+    fn extract_const_mouse(&self, windows: &[FrameWindow], device: &B::Device) -> Tensor<B, 2> {
+        let mouse = windows
             .iter()
-            .map(|data| {
-                let mut mouse_vector: [[i32; 2]; MOUSE_VECTOR_LENGTH] =
-                    [[0; 2]; MOUSE_VECTOR_LENGTH]; // Может не хватить, тк в коде нет ограничений на количество передвижений мыши
+            .map(|window| {
+                let record = &window.action;
+                let mut mouse_vector = Vec::with_capacity(mouse_features(self.mouse_bins));
 
-                for (i, value) in data.keys_record.mouse.iter().enumerate() {
-                    mouse_vector[i as usize] = *value;
-                }
+                // Каналы идут друг за другом в фиксированном порядке
+                mouse_vector.extend(
+                    record.mouse[..self.mouse_bins]
+                        .iter()
+                        .flatten()
+                        .map(|value| *value as f32),
+                );
+                mouse_vector.extend(record.position);
+                mouse_vector.extend(record.delta.iter().map(|value| *value as f32));
+                mouse_vector.extend(record.buttons.iter().map(|value| *value as f32));
+                mouse_vector.extend(record.wheel.iter().map(|value| *value as f32));
 
                 mouse_vector
             })
-            .map(|vector| TensorData::from(vector).convert::<B::FloatElem>())
-            .map(|data| Tensor::<B, 2>::from_data(data, &self.device))
-            .map(|tensor| tensor.reshape([1, 2, MOUSE_VECTOR_LENGTH]))
+            .map(|vector| {
+                let features = vector.len();
+                TensorData::new(vector, [1, features])
+            })
+            .map(|data| Tensor::<B, 2>::from_data(data, device))
             // // Простая нормализация
             // .map(|tensor| tensor.div_scalar(255))
             .collect();
@@ -57,16 +247,21 @@ impl<B: Backend> FrameBatcher<B> {
         Tensor::cat(mouse, 0)
     }
 
-    fn extract_const_images(&self, mydata: &Vec<MyConstData>) -> Tensor<B, 4> {
-        let images = mydata
-            .iter()
-            .map(|data| {
+    fn extract_const_images<'a>(
+        &self,
+        images: impl Iterator<Item = &'a MyImage>,
+        device: &B::Device,
+    ) -> Tensor<B, 4> {
+        let images = images
+            .map(|image| {
+                let format = image.format;
+                // Пиксели уже хранятся по каналам: [channels, height, width]
                 TensorData::new(
-                    data.image.to_image().as_bytes().to_vec(),
-                    [CHANNELS, HEIGHT, WIDTH],
+                    image.pixels.clone(),
+                    [format.channels(), format.height, format.width],
                 )
             })
-            .map(|data| Tensor::<B, 3>::from_data(data, &self.device))
+            .map(|data| Tensor::<B, 3>::from_data(data, device))
             .map(|tensor| tensor.unsqueeze_dim(0)) // [1, ...]
             // Простая нормализация цветов
             .map(|tensor| tensor / 255.0)
@@ -75,38 +270,51 @@ impl<B: Backend> FrameBatcher<B> {
         Tensor::cat(images, 0)
     }
 
-    fn extract_targets(&self, images: &Tensor<B, 4>) -> Tensor<B, 4> {
-        let targets = images
-            .clone()
-            .iter_dim(0)
-            .skip(1)
-            .chain(images.clone().iter_dim(0).take(1))
+    /// [batch, context_frames, channels, height, width]
+    fn extract_context(&self, windows: &[FrameWindow], device: &B::Device) -> Tensor<B, 5> {
+        let context = windows
+            .iter()
+            .map(|window| self.extract_const_images(window.context.iter(), device))
+            .map(|tensor| tensor.unsqueeze_dim(0)) // [1, context_frames, ...]
             .collect();
 
-        Tensor::cat(targets, 0).to_device(&self.device)
+        Tensor::cat(context, 0)
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct FrameBatch<B: Backend> {
+    /// Кадр, на котором совершено действие (последний кадр контекста)
     pub images: Tensor<B, 4>,
+    /// Все кадры контекста: [batch, context_frames, channels, height, width]
+    pub context: Tensor<B, 5>,
     pub keys: Tensor<B, 2>,
-    pub mouse: Tensor<B, 3>,
+    pub mouse: Tensor<B, 2>,
+    /// Настоящий следующий кадр
     pub targets: Tensor<B, 4>,
 }
 
-impl<B: Backend> Batcher<B, MyConstData, FrameBatch<B>> for FrameBatcher<B> {
-    fn batch(&self, mydata: Vec<MyConstData>, device: &Device<B>) -> FrameBatch<B> {
-        let images = self.extract_const_images(&mydata);
-        let keys = self.extract_const_keys(&mydata);
-        let mouse = self.extract_const_mouse(&mydata);
+impl<B: Backend> Batcher<B, FrameWindow, FrameBatch<B>> for FrameBatcher<B> {
+    fn batch(&self, windows: Vec<FrameWindow>, device: &Device<B>) -> FrameBatch<B> {
+        let windows = match &self.augmentation {
+            Some((config, seed)) => config.apply(*seed, &self.passes, windows),
+            None => windows,
+        };
 
-        // Сдвинутые изображения на 1
-        // TODO: Изменить?
-        let targets = self.extract_targets(&images);
+        let images = self.extract_const_images(
+            windows
+                .iter()
+                .map(|window| window.context.last().expect("Окно без кадров")),
+            device,
+        );
+        let context = self.extract_context(&windows, device);
+        let keys = self.extract_const_keys(&windows, device);
+        let mouse = self.extract_const_mouse(&windows, device);
+        let targets = self.extract_const_images(windows.iter().map(|window| &window.next), device);
 
         FrameBatch {
             images,
+            context,
             keys,
             mouse,
             targets,
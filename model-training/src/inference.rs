@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use burn::{
     backend,
     config::Config,
@@ -7,22 +9,24 @@ use burn::{
     record::{CompactRecorder, Recorder},
     tensor::{Distribution, Tensor},
 };
-use common::*;
-use image::{DynamicImage, Rgba32FImage};
+use image::DynamicImage;
 use preprocessor::{
-    csv_processing::{KeysRecordConst, key_to_num},
-    images::MyImage,
-    types::MyConstData,
+    csv_processing::KeysRecordConst, images::MyImage, types::MyConstData, vocabulary::KeyVocabulary,
 };
 
-use crate::{data::FrameBatcher, models::model_v1::model::ModelV1, training::TrainingConfig};
+use crate::{
+    data::{FrameBatcher, FrameWindow},
+    models::model_v1::model::ModelV1,
+    training::TrainingConfig,
+};
 
 /// DDPM sampling loop with simplified Euler method
 fn ddpm_sampling_loop<B: Backend>(
     model: &ModelV1<B>,
+    context: Tensor<B, 4>,
     start_image: Tensor<B, 4>,
     keys: Tensor<B, 2>,
-    mouse: Tensor<B, 3>,
+    mouse: Tensor<B, 2>,
     num_steps: usize,
 ) -> Tensor<B, 4> {
     let device = start_image.device();
@@ -40,7 +44,7 @@ fn ddpm_sampling_loop<B: Backend>(
 
         // Model predicts noise
         let predicted = model.forward(
-            x_t.clone(),
+            context.clone(),
             keys.clone(),
             mouse.clone(),
             x_t.clone(), // Using current x_t as conditional for now
@@ -57,25 +61,35 @@ fn ddpm_sampling_loop<B: Backend>(
 
 fn infer<B: Backend>(
     artifact_dir: &str,
+    config: &TrainingConfig,
     device: B::Device,
     item: MyConstData,
 ) -> Vec<DynamicImage> {
-    let config = TrainingConfig::load(format!("{artifact_dir}/config.json"))
-        .expect("Config should exist for the model");
+    let format = item.image.format;
     let record = CompactRecorder::new()
         .load(format!("{artifact_dir}/model").into(), &device)
         .expect("Trained model should exist");
 
     let model = config.model.init::<B>(&device).load_record(record);
 
-    let batcher = FrameBatcher::new(device.clone());
-    let batch = batcher.batch(vec![item], &device);
+    // Истории кадров при генерации нет: контекст заполняется текущим кадром,
+    // а следующий кадр неизвестен и в генерации не участвует
+    let window = FrameWindow {
+        context: vec![item.image.clone(); config.model.context_frames],
+        action: item.keys_record,
+        next: item.image,
+    };
+
+    let batcher =
+        FrameBatcher::new(config.model.key_count).with_mouse_bins(config.model.mouse_bins);
+    let batch = batcher.batch(vec![window], &device);
 
     // DDPM sampling with 50 steps
     const NUM_STEPS: usize = 50;
 
     let output = ddpm_sampling_loop(
         &model,
+        batch.context.flatten(1, 2),
         batch.images.clone(),
         batch.keys.clone(),
         batch.mouse.clone(),
@@ -87,13 +101,18 @@ fn infer<B: Backend>(
         // Возвращение из нормализации
         // .map(|tensor| tensor * 255)
         .map(&mut |tensor: Tensor<B, 4>| tensor.to_data())
-        .map(&mut |data: burn::prelude::TensorData| data.to_vec().unwrap())
-        // .map(|vector| vector.iter().map(|v| *v as u8).collect())
-        .map(&mut |vector| {
-            let image = Rgba32FImage::from_vec(WIDTH as u32, HEIGHT as u32, vector).unwrap();
-
-            DynamicImage::from(image)
-            // RgbaImage::from_vec(WIDTH as u32, HEIGHT as u32, vector).unwrap()
+        .map(&mut |data: burn::prelude::TensorData| data.to_vec::<f32>().unwrap())
+        .map(&mut |vector: Vec<f32>| {
+            vector
+                .iter()
+                .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect::<Vec<u8>>()
+        })
+        .map(&mut |pixels| {
+            // Выход модели: [channels, height, width] в формате кадров датасета
+            MyImage::from_pixels(format, pixels)
+                .expect("Model output should match the frame format")
+                .to_image()
         })
         .collect();
 
@@ -125,40 +144,39 @@ pub fn generate(
     #[cfg(feature = "cuda")]
     let device = backend::cuda::CudaDevice::default();
 
-    let my_image: MyImage<HEIGHT, WIDTH, CHANNELS> = MyImage::from_image(current_image);
+    let config = TrainingConfig::load(format!("{artifact_dir}/config.json"))
+        .expect("Config should exist for the model");
+    // Кадр уменьшается до формата, на котором обучалась модель, и тем же способом,
+    // что и кадры датасета
+    let format = config
+        .model
+        .frame_format()
+        .expect("Model config should have a valid frame format");
+    let my_image = MyImage::from_image(current_image, format)
+        .expect("Frame should fit the model frame format");
+    // Траектория мыши сводится так же, как в датасете модели
+    let aggregation = config
+        .model
+        .aggregation()
+        .expect("Model config should have a valid aggregation");
 
     // TODO: мб пофиксить?
     // Да не, пока норм вроде
 
+    // Словарь сохраняется рядом с моделью при обучении
+    let vocabulary = KeyVocabulary::load_or_default(Path::new(artifact_dir))
+        .expect("Key vocabulary should be readable");
+
     let keys: Vec<u8> = keys
         .into_iter()
         .filter(|key| !key.is_empty())
-        .map(|key| key.to_lowercase())
-        .map(|key| key_to_num(&key))
+        .map(|key| vocabulary.key_to_num(&key))
         .collect();
 
-    let mut const_keys: [u8; 200] = [0; 200];
-
-    for (i, value) in keys.iter().enumerate() {
-        const_keys[i] = *value;
-    }
-
-    let mut const_mouse: [[i32; 2]; 200] = [[0; 2]; 200];
-
-    for (i, value) in mouse.iter().enumerate() {
-        const_mouse[i] = *value;
-    }
-
     let item = MyConstData {
         image: my_image,
-        keys_record: KeysRecordConst {
-            keys: const_keys,
-            mouse: const_mouse,
-        },
+        keys_record: KeysRecordConst::from_slices_binned(&keys, &mouse, aggregation.mouse_bins),
     };
 
-    let next_image =
-        crate::inference::infer::<MyBackend>(artifact_dir, device.clone(), item)[0].clone();
-
-    next_image
+    crate::inference::infer::<MyBackend>(artifact_dir, &config, device, item)[0].clone()
 }
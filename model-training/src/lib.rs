@@ -1,5 +1,11 @@
+pub mod augmentation;
 pub mod inference;
 pub mod models;
 
 mod data;
+pub use data::{
+    FrameBatch, FrameBatcher, FrameWindow, Hdf5Dataset, MOUSE_FEATURES, WindowDataset,
+    mouse_features,
+};
+pub mod split;
 pub mod training;
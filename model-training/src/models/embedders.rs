@@ -5,7 +5,6 @@ use burn::{
     prelude::Backend,
     tensor::{Tensor, TensorData},
 };
-use common::MOUSE_VECTOR_LENGTH;
 
 #[derive(Module, Debug)]
 pub struct MouseEmbedder<B: Backend> {
@@ -18,14 +17,17 @@ pub struct MouseEmbedder<B: Backend> {
 pub struct MouseEmbedderConfig {
     embed_dim: usize,
     hidden_dim: usize,
+    /// Ширина входного вектора, [`crate::mouse_features`] от длины траектории
+    #[config(default = "crate::data::MOUSE_FEATURES")]
+    mouse_features: usize,
 }
 
 impl MouseEmbedderConfig {
     pub fn init<B: Backend>(&self, device: &B::Device) -> MouseEmbedder<B> {
         MouseEmbedder {
             // LinearConfig::new(input_features, output_features)
-            // input: 2 * MOUSE_VECTOR_LENGTH = 400, output: hidden_dim = 100
-            linear1: LinearConfig::new(2 * MOUSE_VECTOR_LENGTH, self.hidden_dim).init(device),
+            // input: mouse_features = 41 по умолчанию, output: hidden_dim = 100
+            linear1: LinearConfig::new(self.mouse_features, self.hidden_dim).init(device),
             activation: Relu,
             // input: hidden_dim = 100, output: embed_dim = 100
             linear2: LinearConfig::new(self.hidden_dim, self.embed_dim).init(device),
@@ -34,9 +36,8 @@ impl MouseEmbedderConfig {
 }
 
 impl<B: Backend> MouseEmbedder<B> {
-    pub fn forward(&self, mouse: Tensor<B, 3>) -> Tensor<B, 2> {
-        let x = mouse.flatten(1, 2); // [n, 2, 200] -> [n, 400]
-        let x = self.linear1.forward(x); // [n, 400] -> [n, 100]
+    pub fn forward(&self, mouse: Tensor<B, 2>) -> Tensor<B, 2> {
+        let x = self.linear1.forward(mouse); // [n, mouse_features] -> [n, 100]
         let x = self.activation.forward(x);
         let x = self.linear2.forward(x); // [n, 100] -> [n, 100]
         x
@@ -54,14 +55,16 @@ pub struct KeyboardEmbedder<B: Backend> {
 pub struct KeyboardEmbedderConfig {
     embed_dim: usize,
     hidden_dim: usize,
+    /// Размер словаря клавиш (ширина входного вектора)
+    key_count: usize,
 }
 
 impl KeyboardEmbedderConfig {
     pub fn init<B: Backend>(&self, device: &B::Device) -> KeyboardEmbedder<B> {
         KeyboardEmbedder {
             // LinearConfig::new(input_features, output_features)
-            // input: 108, output: hidden_dim = 100
-            linear1: LinearConfig::new(108, self.hidden_dim).init(device),
+            // input: key_count, output: hidden_dim = 100
+            linear1: LinearConfig::new(self.key_count, self.hidden_dim).init(device),
             activation: Relu,
             // input: hidden_dim = 100, output: embed_dim = 100
             linear2: LinearConfig::new(self.hidden_dim, self.embed_dim).init(device),
@@ -71,7 +74,7 @@ impl KeyboardEmbedderConfig {
 
 impl<B: Backend> KeyboardEmbedder<B> {
     pub fn forward(&self, keys: Tensor<B, 2>) -> Tensor<B, 2> {
-        let x = self.linear1.forward(keys); // [n, 108] -> [n, 100]
+        let x = self.linear1.forward(keys); // [n, key_count] -> [n, 100]
         let x = self.activation.forward(x);
         let x = self.linear2.forward(x); // [n, 100] -> [n, 100]
         x
@@ -1,19 +1,26 @@
 use burn::{
     nn::{
-        conv::{Conv2d, Conv2dConfig},
         Relu,
+        conv::{Conv2d, Conv2dConfig},
     },
     prelude::*,
 };
 
-use common::*;
+use common::{
+    aggregation::AggregationConfig,
+    frame::{FrameFormat, Resize},
+    *,
+};
 
-use crate::models::{
-    embedders::{
-        KeyboardEmbedder, KeyboardEmbedderConfig, MouseEmbedder, MouseEmbedderConfig,
-        TimestepEmbedder, TimestepEmbedderConfig,
+use crate::{
+    data::mouse_features,
+    models::{
+        embedders::{
+            KeyboardEmbedder, KeyboardEmbedderConfig, MouseEmbedder, MouseEmbedderConfig,
+            TimestepEmbedder, TimestepEmbedderConfig,
+        },
+        unets::base_unet::model::{BaseUNet, BaseUNetConfig},
     },
-    unets::base_unet::model::{BaseUNet, BaseUNetConfig},
 };
 
 /// Lightweight conditional processing using Conv2d instead of huge Linear layers.
@@ -72,35 +79,88 @@ pub struct ModelV1<B: Backend> {
 pub struct ModelV1Config {
     #[config(default = "100")]
     embed_dim: usize,
+    /// Размер словаря клавиш, на котором обучается модель
+    #[config(default = "preprocessor::vocabulary::DEFAULT_KEY_COUNT")]
+    pub key_count: usize,
+    /// Формат кадров датасета, на котором обучается модель;
+    /// ширина и высота должны делиться на 4 (два уменьшения в U-Net)
+    #[config(default = "WIDTH")]
+    pub width: usize,
+    #[config(default = "HEIGHT")]
+    pub height: usize,
+    #[config(default = "CHANNELS")]
+    pub channels: usize,
+    /// Как кадр экрана уменьшался для датасета; инференс уменьшает кадр так же
+    #[config(default = "Resize::default()")]
+    pub resize: Resize,
+    /// Отрезков траектории мыши в датасете, от неё зависит ширина входа мыши,
+    /// см. [`AggregationConfig::mouse_bins`]
+    #[config(default = "MOUSE_VECTOR_LENGTH")]
+    pub mouse_bins: usize,
+    /// Кадров контекста на входе, они складываются по оси каналов
+    #[config(default = "1")]
+    pub context_frames: usize,
 }
 
 impl ModelV1Config {
+    /// Конфигурация под формат кадров датасета
+    pub fn with_frame_format(self, format: FrameFormat) -> Self {
+        self.with_width(format.width)
+            .with_height(format.height)
+            .with_channels(format.channels())
+            .with_resize(format.resize)
+    }
+
+    pub fn frame_format(&self) -> std::io::Result<FrameFormat> {
+        FrameFormat::from_channels(self.width, self.height, self.channels)?.with_resize(self.resize)
+    }
+
+    /// Конфигурация под сведение действий датасета
+    pub fn with_aggregation(self, aggregation: AggregationConfig) -> Self {
+        self.with_mouse_bins(aggregation.mouse_bins)
+    }
+
+    pub fn aggregation(&self) -> std::io::Result<AggregationConfig> {
+        AggregationConfig::new(self.mouse_bins)
+    }
+
     pub fn init<B: Backend>(&self, device: &B::Device) -> ModelV1<B> {
         ModelV1 {
-            mouse_embedder: MouseEmbedderConfig::new(self.embed_dim, self.embed_dim).init(device),
-            keys_embedder: KeyboardEmbedderConfig::new(self.embed_dim, self.embed_dim).init(device),
+            mouse_embedder: MouseEmbedderConfig::new(self.embed_dim, self.embed_dim)
+                .with_mouse_features(mouse_features(self.mouse_bins))
+                .init(device),
+            keys_embedder: KeyboardEmbedderConfig::new(
+                self.embed_dim,
+                self.embed_dim,
+                self.key_count,
+            )
+            .init(device),
             timestep_embedder: TimestepEmbedderConfig::new(self.embed_dim).init(device),
 
-            conditional: ConditionalBlockConfig::new(CHANNELS).init(device),
+            conditional: ConditionalBlockConfig::new(self.channels).init(device),
 
             unet: BaseUNetConfig::new()
+                .with_channels(self.channels)
+                .with_input_frames(self.context_frames)
                 .with_embed_dim(self.embed_dim)
-                .with_conditional_dim(304) // CHANNELS(4) + embed_dim*3(300) = 304
+                .with_conditional_dim(self.channels + self.embed_dim * 3) // 4 + 300 = 304 по умолчанию
                 .init(device),
         }
     }
 }
 
 impl<B: Backend> ModelV1<B> {
+    /// `images` — кадры контекста, сложенные по каналам:
+    /// [batch, context_frames * channels, height, width]
     pub fn forward(
         &self,
         images: Tensor<B, 4>,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
+        mouse: Tensor<B, 2>,
         next_noise: Tensor<B, 4>, // conditional layers || Зашумлённый следующий кадр при тренировке или случайный шум при генерации
         timestep: Tensor<B, 1>,   // Timestep for diffusion
     ) -> Tensor<B, 4> {
-        let [batch_size, channels, height, width] = images.dims();
+        let [batch_size, channels, height, width] = next_noise.dims();
 
         // Получаем эмбеддинги
         let mouse_emb = self.mouse_embedder.forward(mouse); // [b, embed_dim]
@@ -138,8 +198,6 @@ impl<B: Backend> ModelV1<B> {
         // FIX: Pass images to UNet (has 4 channels), conditional is used inside UNet
         let x = self.unet.forward(images.clone(), conditional);
 
-        let x = x.reshape([batch_size, channels, height, width]);
-
-        x
+        x.reshape([batch_size, channels, height, width])
     }
 }
@@ -1,7 +1,7 @@
 use burn::{
     nn::loss::{MseLoss, Reduction},
     prelude::Backend,
-    tensor::{backend::AutodiffBackend, Tensor},
+    tensor::{Tensor, backend::AutodiffBackend},
     train::{InferenceStep, RegressionOutput, TrainOutput, TrainStep},
 };
 
@@ -14,7 +14,7 @@ impl<B: Backend> ModelV1<B> {
         &self,
         inputs: Tensor<B, 4>,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
+        mouse: Tensor<B, 2>,
         targets: Tensor<B, 4>,
     ) -> RegressionOutput<B> {
         const P_STD: f32 = 1.2;
@@ -37,7 +37,7 @@ impl<B: Backend> ModelV1<B> {
         );
         // Clamp sigma to prevent extreme noise values that cause NaN
         let sigma = (random_normal * P_STD + P_MEAN).exp().clamp(0.001, 10.0);
-        let noise = inputs.random_like(burn::tensor::Distribution::Normal(0.0, 1.0)) * sigma;
+        let noise = targets.random_like(burn::tensor::Distribution::Normal(0.0, 1.0)) * sigma;
 
         let noised_targets = targets.clone() + noise;
 
@@ -57,7 +57,8 @@ impl<B: AutodiffBackend> TrainStep for ModelV1<B> {
     type Output = RegressionOutput<B>;
 
     fn step(&self, batch: FrameBatch<B>) -> TrainOutput<RegressionOutput<B>> {
-        let item = self.forward_generation(batch.images, batch.keys, batch.mouse, batch.targets);
+        let inputs = batch.context.flatten(1, 2);
+        let item = self.forward_generation(inputs, batch.keys, batch.mouse, batch.targets);
         TrainOutput::new(self, item.loss.backward(), item)
     }
 }
@@ -67,6 +68,7 @@ impl<B: Backend> InferenceStep for ModelV1<B> {
     type Output = RegressionOutput<B>;
 
     fn step(&self, batch: FrameBatch<B>) -> RegressionOutput<B> {
-        self.forward_generation(batch.images, batch.keys, batch.mouse, batch.targets)
+        let inputs = batch.context.flatten(1, 2);
+        self.forward_generation(inputs, batch.keys, batch.mouse, batch.targets)
     }
 }
@@ -170,6 +170,10 @@ pub struct ModelV2<B: Backend> {
     keys_embedder: KeyboardEmbedder<B>,
     timestep_embedder: TimestepEmbedder<B>,
     latent_unet: LatentUNet<B>,
+    /// Размер кадра, для которого генерируется латентный шум
+    width: usize,
+    height: usize,
+    channels: usize,
 }
 
 #[derive(Config, Debug)]
@@ -182,6 +186,16 @@ pub struct ModelV2Config {
     pub unet_hidden_dim: usize,
     #[config(default = "1000")]
     pub num_timesteps: usize,
+    /// Размер словаря клавиш, на котором обучается модель
+    #[config(default = "preprocessor::vocabulary::DEFAULT_KEY_COUNT")]
+    pub key_count: usize,
+    /// Формат кадров датасета; ширина и высота должны делиться на 4
+    #[config(default = "WIDTH")]
+    pub width: usize,
+    #[config(default = "HEIGHT")]
+    pub height: usize,
+    #[config(default = "CHANNELS")]
+    pub channels: usize,
 }
 
 impl ModelV2Config {
@@ -190,16 +204,25 @@ impl ModelV2Config {
 
         ModelV2 {
             vae: VAEConfig::new()
+                .with_channels(self.channels)
                 .with_latent_channels(self.latent_channels)
                 .init(device),
             mouse_embedder: MouseEmbedderConfig::new(self.embed_dim, self.embed_dim).init(device),
-            keys_embedder: KeyboardEmbedderConfig::new(self.embed_dim, self.embed_dim).init(device),
+            keys_embedder: KeyboardEmbedderConfig::new(
+                self.embed_dim,
+                self.embed_dim,
+                self.key_count,
+            )
+            .init(device),
             timestep_embedder: TimestepEmbedderConfig::new(self.embed_dim).init(device),
             latent_unet: LatentUNetConfig::new()
                 .with_latent_channels(self.latent_channels)
                 .with_hidden_dim(self.unet_hidden_dim)
                 .with_condition_dim(condition_dim)
                 .init(device),
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
         }
     }
 
@@ -216,7 +239,7 @@ impl<B: Backend> ModelV2<B> {
     fn compute_condition(
         &self,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
+        mouse: Tensor<B, 2>,
         timestep: Tensor<B, 1>,
     ) -> Tensor<B, 2> {
         let mouse_emb = self.mouse_embedder.forward(mouse); // [B, embed_dim]
@@ -239,7 +262,7 @@ impl<B: Backend> ModelV2<B> {
         &self,
         targets: Tensor<B, 4>,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
+        mouse: Tensor<B, 2>,
         timestep_indices: Tensor<B, 1>,
         noise: Tensor<B, 4>,
         alpha: f32,
@@ -267,7 +290,7 @@ impl<B: Backend> ModelV2<B> {
     pub fn sample(
         &self,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
+        mouse: Tensor<B, 2>,
         schedule: &CosineNoiseSchedule,
         num_steps: usize,
     ) -> Tensor<B, 4> {
@@ -275,11 +298,14 @@ impl<B: Backend> ModelV2<B> {
         let batch_size = keys.dims()[0];
         let latent_channels = self
             .vae
-            .encode(Tensor::zeros([1, CHANNELS, HEIGHT, WIDTH], &device))
+            .encode(Tensor::zeros(
+                [1, self.channels, self.height, self.width],
+                &device,
+            ))
             .0
             .dims()[1];
-        let latent_h = HEIGHT / 4;
-        let latent_w = WIDTH / 4;
+        let latent_h = self.height / 4;
+        let latent_w = self.width / 4;
 
         // Start from pure noise in latent space
         let mut z_t = Tensor::random(
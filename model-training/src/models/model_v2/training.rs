@@ -1,7 +1,7 @@
 use burn::{
     nn::loss::{MseLoss, Reduction},
     prelude::Backend,
-    tensor::{backend::AutodiffBackend, Distribution, Tensor},
+    tensor::{Distribution, Tensor, backend::AutodiffBackend},
     train::{InferenceStep, RegressionOutput, TrainOutput, TrainStep},
 };
 
@@ -26,7 +26,7 @@ impl<B: Backend> ModelV2<B> {
         &self,
         _images: Tensor<B, 4>,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
+        mouse: Tensor<B, 2>,
         targets: Tensor<B, 4>,
     ) -> RegressionOutput<B> {
         let batch_size = targets.dims()[0];
@@ -1,12 +1,12 @@
 use burn::{
     nn::{
         conv::{Conv2d, Conv2dConfig, ConvTranspose2d, ConvTranspose2dConfig},
-        pool::{AdaptiveAvgPool2d, AdaptiveAvgPool2dConfig, MaxPool2d, MaxPool2dConfig},
+        pool::{MaxPool2d, MaxPool2dConfig},
         Relu,
     },
     prelude::*,
 };
-use common::{CHANNELS, HEIGHT, WIDTH};
+use common::CHANNELS;
 
 /// Project conditional to bottleneck dimensions
 #[derive(Module, Debug)]
@@ -82,6 +82,14 @@ pub struct BaseUNet<B: Backend> {
 
 #[derive(Config, Debug)]
 pub struct BaseUNetConfig {
+    /// Каналы входного и выходного кадра
+    #[config(default = "CHANNELS")]
+    channels: usize,
+
+    /// Кадров на входе, они сложены по оси каналов
+    #[config(default = "1")]
+    input_frames: usize,
+
     #[config(default = "16")]
     embed_dim: usize,
 
@@ -97,7 +105,7 @@ pub struct BaseUNetConfig {
 impl BaseUNetConfig {
     pub fn init<B: Backend>(&self, device: &B::Device) -> BaseUNet<B> {
         BaseUNet {
-            conv1: Conv2dConfig::new([CHANNELS, self.hidden_dim], [3, 3])
+            conv1: Conv2dConfig::new([self.channels * self.input_frames, self.hidden_dim], [3, 3])
                 .with_padding(nn::PaddingConfig2d::Same)
                 .init(device),
             act1: Relu,
@@ -156,7 +164,7 @@ impl BaseUNetConfig {
                 .with_padding(nn::PaddingConfig2d::Same)
                 .init(device),
             act9: Relu,
-            conv10: Conv2dConfig::new([self.hidden_dim, CHANNELS], [3, 3])
+            conv10: Conv2dConfig::new([self.hidden_dim, self.channels], [3, 3])
                 .with_padding(nn::PaddingConfig2d::Same)
                 .init(device),
             act10: Relu,
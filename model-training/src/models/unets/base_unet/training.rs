@@ -14,7 +14,7 @@ impl<B: Backend> BaseUNet<B> {
         &self,
         inputs: Tensor<B, 4>,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
+        mouse: Tensor<B, 2>,
         targets: Tensor<B, 4>,
     ) -> RegressionOutput<B> {
         const P_STD: f32 = 1.2;
@@ -48,6 +48,9 @@ pub struct VAE<B: Backend> {
 
 #[derive(Config, Debug)]
 pub struct VAEConfig {
+    /// Каналы кадра
+    #[config(default = "CHANNELS")]
+    pub channels: usize,
     /// Latent space channels (default 8)
     #[config(default = "8")]
     pub latent_channels: usize,
@@ -64,7 +67,7 @@ impl VAEConfig {
         VAE {
             encoder: Encoder {
                 // 40x40x4 -> 40x40x16
-                conv1: Conv2dConfig::new([CHANNELS, hc], [3, 3])
+                conv1: Conv2dConfig::new([self.channels, hc], [3, 3])
                     .with_padding(nn::PaddingConfig2d::Same)
                     .init(device),
                 act1: Relu,
@@ -105,7 +108,7 @@ impl VAEConfig {
                     .with_stride([2, 2])
                     .init(device),
                 // 40x40x16 -> 40x40x4
-                conv3: Conv2dConfig::new([hc, CHANNELS], [3, 3])
+                conv3: Conv2dConfig::new([hc, self.channels], [3, 3])
                     .with_padding(nn::PaddingConfig2d::Same)
                     .init(device),
             },
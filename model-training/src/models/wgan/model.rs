@@ -1,4 +1,4 @@
-use burn::{nn::BatchNorm, prelude::*};
+use burn::prelude::*;
 
 use common::*;
 
@@ -53,6 +53,9 @@ pub struct WganDecoder<B: Backend> {
 pub struct WganDecoderConfig {
     #[config(default = "16")]
     embed_dim: usize,
+    /// Размер словаря клавиш, на котором обучается модель
+    #[config(default = "preprocessor::vocabulary::DEFAULT_KEY_COUNT")]
+    key_count: usize,
     // #[config(default = 10)]
     // pub num_timestamps: usize,
 }
@@ -73,7 +76,12 @@ impl WganDecoderConfig {
 
         WganDecoder {
             mouse_embedder: MouseEmbedderConfig::new(self.embed_dim, self.embed_dim).init(device),
-            keys_embedder: KeyboardEmbedderConfig::new(self.embed_dim, self.embed_dim).init(device),
+            keys_embedder: KeyboardEmbedderConfig::new(
+                self.embed_dim,
+                self.embed_dim,
+                self.key_count,
+            )
+            .init(device),
             // timestep_embedder: TimestempEmbedderConfig::new(self.embed_dim, self.embed_dim)
             //     .init(device),
             layer1,
@@ -92,13 +100,13 @@ impl<B: Backend> WganDecoder<B> {
         &self,
         images: Tensor<B, 4>,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
+        mouse: Tensor<B, 2>,
         // timesteps: Tensor<B, 1>,
     ) -> Tensor<B, 4> {
         // Получаем эмбеддинги
         let mouse_emb = self.mouse_embedder.forward(mouse); // [b, embed_dim]
         let keys_emb = self.keys_embedder.forward(keys); // [b, embed_dim]
-                                                         // let timesteps_emb = self.timestep_embedder.forward(timesteps); // [b, embed_dim]
+        // let timesteps_emb = self.timestep_embedder.forward(timesteps); // [b, embed_dim]
 
         // здесь для простоты просто суммируем
         // let embed = mouse_emb + keys_emb; // [b, embed_dim]
@@ -132,9 +140,7 @@ impl<B: Backend> WganDecoder<B> {
         let x = self.fc.forward(x);
         let x = self.tanh.forward(x);
 
-        let x = x.reshape([batch_size, channels, height, width]);
-
-        x
+        x.reshape([batch_size, channels, height, width])
     }
 
     // pub fn latent_to_image(&self, latent: Tensor<B, 4>) -> Vec<Vec<u8>> {
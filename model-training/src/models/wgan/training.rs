@@ -1,11 +1,9 @@
 use burn::{
     nn::loss::{MseLoss, Reduction},
     prelude::Backend,
-    tensor::{backend::AutodiffBackend, Tensor},
+    tensor::Tensor,
 };
 
-use crate::data::FrameBatch;
-
 use super::model::WganDecoder;
 
 impl<B: Backend> WganDecoder<B> {
@@ -13,8 +11,8 @@ impl<B: Backend> WganDecoder<B> {
         &self,
         inputs: Tensor<B, 4>,
         keys: Tensor<B, 2>,
-        mouse: Tensor<B, 3>,
-        targets: Tensor<B, 4>,
+        mouse: Tensor<B, 2>,
+        _targets: Tensor<B, 4>,
     ) -> burn::train::RegressionOutput<B> {
         const P_STD: f32 = 1.2;
         const P_MEAN: f32 = -1.2;
@@ -0,0 +1,245 @@
+//! Разбиение окон на обучающую, проверочную и тестовую части.
+//!
+//! Соседние кадры почти одинаковы, поэтому окна распределяются не по одному,
+//! а целыми единицами: сессиями или блоками кадров внутри сессий. Единицы
+//! упорядочиваются по хэшу от seed и имени, так что разбиение не зависит
+//! от порядка чтения файлов. Итог сохраняется рядом с моделью ([`SPLIT_FILE`])
+//! и может быть повторён в другом эксперименте через [`SplitStrategy::Manifest`].
+
+use std::{collections::HashMap, fmt, io};
+
+use burn::{config::Config, data::dataset::Dataset};
+use serde::{Deserialize, Serialize};
+
+use crate::data::WindowDataset;
+
+/// Разбиение в директории с моделью
+pub const SPLIT_FILE: &str = "split.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitPart {
+    Train,
+    Valid,
+    Test,
+}
+
+/// Как выбрать единицы для каждой части. `valid` и `test` — доли единиц.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum SplitStrategy {
+    /// Сессии целиком
+    Session { valid: f64, test: f64 },
+    /// Блоки по `block_frames` кадров внутри сессий. Окна, которые ближе
+    /// `gap_frames` кадров к блоку другой части, отбрасываются.
+    TimeBlock {
+        block_frames: u64,
+        gap_frames: u64,
+        valid: f64,
+        test: f64,
+    },
+    /// Готовое разбиение из файла [`SplitManifest`], например [`SPLIT_FILE`] прошлого запуска
+    Manifest { path: String },
+}
+
+impl Default for SplitStrategy {
+    /// Блоки по 30 секунд и зазор в секунду при записи 20 кадров в секунду
+    fn default() -> Self {
+        Self::TimeBlock {
+            block_frames: 600,
+            gap_frames: 20,
+            valid: 0.1,
+            test: 0.1,
+        }
+    }
+}
+
+/// Единицы каждой части: имена сессий или блоков `сессия#номер`.
+/// В разбиении по блокам можно указать и сессию целиком.
+#[derive(Config, Debug, PartialEq)]
+pub struct SplitManifest {
+    /// Кадров в блоке; 0 — единицы разбиения целые сессии
+    #[config(default = 0)]
+    pub block_frames: u64,
+    /// Окна ближе стольких кадров к блоку другой части отбрасываются
+    #[config(default = 0)]
+    pub gap_frames: u64,
+    pub train: Vec<String>,
+    pub valid: Vec<String>,
+    pub test: Vec<String>,
+}
+
+/// Номера окон каждой части
+#[derive(Clone, Debug)]
+pub struct DatasetSplit {
+    pub manifest: SplitManifest,
+    pub train: Vec<usize>,
+    pub valid: Vec<usize>,
+    pub test: Vec<usize>,
+    /// Окна у границ блоков разных частей и окна сессий, которых нет в разбиении
+    pub dropped: usize,
+}
+
+impl fmt::Display for DatasetSplit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Окон: обучение {} ({} ед.), проверка {} ({} ед.), тест {} ({} ед.), отброшено {}",
+            self.train.len(),
+            self.manifest.train.len(),
+            self.valid.len(),
+            self.manifest.valid.len(),
+            self.test.len(),
+            self.manifest.test.len(),
+            self.dropped
+        )
+    }
+}
+
+/// Имя единицы, в которую попадает кадр
+fn unit_name(session: &str, frame: u64, block_frames: u64) -> String {
+    match frame.checked_div(block_frames) {
+        Some(block) => format!("{session}#{block}"),
+        None => session.to_string(),
+    }
+}
+
+/// Хэш имени единицы, не зависящий от версии компилятора: FNV-1a и перемешивание splitmix64
+fn unit_hash(seed: u64, name: &str) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+impl SplitStrategy {
+    pub fn resolve(&self, windows: &WindowDataset, seed: u64) -> io::Result<DatasetSplit> {
+        let manifest = match self {
+            Self::Session { valid, test } => assign(windows, seed, 0, 0, *valid, *test),
+            Self::TimeBlock {
+                block_frames,
+                gap_frames,
+                valid,
+                test,
+            } => assign(
+                windows,
+                seed,
+                (*block_frames).max(1),
+                *gap_frames,
+                *valid,
+                *test,
+            ),
+            Self::Manifest { path } => SplitManifest::load(path).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {err}"))
+            })?,
+        };
+
+        Ok(manifest.apply(windows))
+    }
+}
+
+/// Единицы по хэшу: первые — тест, следующие — проверка, остальные — обучение.
+/// Если доля не нулевая, часть получает хотя бы одну единицу, пока для обучения
+/// остаётся хотя бы одна.
+fn assign(
+    windows: &WindowDataset,
+    seed: u64,
+    block_frames: u64,
+    gap_frames: u64,
+    valid: f64,
+    test: f64,
+) -> SplitManifest {
+    let mut units: Vec<String> = (0..windows.len())
+        .filter_map(|index| windows.source(index))
+        .map(|(session, _, last)| unit_name(session, last, block_frames))
+        .collect();
+    units.sort();
+    units.dedup();
+    units.sort_by_cached_key(|name| (unit_hash(seed, name), name.clone()));
+
+    let total = units.len();
+    let count = |fraction: f64, taken: usize| {
+        let wanted = (total as f64 * fraction).round() as usize;
+        let wanted = if fraction > 0.0 {
+            wanted.max(1)
+        } else {
+            wanted
+        };
+
+        wanted.min(total.saturating_sub(taken + 1))
+    };
+    let test_count = count(test, 0);
+    let valid_count = count(valid, test_count);
+
+    let train = units.split_off(test_count + valid_count);
+    let valid = units.split_off(test_count);
+
+    SplitManifest::new(train, valid, units)
+        .with_block_frames(block_frames)
+        .with_gap_frames(gap_frames)
+}
+
+impl SplitManifest {
+    /// Распределение окон по частям
+    pub fn apply(&self, windows: &WindowDataset) -> DatasetSplit {
+        let parts: HashMap<&str, SplitPart> = [
+            (&self.train, SplitPart::Train),
+            (&self.valid, SplitPart::Valid),
+            (&self.test, SplitPart::Test),
+        ]
+        .into_iter()
+        .flat_map(|(names, part)| names.iter().map(move |name| (name.as_str(), part)))
+        .collect();
+
+        let part_of = |session: &str, frame: u64| {
+            parts
+                .get(unit_name(session, frame, self.block_frames).as_str())
+                .or_else(|| parts.get(session))
+                .copied()
+        };
+
+        let mut split = DatasetSplit {
+            manifest: self.clone(),
+            train: Vec::new(),
+            valid: Vec::new(),
+            test: Vec::new(),
+            dropped: 0,
+        };
+
+        for index in 0..windows.len() {
+            let Some((session, first, last)) = windows.source(index) else {
+                continue;
+            };
+            let Some(part) = part_of(session, last) else {
+                split.dropped += 1;
+                continue;
+            };
+
+            // Окно или зазор вокруг него задевает блок другой части; зазор может
+            // быть длиннее блока, поэтому проверяются все блоки между краями
+            let near_other = self.block_frames > 0 && {
+                let from = first.saturating_sub(self.gap_frames) / self.block_frames;
+                let to = (last + self.gap_frames) / self.block_frames;
+                (from..=to).any(|block| {
+                    part_of(session, block * self.block_frames).is_some_and(|other| other != part)
+                })
+            };
+            if near_other {
+                split.dropped += 1;
+                continue;
+            }
+
+            match part {
+                SplitPart::Train => split.train.push(index),
+                SplitPart::Valid => split.valid.push(index),
+                SplitPart::Test => split.test.push(index),
+            }
+        }
+
+        split
+    }
+}
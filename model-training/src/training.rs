@@ -1,10 +1,19 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
-use crate::{data::FrameBatcher, models::model_v1::model::ModelV1Config};
+use crate::{
+    augmentation::AugmentationConfig,
+    data::{FrameBatcher, FrameWindow, Hdf5Dataset, WindowDataset},
+    models::model_v1::model::ModelV1Config,
+    split::{SPLIT_FILE, SplitStrategy},
+};
 
 use burn::{
     backend::{self, Autodiff},
-    data::{dataloader::DataLoaderBuilder, dataset::InMemDataset},
+    data::{dataloader::DataLoaderBuilder, dataset::transform::SelectionDataset},
     optim::AdamConfig,
     prelude::*,
     record::CompactRecorder,
@@ -12,7 +21,7 @@ use burn::{
     train::{Learner, SupervisedTraining, metric::LossMetric},
 };
 
-use preprocessor::{hdf5_processing::read_all_hdf5_files, types::MyConstData};
+use preprocessor::vocabulary::{KEY_VOCABULARY_FILE, KeyVocabulary};
 
 #[derive(Config, Debug)]
 pub(crate) struct TrainingConfig {
@@ -26,8 +35,17 @@ pub(crate) struct TrainingConfig {
     pub num_workers: usize,
     #[config(default = 42)]
     pub seed: u64,
+    /// Кадров контекста перед предсказываемым кадром
+    #[config(default = 1)]
+    pub context_frames: usize,
     #[config(default = 1.0e-4)]
     pub learning_rate: f64,
+    /// Разбиение на обучение и проверку; тестовая часть в обучении не участвует
+    #[config(default = "SplitStrategy::default()")]
+    pub split: SplitStrategy,
+    /// Искажения обучающих пакетов, воспроизводимые при том же `seed`
+    #[config(default = "AugmentationConfig::new()")]
+    pub augmentation: AugmentationConfig,
 }
 
 fn create_artifact_dir(artifact_dir: &str) {
@@ -36,38 +54,74 @@ fn create_artifact_dir(artifact_dir: &str) {
     std::fs::create_dir_all(artifact_dir).ok();
 }
 
-fn train<B: AutodiffBackend>(artifact_dir: &str, config: TrainingConfig, device: B::Device) {
+fn train<B: AutodiffBackend>(
+    artifact_dir: &str,
+    mut config: TrainingConfig,
+    vocabulary: &KeyVocabulary,
+    device: B::Device,
+) {
+    let data_path = PathBuf::from_str("data").unwrap();
+    let data_path = &data_path.join("hdf5_files");
+
+    // Записи читаются с диска по мере обучения
+    let records = Hdf5Dataset::open(data_path).expect("Чтение всех файлов hdf5");
+    // Модель строится под формат кадров, сведение действий датасета и число кадров контекста,
+    // они же сохраняются для инференса
+    config.context_frames = config.context_frames.max(1);
+    config.model = config
+        .model
+        .with_frame_format(records.format())
+        .with_aggregation(records.aggregation())
+        .with_context_frames(config.context_frames);
+
+    let my_data = Arc::new(WindowDataset::new(records, config.context_frames));
+    // Разбиение читается до очистки директории: файл может лежать в ней
+    let split = config
+        .split
+        .resolve(&my_data, config.seed)
+        .expect("Разбиение датасета");
+    println!("{split}");
+
     create_artifact_dir(artifact_dir);
+
     config
         .save(format!("{artifact_dir}/config.json"))
         .expect("Config should be saved successfully");
+    // Инференс кодирует клавиши тем же словарём, что и датасет
+    vocabulary
+        .save(&Path::new(artifact_dir).join(KEY_VOCABULARY_FILE))
+        .expect("Key vocabulary should be saved successfully");
+    // С этим файлом разбиение можно повторить, см. [`SplitStrategy::Manifest`]
+    split
+        .manifest
+        .save(format!("{artifact_dir}/{SPLIT_FILE}"))
+        .expect("Split should be saved successfully");
 
     B::seed(&device, config.seed);
 
-    let data_path = PathBuf::from_str("data").unwrap();
-    let data_path = &data_path.join("hdf5_files");
-
-    let my_data = read_all_hdf5_files(data_path).expect("Чтение всех файлов hdf5");
-
-    let train_percintil = 0.8;
-    let train_len = (my_data.len() as f64 * train_percintil) as usize;
-
-    let train_data = my_data[..train_len].to_vec();
-    let test_data = my_data[train_len..].to_vec();
-
-    let dataset_train: InMemDataset<MyConstData> = InMemDataset::new(train_data);
-    let dataset_test: InMemDataset<MyConstData> = InMemDataset::new(test_data);
+    let dataset_train = SelectionDataset::<WindowDataset, FrameWindow>::from_indices_checked(
+        my_data.clone(),
+        split.train,
+    );
+    let dataset_test =
+        SelectionDataset::<WindowDataset, FrameWindow>::from_indices_checked(my_data, split.valid);
 
-    let batcher_train = FrameBatcher::<B>::new(device.clone());
-    let batcher_valid = FrameBatcher::<B::InnerBackend>::new(device.clone());
+    let batcher_train = FrameBatcher::<B>::new(config.model.key_count)
+        .with_mouse_bins(config.model.mouse_bins)
+        .with_augmentation(config.augmentation.clone(), config.seed);
+    let batcher_valid = FrameBatcher::<B::InnerBackend>::new(config.model.key_count)
+        .with_mouse_bins(config.model.mouse_bins);
 
+    // Пакеты собираются на устройстве обучения
     let dataloader_train = DataLoaderBuilder::new(batcher_train)
+        .set_device(device.clone())
         .batch_size(config.batch_size)
         .shuffle(config.seed)
         .num_workers(config.num_workers)
         .build(dataset_train);
 
     let dataloader_test = DataLoaderBuilder::new(batcher_valid)
+        .set_device(device.clone())
         .batch_size(config.batch_size)
         .shuffle(config.seed)
         .num_workers(config.num_workers)
@@ -124,9 +178,14 @@ pub fn run() {
 
     type MyAutodiffBackend = Autodiff<MyBackend>;
 
+    let vocabulary =
+        KeyVocabulary::load_or_default(Path::new("data")).expect("Чтение словаря клавиш");
+    let model = ModelV1Config::new().with_key_count(vocabulary.size());
+
     crate::training::train::<MyAutodiffBackend>(
         artifact_dir,
-        TrainingConfig::new(ModelV1Config::new(), AdamConfig::new()),
-        device.clone(),
+        TrainingConfig::new(model, AdamConfig::new()),
+        &vocabulary,
+        device,
     );
 }
@@ -6,6 +6,8 @@ use burn::backend::NdArray;
 use burn::nn::LinearConfig;
 use burn::tensor::{Tensor, TensorData};
 use common::*;
+use model_training::MOUSE_FEATURES;
+use preprocessor::vocabulary::DEFAULT_KEY_COUNT;
 
 /// Verify LinearConfig::new(input, output) API
 #[test]
@@ -22,7 +24,7 @@ fn test_linear_config_api() {
     assert_eq!(out.dims(), [2, 20]);
 }
 
-/// Test MouseEmbedder: [batch, 2, 200] -> [batch, embed_dim]
+/// Test MouseEmbedder: [batch, MOUSE_FEATURES] -> [batch, embed_dim]
 #[test]
 fn test_mouse_embedder() {
     use model_training::models::embedders::MouseEmbedderConfig;
@@ -31,11 +33,8 @@ fn test_mouse_embedder() {
 
     let embedder = MouseEmbedderConfig::new(100, 100).init::<B>(&device);
 
-    let mouse = Tensor::<B, 3>::from_data(
-        TensorData::new(
-            vec![0.0f32; 4 * 2 * MOUSE_VECTOR_LENGTH],
-            [4, 2, MOUSE_VECTOR_LENGTH],
-        ),
+    let mouse = Tensor::<B, 2>::from_data(
+        TensorData::new(vec![0.0f32; 4 * MOUSE_FEATURES], [4, MOUSE_FEATURES]),
         &device,
     );
 
@@ -47,16 +46,19 @@ fn test_mouse_embedder() {
     );
 }
 
-/// Test KeyboardEmbedder: [batch, 108] -> [batch, embed_dim]
+/// Test KeyboardEmbedder: [batch, key_count] -> [batch, embed_dim]
 #[test]
 fn test_keyboard_embedder() {
     use model_training::models::embedders::KeyboardEmbedderConfig;
     type B = NdArray<f32>;
     let device = Default::default();
 
-    let embedder = KeyboardEmbedderConfig::new(100, 100).init::<B>(&device);
+    let embedder = KeyboardEmbedderConfig::new(100, 100, DEFAULT_KEY_COUNT).init::<B>(&device);
 
-    let keys = Tensor::<B, 2>::from_data(TensorData::new(vec![0.0f32; 4 * 108], [4, 108]), &device);
+    let keys = Tensor::<B, 2>::from_data(
+        TensorData::new(vec![0.0f32; 4 * DEFAULT_KEY_COUNT], [4, DEFAULT_KEY_COUNT]),
+        &device,
+    );
 
     let output = embedder.forward(keys);
     assert_eq!(
@@ -131,13 +133,16 @@ fn test_model_forward() {
         &device,
     );
     let keys = Tensor::<B, 2>::from_data(
-        TensorData::new(vec![0.0f32; batch * 108], [batch, 108]),
+        TensorData::new(
+            vec![0.0f32; batch * DEFAULT_KEY_COUNT],
+            [batch, DEFAULT_KEY_COUNT],
+        ),
         &device,
     );
-    let mouse = Tensor::<B, 3>::from_data(
+    let mouse = Tensor::<B, 2>::from_data(
         TensorData::new(
-            vec![0.0f32; batch * 2 * MOUSE_VECTOR_LENGTH],
-            [batch, 2, MOUSE_VECTOR_LENGTH],
+            vec![0.0f32; batch * MOUSE_FEATURES],
+            [batch, MOUSE_FEATURES],
         ),
         &device,
     );
@@ -159,6 +164,501 @@ fn test_model_forward() {
     );
 }
 
+/// Test Hdf5Dataset reads records from disk and splits like an in-memory dataset
+#[test]
+fn test_hdf5_dataset() {
+    use burn::data::dataset::{Dataset, transform::PartialDataset};
+    use model_training::Hdf5Dataset;
+    use preprocessor::{
+        csv_processing::KeysRecordConst,
+        hdf5_processing::{WriterOptions, append_to_dataset},
+        images::MyImage,
+        types::MyConstData,
+    };
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join("test_hdf5_dataset");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let records: Vec<_> = (0..10u8)
+        .map(|key| {
+            let data = MyConstData {
+                image: MyImage::filled(frame::FrameFormat::default(), key),
+                keys_record: KeysRecordConst::from_slices(&[key], &[]),
+            };
+            (key as usize, data)
+        })
+        .collect();
+    append_to_dataset(
+        &dir.join("session"),
+        &records,
+        frame::FrameFormat::default(),
+        WriterOptions::default(),
+    )
+    .unwrap();
+
+    let dataset = Arc::new(Hdf5Dataset::open(&dir).unwrap());
+    assert_eq!(dataset.len(), 10);
+
+    let test = PartialDataset::new(dataset.clone(), 8, dataset.len());
+    assert_eq!(test.len(), 2);
+    assert_eq!(test.get(1).unwrap().keys_record.keys[0], 9);
+    assert_eq!(test.get(0).unwrap().image.pixel(0, 0, 0), 8);
+    assert!(dataset.get(10).is_none());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Test WindowDataset yields context frames, the action on the last one and the true next frame
+#[test]
+fn test_window_dataset() {
+    use burn::data::dataset::Dataset;
+    use model_training::{Hdf5Dataset, WindowDataset};
+    use preprocessor::{
+        csv_processing::KeysRecordConst,
+        hdf5_processing::{WriterOptions, append_to_dataset},
+        images::MyImage,
+        types::MyConstData,
+    };
+
+    let dir = std::env::temp_dir().join("test_window_dataset");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // Two sessions, the first one with frame 3 missing
+    let record = |key: u8| MyConstData {
+        image: MyImage::filled(frame::FrameFormat::default(), key),
+        keys_record: KeysRecordConst::from_slices(&[key], &[]),
+    };
+    let first: Vec<_> = [0, 1, 2, 4, 5]
+        .map(|index| (index, record(index as u8)))
+        .into();
+    let second: Vec<_> = (0..3)
+        .map(|index| (index, record(10 + index as u8)))
+        .collect();
+    let format = frame::FrameFormat::default();
+    append_to_dataset(&dir.join("a"), &first, format, WriterOptions::default()).unwrap();
+    append_to_dataset(&dir.join("b"), &second, format, WriterOptions::default()).unwrap();
+
+    let windows = WindowDataset::new(Hdf5Dataset::open(&dir).unwrap(), 2);
+    assert_eq!(windows.len(), 2);
+
+    let pixel = |image: &MyImage| image.pixel(0, 0, 0);
+    let window = windows.get(0).unwrap();
+    assert_eq!(window.context.iter().map(pixel).collect::<Vec<_>>(), [0, 1]);
+    assert_eq!(window.action.keys[0], 1);
+    assert_eq!(pixel(&window.next), 2);
+
+    let window = windows.get(1).unwrap();
+    assert_eq!(
+        window.context.iter().map(pixel).collect::<Vec<_>>(),
+        [10, 11]
+    );
+    assert_eq!(pixel(&window.next), 12);
+    assert!(windows.get(2).is_none());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Test splits keep sessions and time blocks apart and are reproduced from the saved manifest
+#[test]
+fn test_dataset_split() {
+    use burn::{config::Config, data::dataset::Dataset};
+    use model_training::{
+        Hdf5Dataset, WindowDataset,
+        split::{SplitManifest, SplitStrategy},
+    };
+    use preprocessor::{
+        csv_processing::KeysRecordConst,
+        hdf5_processing::{WriterOptions, append_to_dataset},
+        images::MyImage,
+        types::MyConstData,
+    };
+
+    let dir = std::env::temp_dir().join("test_dataset_split");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let format = frame::FrameFormat::default();
+    let records: Vec<_> = (0..40)
+        .map(|index| {
+            let data = MyConstData {
+                image: MyImage::filled(format, index as u8),
+                keys_record: KeysRecordConst::from_slices(&[], &[]),
+            };
+            (index, data)
+        })
+        .collect();
+    for session in ["a", "b", "c"] {
+        append_to_dataset(
+            &dir.join(session),
+            &records,
+            format,
+            WriterOptions::default(),
+        )
+        .unwrap();
+    }
+    let windows = WindowDataset::new(Hdf5Dataset::open(&dir).unwrap(), 1);
+    assert_eq!(windows.len(), 117);
+
+    // По сессиям: каждая сессия целиком в одной части
+    let strategy = SplitStrategy::Session {
+        valid: 0.3,
+        test: 0.3,
+    };
+    let split = strategy.resolve(&windows, 7).unwrap();
+    assert_eq!(split.dropped, 0);
+    for part in [&split.train, &split.valid, &split.test] {
+        assert_eq!(part.len(), 39);
+        let session = windows.source(part[0]).unwrap().0;
+        assert!(
+            part.iter()
+                .all(|&i| windows.source(i).unwrap().0 == session)
+        );
+    }
+    assert_eq!(
+        strategy.resolve(&windows, 7).unwrap().manifest,
+        split.manifest,
+        "the same seed gives the same split"
+    );
+
+    // По блокам: окна у границы с блоком другой части отбрасываются
+    let strategy = SplitStrategy::TimeBlock {
+        block_frames: 10,
+        gap_frames: 2,
+        valid: 0.25,
+        test: 0.25,
+    };
+    let split = strategy.resolve(&windows, 7).unwrap();
+    let manifest = &split.manifest;
+    assert_eq!(
+        (
+            manifest.train.len(),
+            manifest.valid.len(),
+            manifest.test.len()
+        ),
+        (6, 3, 3)
+    );
+    assert!(split.dropped > 0);
+    assert_eq!(
+        split.train.len() + split.valid.len() + split.test.len() + split.dropped,
+        windows.len()
+    );
+
+    let part_of = |session: &str, frame: u64| {
+        let unit = format!("{session}#{}", frame / 10);
+        [&manifest.train, &manifest.valid, &manifest.test]
+            .into_iter()
+            .position(|units| units.contains(&unit))
+    };
+    for (part, indices) in [&split.train, &split.valid, &split.test]
+        .into_iter()
+        .enumerate()
+    {
+        for &index in indices {
+            let (session, first, last) = windows.source(index).unwrap();
+            assert_eq!(part_of(session, last), Some(part));
+            for frame in [first.saturating_sub(2), last + 2] {
+                assert!(part_of(session, frame).is_none_or(|other| other == part));
+            }
+        }
+    }
+
+    // Сохранённое разбиение повторяется при другом seed
+    let path = dir.join("split.json");
+    manifest.save(&path).unwrap();
+    assert_eq!(&SplitManifest::load(&path).unwrap(), manifest);
+    let strategy = SplitStrategy::Manifest {
+        path: path.to_string_lossy().into_owned(),
+    };
+    let repeated = strategy.resolve(&windows, 1).unwrap();
+    assert_eq!(repeated.train, split.train);
+    assert_eq!(repeated.valid, split.valid);
+    assert_eq!(repeated.test, split.test);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Test a gap wider than a block drops windows on both sides of a block of another part
+#[test]
+fn test_split_gap_wider_than_block() {
+    use burn::data::dataset::Dataset;
+    use model_training::{Hdf5Dataset, WindowDataset, split::SplitManifest};
+    use preprocessor::{
+        csv_processing::KeysRecordConst,
+        hdf5_processing::{WriterOptions, append_to_dataset},
+        images::MyImage,
+        types::MyConstData,
+    };
+
+    let dir = std::env::temp_dir().join("test_split_gap_wider_than_block");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let format = frame::FrameFormat::default();
+    let records: Vec<_> = (0..40)
+        .map(|index| {
+            let data = MyConstData {
+                image: MyImage::filled(format, index as u8),
+                keys_record: KeysRecordConst::from_slices(&[], &[]),
+            };
+            (index, data)
+        })
+        .collect();
+    append_to_dataset(&dir.join("a"), &records, format, WriterOptions::default()).unwrap();
+    let windows = WindowDataset::new(Hdf5Dataset::open(&dir).unwrap(), 1);
+
+    // Тестовый блок кадров 15..20 окружён обучающими, зазор длиннее блока
+    let units = |blocks: &[u64]| blocks.iter().map(|block| format!("a#{block}")).collect();
+    let manifest = SplitManifest::new(units(&[0, 1, 2, 4, 5, 6, 7]), Vec::new(), units(&[3]))
+        .with_block_frames(5)
+        .with_gap_frames(12);
+    let split = manifest.apply(&windows);
+
+    let firsts: Vec<u64> = split
+        .train
+        .iter()
+        .map(|&index| windows.source(index).unwrap().1)
+        .collect();
+    assert_eq!(firsts, [0, 1, 32, 33, 34, 35, 36, 37, 38]);
+    assert!(split.test.is_empty());
+    assert_eq!(split.dropped, windows.len() - firsts.len());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Test a dataset with non-default frames and mouse bins is batched and fed to a model built for it
+#[test]
+fn test_custom_frame_format() {
+    use aggregation::AggregationConfig;
+    use burn::data::{dataloader::batcher::Batcher, dataset::Dataset};
+    use frame::{ChannelLayout, FrameFormat, Resize, ResizeMode};
+    use model_training::{
+        FrameBatcher, Hdf5Dataset, WindowDataset, models::model_v1::model::ModelV1Config,
+        mouse_features,
+    };
+    use preprocessor::{
+        csv_processing::KeysRecordConst,
+        hdf5_processing::{WriterOptions, append_to_dataset},
+        images::MyImage,
+        types::MyConstData,
+    };
+    type B = NdArray<f32>;
+    let device = Default::default();
+
+    let dir = std::env::temp_dir().join("test_custom_frame_format");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let format = FrameFormat::new(16, 8, ChannelLayout::Rgb)
+        .unwrap()
+        .with_resize(Resize {
+            mode: ResizeMode::Crop,
+            ..Resize::default()
+        })
+        .unwrap();
+    let aggregation = AggregationConfig::new(4).unwrap();
+    let records: Vec<_> = (0..3u8)
+        .map(|key| {
+            let data = MyConstData {
+                image: MyImage::filled(format, key * 100),
+                keys_record: KeysRecordConst::from_slices_binned(&[key], &[[1, 2]; 8], 4),
+            };
+            (key as usize, data)
+        })
+        .collect();
+    let options = WriterOptions {
+        aggregation,
+        ..Default::default()
+    };
+    append_to_dataset(&dir.join("session"), &records, format, options).unwrap();
+
+    let windows = WindowDataset::new(Hdf5Dataset::open(&dir).unwrap(), 1);
+    assert_eq!(windows.format(), format);
+    assert_eq!(windows.aggregation(), aggregation);
+
+    let batcher = FrameBatcher::<B>::new(DEFAULT_KEY_COUNT).with_mouse_bins(4);
+    let batch = batcher.batch(
+        (0..windows.len()).filter_map(|i| windows.get(i)).collect(),
+        &device,
+    );
+    assert_eq!(batch.images.dims(), [2, 3, 8, 16]);
+    assert_eq!(batch.context.dims(), [2, 1, 3, 8, 16]);
+    // Траектория из 4 отрезков по два движения
+    assert_eq!(batch.mouse.dims(), [2, mouse_features(4)]);
+    let mouse: Vec<f32> = batch.mouse.to_data().to_vec().unwrap();
+    assert_eq!(mouse[..8], [2.0, 4.0, 2.0, 4.0, 2.0, 4.0, 2.0, 4.0]);
+    let target: Vec<f32> = batch.targets.to_data().to_vec().unwrap();
+    assert!((target[0] - 100.0 / 255.0).abs() < 1e-6);
+
+    let config = ModelV1Config::new()
+        .with_frame_format(format)
+        .with_aggregation(aggregation);
+    assert_eq!(config.frame_format().unwrap(), format);
+    assert_eq!(config.aggregation().unwrap(), aggregation);
+    let model = config.init::<B>(&device);
+    let timestep = Tensor::<B, 1>::from_data(TensorData::new(vec![0.5f32; 2], [2]), &device);
+    let output = model.forward(
+        batch.images.clone(),
+        batch.keys,
+        batch.mouse,
+        batch.images,
+        timestep,
+    );
+    assert_eq!(output.dims(), [2, 3, 8, 16]);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Test every context frame is stacked on the channel axis and affects the model output
+#[test]
+fn test_model_context_frames() {
+    use burn::{data::dataloader::batcher::Batcher, tensor::backend::Backend};
+    use frame::{ChannelLayout, FrameFormat};
+    use model_training::{
+        FrameBatcher, FrameWindow,
+        models::model_v1::model::{ModelV1, ModelV1Config},
+    };
+    use preprocessor::{csv_processing::KeysRecordConst, images::MyImage};
+    type B = NdArray<f32>;
+    let device = Default::default();
+
+    let format = FrameFormat::new(8, 8, ChannelLayout::Rgb).unwrap();
+    let window = |first: u8| FrameWindow {
+        context: vec![
+            MyImage::filled(format, first),
+            MyImage::filled(format, 100),
+            MyImage::filled(format, 200),
+        ],
+        action: KeysRecordConst::from_slices(&[1], &[]),
+        next: MyImage::filled(format, 50),
+    };
+    let batcher = FrameBatcher::<B>::new(DEFAULT_KEY_COUNT);
+
+    let config = ModelV1Config::new()
+        .with_frame_format(format)
+        .with_context_frames(3);
+    let predict = |model: &ModelV1<B>, first: u8| {
+        let batch = batcher.batch(vec![window(first)], &device);
+        assert_eq!(batch.context.dims(), [1, 3, 3, 8, 8]);
+        let timestep = Tensor::<B, 1>::from_data(TensorData::new(vec![0.5f32], [1]), &device);
+        let output = model.forward(
+            batch.context.flatten(1, 2),
+            batch.keys,
+            batch.mouse,
+            batch.targets,
+            timestep,
+        );
+        assert_eq!(output.dims(), [1, 3, 8, 8]);
+        output.to_data().to_vec::<f32>().unwrap()
+    };
+
+    // Веса фиксированы: выход заканчивается ReLU и при части весов целиком нулевой
+    B::seed(&device, 0);
+    let model = config.init::<B>(&device);
+    assert!(predict(&model, 0).iter().any(|value| *value != 0.0));
+    // Самый ранний кадр контекста тоже доходит до модели
+    assert_ne!(predict(&model, 0), predict(&model, 255));
+}
+
+/// Test augmentation is reproducible under the seed and moves context and target frames together
+#[test]
+fn test_augmentation() {
+    use burn::data::dataloader::batcher::Batcher;
+    use frame::{ChannelLayout, FrameFormat};
+    use model_training::{FrameBatcher, FrameWindow, augmentation::AugmentationConfig};
+    use preprocessor::{csv_processing::KeysRecordConst, images::MyImage};
+    type B = NdArray<f32>;
+    let device = Default::default();
+
+    // Горизонтальный градиент, следующий кадр совпадает с кадром контекста
+    let format = FrameFormat::new(16, 8, ChannelLayout::Rgba).unwrap();
+    let pixels = (0..format.pixel_count())
+        .map(|i| ((i % format.width) * 16) as u8)
+        .collect();
+    let image = MyImage::from_pixels(format, pixels).unwrap();
+    let windows: Vec<_> = (0..4u8)
+        .map(|key| FrameWindow {
+            context: vec![image.clone(); 2],
+            action: KeysRecordConst::from_slices(&[key + 1], &[]),
+            next: image.clone(),
+        })
+        .collect();
+
+    // Без искажений пакет не меняется
+    let config = AugmentationConfig::new();
+    assert!(config.is_identity());
+    let plain = FrameBatcher::<B>::new(DEFAULT_KEY_COUNT);
+    let batch = plain
+        .clone()
+        .with_augmentation(config, 7)
+        .batch(windows.clone(), &device);
+    let reference = plain.batch(windows.clone(), &device);
+    batch
+        .targets
+        .to_data()
+        .assert_eq(&reference.targets.to_data(), true);
+
+    let geometric = AugmentationConfig::new()
+        .with_crop(0.25)
+        .with_max_shift(3)
+        .with_brightness(0.2)
+        .with_contrast(0.2);
+    let augmented = |config: &AugmentationConfig, seed| {
+        FrameBatcher::<B>::new(DEFAULT_KEY_COUNT)
+            .with_augmentation(config.clone(), seed)
+            .batch(windows.clone(), &device)
+    };
+    let first = augmented(&geometric, 7);
+    first
+        .targets
+        .to_data()
+        .assert_eq(&augmented(&geometric, 7).targets.to_data(), true);
+    assert_ne!(
+        first.targets.to_data(),
+        augmented(&geometric, 8).targets.to_data()
+    );
+    assert_ne!(first.targets.to_data(), reference.targets.to_data());
+
+    // Тот же пакет в следующей эпохе искажается иначе, и это тоже повторяется
+    let epochs = |seed| {
+        let batcher =
+            FrameBatcher::<B>::new(DEFAULT_KEY_COUNT).with_augmentation(geometric.clone(), seed);
+        [0, 1].map(|_| {
+            batcher
+                .clone()
+                .batch(windows.clone(), &device)
+                .targets
+                .to_data()
+        })
+    };
+    let [epoch_1, epoch_2] = epochs(7);
+    epoch_1.assert_eq(&first.targets.to_data(), true);
+    assert_ne!(epoch_1, epoch_2);
+    epoch_2.assert_eq(&epochs(7)[1], true);
+
+    // Кадры контекста искажены так же, как следующий кадр
+    first
+        .images
+        .to_data()
+        .assert_eq(&first.targets.to_data(), true);
+    let context = first.context.clone().narrow(1, 0, 1).squeeze_dim::<4>(1);
+    context.to_data().assert_eq(&first.targets.to_data(), true);
+    first
+        .keys
+        .to_data()
+        .assert_eq(&reference.keys.to_data(), true);
+
+    // Шум только во входных кадрах, выпадение действия обнуляет клавиши
+    let noisy = AugmentationConfig::new()
+        .with_noise_std(0.05)
+        .with_action_dropout(1.0);
+    let batch = augmented(&noisy, 7);
+    batch
+        .targets
+        .to_data()
+        .assert_eq(&reference.targets.to_data(), true);
+    assert_ne!(batch.images.to_data(), reference.images.to_data());
+    let keys: Vec<f32> = batch.keys.to_data().to_vec().unwrap();
+    assert!(keys.iter().all(|value| *value == 0.0));
+}
+
 /// Full training run — reads real data from data/hdf5_files/ and trains.
 /// Uses CUDA backend when --features cuda, otherwise NdArray.
 ///
@@ -0,0 +1,350 @@
+//! Сведение действий за кадр к записи фиксированного размера.
+//!
+//! За один кадр может прийти сколько угодно событий, а в [`KeysRecordConst`] для них
+//! отведено постоянное место:
+//! - траектория мыши — смещения, сложенные по равным отрезкам времени кадра; число
+//!   отрезков задаётся настройками сведения ([`common::aggregation::AggregationConfig::mouse_bins`]),
+//!   место в записи — [`MOUSE_VECTOR_LENGTH`], неиспользуемые отрезки остаются нулевыми;
+//! - суммарное смещение, прокрутка;
+//! - число нажатий и отпусканий клавиш и кнопок;
+//! - доля кадра, в течение которой удерживалась каждая клавиша.
+//!
+//! Если меток времени нет (старый формат, [`crate::csv_processing`], инференс),
+//! движения раскладываются по отрезкам равномерно в порядке поступления.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use common::MOUSE_VECTOR_LENGTH;
+
+use crate::{
+    csv_processing::{KeysRecordConst, button_to_index},
+    vocabulary::KeyVocabulary,
+};
+
+/// Траектория мыши за кадр
+pub type Trajectory = [[i32; 2]; MOUSE_VECTOR_LENGTH];
+
+/// Сумма смещений с насыщением: значения в файлах записи ничем не ограничены,
+/// и переполнение не должно обрывать обработку
+pub fn add_delta(sum: [i32; 2], delta: [i32; 2]) -> [i32; 2] {
+    [
+        sum[0].saturating_add(delta[0]),
+        sum[1].saturating_add(delta[1]),
+    ]
+}
+
+/// Смещения с метками времени по `bins` отрезкам интервала `[start_us, end_us)`
+pub fn bin_by_time(
+    moves: &[(u64, [i32; 2])],
+    start_us: u64,
+    end_us: u64,
+    bins: usize,
+) -> Trajectory {
+    let mut trajectory = [[0; 2]; MOUSE_VECTOR_LENGTH];
+    let bins = bins.clamp(1, MOUSE_VECTOR_LENGTH);
+    let duration = end_us.saturating_sub(start_us).max(1) as u128;
+
+    for (timestamp_us, delta) in moves {
+        let offset = timestamp_us.saturating_sub(start_us) as u128;
+        let bin = (offset * bins as u128 / duration) as usize;
+        let bin = bin.min(bins - 1);
+
+        trajectory[bin] = add_delta(trajectory[bin], *delta);
+    }
+
+    trajectory
+}
+
+/// Смещения без меток времени по `bins` отрезкам: считается, что они шли
+/// через равные промежутки
+pub fn bin_by_order(moves: &[[i32; 2]], bins: usize) -> Trajectory {
+    let mut trajectory = [[0; 2]; MOUSE_VECTOR_LENGTH];
+    let bins = bins.clamp(1, MOUSE_VECTOR_LENGTH);
+
+    for (i, delta) in moves.iter().enumerate() {
+        let bin = i * bins / moves.len();
+
+        trajectory[bin] = add_delta(trajectory[bin], *delta);
+    }
+
+    trajectory
+}
+
+/// Накопление действий одного кадра с интервалом `[start_us, end_us)`.
+///
+/// `end_us` может быть `u64::MAX` у последнего кадра, тогда кадр считается
+/// закончившимся на последнем своём событии.
+pub struct FrameAggregator {
+    start_us: u64,
+    end_us: u64,
+    last_us: u64,
+    moves: Vec<(u64, [i32; 2])>,
+    wheel: [i32; 2],
+    /// Клавиша -> начало текущего удержания (`None`, если уже отпущена) и время удержания
+    holds: BTreeMap<String, (Option<u64>, u64)>,
+    buttons: BTreeSet<String>,
+    key_events: [u16; 2],
+    button_events: [u16; 2],
+    paused: bool,
+}
+
+impl FrameAggregator {
+    /// Клавиши и кнопки, удерживаемые к началу кадра, входят в кадр с самого начала
+    pub fn new<'a>(
+        start_us: u64,
+        end_us: u64,
+        held: impl IntoIterator<Item = &'a String>,
+        held_buttons: impl IntoIterator<Item = &'a String>,
+        paused: bool,
+    ) -> Self {
+        let since = (!paused).then_some(start_us);
+
+        Self {
+            start_us,
+            end_us,
+            last_us: start_us,
+            moves: Vec::new(),
+            wheel: [0; 2],
+            holds: held
+                .into_iter()
+                .map(|key| (key.clone(), (since, 0)))
+                .collect(),
+            buttons: held_buttons.into_iter().cloned().collect(),
+            key_events: [0; 2],
+            button_events: [0; 2],
+            paused,
+        }
+    }
+
+    fn touch(&mut self, timestamp_us: u64) {
+        self.last_us = self.last_us.max(timestamp_us);
+    }
+
+    pub fn key_press(&mut self, key: &str, timestamp_us: u64) {
+        self.touch(timestamp_us);
+        self.key_events[0] = self.key_events[0].saturating_add(1);
+
+        let (since, _) = self.holds.entry(key.to_string()).or_default();
+        // Автоповтор присылает нажатия без отпускания, удержание не прерывается
+        since.get_or_insert(timestamp_us);
+    }
+
+    pub fn key_release(&mut self, key: &str, timestamp_us: u64) {
+        self.touch(timestamp_us);
+        self.key_events[1] = self.key_events[1].saturating_add(1);
+
+        if let Some((since, held)) = self.holds.get_mut(key)
+            && let Some(since) = since.take()
+        {
+            *held += timestamp_us.saturating_sub(since);
+        }
+    }
+
+    pub fn button_press(&mut self, button: &str, timestamp_us: u64) {
+        self.touch(timestamp_us);
+        self.button_events[0] = self.button_events[0].saturating_add(1);
+        self.buttons.insert(button.to_string());
+    }
+
+    pub fn button_release(&mut self, timestamp_us: u64) {
+        self.touch(timestamp_us);
+        self.button_events[1] = self.button_events[1].saturating_add(1);
+    }
+
+    pub fn mouse_move(&mut self, delta: [i32; 2], timestamp_us: u64) {
+        self.touch(timestamp_us);
+        self.moves.push((timestamp_us, delta));
+    }
+
+    pub fn wheel(&mut self, delta: [i32; 2], timestamp_us: u64) {
+        self.touch(timestamp_us);
+        self.wheel = add_delta(self.wheel, delta);
+    }
+
+    /// Время паузы не считается временем удержания
+    pub fn pause(&mut self, timestamp_us: u64) {
+        for (since, held) in self.holds.values_mut() {
+            if let Some(since) = since.take() {
+                *held += timestamp_us.saturating_sub(since);
+            }
+        }
+        self.paused = true;
+    }
+
+    /// После паузы удержание продолжается для клавиш, которые всё ещё нажаты
+    pub fn resume<'a>(&mut self, held: impl IntoIterator<Item = &'a String>, timestamp_us: u64) {
+        self.touch(timestamp_us);
+        for key in held {
+            let (since, _) = self.holds.entry(key.clone()).or_default();
+            since.get_or_insert(timestamp_us);
+        }
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Запись кадра; `position` — положение курсора в начале кадра,
+    /// `mouse_bins` — отрезков траектории мыши
+    pub fn finish(
+        self,
+        position: [f32; 2],
+        vocabulary: &KeyVocabulary,
+        mouse_bins: usize,
+    ) -> KeysRecordConst {
+        let end_us = if self.end_us == u64::MAX {
+            self.last_us
+        } else {
+            self.end_us
+        };
+        let duration = end_us.saturating_sub(self.start_us);
+
+        // У одной клавиши словаря может быть несколько имён, удержание берётся наибольшее
+        let mut holds: BTreeMap<u8, f32> = BTreeMap::new();
+        for (key, (since, held)) in &self.holds {
+            let held = held + since.map_or(0, |since| end_us.saturating_sub(since));
+            let fraction = if duration == 0 {
+                1.0
+            } else {
+                (held as f64 / duration as f64).min(1.0) as f32
+            };
+
+            let hold = holds.entry(vocabulary.key_to_num(key)).or_default();
+            *hold = hold.max(fraction);
+        }
+
+        let keys: Vec<u8> = holds.keys().copied().collect();
+        let mut record = KeysRecordConst::from_slices(&keys, &[]);
+        for (i, hold) in holds.values().enumerate().take(record.hold.len()) {
+            record.hold[i] = *hold;
+        }
+
+        record.mouse = bin_by_time(
+            &self.moves,
+            self.start_us,
+            end_us.max(self.last_us + 1),
+            mouse_bins,
+        );
+        for (_, delta) in &self.moves {
+            record.delta = add_delta(record.delta, *delta);
+        }
+
+        record.position = position;
+        record.wheel = self.wheel;
+        record.key_events = self.key_events;
+        record.button_events = self.button_events;
+        for index in self
+            .buttons
+            .iter()
+            .filter_map(|button| button_to_index(button))
+        {
+            record.buttons[index] = 1;
+        }
+
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vocabulary::{KEY_PADDING, default_vocabulary};
+
+    /// Test moves are summed into time bins and the total is kept
+    #[test]
+    fn test_bin_by_time() {
+        let moves = vec![(0, [1, 1]), (10, [2, 0]), (999, [0, 3]), (5_000, [4, 4])];
+
+        let trajectory = bin_by_time(&moves, 0, 1_000, MOUSE_VECTOR_LENGTH);
+
+        assert_eq!(trajectory[0], [3, 1]);
+        assert_eq!(trajectory[MOUSE_VECTOR_LENGTH - 1], [4, 7]);
+        let total = trajectory
+            .iter()
+            .fold([0, 0], |sum, delta| [sum[0] + delta[0], sum[1] + delta[1]]);
+        assert_eq!(total, [7, 8]);
+
+        // Меньше отрезков: отрезки шире, остальное место в записи пустое
+        let trajectory = bin_by_time(&moves, 0, 1_000, 4);
+        assert_eq!(trajectory[0], [3, 1]);
+        assert_eq!(trajectory[3], [4, 7]);
+        assert!(trajectory[4..].iter().all(|delta| *delta == [0, 0]));
+    }
+
+    /// Test any number of moves fits into the trajectory without losing the total
+    #[test]
+    fn test_bin_by_order_bounded() {
+        let moves = vec![[1, -1]; 1_000];
+
+        let trajectory = bin_by_order(&moves, MOUSE_VECTOR_LENGTH);
+
+        assert_eq!(trajectory.len(), MOUSE_VECTOR_LENGTH);
+        assert!(trajectory.iter().all(|delta| delta[0] > 0));
+        let total: i32 = trajectory.iter().map(|delta| delta[0]).sum();
+        assert_eq!(total, 1_000);
+        assert!(
+            bin_by_order(&[], MOUSE_VECTOR_LENGTH)
+                .iter()
+                .all(|delta| *delta == [0, 0])
+        );
+
+        let trajectory = bin_by_order(&moves, 5);
+        assert_eq!(trajectory[..5], [[200, -200]; 5]);
+        assert!(trajectory[5..].iter().all(|delta| *delta == [0, 0]));
+    }
+
+    /// Test sums near i32::MAX saturate instead of overflowing
+    #[test]
+    fn test_bins_saturate() {
+        let big = [i32::MAX - 1, i32::MIN + 1];
+
+        let trajectory = bin_by_order(&[big, big], 1);
+        assert_eq!(trajectory[0], [i32::MAX, i32::MIN]);
+
+        let trajectory = bin_by_time(&[(0, big), (1, big)], 0, 1_000, 1);
+        assert_eq!(trajectory[0], [i32::MAX, i32::MIN]);
+    }
+
+    /// Test hold fractions and press/release counts
+    #[test]
+    fn test_frame_aggregator_holds_and_counts() {
+        let vocabulary = default_vocabulary();
+        let held = vec!["KeyW".to_string()];
+
+        let mut frame = FrameAggregator::new(0, 100, &held, &[], false);
+        frame.key_press("Space", 20);
+        frame.key_release("Space", 45);
+        frame.key_release("KeyW", 50);
+        frame.button_press("Left", 60);
+        frame.button_release(70);
+
+        let record = frame.finish([0.0; 2], vocabulary, MOUSE_VECTOR_LENGTH);
+
+        let space = vocabulary.key_to_num("Space");
+        let w = vocabulary.key_to_num("KeyW");
+        // Клавиши идут по возрастанию номеров
+        assert_eq!(&record.keys[..2], &[space.min(w), space.max(w)]);
+        assert_eq!(record.keys[2], KEY_PADDING);
+        let hold = |id: u8| record.hold[record.keys.iter().position(|key| *key == id).unwrap()];
+        assert_eq!(hold(w), 0.5);
+        assert_eq!(hold(space), 0.25);
+        assert_eq!(record.key_events, [1, 2]);
+        assert_eq!(record.button_events, [1, 1]);
+        assert_eq!(record.buttons, [1, 0, 0]);
+    }
+
+    /// Test pause stops hold time
+    #[test]
+    fn test_frame_aggregator_pause() {
+        let held = vec!["KeyW".to_string()];
+
+        let mut frame = FrameAggregator::new(0, 100, &held, &[], false);
+        frame.pause(20);
+        frame.resume(&held, 70);
+
+        let record = frame.finish([0.0; 2], default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        assert_eq!(record.hold[0], 0.5);
+    }
+}
@@ -0,0 +1,451 @@
+//! Выравнивание событий клавиатуры и мыши по кадрам.
+//!
+//! Кадры и события записываются с метками одних и тех же монотонных часов.
+//! Интервал кадра `i` — это `[t_i, t_{i+1})`, у последнего кадра интервал не ограничен сверху.
+//! Каждому кадру назначаются все клавиши, которые удерживались хотя бы часть его интервала,
+//! и все движения мыши, накопленные за этот интервал.
+//! Мышь раскладывается по отдельным каналам: траектория и суммарное смещение за интервал,
+//! положение курсора в момент кадра, кнопки (как и клавиши — удерживаемые хотя бы часть
+//! интервала) и прокрутка колёсика.
+//! Метки `Pause`/`Resume` обрывают интервал: события во время паузы обновляют состояние клавиш,
+//! но не приписываются кадру перед паузой.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    io,
+    path::Path,
+};
+
+use common::MOUSE_VECTOR_LENGTH;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregation::FrameAggregator,
+    csv_processing::KeysRecordConst,
+    event_log::{EventRecord, InputEvent},
+    vocabulary::KeyVocabulary,
+};
+
+/// Строка индекса кадров, который пишет `recorder`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrameStamp {
+    pub index: usize,
+    pub file: String,
+    pub timestamp_us: u64,
+}
+
+/// Чтение индекса кадров
+pub fn load_frame_stamps(path: &Path) -> io::Result<Vec<FrameStamp>> {
+    let mut frames = Vec::new();
+
+    let mut reader = csv::Reader::from_path(path)?;
+    for result in reader.deserialize() {
+        let record: FrameStamp = result?;
+        frames.push(record);
+    }
+
+    Ok(frames)
+}
+
+/// Состояние ввода, восстановленное по журналу событий
+#[derive(Default)]
+struct InputState {
+    held: BTreeSet<String>,
+    held_buttons: BTreeSet<String>,
+    mouse_position: Option<(f64, f64)>,
+    paused: bool,
+}
+
+/// Изменение мыши от одного события
+enum MouseChange {
+    Move([i32; 2]),
+    Wheel([i32; 2]),
+}
+
+impl InputState {
+    /// Применяет событие к состоянию и возвращает изменение мыши, если оно было
+    fn apply(&mut self, event: &EventRecord) -> Option<MouseChange> {
+        match &event.event {
+            InputEvent::KeyPress(key) => {
+                self.held.insert(key.clone());
+                None
+            }
+            InputEvent::KeyRelease(key) => {
+                self.held.remove(key);
+                None
+            }
+            InputEvent::ButtonPress(button) => {
+                self.held_buttons.insert(button.clone());
+                None
+            }
+            InputEvent::ButtonRelease(button) => {
+                self.held_buttons.remove(button);
+                None
+            }
+            &InputEvent::MouseMove { x, y } => {
+                let delta = self.mouse_position.map(|(last_x, last_y)| {
+                    [(x - last_x).round() as i32, (y - last_y).round() as i32]
+                });
+                self.mouse_position = Some((x, y));
+                delta.map(MouseChange::Move)
+            }
+            &InputEvent::Wheel { delta_x, delta_y } => {
+                Some(MouseChange::Wheel([delta_x as i32, delta_y as i32]))
+            }
+            InputEvent::Pause => {
+                self.paused = true;
+                None
+            }
+            InputEvent::Resume => {
+                self.paused = false;
+                None
+            }
+        }
+    }
+}
+
+/// Назначение каждому кадру удерживаемых клавиш и движений мыши за его интервал;
+/// траектория мыши раскладывается по `mouse_bins` отрезкам.
+///
+/// Возвращает ровно по одной записи на каждый кадр из `frames` в том же порядке,
+/// даже если кадры в `frames` не упорядочены по времени.
+pub fn align_events(
+    frames: &[FrameStamp],
+    events: &[EventRecord],
+    vocabulary: &KeyVocabulary,
+    mouse_bins: usize,
+) -> Vec<KeysRecordConst> {
+    let mut order: Vec<usize> = (0..frames.len()).collect();
+    order.sort_by_key(|&i| (frames[i].timestamp_us, frames[i].index));
+
+    let mut aligner = Aligner::new(vocabulary.clone()).with_mouse_bins(mouse_bins);
+    for event in events {
+        aligner.push_event(event.clone());
+    }
+
+    // Кадры закрываются по времени, а записи раскладываются по местам кадров
+    let mut records = vec![None; frames.len()];
+    for (position, &i) in order.iter().enumerate() {
+        let end = order
+            .get(position + 1)
+            .map_or(u64::MAX, |&next| frames[next].timestamp_us);
+
+        records[i] = Some(aligner.align_frame(frames[i].timestamp_us, end));
+    }
+
+    records.into_iter().flatten().collect()
+}
+
+/// Пошаговое выравнивание для записи на лету.
+///
+/// События добавляются по мере поступления, кадр закрывается, когда известно начало
+/// следующего кадра. Кадры нужно закрывать по порядку, события раньше начала
+/// уже закрытого кадра только обновляют состояние клавиш.
+pub struct Aligner {
+    state: InputState,
+    pending: VecDeque<EventRecord>,
+    vocabulary: KeyVocabulary,
+    /// Отрезков траектории мыши, см. [`common::aggregation::AggregationConfig::mouse_bins`]
+    mouse_bins: usize,
+}
+
+impl Default for Aligner {
+    fn default() -> Self {
+        Self::new(KeyVocabulary::default())
+    }
+}
+
+impl Aligner {
+    pub fn new(vocabulary: KeyVocabulary) -> Self {
+        Self {
+            state: InputState::default(),
+            pending: VecDeque::new(),
+            vocabulary,
+            mouse_bins: MOUSE_VECTOR_LENGTH,
+        }
+    }
+
+    pub fn with_mouse_bins(mut self, mouse_bins: usize) -> Self {
+        self.mouse_bins = mouse_bins;
+        self
+    }
+
+    pub fn push_event(&mut self, event: EventRecord) {
+        // События обычно приходят по порядку, тогда вставка идёт в конец очереди
+        let position = self
+            .pending
+            .partition_point(|pending| pending.timestamp_us <= event.timestamp_us);
+        self.pending.insert(position, event);
+    }
+
+    /// Действия кадра с интервалом `[start_us, end_us)`
+    pub fn align_frame(&mut self, start_us: u64, end_us: u64) -> KeysRecordConst {
+        // События до начала кадра только обновляют состояние
+        while let Some(event) = self
+            .pending
+            .pop_front_if(|event| event.timestamp_us < start_us)
+        {
+            self.state.apply(&event);
+        }
+
+        let position = self.state.mouse_position.unwrap_or_default();
+        let mut frame = FrameAggregator::new(
+            start_us,
+            end_us,
+            &self.state.held,
+            &self.state.held_buttons,
+            self.state.paused,
+        );
+
+        while let Some(event) = self
+            .pending
+            .pop_front_if(|event| event.timestamp_us < end_us)
+        {
+            let change = self.state.apply(&event);
+            let timestamp_us = event.timestamp_us;
+
+            match event.event {
+                InputEvent::Pause => frame.pause(timestamp_us),
+                InputEvent::Resume => frame.resume(&self.state.held, timestamp_us),
+                // Во время паузы кадру ничего не приписывается
+                _ if frame.is_paused() => {}
+                InputEvent::KeyPress(key) => frame.key_press(&key, timestamp_us),
+                InputEvent::KeyRelease(key) => frame.key_release(&key, timestamp_us),
+                InputEvent::ButtonPress(button) => frame.button_press(&button, timestamp_us),
+                InputEvent::ButtonRelease(_) => frame.button_release(timestamp_us),
+                InputEvent::MouseMove { .. } | InputEvent::Wheel { .. } => match change {
+                    Some(MouseChange::Move(delta)) => frame.mouse_move(delta, timestamp_us),
+                    Some(MouseChange::Wheel(delta)) => frame.wheel(delta, timestamp_us),
+                    None => {}
+                },
+            }
+        }
+
+        frame.finish(
+            [position.0 as f32, position.1 as f32],
+            &self.vocabulary,
+            self.mouse_bins,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        csv_processing::key_to_num,
+        temp_dir,
+        vocabulary::{KEY_PADDING, default_vocabulary},
+    };
+    use std::fs;
+
+    fn frame(index: usize, timestamp_us: u64) -> FrameStamp {
+        FrameStamp {
+            index,
+            file: format!("image-{}.png", index),
+            timestamp_us,
+        }
+    }
+
+    fn event(timestamp_us: u64, kind: &str, key: &str, x: f64, y: f64) -> EventRecord {
+        EventRecord {
+            timestamp_us,
+            event: InputEvent::from_parts(kind, key, x, y).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_align_one_record_per_frame() {
+        let frames = vec![frame(0, 0), frame(1, 50_000), frame(2, 100_000)];
+
+        let records = align_events(&frames, &[], default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        assert_eq!(records.len(), 3);
+    }
+
+    #[test]
+    fn test_align_held_key_spans_frames() {
+        let frames = vec![frame(0, 0), frame(1, 50_000), frame(2, 100_000)];
+        let events = vec![
+            event(10_000, "KeyPress", "KeyW", 0.0, 0.0),
+            event(70_000, "KeyRelease", "KeyW", 0.0, 0.0),
+        ];
+
+        let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        assert_eq!(records[0].keys[0], key_to_num("KeyW"));
+        assert_eq!(records[1].keys[0], key_to_num("KeyW")); // удерживается с прошлого кадра
+        assert_eq!(records[2].keys[0], KEY_PADDING);
+    }
+
+    #[test]
+    fn test_align_keeps_input_order() {
+        let frames = vec![frame(2, 100_000), frame(0, 0), frame(1, 50_000)];
+        let events = vec![
+            event(60_000, "KeyPress", "KeyW", 0.0, 0.0),
+            event(90_000, "KeyRelease", "KeyW", 0.0, 0.0),
+        ];
+
+        let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        // Клавиша нажата только в интервале кадра 1, он в `frames` третий
+        assert_eq!(records[0].keys[0], KEY_PADDING);
+        assert_eq!(records[1].keys[0], KEY_PADDING);
+        assert_eq!(records[2].keys[0], key_to_num("KeyW"));
+    }
+
+    #[test]
+    fn test_align_short_press_inside_interval() {
+        let frames = vec![frame(0, 0), frame(1, 50_000)];
+        let events = vec![
+            event(10_000, "KeyPress", "Space", 0.0, 0.0),
+            event(20_000, "KeyRelease", "Space", 0.0, 0.0),
+        ];
+
+        let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        assert_eq!(records[0].keys[0], key_to_num("Space"));
+        assert_eq!(records[1].keys[0], KEY_PADDING);
+    }
+
+    #[test]
+    fn test_align_mouse_deltas_per_interval() {
+        let frames = vec![frame(0, 0), frame(1, 50_000)];
+        let events = vec![
+            event(5_000, "MouseMove", "", 100.0, 100.0),
+            event(10_000, "MouseMove", "", 110.0, 95.0),
+            event(60_000, "MouseMove", "", 120.0, 95.0),
+        ];
+
+        let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        // Первое движение задаёт начальную позицию и не даёт смещения,
+        // смещения складываются по отрезкам времени кадра
+        let bin = |offset_us: usize| offset_us * MOUSE_VECTOR_LENGTH / 50_000;
+        assert_eq!(records[0].mouse[bin(10_000)], [10, -5]);
+        assert_eq!(records[0].mouse[0], [0, 0]);
+        assert_eq!(records[0].delta, [10, -5]);
+        assert_eq!(records[1].delta, [10, 0]);
+    }
+
+    #[test]
+    fn test_align_events_before_first_frame_set_state() {
+        let frames = vec![frame(0, 100_000)];
+        let events = vec![event(10_000, "ButtonPress", "Left", 0.0, 0.0)];
+
+        let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        // Кнопки мыши идут отдельным каналом, а не в клавиши
+        assert_eq!(records[0].buttons, [1, 0, 0]);
+        assert_eq!(records[0].keys[0], KEY_PADDING);
+    }
+
+    #[test]
+    fn test_align_pause_cuts_interval() {
+        let frames = vec![frame(0, 0), frame(1, 1_000_000)];
+        let events = vec![
+            event(5_000, "MouseMove", "", 0.0, 0.0),
+            event(10_000, "Pause", "", 0.0, 0.0),
+            event(20_000, "KeyPress", "KeyE", 0.0, 0.0),
+            event(30_000, "MouseMove", "", 50.0, 50.0),
+            event(900_000, "Resume", "", 0.0, 0.0),
+        ];
+
+        let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        // Во время паузы кадру ничего не приписывается: клавиша засчитывается
+        // только с момента продолжения записи
+        assert_eq!(records[0].keys[0], key_to_num("KeyE"));
+        assert!((records[0].hold[0] - 0.1).abs() < 1e-6);
+        assert_eq!(records[0].key_events, [0, 0]);
+        assert_eq!(records[0].delta, [0, 0]);
+        // Клавиша, нажатая во время паузы, удерживается после неё
+        assert_eq!(records[1].keys[0], key_to_num("KeyE"));
+    }
+
+    #[test]
+    fn test_align_mouse_channels() {
+        let frames = vec![frame(0, 0), frame(1, 50_000), frame(2, 100_000)];
+        let events = vec![
+            event(5_000, "MouseMove", "", 100.0, 100.0),
+            event(10_000, "MouseMove", "", 110.0, 95.0),
+            event(20_000, "Wheel", "", 0.0, -1.0),
+            event(30_000, "Wheel", "", 0.0, -2.0),
+            event(40_000, "ButtonPress", "Right", 0.0, 0.0),
+            event(45_000, "ButtonRelease", "Right", 0.0, 0.0),
+            event(60_000, "ButtonPress", "Left", 0.0, 0.0),
+            event(70_000, "MouseMove", "", 130.0, 95.0),
+        ];
+
+        let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        // Прокрутка не попадает в движения мыши
+        assert_eq!(records[0].delta, [10, -5]);
+        assert_eq!(records[0].mouse[1], [0, 0]);
+        assert_eq!(records[0].wheel, [0, -3]);
+        assert_eq!(records[0].buttons, [0, 1, 0]);
+        assert_eq!(records[0].position, [0.0, 0.0]);
+
+        // Положение курсора — на момент начала кадра
+        assert_eq!(records[1].position, [110.0, 95.0]);
+        assert_eq!(records[1].delta, [20, 0]);
+        assert_eq!(records[1].wheel, [0, 0]);
+        assert_eq!(records[1].buttons, [1, 0, 0]);
+
+        // Кнопка удерживается с прошлого кадра
+        assert_eq!(records[2].position, [130.0, 95.0]);
+        assert_eq!(records[2].buttons, [1, 0, 0]);
+    }
+
+    #[test]
+    fn test_aligner_incremental_matches_batch() {
+        let frames = vec![frame(0, 0), frame(1, 50_000), frame(2, 100_000)];
+        let events = vec![
+            event(10_000, "KeyPress", "KeyW", 0.0, 0.0),
+            event(20_000, "MouseMove", "", 10.0, 10.0),
+            event(60_000, "MouseMove", "", 15.0, 10.0),
+            event(70_000, "KeyRelease", "KeyW", 0.0, 0.0),
+        ];
+
+        let batch = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+
+        // События приходят вперемешку с кадрами, как во время записи
+        let mut aligner = Aligner::default();
+        aligner.push_event(events[0].clone());
+        aligner.push_event(events[1].clone());
+        let first = aligner.align_frame(0, 50_000);
+        aligner.push_event(events[3].clone());
+        aligner.push_event(events[2].clone());
+        let second = aligner.align_frame(50_000, 100_000);
+        let third = aligner.align_frame(100_000, u64::MAX);
+
+        for (incremental, batch) in [first, second, third].iter().zip(&batch) {
+            assert_eq!(incremental.keys, batch.keys);
+            assert_eq!(incremental.mouse, batch.mouse);
+            assert_eq!(incremental.delta, batch.delta);
+            assert_eq!(incremental.position, batch.position);
+        }
+    }
+
+    #[test]
+    fn test_load_frame_stamps() {
+        let temp_dir = temp_dir("test_load_frame_stamps");
+
+        let path = temp_dir.join("frames.csv");
+        {
+            let mut writer = csv::Writer::from_path(&path).unwrap();
+            writer.serialize(frame(0, 0)).unwrap();
+            writer.serialize(frame(1, 50_000)).unwrap();
+            writer.flush().unwrap();
+        }
+
+        let frames = load_frame_stamps(&path).unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].file, "image-1.png");
+        assert_eq!(frames[1].timestamp_us, 50_000);
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+}
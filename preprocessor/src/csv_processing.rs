@@ -1,26 +1,132 @@
 // TODO: перенести в предобработку
-use std::{fs, io, path::PathBuf};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
+use common::{MOUSE_BUTTONS, MOUSE_VECTOR_LENGTH};
 use hdf5_metno::H5Type;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    aggregation::{Trajectory, add_delta, bin_by_order},
+    error::PreprocessError,
+    event_log::{EventRecord, InputEvent},
+    vocabulary::{KEY_PADDING, default_vocabulary},
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 struct CsvRecord {
     keys: String,
     mouse: String,
 }
 
+/// Строка текстового журнала событий, который `recorder` писал до двоичного формата
+/// (см. [`crate::event_log`]).
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvEventRecord {
+    timestamp_us: u64,
+    event: String,
+    key: String,
+    x: f64,
+    y: f64,
+}
+
+/// Строка старого формата, разобранная на имена клавиш и значения мыши
+pub(crate) struct LegacyRow {
+    pub keys: Vec<String>,
+    pub mouse: Vec<[i32; 2]>,
+}
+
+impl TryFrom<CsvRecord> for LegacyRow {
+    type Error = String;
+
+    fn try_from(record: CsvRecord) -> Result<Self, String> {
+        Ok(Self {
+            keys: split_list(&record.keys).map(str::to_string).collect(),
+            mouse: split_list(&record.mouse)
+                .map(mouse_to_num)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Значения в старом формате склеены через `", "`
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(", ").filter(|item| !item.is_empty())
+}
+
 // #[derive(Clone, Debug)]
 // pub struct KeysRecord {
 //     pub keys: Vec<u8>,        // преобразованные названия клавиш в числа
 //     pub mouse: Vec<[i32; 2]>, // Движения и скроллинг мыши
 // }
 
+/// Действия за один кадр в виде фиксированного размера, см. [`crate::aggregation`].
+///
+/// Мышь записывается отдельными каналами: траектория движения, суммарное смещение,
+/// положение курсора, кнопки и колёсико.
 #[derive(Clone, Debug, H5Type)]
 #[repr(C)]
 pub struct KeysRecordConst {
-    pub keys: [u8; 200],        // преобразованные названия клавиш в числа
-    pub mouse: [[i32; 2]; 200], // Движения и скроллинг мыши
+    pub keys: [u8; 200],              // Номера клавиш, пустые ячейки — KEY_PADDING
+    pub hold: [f32; 200],             // Доля кадра, в течение которой удерживалась клавиша
+    pub key_events: [u16; 2],         // Нажатия и отпускания клавиш за кадр
+    pub mouse: Trajectory,            // Смещения мыши по равным отрезкам времени кадра
+    pub position: [f32; 2],           // Положение курсора в момент кадра
+    pub delta: [i32; 2],              // Смещение мыши с предыдущего кадра
+    pub buttons: [u8; MOUSE_BUTTONS], // Левая, правая, средняя кнопки: 1 — нажата
+    pub button_events: [u16; 2],      // Нажатия и отпускания кнопок за кадр
+    pub wheel: [i32; 2],              // Прокрутка колёсика за кадр
+}
+
+impl KeysRecordConst {
+    /// Упаковка произвольного числа клавиш и движений мыши без меток времени.
+    /// Лишние клавиши отбрасываются, клавиши считаются удерживаемыми весь кадр.
+    /// Движения раскладываются по траектории в порядке поступления
+    /// ([`bin_by_order`]) на все [`MOUSE_VECTOR_LENGTH`] отрезков, смещение за кадр —
+    /// сумма всех движений, остальные каналы заполняются отдельно.
+    pub fn from_slices(keys: &[u8], mouse: &[[i32; 2]]) -> Self {
+        Self::from_slices_binned(keys, mouse, MOUSE_VECTOR_LENGTH)
+    }
+
+    /// То же, что [`KeysRecordConst::from_slices`], с `mouse_bins` отрезками траектории,
+    /// см. [`common::aggregation::AggregationConfig::mouse_bins`]
+    pub fn from_slices_binned(keys: &[u8], mouse: &[[i32; 2]], mouse_bins: usize) -> Self {
+        let mut keys_const = [KEY_PADDING; 200];
+        let mut hold = [0.0; 200];
+
+        for (i, value) in keys.iter().take(keys_const.len()).enumerate() {
+            keys_const[i] = *value;
+            hold[i] = 1.0;
+        }
+
+        let delta = mouse
+            .iter()
+            .fold([0; 2], |sum, value| add_delta(sum, *value));
+
+        KeysRecordConst {
+            keys: keys_const,
+            hold,
+            key_events: [0; 2],
+            mouse: bin_by_order(mouse, mouse_bins),
+            position: [0.0; 2],
+            delta,
+            buttons: [0; MOUSE_BUTTONS],
+            button_events: [0; 2],
+            wheel: [0; 2],
+        }
+    }
+}
+
+/// Номер канала кнопки мыши в [`KeysRecordConst::buttons`]
+pub fn button_to_index(button: &str) -> Option<usize> {
+    match button.to_lowercase().as_str() {
+        "left" => Some(0),
+        "right" => Some(1),
+        "middle" => Some(2),
+        _ => None,
+    }
 }
 
 /// Получение всех записей из всех файлов в директории
@@ -32,191 +138,128 @@ pub fn load_records_from_directory(dir: &PathBuf) -> io::Result<Vec<KeysRecordCo
         let path = entry.path();
 
         if path.is_file() {
-            let mut reader = csv::Reader::from_path(path).unwrap();
-            for result in reader.deserialize() {
-                let record: CsvRecord = result.unwrap();
-                let keys_record = parse_csv_record(record);
-
-                dataset.push(keys_record);
-            }
+            dataset.extend(load_records_from_file(&path)?);
         }
     }
 
     Ok(dataset)
 }
 
-fn parse_csv_record(record: CsvRecord) -> KeysRecordConst {
-    let keys: Vec<&str> = record.keys.split(", ").collect();
-    let mouse: Vec<&str> = record.mouse.split(", ").collect();
+/// Получение всех записей из одного файла старого формата (`keys`, `mouse`)
+pub fn load_records_from_file(path: &Path) -> io::Result<Vec<KeysRecordConst>> {
+    let mut dataset = Vec::new();
 
-    if keys.len() > 200 || mouse.len() > 200 {
-        panic!("Несоответствие размеров")
-    }
+    let mut reader = csv::Reader::from_path(path)?;
+    for (i, result) in reader.deserialize().enumerate() {
+        let record: CsvRecord = result?;
+        let keys_record = parse_csv_record(record).map_err(|err| row_error(path, i, err))?;
 
-    let keys = if keys.len() > 1 {
-        keys.iter().map(|&s| key_to_num(s)).collect()
-    } else {
-        if record.keys == "" {
-            vec![]
-        } else {
-            vec![key_to_num(&record.keys)]
-        }
-    };
+        dataset.push(keys_record);
+    }
 
-    let mouse = if mouse.len() > 1 {
-        mouse.iter().map(|&s| mouse_to_num(s)).collect()
-    } else {
-        if record.mouse == "" {
-            vec![]
-        } else {
-            vec![mouse_to_num(&record.mouse)]
-        }
-    };
+    Ok(dataset)
+}
 
-    let mut keys_const = [0; 200];
-    let mut mouse_const = [[0; 2]; 200];
+/// Строки старого формата без преобразования в числа, для импорта в журнал событий
+pub(crate) fn load_legacy_rows(path: &Path) -> io::Result<Vec<LegacyRow>> {
+    let mut rows = Vec::new();
 
-    for (i, value) in keys.iter().enumerate() {
-        keys_const[i] = *value;
+    let mut reader = csv::Reader::from_path(path)?;
+    for (i, result) in reader.deserialize().enumerate() {
+        let record: CsvRecord = result?;
+        rows.push(LegacyRow::try_from(record).map_err(|err| row_error(path, i, err))?);
     }
 
-    for (i, value) in mouse.iter().enumerate() {
-        mouse_const[i] = *value;
+    Ok(rows)
+}
+
+/// Ошибка в строке `i` (без заголовка) файла старого формата
+fn row_error(path: &Path, i: usize, message: String) -> io::Error {
+    PreprocessError::invalid_data(path, format!("строка {}: {}", i + 2, message)).into()
+}
+
+/// Чтение текстового журнала событий `events.csv`.
+/// Строки с неизвестным видом события пропускаются.
+pub fn load_events(path: &Path) -> io::Result<Vec<EventRecord>> {
+    let mut events = Vec::new();
+
+    let mut reader = csv::Reader::from_path(path)?;
+    for result in reader.deserialize() {
+        let record: CsvEventRecord = result?;
+
+        if let Some(event) = InputEvent::from_parts(&record.event, &record.key, record.x, record.y)
+        {
+            events.push(EventRecord {
+                timestamp_us: record.timestamp_us,
+                event,
+            });
+        }
     }
 
-    KeysRecordConst {
-        keys: keys_const,
-        mouse: mouse_const,
+    Ok(events)
+}
+
+/// Лишние клавиши и движения мыши не теряют запись целиком, см. [`KeysRecordConst::from_slices`]
+fn parse_csv_record(record: CsvRecord) -> Result<KeysRecordConst, String> {
+    let row = LegacyRow::try_from(record)?;
+    // Кнопки мыши в старом формате шли вместе с клавишами
+    let (buttons, keys): (Vec<&String>, Vec<&String>) = row
+        .keys
+        .iter()
+        .partition(|key| button_to_index(key).is_some());
+    let keys: Vec<u8> = keys.iter().map(|key| key_to_num(key)).collect();
+
+    let mut keys_record = KeysRecordConst::from_slices(&keys, &row.mouse);
+    for index in buttons.iter().filter_map(|button| button_to_index(button)) {
+        keys_record.buttons[index] = 1;
     }
+
+    Ok(keys_record)
 }
 
-fn mouse_to_num(s: &str) -> [i32; 2] {
-    let values: Vec<i32> = s.split(",").map(|s| s.parse::<i32>().unwrap()).collect();
+/// `rdev` пишет координаты курсора дробными. Нечисловые, бесконечные
+/// и не помещающиеся в `i32` значения — ошибка.
+fn mouse_to_num(s: &str) -> Result<[i32; 2], String> {
+    let values: Vec<&str> = s.split(",").collect();
+    let [x, y] = values[..] else {
+        return Err(format!(
+            "ожидались две координаты мыши через запятую: {s:?}"
+        ));
+    };
+
+    let parse = |value: &str| {
+        let number = value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("координата мыши не число: {value:?}"))?
+            .round();
+
+        if !number.is_finite() || number < i32::MIN as f64 || number > i32::MAX as f64 {
+            return Err(format!(
+                "координата мыши вне допустимого диапазона: {value:?}"
+            ));
+        }
+
+        Ok(number as i32)
+    };
 
-    [values[0], values[1]]
+    Ok([parse(x)?, parse(y)?])
 }
 
+/// Номер клавиши в словаре по умолчанию, см. [`crate::vocabulary::KeyVocabulary::key_to_num`]
 pub fn key_to_num(key: &str) -> u8 {
-    let key = key.to_lowercase();
-
-    match key.as_str() {
-        "alt" => 0,
-        "altgr" => 1,
-        "backspace" => 2,
-        "capslock" => 3,
-        "controlleft" | "control" => 4,
-        "controlright" => 5,
-        "delete" => 6,
-        "downarrow" => 7,
-        "end" => 8,
-        "escape" => 9,
-        "f1" => 10,
-        "f10" => 11,
-        "f11" => 12,
-        "f12" => 13,
-        "f2" => 14,
-        "f3" => 15,
-        "f4" => 16,
-        "f5" => 17,
-        "f6" => 18,
-        "f7" => 19,
-        "f8" => 20,
-        "f9" => 21,
-        "home" => 22,
-        "leftarrow" => 23,
-        "metaleft" => 24,
-        "metaright" => 25,
-        "pagedown" => 26,
-        "pageup" => 27,
-        "return" => 28,
-        "rightarrow" => 29,
-        "shiftleft" | "shift" => 30,
-        "shiftright" => 31,
-        "space" => 32,
-        "tab" => 33,
-        "uparrow" => 34,
-        "printscreen" => 35,
-        "scrolllock" => 36,
-        "pause" => 37,
-        "numlock" => 38,
-        "backquote" => 39,
-        "num1" | "1" => 40,
-        "num2" | "2" => 41,
-        "num3" | "3" => 42,
-        "num4" | "4" => 43,
-        "num5" | "5" => 44,
-        "num6" | "6" => 45,
-        "num7" | "7" => 46,
-        "num8" | "8" => 47,
-        "num9" | "9" => 48,
-        "num0" | "0" => 49,
-        "minus" | "-" => 50,
-        "equal" | "=" => 51,
-        "keyq" | "q" => 52,
-        "keyw" | "w" => 53,
-        "keye" | "e" => 54,
-        "keyr" | "r" => 55,
-        "keyt" | "t" => 56,
-        "keyy" | "y" => 57,
-        "keyu" | "u" => 58,
-        "keyi" | "i" => 59,
-        "keyo" | "o" => 60,
-        "keyp" | "p" => 61,
-        "leftbracket" => 62,
-        "rightbracket" => 63,
-        "keya" | "a" => 64,
-        "keys" | "s" => 65,
-        "keyd" | "d" => 66,
-        "keyf" | "f" => 67,
-        "keyg" | "g" => 68,
-        "keyh" | "h" => 69,
-        "keyj" | "j" => 70,
-        "keyk" | "k" => 71,
-        "keyl" | "l" => 72,
-        "semicolon" => 73,
-        "quote" => 74,
-        "backslash" => 75,
-        "intlbackslash" => 76,
-        "keyz" | "z" => 77,
-        "keyx" | "x" => 78,
-        "keyc" | "c" => 79,
-        "keyv" | "v" => 80,
-        "keyb" | "b" => 81,
-        "keyn" | "n" => 82,
-        "keym" | "m" => 83,
-        "comma" | "," => 84,
-        "dot" | "." => 85,
-        "slash" | "/" => 86,
-        "insert" => 87,
-        "kpretun" => 88,
-        "kpminus" => 89,
-        "kpplus" => 90,
-        "kpmultiply" => 91,
-        "kpdivide" => 92,
-        "kp0" => 93,
-        "kp1" => 94,
-        "kp2" => 95,
-        "kp3" => 96,
-        "kp4" => 97,
-        "kp5" => 98,
-        "kp6" => 99,
-        "kp7" => 100,
-        "kp8" => 101,
-        "kp9" => 102,
-        "kpdelete" => 103,
-        "function" => 104,
-        "unknown(u32)" => 105, // todo: рассмотреть вариант
-        "left" => 106,
-        "right" => 107,
-        "middle" => 108,
-        "unknown(u8)" => 109,
-        _ => 110,
-    }
+    default_vocabulary().key_to_num(key)
+}
+
+/// Имя клавиши по номеру в словаре по умолчанию, см. [`crate::vocabulary::KeyVocabulary::num_to_key`]
+pub fn num_to_key(id: u8) -> Option<&'static str> {
+    default_vocabulary().num_to_key(id)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::temp_dir;
     use std::io::Write;
 
     // === key_to_num tests ===
@@ -418,9 +461,19 @@ mod tests {
 
     #[test]
     fn test_key_to_num_mouse_buttons() {
-        assert_eq!(key_to_num("left"), 106);
-        assert_eq!(key_to_num("right"), 107);
-        assert_eq!(key_to_num("middle"), 108);
+        // Кнопки мыши пишутся отдельным каналом и в словарь клавиш не входят
+        let unknown = default_vocabulary().unknown_id();
+        assert_eq!(key_to_num("left"), unknown);
+        assert_eq!(key_to_num("right"), unknown);
+        assert_eq!(key_to_num("middle"), unknown);
+    }
+
+    #[test]
+    fn test_num_to_key() {
+        assert_eq!(num_to_key(9), Some("Escape"));
+        assert_eq!(num_to_key(52), Some("KeyQ"));
+        assert_eq!(num_to_key(105), Some(crate::vocabulary::UNKNOWN_KEY));
+        assert_eq!(num_to_key(KEY_PADDING), None);
     }
 
     #[test]
@@ -434,17 +487,45 @@ mod tests {
 
     #[test]
     fn test_key_to_num_unknown_key() {
-        // Unknown keys should return 110
-        assert_eq!(key_to_num("unknownkey"), 110);
-        assert_eq!(key_to_num("someweirdkey"), 110);
-        assert_eq!(key_to_num(""), 110);
+        // Unknown keys go to the unknown bucket, the last id of the vocabulary
+        assert_eq!(key_to_num("unknownkey"), 105);
+        assert_eq!(key_to_num("someweirdkey"), 105);
+        assert_eq!(key_to_num("Unknown(42)"), 105);
+        assert_eq!(key_to_num(""), 105);
     }
 
     #[test]
     fn test_mouse_to_num_valid() {
-        assert_eq!(mouse_to_num("100,200"), [100, 200]);
-        assert_eq!(mouse_to_num("0,0"), [0, 0]);
-        assert_eq!(mouse_to_num("-50,-100"), [-50, -100]);
+        assert_eq!(mouse_to_num("100,200"), Ok([100, 200]));
+        assert_eq!(mouse_to_num("0,0"), Ok([0, 0]));
+        assert_eq!(mouse_to_num("-50,-100"), Ok([-50, -100]));
+        assert_eq!(mouse_to_num("512.75,300.25"), Ok([513, 300]));
+    }
+
+    /// Test malformed, NaN and overflowing mouse values are errors instead of panics
+    #[test]
+    fn test_mouse_to_num_invalid() {
+        assert!(mouse_to_num("100").is_err());
+        assert!(mouse_to_num("1,2,3").is_err());
+        assert!(mouse_to_num("abc,2").is_err());
+        assert!(mouse_to_num("NaN,0").is_err());
+        assert!(mouse_to_num("inf,0").is_err());
+        assert!(mouse_to_num("0,1e12").is_err());
+    }
+
+    /// Test a bad row reports the file and line
+    #[test]
+    fn test_load_records_invalid_mouse() {
+        let temp_dir = temp_dir("test_invalid_mouse_csv");
+
+        let csv_path = temp_dir.join("test.csv");
+        fs::write(&csv_path, "keys,mouse\nKeyQ,\"1,2\"\nKeyW,\"NaN,2\"\n").unwrap();
+
+        let err = load_records_from_file(&csv_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("test.csv: строка 3"));
+
+        let _ = fs::remove_dir_all(&temp_dir);
     }
 
     // === load_records_from_directory tests ===
@@ -452,9 +533,7 @@ mod tests {
     /// Test load_records_from_directory with empty directory
     #[test]
     fn test_load_records_empty_directory() {
-        let temp_dir = std::env::temp_dir().join("test_empty_csv");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&temp_dir).unwrap();
+        let temp_dir = temp_dir("test_empty_csv");
 
         let result = load_records_from_directory(&temp_dir);
 
@@ -468,9 +547,7 @@ mod tests {
     /// Test load_records_from_directory with valid CSV
     #[test]
     fn test_load_records_valid_csv() {
-        let temp_dir = std::env::temp_dir().join("test_valid_csv");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&temp_dir).unwrap();
+        let temp_dir = temp_dir("test_valid_csv");
 
         // Create a valid CSV file using csv::Writer
         let csv_path = temp_dir.join("test.csv");
@@ -496,9 +573,7 @@ mod tests {
     /// Test load_records_from_directory with empty CSV
     #[test]
     fn test_load_records_empty_csv() {
-        let temp_dir = std::env::temp_dir().join("test_empty_csv_file");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&temp_dir).unwrap();
+        let temp_dir = temp_dir("test_empty_csv_file");
 
         // Create an empty CSV file
         let csv_path = temp_dir.join("empty.csv");
@@ -524,7 +599,7 @@ mod tests {
             mouse: "100,200".to_string(),
         };
 
-        let result = parse_csv_record(record);
+        let result = parse_csv_record(record).unwrap();
 
         assert_eq!(result.keys[0], 52); // 'q' -> 52
         assert_eq!(result.mouse[0], [100, 200]);
@@ -538,7 +613,7 @@ mod tests {
             mouse: "100,200, 300,400".to_string(),
         };
 
-        let result = parse_csv_record(record);
+        let result = parse_csv_record(record).unwrap();
 
         assert_eq!(result.keys[0], 52); // 'q' -> 52
         assert_eq!(result.keys[1], 53); // 'w' -> 53
@@ -553,28 +628,60 @@ mod tests {
             mouse: "100,200".to_string(),
         };
 
-        let result = parse_csv_record(record);
+        let result = parse_csv_record(record).unwrap();
 
-        assert_eq!(result.keys[0], 0); // Empty becomes 0
+        assert_eq!(result.keys[0], KEY_PADDING); // Empty stays padding
         assert_eq!(result.mouse[0], [100, 200]);
     }
 
-    /// Test parse_csv_record fills rest with zeros
+    /// Test parse_csv_record fills rest with padding
     #[test]
-    fn test_parse_csv_record_zeros_remainder() {
+    fn test_parse_csv_record_padding_remainder() {
         let record = CsvRecord {
             keys: "q".to_string(),
             mouse: "100,200".to_string(),
         };
 
-        let result = parse_csv_record(record);
+        let result = parse_csv_record(record).unwrap();
 
         // First position should have values
         assert_eq!(result.keys[0], 52);
-        // Rest should be zeros
-        assert_eq!(result.keys[1], 0);
-        assert_eq!(result.keys[100], 0);
-        assert_eq!(result.keys[199], 0);
+        // Rest should be padding, not the id of the first key
+        assert_eq!(result.keys[1], KEY_PADDING);
+        assert_eq!(result.keys[100], KEY_PADDING);
+        assert_eq!(result.keys[199], KEY_PADDING);
+    }
+
+    /// Test parse_csv_record moves legacy mouse buttons to the buttons channel
+    #[test]
+    fn test_parse_csv_record_legacy_buttons() {
+        let record = CsvRecord {
+            keys: "w, Left".to_string(),
+            mouse: "".to_string(),
+        };
+
+        let result = parse_csv_record(record).unwrap();
+
+        assert_eq!(result.keys[0], 53);
+        assert_eq!(result.keys[1], KEY_PADDING);
+        assert_eq!(result.buttons, [1, 0, 0]);
+    }
+
+    /// Test parse_csv_record keeps rows with more than 200 entries instead of panicking
+    #[test]
+    fn test_parse_csv_record_overflow() {
+        let record = CsvRecord {
+            keys: vec!["q"; 250].join(", "),
+            mouse: vec!["1,1"; 250].join(", "),
+        };
+
+        let result = parse_csv_record(record).unwrap();
+
+        assert_eq!(result.keys[199], 52);
+        assert_eq!(result.mouse.len(), MOUSE_VECTOR_LENGTH);
+        let total: i32 = result.mouse.iter().map(|delta| delta[0]).sum();
+        assert_eq!(total, 250);
+        assert_eq!(result.delta, [250, 250]);
     }
 
     /// Test KeysRecordConst has correct size
@@ -582,11 +689,51 @@ mod tests {
     fn test_keys_record_const_size() {
         let record = KeysRecordConst {
             keys: [0; 200],
-            mouse: [[0; 2]; 200],
+            hold: [0.0; 200],
+            key_events: [0; 2],
+            mouse: [[0; 2]; MOUSE_VECTOR_LENGTH],
+            position: [0.0; 2],
+            delta: [0; 2],
+            buttons: [0; MOUSE_BUTTONS],
+            button_events: [0; 2],
+            wheel: [0; 2],
         };
 
         // Just verify it can be created
         assert_eq!(record.keys.len(), 200);
-        assert_eq!(record.mouse.len(), 200);
+        assert_eq!(record.mouse.len(), MOUSE_VECTOR_LENGTH);
+        assert_eq!(record.buttons.len(), MOUSE_BUTTONS);
+    }
+
+    #[test]
+    fn test_from_slices_delta_is_sum_of_moves() {
+        let record = KeysRecordConst::from_slices(&[], &[[1, 2], [3, -4]]);
+
+        assert_eq!(record.delta, [4, -2]);
+        assert_eq!(record.wheel, [0, 0]);
+    }
+
+    #[test]
+    fn test_from_slices_delta_saturates() {
+        let big = [i32::MAX - 1, i32::MIN + 1];
+        let record = KeysRecordConst::from_slices(&[], &[big, big]);
+
+        assert_eq!(record.delta, [i32::MAX, i32::MIN]);
+    }
+
+    #[test]
+    fn test_from_slices_keys_held_whole_frame() {
+        let record = KeysRecordConst::from_slices(&[52, 53], &[]);
+
+        assert_eq!(&record.hold[..3], &[1.0, 1.0, 0.0]);
+        assert_eq!(record.key_events, [0, 0]);
+    }
+
+    #[test]
+    fn test_button_to_index() {
+        assert_eq!(button_to_index("Left"), Some(0));
+        assert_eq!(button_to_index("Right"), Some(1));
+        assert_eq!(button_to_index("Middle"), Some(2));
+        assert_eq!(button_to_index("Unknown(4)"), None);
     }
 }
@@ -0,0 +1,637 @@
+//! Проверка данных перед обработкой и обучением.
+//!
+//! Проверка только читает файлы и собирает все найденные проблемы в [`DoctorReport`],
+//! а не останавливается на первой. Проверяются:
+//! - кадры сессий и старого формата: декодируются ли, совпадает ли размер с форматом,
+//!   нет ли пропусков и повторов в номерах;
+//! - журналы событий: читаются ли, идут ли метки времени по порядку, конечны ли
+//!   координаты мыши;
+//! - файлы HDF5: версия схемы, устройство, формат кадров и сведение действий, длины наборов,
+//!   читаемость записей и допустимость значений;
+//! - выравнивание: у каждой строки индекса кадров есть уменьшенный кадр, в датасете
+//!   не больше записей, чем кадров в сессии.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use common::{
+    aggregation::{AGGREGATION_CONFIG_FILE, AggregationConfig},
+    frame::{FRAME_FORMAT_FILE, FrameFormat},
+    session::LEGACY_EVENTS_FILE,
+};
+use image::GenericImageView;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    alignment::load_frame_stamps,
+    csv_processing::load_legacy_rows,
+    event_log::{EventRecord, InputEvent},
+    filtering::{FILTER_REPORT_FILE, FilterReport},
+    hdf5_processing::{DATASET_FILE, Hdf5Reader, SCHEMA_VERSION, find_hdf5_files, read_schema},
+    images::scan_frames,
+    sessions::{Session, discover_sessions},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Данные можно обработать, но результат может быть не тем, что ожидается
+    Warning,
+    /// Обработка или обучение на этих данных завершится ошибкой
+    Error,
+}
+
+/// Проблема в конкретном файле или директории
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub path: PathBuf,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DoctorReport {
+    /// Сколько файлов проверено
+    pub checked_files: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl DoctorReport {
+    fn push(&mut self, severity: Severity, path: &Path, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity,
+            path: path.to_path_buf(),
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, path: &Path, message: impl Into<String>) {
+        self.push(Severity::Warning, path, message);
+    }
+
+    fn error(&mut self, path: &Path, message: impl Into<String>) {
+        self.push(Severity::Error, path, message);
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    /// Ошибок нет, предупреждения допустимы
+    pub fn is_healthy(&self) -> bool {
+        self.count(Severity::Error) == 0
+    }
+
+    pub fn to_json(&self) -> io::Result<String> {
+        serde_json::to_string_pretty(self).map_err(io::Error::other)
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            let severity = match finding.severity {
+                Severity::Warning => "предупреждение",
+                Severity::Error => "ошибка",
+            };
+            writeln!(
+                f,
+                "{}: {}: {}",
+                severity,
+                finding.path.display(),
+                finding.message
+            )?;
+        }
+
+        write!(
+            f,
+            "Проверено файлов: {}, ошибок: {}, предупреждений: {}",
+            self.checked_files,
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        )
+    }
+}
+
+/// Кадры в директории. Если задан `format`, кадры уже уменьшены и должны
+/// совпадать с ним по размеру; иначе все кадры должны быть одного размера.
+pub fn check_frames(dir: &Path, format: Option<FrameFormat>, report: &mut DoctorReport) {
+    let scan = match scan_frames(dir) {
+        Ok(scan) => scan,
+        Err(err) => return report.error(dir, format!("кадры не читаются: {err}")),
+    };
+
+    if !scan.is_complete() {
+        report.warning(dir, scan.to_string());
+    }
+    report.checked_files += scan.frames.len();
+
+    let decoded: Vec<image::ImageResult<(u32, u32)>> = scan
+        .frames
+        .par_iter()
+        .map(|(_, data)| image::open(data.path()).map(|image| image.dimensions()))
+        .collect();
+
+    let mut expected = format.map(|format| (format.width as u32, format.height as u32));
+    for ((_, data), dimensions) in scan.frames.iter().zip(decoded) {
+        let path = data.path();
+        match dimensions {
+            Err(err) => report.error(path, format!("изображение не декодируется: {err}")),
+            Ok(dimensions) => match expected {
+                None => expected = Some(dimensions),
+                Some(size) if size == dimensions => {}
+                Some((width, height)) if format.is_some() => report.error(
+                    path,
+                    format!(
+                        "кадр {}×{}, формат {}×{}: удалите уменьшенные кадры \
+                         и запустите обработку заново",
+                        dimensions.0, dimensions.1, width, height
+                    ),
+                ),
+                Some((width, height)) => report.warning(
+                    path,
+                    format!(
+                        "кадр {}×{}, у предыдущих {}×{}",
+                        dimensions.0, dimensions.1, width, height
+                    ),
+                ),
+            },
+        }
+    }
+}
+
+/// События журнала: метки времени по порядку, координаты мыши конечны
+pub fn check_events(path: &Path, events: &[EventRecord], report: &mut DoctorReport) {
+    let unordered = events
+        .windows(2)
+        .filter(|pair| pair[1].timestamp_us < pair[0].timestamp_us)
+        .count();
+    if unordered > 0 {
+        report.warning(
+            path,
+            format!("метки времени идут не по порядку: {unordered} раз"),
+        );
+    }
+
+    let invalid = events
+        .iter()
+        .filter(|record| match record.event {
+            InputEvent::MouseMove { x, y } => !x.is_finite() || !y.is_finite(),
+            _ => false,
+        })
+        .count();
+    if invalid > 0 {
+        report.error(
+            path,
+            format!("координаты мыши NaN или бесконечны: {invalid} событий"),
+        );
+    }
+}
+
+/// Файл датасета: устройство, формат кадров и сведение действий, читаемость и значения
+/// записей. Возвращает число записей, если файл читается.
+pub fn check_dataset(
+    path: &Path,
+    format: FrameFormat,
+    aggregation: AggregationConfig,
+    report: &mut DoctorReport,
+) -> Option<usize> {
+    report.checked_files += 1;
+
+    let schema = match read_schema(path) {
+        Ok(schema) => schema,
+        Err(err) => {
+            report.error(path, format!("не читается устройство файла: {err}"));
+            return None;
+        }
+    };
+
+    if schema.version < SCHEMA_VERSION {
+        report.error(
+            path,
+            format!(
+                "схема версии {}, текущая {SCHEMA_VERSION}: обновите файл командой `preprocessor migrate`",
+                schema.version
+            ),
+        );
+        return None;
+    }
+    if schema.version > SCHEMA_VERSION {
+        report.error(
+            path,
+            format!(
+                "схема версии {} новее поддерживаемой версии {SCHEMA_VERSION}",
+                schema.version
+            ),
+        );
+        return None;
+    }
+    if !schema.tagged {
+        report.warning(
+            path,
+            "версия схемы не записана в файле, её допишет `preprocessor migrate`",
+        );
+    }
+
+    if schema.format != format {
+        report.warning(
+            path,
+            format!(
+                "кадры в формате {:?}, в каталоге данных {:?}",
+                schema.format, format
+            ),
+        );
+    }
+    if schema.aggregation != aggregation {
+        report.warning(
+            path,
+            format!(
+                "траектория мыши по {} отрезкам, в каталоге данных по {}",
+                schema.aggregation.mouse_bins, aggregation.mouse_bins
+            ),
+        );
+    }
+
+    let lengths: Vec<Option<usize>> = ["data", "images", "frames"]
+        .iter()
+        .map(|name| {
+            schema
+                .datasets
+                .get(*name)
+                .and_then(|shape| shape.first().copied())
+        })
+        .collect();
+    let [Some(records), Some(images), Some(frames)] = lengths[..] else {
+        report.error(
+            path,
+            format!("нет наборов data, images и frames: {:?}", schema.datasets),
+        );
+        return None;
+    };
+    if images < frames || records < frames {
+        report.error(
+            path,
+            format!("разная длина наборов: data {records}, images {images}, frames {frames}"),
+        );
+        return None;
+    }
+    if images > frames || records > frames {
+        report.warning(
+            path,
+            format!(
+                "прерванная дозапись: data {records}, images {images}, frames {frames}; \
+                 лишние записи не читаются и отбрасываются при следующей дозаписи"
+            ),
+        );
+    }
+
+    let image_shape = &schema.datasets["images"][1..];
+    let expected = [
+        schema.format.channels(),
+        schema.format.height,
+        schema.format.width,
+    ];
+    if image_shape != expected {
+        report.error(
+            path,
+            format!(
+                "кадры размера {:?}, по атрибутам {:?}",
+                image_shape, expected
+            ),
+        );
+        return None;
+    }
+
+    let reader = match Hdf5Reader::open(path, 1) {
+        Ok(reader) => reader,
+        Err(err) => {
+            report.error(path, format!("файл не открывается: {err}"));
+            return None;
+        }
+    };
+
+    let mut invalid = 0;
+    for index in 0..reader.len() {
+        let record = match reader.read(index) {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(err) => {
+                report.error(
+                    path,
+                    format!("запись {index} не читается, тип записей не совпадает: {err}"),
+                );
+                return None;
+            }
+        };
+
+        let keys_record = &record.keys_record;
+        let valid = keys_record
+            .hold
+            .iter()
+            .all(|hold| (0.0..=1.0).contains(hold))
+            && keys_record.position.iter().all(|value| value.is_finite());
+        if !valid {
+            invalid += 1;
+        }
+    }
+    if invalid > 0 {
+        report.error(
+            path,
+            format!("записей с NaN или значениями вне диапазона: {invalid}"),
+        );
+    }
+
+    Some(records)
+}
+
+/// Сессия: кадры, журнал событий, индекс кадров и её датасет
+fn check_session(
+    session: &Session,
+    hdf5_path: &Path,
+    format: FrameFormat,
+    aggregation: AggregationConfig,
+    report: &mut DoctorReport,
+) {
+    if session.is_interrupted() {
+        report.warning(
+            &session.dir,
+            "запись прервана, сессия будет восстановлена при обработке",
+        );
+    }
+
+    if session.frames_dir().exists() {
+        check_frames(&session.frames_dir(), None, report);
+    }
+    if session.resized_dir().exists() {
+        check_frames(&session.resized_dir(), Some(format), report);
+    }
+
+    let events_path = if session.events_path().exists() {
+        session.events_path()
+    } else {
+        session.dir.join(LEGACY_EVENTS_FILE)
+    };
+    report.checked_files += 1;
+    match session.load_events() {
+        Ok(events) => check_events(&events_path, &events, report),
+        Err(err) => report.error(&events_path, format!("журнал событий не читается: {err}")),
+    }
+
+    let index_path = session.frames_index();
+    report.checked_files += 1;
+    let frames = match load_frame_stamps(&index_path) {
+        Ok(frames) => frames,
+        Err(err) => return report.error(&index_path, format!("индекс кадров не читается: {err}")),
+    };
+
+    // Кадры, уменьшенные при захвате, пишутся сразу в датасет
+    if !session.manifest.dataset_written {
+        let missing = frames
+            .iter()
+            .filter(|frame| !session.resized_dir().join(&frame.file).exists())
+            .count();
+        if missing > 0 {
+            report.warning(
+                &session.resized_dir(),
+                format!(
+                    "нет уменьшенных кадров для {missing} строк индекса из {}: \
+                     запустите обработку кадров",
+                    frames.len()
+                ),
+            );
+        }
+    }
+
+    let dataset_dir = hdf5_path.join(session.id());
+    let dataset_path = dataset_dir.join(DATASET_FILE);
+    if !dataset_path.exists() {
+        return;
+    }
+    let Some(records) = check_dataset(&dataset_path, format, aggregation, report) else {
+        return;
+    };
+
+    let dropped = FilterReport::load_or_default(&dataset_dir.join(FILTER_REPORT_FILE))
+        .map_or(0, |filter| filter.dropped.len());
+    if records > frames.len() {
+        report.error(
+            &dataset_path,
+            format!(
+                "записей {records}, а кадров в сессии {}: датасет не соответствует сессии",
+                frames.len()
+            ),
+        );
+    } else if records + dropped < frames.len() {
+        report.warning(
+            &dataset_path,
+            format!(
+                "записано {records} кадров из {}: запустите запись датасета",
+                frames.len()
+            ),
+        );
+    }
+}
+
+/// Проверка каталога данных: сессий, данных старого формата и всех файлов HDF5
+pub fn diagnose(data_path: &Path) -> DoctorReport {
+    let mut report = DoctorReport::default();
+
+    let format = match FrameFormat::load_or_default(data_path) {
+        Ok(format) => format,
+        Err(err) => {
+            report.error(
+                &data_path.join(FRAME_FORMAT_FILE),
+                format!("формат кадров не читается, проверка с форматом по умолчанию: {err}"),
+            );
+            FrameFormat::default()
+        }
+    };
+    let aggregation = match AggregationConfig::load_or_default(data_path) {
+        Ok(aggregation) => aggregation,
+        Err(err) => {
+            report.error(
+                &data_path.join(AGGREGATION_CONFIG_FILE),
+                format!("сведение действий не читается, проверка со сведением по умолчанию: {err}"),
+            );
+            AggregationConfig::default()
+        }
+    };
+    let hdf5_path = data_path.join("hdf5_files");
+
+    let sessions = match discover_sessions(data_path) {
+        Ok(sessions) => sessions,
+        Err(err) => {
+            report.error(data_path, format!("сессии не читаются: {err}"));
+            Vec::new()
+        }
+    };
+    for session in &sessions {
+        check_session(session, &hdf5_path, format, aggregation, &mut report);
+    }
+
+    // Старый формат записи без сессий
+    let raw_dir = data_path.join("images/raw");
+    if raw_dir.exists() {
+        check_frames(&raw_dir, None, &mut report);
+    }
+    let resized_dir = data_path.join("images/resized_images");
+    if resized_dir.exists() {
+        check_frames(&resized_dir, Some(format), &mut report);
+    }
+    let csv_path = data_path.join("keys/key_events.csv");
+    if csv_path.exists() {
+        report.checked_files += 1;
+        match load_legacy_rows(&csv_path) {
+            Ok(rows) => {
+                let frames = scan_frames(&resized_dir).map_or(0, |scan| scan.frames.len());
+                if frames > 0 && rows.len() != frames {
+                    report.warning(
+                        &csv_path,
+                        format!("строк журнала {}, кадров {}", rows.len(), frames),
+                    );
+                }
+            }
+            Err(err) => report.error(&csv_path, err.to_string()),
+        }
+    }
+
+    // Датасеты сессий проверены вместе с сессиями
+    let session_datasets: Vec<PathBuf> = sessions
+        .iter()
+        .map(|session| hdf5_path.join(session.id()).join(DATASET_FILE))
+        .collect();
+    if hdf5_path.exists() {
+        match find_hdf5_files(&hdf5_path) {
+            Ok(files) => {
+                for path in files.iter().filter(|path| !session_datasets.contains(path)) {
+                    check_dataset(path, format, aggregation, &mut report);
+                }
+            }
+            Err(err) => report.error(&hdf5_path, format!("датасеты не читаются: {err}")),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        csv_processing::KeysRecordConst,
+        hdf5_processing::{WriterOptions, append_to_dataset},
+        images::MyImage,
+        temp_dir,
+        types::MyConstData,
+    };
+    use common::frame::ChannelLayout;
+    use image::{DynamicImage, RgbImage};
+    use std::fs;
+
+    /// Test undecodable and mis-sized frames are reported per file
+    #[test]
+    fn test_check_frames() {
+        let dir = temp_dir("test_doctor_frames");
+        let format = FrameFormat::new(4, 4, ChannelLayout::Rgb).unwrap();
+
+        DynamicImage::ImageRgb8(RgbImage::new(4, 4))
+            .save(dir.join("image-0.png"))
+            .unwrap();
+        DynamicImage::ImageRgb8(RgbImage::new(8, 8))
+            .save(dir.join("image-1.png"))
+            .unwrap();
+        fs::write(dir.join("image-3.png"), b"not a png").unwrap();
+
+        let mut report = DoctorReport::default();
+        check_frames(&dir, Some(format), &mut report);
+
+        assert_eq!(report.checked_files, 3);
+        assert_eq!(report.count(Severity::Error), 2);
+        assert_eq!(report.count(Severity::Warning), 1);
+        assert!(report.findings.iter().any(|finding| {
+            finding.path == dir.join("image-3.png") && finding.message.contains("не декодируется")
+        }));
+        assert!(!report.is_healthy());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test NaN mouse positions and out-of-order timestamps in an event log
+    #[test]
+    fn test_check_events() {
+        let events = [
+            EventRecord {
+                timestamp_us: 10,
+                event: InputEvent::MouseMove { x: 1.0, y: 2.0 },
+            },
+            EventRecord {
+                timestamp_us: 5,
+                event: InputEvent::MouseMove {
+                    x: f64::NAN,
+                    y: 0.0,
+                },
+            },
+        ];
+
+        let mut report = DoctorReport::default();
+        check_events(Path::new("events.bin"), &events, &mut report);
+
+        assert_eq!(report.count(Severity::Warning), 1);
+        assert_eq!(report.count(Severity::Error), 1);
+    }
+
+    /// Test a whole data directory with a healthy dataset, a mismatched one and a corrupt legacy log
+    #[test]
+    fn test_diagnose() {
+        let dir = temp_dir("test_doctor_diagnose");
+        let format = FrameFormat::default();
+        let record = MyConstData {
+            image: MyImage::filled(format, 0),
+            keys_record: KeysRecordConst::from_slices(&[], &[]),
+        };
+
+        append_to_dataset(
+            &dir.join("hdf5_files/legacy"),
+            &[(0, record.clone())],
+            format,
+            WriterOptions::default(),
+        )
+        .unwrap();
+        let mut bad = record;
+        bad.keys_record.position = [f32::NAN, 0.0];
+        append_to_dataset(
+            &dir.join("hdf5_files/other"),
+            &[(0, bad)],
+            format,
+            WriterOptions::default(),
+        )
+        .unwrap();
+        fs::create_dir_all(dir.join("keys")).unwrap();
+        fs::write(
+            dir.join("keys/key_events.csv"),
+            "keys,mouse\nKeyQ,\"1,NaN\"\n",
+        )
+        .unwrap();
+
+        let report = diagnose(&dir);
+
+        assert_eq!(report.checked_files, 3);
+        assert_eq!(report.count(Severity::Error), 2);
+        assert!(report.findings.iter().any(|finding| {
+            finding.path == dir.join("hdf5_files/other").join(DATASET_FILE)
+                && finding.message.contains("NaN")
+        }));
+        assert!(report.to_string().contains("ошибок: 2"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["findings"][0]["severity"], "error");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
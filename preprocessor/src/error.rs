@@ -0,0 +1,136 @@
+//! Ошибки предобработки. Каждая ошибка знает файл, на котором возникла,
+//! чтобы по сообщению было понятно, что исправить или удалить.
+
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    /// Файл не читается или не записывается
+    Io { path: PathBuf, source: io::Error },
+    /// Изображение не декодируется или не сохраняется
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    /// Файл HDF5 повреждён или устроен не так, как ожидается
+    Hdf5 {
+        path: PathBuf,
+        source: hdf5_metno::Error,
+    },
+    /// Содержимое файла не подходит: неверные значения, несовпадение формата
+    InvalidData { path: PathBuf, message: String },
+}
+
+impl PreprocessError {
+    pub fn invalid_data(path: &Path, message: impl Into<String>) -> Self {
+        Self::InvalidData {
+            path: path.to_path_buf(),
+            message: message.into(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Io { path, .. }
+            | Self::Image { path, .. }
+            | Self::Hdf5 { path, .. }
+            | Self::InvalidData { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path().display();
+
+        match self {
+            Self::Io { source, .. } => write!(f, "{path}: {source}"),
+            Self::Image { source, .. } => write!(f, "{path}: изображение не читается: {source}"),
+            Self::Hdf5 { source, .. } => write!(f, "{path}: ошибка HDF5: {source}"),
+            Self::InvalidData { message, .. } => write!(f, "{path}: {message}"),
+        }
+    }
+}
+
+impl Error for PreprocessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Image { source, .. } => Some(source),
+            Self::Hdf5 { source, .. } => Some(source),
+            Self::InvalidData { .. } => None,
+        }
+    }
+}
+
+/// Для функций, которые возвращают [`io::Result`]: сообщение сохраняет путь к файлу
+impl From<PreprocessError> for io::Error {
+    fn from(err: PreprocessError) -> Self {
+        let kind = match &err {
+            PreprocessError::Io { source, .. } => source.kind(),
+            _ => io::ErrorKind::InvalidData,
+        };
+
+        io::Error::new(kind, err)
+    }
+}
+
+/// Привязка ошибки к файлу: `File::open(path).at(path)?`
+pub trait ErrorPath<T> {
+    fn at(self, path: &Path) -> Result<T, PreprocessError>;
+}
+
+impl<T> ErrorPath<T> for io::Result<T> {
+    fn at(self, path: &Path) -> Result<T, PreprocessError> {
+        self.map_err(|source| PreprocessError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+impl<T> ErrorPath<T> for image::ImageResult<T> {
+    fn at(self, path: &Path) -> Result<T, PreprocessError> {
+        self.map_err(|source| PreprocessError::Image {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+impl<T> ErrorPath<T> for hdf5_metno::Result<T> {
+    fn at(self, path: &Path) -> Result<T, PreprocessError> {
+        self.map_err(|source| PreprocessError::Hdf5 {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+impl<T> ErrorPath<T> for zip::result::ZipResult<T> {
+    fn at(self, path: &Path) -> Result<T, PreprocessError> {
+        self.map_err(|err| match err {
+            zip::result::ZipError::Io(source) => PreprocessError::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+            err => PreprocessError::invalid_data(path, format!("архив не читается: {err}")),
+        })
+    }
+}
+
+impl<T> ErrorPath<T> for Result<T, safetensors::SafeTensorError> {
+    fn at(self, path: &Path) -> Result<T, PreprocessError> {
+        self.map_err(|err| match err {
+            safetensors::SafeTensorError::IoError(source) => PreprocessError::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+            err => PreprocessError::invalid_data(path, format!("ошибка safetensors: {err}")),
+        })
+    }
+}
@@ -0,0 +1,445 @@
+//! Двоичный журнал событий клавиатуры и мыши.
+//!
+//! Файл начинается с заголовка: сигнатура `EVLG` и версия формата (`u16`).
+//! Дальше идут записи: тег события (`u8`), метка времени в микросекундах (`u64`)
+//! и данные события. Имена клавиш и кнопок хранятся как длина (`u8`) и UTF-8 байты,
+//! координаты мыши — как два `f64`, прокрутка — как два `i64`. Все числа little-endian.
+//!
+//! Если запись прервалась на середине события, неполный хвост файла при чтении отбрасывается.
+
+use std::{
+    collections::BTreeSet,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use crate::csv_processing::{button_to_index, load_legacy_rows};
+
+pub const EVENT_LOG_MAGIC: [u8; 4] = *b"EVLG";
+pub const EVENT_LOG_VERSION: u16 = 1;
+
+/// Интервал между строками старого `key_events.csv`: рекордер писал их раз в 50 мс
+pub const LEGACY_FRAME_INTERVAL_US: u64 = 50_000;
+
+const TAG_KEY_PRESS: u8 = 0;
+const TAG_KEY_RELEASE: u8 = 1;
+const TAG_BUTTON_PRESS: u8 = 2;
+const TAG_BUTTON_RELEASE: u8 = 3;
+const TAG_MOUSE_MOVE: u8 = 4;
+const TAG_WHEEL: u8 = 5;
+const TAG_PAUSE: u8 = 6;
+const TAG_RESUME: u8 = 7;
+
+/// Событие клавиатуры или мыши.
+///
+/// Клавиши и кнопки называются так же, как их выводит `rdev` (`KeyW`, `Left`).
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    KeyPress(String),
+    KeyRelease(String),
+    ButtonPress(String),
+    ButtonRelease(String),
+    /// Положение курсора
+    MouseMove {
+        x: f64,
+        y: f64,
+    },
+    /// Прокрутка колёсика
+    Wheel {
+        delta_x: i64,
+        delta_y: i64,
+    },
+    /// Служебные метки паузы записи
+    Pause,
+    Resume,
+}
+
+impl InputEvent {
+    /// Событие из полей текстового журнала: вида события, имени клавиши и координат
+    pub fn from_parts(event: &str, key: &str, x: f64, y: f64) -> Option<Self> {
+        let event = match event {
+            "KeyPress" => Self::KeyPress(key.to_string()),
+            "KeyRelease" => Self::KeyRelease(key.to_string()),
+            "ButtonPress" => Self::ButtonPress(key.to_string()),
+            "ButtonRelease" => Self::ButtonRelease(key.to_string()),
+            "MouseMove" => Self::MouseMove { x, y },
+            "Wheel" => Self::Wheel {
+                delta_x: x as i64,
+                delta_y: y as i64,
+            },
+            "Pause" => Self::Pause,
+            "Resume" => Self::Resume,
+            _ => return None,
+        };
+
+        Some(event)
+    }
+}
+
+/// Событие с меткой времени по часам сессии
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventRecord {
+    pub timestamp_us: u64,
+    pub event: InputEvent,
+}
+
+/// Запись журнала событий
+pub struct EventLogWriter<W: Write> {
+    inner: W,
+}
+
+impl EventLogWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Дописывание в конец существующего журнала. Неполный хвост должен быть
+    /// заранее отброшен, иначе новые события окажутся после обрыва.
+    pub fn append(path: &Path) -> io::Result<Self> {
+        EventLogReader::new(File::open(path)?)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+
+        Ok(Self {
+            inner: BufWriter::new(file),
+        })
+    }
+}
+
+impl<W: Write> EventLogWriter<W> {
+    /// Сразу пишет заголовок журнала
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(&EVENT_LOG_MAGIC)?;
+        inner.write_all(&EVENT_LOG_VERSION.to_le_bytes())?;
+
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, record: &EventRecord) -> io::Result<()> {
+        let (tag, name) = match &record.event {
+            InputEvent::KeyPress(key) => (TAG_KEY_PRESS, Some(key)),
+            InputEvent::KeyRelease(key) => (TAG_KEY_RELEASE, Some(key)),
+            InputEvent::ButtonPress(button) => (TAG_BUTTON_PRESS, Some(button)),
+            InputEvent::ButtonRelease(button) => (TAG_BUTTON_RELEASE, Some(button)),
+            InputEvent::MouseMove { .. } => (TAG_MOUSE_MOVE, None),
+            InputEvent::Wheel { .. } => (TAG_WHEEL, None),
+            InputEvent::Pause => (TAG_PAUSE, None),
+            InputEvent::Resume => (TAG_RESUME, None),
+        };
+
+        self.inner.write_all(&[tag])?;
+        self.inner.write_all(&record.timestamp_us.to_le_bytes())?;
+
+        if let Some(name) = name {
+            let length = u8::try_from(name.len()).map_err(|_| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Слишком длинное имя: {name}"),
+                )
+            })?;
+
+            self.inner.write_all(&[length])?;
+            self.inner.write_all(name.as_bytes())?;
+        }
+
+        match record.event {
+            InputEvent::MouseMove { x, y } => {
+                self.inner.write_all(&x.to_le_bytes())?;
+                self.inner.write_all(&y.to_le_bytes())?;
+            }
+            InputEvent::Wheel { delta_x, delta_y } => {
+                self.inner.write_all(&delta_x.to_le_bytes())?;
+                self.inner.write_all(&delta_y.to_le_bytes())?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Чтение журнала событий по одному событию
+pub struct EventLogReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> EventLogReader<R> {
+    /// Проверяет сигнатуру и версию журнала
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        let mut version = [0; 2];
+        inner.read_exact(&mut magic)?;
+        inner.read_exact(&mut version)?;
+
+        if magic != EVENT_LOG_MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Файл не является журналом событий",
+            ));
+        }
+
+        let version = u16::from_le_bytes(version);
+        if version != EVENT_LOG_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Неподдерживаемая версия журнала событий: {version}"),
+            ));
+        }
+
+        Ok(Self { inner })
+    }
+
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buffer = [0; N];
+        self.inner.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn read_name(&mut self) -> io::Result<String> {
+        let [length] = self.read_array::<1>()?;
+        let mut buffer = vec![0; length as usize];
+        self.inner.read_exact(&mut buffer)?;
+
+        String::from_utf8(buffer).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+    }
+
+    fn read_record(&mut self, tag: u8) -> io::Result<EventRecord> {
+        let timestamp_us = u64::from_le_bytes(self.read_array()?);
+
+        let event = match tag {
+            TAG_KEY_PRESS => InputEvent::KeyPress(self.read_name()?),
+            TAG_KEY_RELEASE => InputEvent::KeyRelease(self.read_name()?),
+            TAG_BUTTON_PRESS => InputEvent::ButtonPress(self.read_name()?),
+            TAG_BUTTON_RELEASE => InputEvent::ButtonRelease(self.read_name()?),
+            TAG_MOUSE_MOVE => InputEvent::MouseMove {
+                x: f64::from_le_bytes(self.read_array()?),
+                y: f64::from_le_bytes(self.read_array()?),
+            },
+            TAG_WHEEL => InputEvent::Wheel {
+                delta_x: i64::from_le_bytes(self.read_array()?),
+                delta_y: i64::from_le_bytes(self.read_array()?),
+            },
+            TAG_PAUSE => InputEvent::Pause,
+            TAG_RESUME => InputEvent::Resume,
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Неизвестный тег события: {tag}"),
+                ));
+            }
+        };
+
+        Ok(EventRecord {
+            timestamp_us,
+            event,
+        })
+    }
+}
+
+impl<R: Read> Iterator for EventLogReader<R> {
+    type Item = io::Result<EventRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tag = match self.read_array::<1>() {
+            Ok([tag]) => tag,
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(error) => return Some(Err(error)),
+        };
+
+        match self.read_record(tag) {
+            // Обрыв записи посреди события
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => None,
+            result => Some(result),
+        }
+    }
+}
+
+/// Чтение всего журнала событий
+pub fn load_event_log(path: &Path) -> io::Result<Vec<EventRecord>> {
+    EventLogReader::new(BufReader::new(File::open(path)?))?.collect()
+}
+
+/// Импорт старого `key_events.csv` в журнал событий.
+///
+/// В старом формате у строк нет меток времени, поэтому строка `i` получает метку
+/// `i * frame_interval_us`. Клавиши и кнопки, появившиеся в строке, становятся нажатиями,
+/// пропавшие — отпусканиями. Движения мыши и прокрутка в старом формате неразличимы
+/// и импортируются как положения курсора.
+///
+/// Возвращает число записанных событий.
+pub fn import_key_events_csv(
+    csv_path: &Path,
+    log_path: &Path,
+    frame_interval_us: u64,
+) -> io::Result<usize> {
+    let rows = load_legacy_rows(csv_path)?;
+    let mut writer = EventLogWriter::create(log_path)?;
+
+    let mut held = BTreeSet::new();
+    let mut count = 0;
+
+    for (i, row) in rows.into_iter().enumerate() {
+        let timestamp_us = i as u64 * frame_interval_us;
+        let keys: BTreeSet<String> = row.keys.into_iter().collect();
+
+        let released = held.difference(&keys).map(|key: &String| {
+            if button_to_index(key).is_some() {
+                InputEvent::ButtonRelease(key.clone())
+            } else {
+                InputEvent::KeyRelease(key.clone())
+            }
+        });
+        let pressed = keys.difference(&held).map(|key| {
+            if button_to_index(key).is_some() {
+                InputEvent::ButtonPress(key.clone())
+            } else {
+                InputEvent::KeyPress(key.clone())
+            }
+        });
+        let moves = row.mouse.iter().map(|[x, y]| InputEvent::MouseMove {
+            x: *x as f64,
+            y: *y as f64,
+        });
+
+        for event in released.chain(pressed).chain(moves).collect::<Vec<_>>() {
+            writer.write(&EventRecord {
+                timestamp_us,
+                event,
+            })?;
+            count += 1;
+        }
+
+        held = keys;
+    }
+
+    writer.flush()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+    use std::fs;
+
+    fn record(timestamp_us: u64, event: InputEvent) -> EventRecord {
+        EventRecord {
+            timestamp_us,
+            event,
+        }
+    }
+
+    fn sample_events() -> Vec<EventRecord> {
+        vec![
+            record(0, InputEvent::KeyPress("KeyW".to_string())),
+            record(10, InputEvent::MouseMove { x: 12.5, y: -3.0 }),
+            record(
+                20,
+                InputEvent::Wheel {
+                    delta_x: 0,
+                    delta_y: -1,
+                },
+            ),
+            record(30, InputEvent::ButtonPress("Left".to_string())),
+            record(40, InputEvent::Pause),
+            record(50, InputEvent::Resume),
+            record(60, InputEvent::ButtonRelease("Left".to_string())),
+            record(70, InputEvent::KeyRelease("KeyW".to_string())),
+        ]
+    }
+
+    /// Test every event kind survives a write/read round trip
+    #[test]
+    fn test_event_log_round_trip() {
+        let events = sample_events();
+
+        let mut writer = EventLogWriter::new(Vec::new()).unwrap();
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+
+        let bytes = writer.inner;
+        assert_eq!(&bytes[..4], b"EVLG");
+
+        let loaded: Vec<EventRecord> = EventLogReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(loaded, events);
+    }
+
+    /// Test a record cut off mid-write is dropped, earlier ones are kept
+    #[test]
+    fn test_event_log_truncated_tail() {
+        let mut writer = EventLogWriter::new(Vec::new()).unwrap();
+        for event in sample_events().iter().take(2) {
+            writer.write(event).unwrap();
+        }
+
+        let mut bytes = writer.inner;
+        bytes.truncate(bytes.len() - 3);
+
+        let loaded: Vec<EventRecord> = EventLogReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].event, InputEvent::KeyPress("KeyW".to_string()));
+    }
+
+    /// Test wrong magic and unknown version are rejected
+    #[test]
+    fn test_event_log_rejects_bad_header() {
+        let error = EventLogReader::new(b"keys,mouse\n".as_slice())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let mut bytes = EVENT_LOG_MAGIC.to_vec();
+        bytes.extend(99u16.to_le_bytes());
+        let error = EventLogReader::new(bytes.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    /// Test legacy CSV rows become press/release events and mouse positions
+    #[test]
+    fn test_import_key_events_csv() {
+        let temp_dir = temp_dir("test_import_key_events_csv");
+
+        let csv_path = temp_dir.join("key_events.csv");
+        let log_path = temp_dir.join("key_events.bin");
+        fs::write(
+            &csv_path,
+            "keys,mouse\n\"KeyW, Left\",\"10,20, 15,25\"\nKeyW,\n,\"30,40\"\n",
+        )
+        .unwrap();
+
+        let count = import_key_events_csv(&csv_path, &log_path, 50_000).unwrap();
+        let events = load_event_log(&log_path).unwrap();
+
+        assert_eq!(count, events.len());
+        assert_eq!(
+            events,
+            vec![
+                record(0, InputEvent::KeyPress("KeyW".to_string())),
+                record(0, InputEvent::ButtonPress("Left".to_string())),
+                record(0, InputEvent::MouseMove { x: 10.0, y: 20.0 }),
+                record(0, InputEvent::MouseMove { x: 15.0, y: 25.0 }),
+                record(50_000, InputEvent::ButtonRelease("Left".to_string())),
+                record(100_000, InputEvent::KeyRelease("KeyW".to_string())),
+                record(100_000, InputEvent::MouseMove { x: 30.0, y: 40.0 }),
+            ]
+        );
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+}
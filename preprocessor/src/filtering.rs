@@ -0,0 +1,531 @@
+//! Отбор кадров перед записью датасета.
+//!
+//! Когда ввода мало, большая часть кадров повторяет предыдущие и ничему не учит.
+//! Каждый кадр проверяется на:
+//! - повтор — почти не отличается от предыдущего кадра ([`FilterConfig::duplicate_threshold`]);
+//! - бездействие — за кадр не нажато и не удерживается ни одной клавиши, мышь не двигалась;
+//! - экран загрузки — кадр почти одного цвета ([`FilterConfig::loading_threshold`]);
+//! - смену сцены — кадр резко отличается от предыдущего ([`FilterConfig::scene_cut_threshold`]).
+//!
+//! Что делать с найденными кадрами, задаётся отдельно для каждой проверки ([`FilterPolicy`]).
+//! Настройки лежат в [`FRAME_FILTER_FILE`] в каталоге данных; без файла все кадры остаются.
+//! Итоги сохраняются рядом с датасетом в [`FILTER_REPORT_FILE`]: удалённые кадры не
+//! обрабатываются повторно, помеченные можно учесть при обучении.
+
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+use common::session::write_atomic;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    csv_processing::KeysRecordConst, images::MyImage, types::MyConstData, vocabulary::KEY_PADDING,
+};
+
+/// Настройки отбора в каталоге данных
+pub const FRAME_FILTER_FILE: &str = "frame_filter.json";
+
+/// Итоги отбора в директории датасета
+pub const FILTER_REPORT_FILE: &str = "filter_report.json";
+
+/// Что делать с кадрами, не прошедшими проверку
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterPolicy {
+    #[default]
+    Keep,
+    Drop,
+    /// Из подряд идущих таких кадров оставить каждый n-й, начиная с первого
+    Subsample(usize),
+    /// Оставить и записать в отчёт
+    Tag,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameIssue {
+    Duplicate,
+    Idle,
+    Loading,
+    SceneCut,
+}
+
+impl FrameIssue {
+    pub const ALL: [FrameIssue; 4] = [
+        FrameIssue::Duplicate,
+        FrameIssue::Idle,
+        FrameIssue::Loading,
+        FrameIssue::SceneCut,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FrameIssue::Duplicate => "повторы",
+            FrameIssue::Idle => "без действий",
+            FrameIssue::Loading => "загрузка",
+            FrameIssue::SceneCut => "смена сцены",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    /// Средняя разница значений пикселей (0–255) с предыдущим кадром, ниже которой кадр — повтор
+    pub duplicate_threshold: f32,
+    /// Средняя разница с предыдущим кадром, выше которой начинается новая сцена
+    pub scene_cut_threshold: f32,
+    /// Стандартное отклонение значений пикселей, ниже которого кадр — экран загрузки
+    pub loading_threshold: f32,
+    pub duplicate: FilterPolicy,
+    pub idle: FilterPolicy,
+    pub loading: FilterPolicy,
+    pub scene_cut: FilterPolicy,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            duplicate_threshold: 1.0,
+            scene_cut_threshold: 64.0,
+            loading_threshold: 3.0,
+            duplicate: FilterPolicy::Keep,
+            idle: FilterPolicy::Keep,
+            loading: FilterPolicy::Keep,
+            scene_cut: FilterPolicy::Keep,
+        }
+    }
+}
+
+impl FilterConfig {
+    pub fn policy(&self, issue: FrameIssue) -> FilterPolicy {
+        match issue {
+            FrameIssue::Duplicate => self.duplicate,
+            FrameIssue::Idle => self.idle,
+            FrameIssue::Loading => self.loading,
+            FrameIssue::SceneCut => self.scene_cut,
+        }
+    }
+
+    /// Все проверки оставляют кадры без пометок
+    pub fn keeps_all(&self) -> bool {
+        FrameIssue::ALL
+            .iter()
+            .all(|issue| self.policy(*issue) == FilterPolicy::Keep)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+
+        serde_json::from_reader(file).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Настройки из [`FRAME_FILTER_FILE`] в каталоге `dir`, если файла нет — по умолчанию
+    pub fn load_or_default(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(FRAME_FILTER_FILE);
+
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+
+        write_atomic(path, &json)
+    }
+}
+
+/// Средняя по всем значениям абсолютная разница двух кадров одного формата
+pub fn mean_difference(a: &MyImage, b: &MyImage) -> f32 {
+    if a.pixels.is_empty() || a.pixels.len() != b.pixels.len() {
+        return f32::MAX;
+    }
+
+    let sum: u64 = a
+        .pixels
+        .iter()
+        .zip(&b.pixels)
+        .map(|(a, b)| a.abs_diff(*b) as u64)
+        .sum();
+
+    sum as f32 / a.pixels.len() as f32
+}
+
+/// Стандартное отклонение значений пикселей кадра
+pub fn deviation(image: &MyImage) -> f32 {
+    if image.pixels.is_empty() {
+        return 0.0;
+    }
+
+    let count = image.pixels.len() as f64;
+    let mean = image.pixels.iter().map(|value| *value as f64).sum::<f64>() / count;
+    let variance = image
+        .pixels
+        .iter()
+        .map(|value| (*value as f64 - mean).powi(2))
+        .sum::<f64>()
+        / count;
+
+    variance.sqrt() as f32
+}
+
+/// За кадр не было ни нажатий, ни удержания клавиш и кнопок, ни движения мыши
+pub fn is_idle(record: &KeysRecordConst) -> bool {
+    record.keys.iter().all(|key| *key == KEY_PADDING)
+        && record.key_events == [0; 2]
+        && record.button_events == [0; 2]
+        && record.buttons.iter().all(|button| *button == 0)
+        && record.delta == [0; 2]
+        && record.wheel == [0; 2]
+        && record.mouse.iter().all(|delta| *delta == [0; 2])
+}
+
+/// Итоги отбора: номера удалённых и помеченных кадров с причинами
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FilterReport {
+    pub kept: usize,
+    pub dropped: BTreeMap<usize, Vec<FrameIssue>>,
+    pub tagged: BTreeMap<usize, Vec<FrameIssue>>,
+    /// Длины серий кадров с каждой из проблем на последнем проверенном кадре,
+    /// с них продолжает следующий запуск, см. [`FrameFilter::resume`]
+    #[serde(default)]
+    pub runs: [usize; FrameIssue::ALL.len()],
+    /// Номер последнего проверенного кадра, записанного или удалённого:
+    /// с ним сравнивается первый кадр следующего запуска
+    #[serde(default)]
+    pub last_checked: Option<usize>,
+}
+
+impl FilterReport {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+
+        serde_json::from_reader(file).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Отчёт прошлых запусков, если файла нет — пустой
+    pub fn load_or_default(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+
+        write_atomic(path, &json)
+    }
+
+    /// Удалённых кадров по каждой причине; у кадра может быть несколько причин
+    pub fn dropped_by(&self, issue: FrameIssue) -> usize {
+        self.dropped
+            .values()
+            .filter(|issues| issues.contains(&issue))
+            .count()
+    }
+}
+
+impl fmt::Display for FilterReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "оставлено {}, удалено {}", self.kept, self.dropped.len())?;
+
+        let reasons: Vec<String> = FrameIssue::ALL
+            .iter()
+            .map(|issue| (issue.name(), self.dropped_by(*issue)))
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| format!("{name}: {count}"))
+            .collect();
+        if !reasons.is_empty() {
+            write!(f, " ({})", reasons.join(", "))?;
+        }
+
+        write!(f, ", помечено {}", self.tagged.len())
+    }
+}
+
+/// Проверка кадров по порядку. Повторы и смена сцены определяются сравнением
+/// с предыдущим кадром, даже если тот был удалён.
+pub struct FrameFilter {
+    config: FilterConfig,
+    previous: Option<MyImage>,
+    /// Длина текущей серии кадров с каждой из проблем, для [`FilterPolicy::Subsample`]
+    runs: [usize; FrameIssue::ALL.len()],
+}
+
+impl FrameFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        Self {
+            config,
+            previous: None,
+            runs: [0; FrameIssue::ALL.len()],
+        }
+    }
+
+    /// Продолжение отбора прошлого запуска: серии берутся из `report`, первый новый
+    /// кадр сравнивается с `previous` — кадром [`FilterReport::last_checked`], даже если
+    /// тот был удалён. Так отбор по частям даёт тот же результат, что и за один проход.
+    pub fn resume(config: FilterConfig, previous: Option<MyImage>, report: &FilterReport) -> Self {
+        Self {
+            config,
+            previous,
+            runs: report.runs,
+        }
+    }
+
+    /// Проблемы кадра; кадр становится предыдущим для следующей проверки
+    pub fn check(&mut self, data: &MyConstData) -> Vec<FrameIssue> {
+        let mut issues = Vec::new();
+
+        if let Some(previous) = &self.previous {
+            let difference = mean_difference(previous, &data.image);
+
+            if difference < self.config.duplicate_threshold {
+                issues.push(FrameIssue::Duplicate);
+            }
+            if difference > self.config.scene_cut_threshold {
+                issues.push(FrameIssue::SceneCut);
+            }
+        }
+        if is_idle(&data.keys_record) {
+            issues.push(FrameIssue::Idle);
+        }
+        if deviation(&data.image) < self.config.loading_threshold {
+            issues.push(FrameIssue::Loading);
+        }
+
+        self.previous = Some(data.image.clone());
+
+        issues
+    }
+
+    /// Отбор записей по порядку кадров; решения записываются в `report`.
+    /// Кадр удаляется, если этого требует политика хотя бы одной из его проблем.
+    pub fn apply(
+        &mut self,
+        records: Vec<(usize, MyConstData)>,
+        report: &mut FilterReport,
+    ) -> Vec<(usize, MyConstData)> {
+        let mut kept = Vec::with_capacity(records.len());
+
+        for (index, data) in records {
+            let issues = self.check(&data);
+            report.last_checked = Some(index);
+
+            let mut drop = false;
+            let mut tag = false;
+            for (i, issue) in FrameIssue::ALL.iter().enumerate() {
+                if !issues.contains(issue) {
+                    self.runs[i] = 0;
+                    continue;
+                }
+                self.runs[i] += 1;
+
+                match self.config.policy(*issue) {
+                    FilterPolicy::Keep => {}
+                    FilterPolicy::Drop => drop = true,
+                    FilterPolicy::Subsample(step) => {
+                        drop |= !(self.runs[i] - 1).is_multiple_of(step.max(1))
+                    }
+                    FilterPolicy::Tag => tag = true,
+                }
+            }
+
+            if drop {
+                report.dropped.insert(index, issues);
+            } else {
+                if tag {
+                    report.tagged.insert(index, issues);
+                }
+                report.kept += 1;
+                kept.push((index, data));
+            }
+        }
+        report.runs = self.runs;
+
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+    use common::frame::{ChannelLayout, FrameFormat};
+
+    fn record(value: u8, keys: &[u8]) -> MyConstData {
+        let format = FrameFormat::new(4, 4, ChannelLayout::Gray).unwrap();
+        let mut image = MyImage::filled(format, value);
+        // Шахматный узор, чтобы кадр не считался экраном загрузки
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            if (i + i / 4) % 2 == 0 {
+                *pixel = pixel.saturating_add(100);
+            }
+        }
+
+        MyConstData {
+            image,
+            keys_record: KeysRecordConst::from_slices(keys, &[]),
+        }
+    }
+
+    /// Test duplicates, idle frames, loading screens and scene cuts are detected
+    #[test]
+    fn test_frame_filter_checks() {
+        let mut filter = FrameFilter::new(FilterConfig::default());
+
+        assert_eq!(filter.check(&record(10, &[1])), []);
+        assert_eq!(filter.check(&record(10, &[1])), [FrameIssue::Duplicate]);
+        assert_eq!(
+            filter.check(&record(10, &[])),
+            [FrameIssue::Duplicate, FrameIssue::Idle]
+        );
+        assert_eq!(filter.check(&record(150, &[1])), [FrameIssue::SceneCut]);
+
+        let format = FrameFormat::new(4, 4, ChannelLayout::Gray).unwrap();
+        let black = MyConstData {
+            image: MyImage::filled(format, 0),
+            ..record(0, &[1])
+        };
+        assert!(filter.check(&black).contains(&FrameIssue::Loading));
+    }
+
+    /// Test drop, subsample and tag policies and the report
+    #[test]
+    fn test_frame_filter_policies() {
+        let config = FilterConfig {
+            duplicate: FilterPolicy::Subsample(2),
+            idle: FilterPolicy::Tag,
+            scene_cut: FilterPolicy::Drop,
+            ..FilterConfig::default()
+        };
+        let mut filter = FrameFilter::new(config);
+        let mut report = FilterReport::default();
+
+        let records: Vec<_> = [
+            record(10, &[1]),
+            record(10, &[1]),
+            record(10, &[1]),
+            record(10, &[1]),
+            record(20, &[]),
+            record(200, &[1]),
+        ]
+        .into_iter()
+        .enumerate()
+        .collect();
+
+        let kept = filter.apply(records, &mut report);
+
+        let indices: Vec<usize> = kept.iter().map(|(index, _)| *index).collect();
+        // Повторы 1, 2, 3: первый и третий остаются
+        assert_eq!(indices, [0, 1, 3, 4]);
+        assert_eq!(report.kept, 4);
+        assert_eq!(report.dropped[&2], [FrameIssue::Duplicate]);
+        assert_eq!(report.dropped[&5], [FrameIssue::SceneCut]);
+        assert_eq!(report.tagged[&4], [FrameIssue::Idle]);
+        assert_eq!(report.dropped_by(FrameIssue::Duplicate), 1);
+        assert_eq!(
+            report.to_string(),
+            "оставлено 4, удалено 2 (повторы: 1, смена сцены: 1), помечено 1"
+        );
+    }
+
+    /// Test a resumed filter keeps the same frames as one run over all of them
+    #[test]
+    fn test_frame_filter_resume() {
+        let config = FilterConfig {
+            duplicate: FilterPolicy::Subsample(2),
+            ..FilterConfig::default()
+        };
+        let records: Vec<_> = (0..5).map(|index| (index, record(10, &[1]))).collect();
+
+        let mut report = FilterReport::default();
+        let whole = FrameFilter::new(config.clone()).apply(records.clone(), &mut report);
+        let whole: Vec<usize> = whole.iter().map(|(index, _)| *index).collect();
+        assert_eq!(whole, [0, 1, 3]);
+
+        let mut report = FilterReport::default();
+        let mut records = records;
+        let rest = records.split_off(3);
+        let first = FrameFilter::new(config.clone()).apply(records.clone(), &mut report);
+        // Продолжение с последнего проверенного кадра и серий из отчёта
+        assert_eq!(report.last_checked, Some(2));
+        let previous = records.last().map(|(_, data)| data.image.clone());
+        let second = FrameFilter::resume(config, previous, &report).apply(rest, &mut report);
+
+        let indices: Vec<usize> = first
+            .iter()
+            .chain(&second)
+            .map(|(index, _)| *index)
+            .collect();
+        assert_eq!(indices, whole);
+        assert_eq!(report.kept, 3);
+        assert_eq!(report.last_checked, Some(4));
+    }
+
+    /// Test a run ending on a dropped frame resumes from that frame, not the last written one
+    #[test]
+    fn test_frame_filter_resume_after_dropped() {
+        let config = FilterConfig {
+            duplicate: FilterPolicy::Drop,
+            idle: FilterPolicy::Drop,
+            ..FilterConfig::default()
+        };
+        // Кадр 1 удаляется как бездействие, кадр 2 повторяет его
+        let records = vec![
+            (0, record(10, &[1])),
+            (1, record(40, &[])),
+            (2, record(40, &[1])),
+        ];
+
+        let mut report = FilterReport::default();
+        let whole = FrameFilter::new(config.clone()).apply(records.clone(), &mut report);
+        assert_eq!(whole.len(), 1);
+        assert_eq!(report.dropped[&2], [FrameIssue::Duplicate]);
+
+        let mut report = FilterReport::default();
+        let mut records = records;
+        let rest = records.split_off(2);
+        let first = FrameFilter::new(config.clone()).apply(records.clone(), &mut report);
+        assert_eq!(first.len(), 1);
+        assert_eq!(report.last_checked, Some(1));
+        let previous = Some(records[1].1.image.clone());
+        let second = FrameFilter::resume(config, previous, &report).apply(rest, &mut report);
+
+        assert!(second.is_empty());
+        assert_eq!(report.dropped[&2], [FrameIssue::Duplicate]);
+    }
+
+    /// Test the default config keeps everything and config and report survive a round trip
+    #[test]
+    fn test_filter_config_and_report_files() {
+        let dir = temp_dir("test_filter_files");
+
+        assert!(FilterConfig::load_or_default(&dir).unwrap().keeps_all());
+
+        fs::write(
+            dir.join(FRAME_FILTER_FILE),
+            r#"{ "duplicate": "drop", "idle": { "subsample": 5 } }"#,
+        )
+        .unwrap();
+        let config = FilterConfig::load_or_default(&dir).unwrap();
+        assert_eq!(config.duplicate, FilterPolicy::Drop);
+        assert_eq!(config.idle, FilterPolicy::Subsample(5));
+        assert_eq!(config.loading, FilterPolicy::Keep);
+        assert_eq!(config.duplicate_threshold, 1.0);
+
+        let mut report = FilterReport {
+            kept: 3,
+            ..FilterReport::default()
+        };
+        report.dropped.insert(7, vec![FrameIssue::Duplicate]);
+        let path = dir.join(FILTER_REPORT_FILE);
+        report.save(&path).unwrap();
+        assert_eq!(FilterReport::load_or_default(&path).unwrap(), report);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
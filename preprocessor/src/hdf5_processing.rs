@@ -1,74 +1,1163 @@
-use std::{fs, io, path::PathBuf};
-
-use hdf5_metno::{File, Result};
-use ndarray::{Array, ArrayBase, Dim, OwnedRepr};
-
-use crate::types::MyConstData;
-
-fn write_hdf5_file(
-    file_path: &PathBuf,
-    my_data: &ArrayBase<OwnedRepr<MyConstData>, Dim<[usize; 1]>>,
-) -> Result<()> {
-    let file = File::create(file_path)?; // open for writing
-    let group = file.create_group("dir")?; // create a group
-    // #[cfg(feature = "blosc")]
-    // blosc_set_nthreads(2); // set number of blosc threads
-    let builder = group.new_dataset_builder();
-    // #[cfg(feature = "blosc")]
-    // let builder = builder.blosc_zstd(9, true); // zstd + shuffle
-
-    let _ = builder
-        .with_data(my_data)
-        // finalize and write the dataset
-        .create("data")?;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use common::{
+    aggregation::AggregationConfig,
+    frame::{FrameFormat, Resize, ResizeFilter, ResizeMode},
+};
+use hdf5_metno::{Dataset, File, Group, Result};
+use ndarray::{Array, Ix4, s};
+use serde::Serialize;
+
+use crate::{
+    csv_processing::KeysRecordConst, error::ErrorPath, images::MyImage, types::MyConstData,
+};
+
+/// Датасет сессии: `hdf5_files/<session_id>/data.h5`
+pub const DATASET_FILE: &str = "data.h5";
+
+/// Датасет старого формата записи без сессий: `hdf5_files/legacy/`
+pub const LEGACY_DATASET_DIR: &str = "legacy";
+
+/// Записей в одном блоке (chunk) набора данных
+pub const RECORDS_PER_CHUNK: usize = 100;
+
+/// Текущая версия схемы файла, пишется в атрибут группы `dir`:
+/// - 0 — файлы `my_data_{i}.h5` прежнего формата: без номеров кадров, записи вместе с кадрами;
+/// - 1 — датасет сессии: записи вместе с кадрами 40×40 RGBA, номера кадров в `dir/frames`;
+/// - 2 — кадры отдельно в `dir/images`, формат кадров в атрибутах группы.
+///
+/// Файлы старых версий не читаются, их обновляет [`crate::migration`].
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Действия за кадр, [`KeysRecordConst`]
+pub(crate) const DATA_PATH: &str = "data";
+/// Кадры: `[запись, канал, y, x]`, размер задаётся атрибутами группы
+pub(crate) const IMAGES_PATH: &str = "images";
+/// Номера исходных кадров, по одному на запись: по ним видно, что уже записано
+pub(crate) const FRAMES_PATH: &str = "frames";
+
+/// Атрибуты группы `dir` с форматом кадров
+const WIDTH_ATTR: &str = "width";
+const HEIGHT_ATTR: &str = "height";
+const CHANNELS_ATTR: &str = "channels";
+/// Способ уменьшения кадра: `[способ, фильтр, параметры × 4]`, см. [`encode_resize`].
+/// В файлах без атрибута кадры растянуты
+const RESIZE_ATTR: &str = "resize";
+/// Отрезков траектории мыши, [`AggregationConfig::mouse_bins`]. Хранится отдельно
+/// от формата кадров; в файлах без атрибута — все
+const MOUSE_BINS_ATTR: &str = "mouse_bins";
+/// Версия схемы файла, [`SCHEMA_VERSION`]
+const VERSION_ATTR: &str = "version";
+
+/// Сжатие наборов данных
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// gzip, уровень 0–9
+    Deflate(u8),
+    /// blosc + zstd с перемешиванием байтов, уровень 0–9
+    #[cfg(feature = "blosc")]
+    BloscZstd(u8),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WriterOptions {
+    /// Записей в одном блоке
+    pub chunk_size: usize,
+    pub compression: Compression,
+    /// Как сведены действия записей; при дозаписи должно совпадать с файлом
+    pub aggregation: AggregationConfig,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: RECORDS_PER_CHUNK,
+            compression: Compression::None,
+            aggregation: AggregationConfig::default(),
+        }
+    }
+}
+
+/// Способ уменьшения в виде чисел: параметры — цвет полей или область кадра
+fn encode_resize(resize: Resize) -> [u32; 6] {
+    let filter = match resize.filter {
+        ResizeFilter::Nearest => 0,
+        ResizeFilter::Triangle => 1,
+        ResizeFilter::CatmullRom => 2,
+        ResizeFilter::Gaussian => 3,
+        ResizeFilter::Lanczos3 => 4,
+    };
+
+    match resize.mode {
+        ResizeMode::Stretch => [0, filter, 0, 0, 0, 0],
+        ResizeMode::Crop => [1, filter, 0, 0, 0, 0],
+        ResizeMode::Letterbox { color } => {
+            let [r, g, b, a] = color.map(u32::from);
+            [2, filter, r, g, b, a]
+        }
+        ResizeMode::Region {
+            x,
+            y,
+            width,
+            height,
+        } => [3, filter, x, y, width, height],
+    }
+}
+
+fn decode_resize(values: [u32; 6]) -> Result<Resize> {
+    let [mode, filter, a, b, c, d] = values;
+
+    let filter = match filter {
+        0 => ResizeFilter::Nearest,
+        1 => ResizeFilter::Triangle,
+        2 => ResizeFilter::CatmullRom,
+        3 => ResizeFilter::Gaussian,
+        4 => ResizeFilter::Lanczos3,
+        _ => return Err(format!("Неизвестный фильтр уменьшения: {filter}").into()),
+    };
+    let mode = match mode {
+        0 => ResizeMode::Stretch,
+        1 => ResizeMode::Crop,
+        2 => ResizeMode::Letterbox {
+            color: [a, b, c, d].map(|value| value as u8),
+        },
+        3 => ResizeMode::Region {
+            x: a,
+            y: b,
+            width: c,
+            height: d,
+        },
+        _ => return Err(format!("Неизвестный способ уменьшения: {mode}").into()),
+    };
+
+    Ok(Resize { mode, filter })
+}
+
+/// Формат кадров файла из атрибутов группы `dir`
+fn read_format(group: &Group) -> Result<FrameFormat> {
+    let attr =
+        |name: &str| -> Result<usize> { Ok(group.attr(name)?.read_scalar::<u32>()? as usize) };
+
+    let resize = if group.attr_names()?.iter().any(|name| name == RESIZE_ATTR) {
+        decode_resize(group.attr(RESIZE_ATTR)?.read_scalar::<[u32; 6]>()?)?
+    } else {
+        Resize::default()
+    };
+
+    FrameFormat::from_channels(attr(WIDTH_ATTR)?, attr(HEIGHT_ATTR)?, attr(CHANNELS_ATTR)?)
+        .and_then(|format| format.with_resize(resize))
+        .map_err(|err| err.to_string().into())
+}
+
+/// Сведение действий файла из атрибута группы `dir`
+fn read_aggregation(group: &Group) -> Result<AggregationConfig> {
+    if !group
+        .attr_names()?
+        .iter()
+        .any(|name| name == MOUSE_BINS_ATTR)
+    {
+        return Ok(AggregationConfig::default());
+    }
+
+    let mouse_bins = group.attr(MOUSE_BINS_ATTR)?.read_scalar::<u32>()? as usize;
+
+    AggregationConfig::new(mouse_bins).map_err(|err| err.to_string().into())
+}
+
+fn write_aggregation(group: &Group, aggregation: AggregationConfig) -> Result<()> {
+    group
+        .new_attr::<u32>()
+        .create(MOUSE_BINS_ATTR)?
+        .write_scalar(&(aggregation.mouse_bins as u32))
+}
+
+pub(crate) fn write_format(group: &Group, format: FrameFormat) -> Result<()> {
+    for (name, value) in [
+        (WIDTH_ATTR, format.width),
+        (HEIGHT_ATTR, format.height),
+        (CHANNELS_ATTR, format.channels()),
+    ] {
+        group
+            .new_attr::<u32>()
+            .create(name)?
+            .write_scalar(&(value as u32))?;
+    }
+
+    group
+        .new_attr::<[u32; 6]>()
+        .create(RESIZE_ATTR)?
+        .write_scalar(&encode_resize(format.resize))?;
 
     Ok(())
 }
 
-pub fn write_data_to_hdf5_files(data_path: &PathBuf, my_data: &Vec<MyConstData>) {
-    fs::create_dir_all(data_path).unwrap();
+/// Версия схемы из атрибута и признак того, что она записана. В файлах без атрибута
+/// версия определяется по наборам данных: без номеров кадров — 0, без отдельных кадров — 1.
+pub(crate) fn schema_version(group: &Group) -> Result<(u32, bool)> {
+    if group.attr_names()?.iter().any(|name| name == VERSION_ATTR) {
+        return Ok((group.attr(VERSION_ATTR)?.read_scalar::<u32>()?, true));
+    }
+
+    let version = if !group.link_exists(FRAMES_PATH) {
+        0
+    } else if !group.link_exists(IMAGES_PATH) {
+        1
+    } else {
+        2
+    };
+
+    Ok((version, false))
+}
+
+pub(crate) fn write_version(group: &Group) -> Result<()> {
+    group
+        .new_attr::<u32>()
+        .create(VERSION_ATTR)?
+        .write_scalar(&SCHEMA_VERSION)
+}
+
+/// Ошибка для файлов не текущей версии: старые нужно обновить, новые не поддерживаются
+fn ensure_current(group: &Group) -> Result<()> {
+    match schema_version(group)?.0 {
+        SCHEMA_VERSION => Ok(()),
+        version if version < SCHEMA_VERSION => Err(format!(
+            "схема версии {version} устарела, обновите файл командой `preprocessor migrate`"
+        )
+        .into()),
+        version => Err(format!(
+            "схема версии {version} новее поддерживаемой версии {SCHEMA_VERSION}"
+        )
+        .into()),
+    }
+}
+
+/// Устройство файла датасета: версия схемы, формат кадров и размеры наборов данных
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DatasetSchema {
+    /// См. [`SCHEMA_VERSION`]
+    pub version: u32,
+    /// Версия записана в файле, а не определена по наборам данных
+    pub tagged: bool,
+    pub format: FrameFormat,
+    pub aggregation: AggregationConfig,
+    /// Размеры наборов данных группы `dir` по именам
+    pub datasets: BTreeMap<String, Vec<usize>>,
+}
+
+/// Устройство файла датасета без чтения записей. Читается и у файлов старых версий.
+pub fn read_schema(path: &Path) -> Result<DatasetSchema> {
+    let group = File::open(path)?.group("dir")?;
+    let (version, tagged) = schema_version(&group)?;
+
+    let mut datasets = BTreeMap::new();
+    for name in group.member_names()? {
+        datasets.insert(name.clone(), group.dataset(&name)?.shape());
+    }
 
-    let mut file_count = 0; // Счетчик записанных файлов
+    // До версии 2 кадры всегда 40×40 RGBA — формат по умолчанию
+    let format = if version < 2 {
+        FrameFormat::default()
+    } else {
+        read_format(&group)?
+    };
 
-    for (i, data) in my_data.chunks(100).enumerate() {
-        let array_data = Array::from_vec(data.to_vec());
+    Ok(DatasetSchema {
+        version,
+        tagged,
+        format,
+        aggregation: read_aggregation(&group)?,
+        datasets,
+    })
+}
+
+/// Дозапись в датасет сессии.
+///
+/// Действия хранятся в расширяемом наборе `dir/data`, кадры — в `dir/images`, оба из
+/// блоков по [`WriterOptions::chunk_size`] записей; рядом в `dir/frames` лежат номера
+/// исходных кадров. Формат кадров, сведение действий и версия схемы записываются
+/// в атрибуты группы при создании файла.
+/// Повторная запись уже записанного кадра пропускается, поэтому обработку можно
+/// запускать заново после дозаписи сессии. Параметры сжатия применяются при создании файла.
+///
+/// Число записей определяется по `dir/frames`: номера дописываются последними, поэтому
+/// после прерванной дозаписи в `data` и `images` могут остаться лишние строки. Читатели их
+/// не видят, а [`Hdf5Writer::open`] отбрасывает.
+pub struct Hdf5Writer {
+    format: FrameFormat,
+    data: Dataset,
+    images: Dataset,
+    frames: Dataset,
+    ingested: BTreeSet<usize>,
+    len: usize,
+}
 
-        let file_path = data_path.join(format!("my_data_{}.h5", i));
+impl Hdf5Writer {
+    /// Открытие существующего файла или создание нового.
+    /// Существующий файл должен быть текущей версии схемы, файлу без записанной
+    /// версии она дописывается. Формат кадров должен совпадать с `format`, сведение
+    /// действий — с [`WriterOptions::aggregation`].
+    pub fn open(path: &Path, format: FrameFormat, options: WriterOptions) -> Result<Self> {
+        let file = File::append(path)?;
 
-        // Проверка на наличие файла
-        if fs::metadata(&file_path).is_ok() {
-            // println!("Файл уже существует: {:?}", file_path);
-            continue; // Пропускаем запись, если файл существует
+        if !file.link_exists("dir") {
+            let group = file.create_group("dir")?;
+            let chunk_size = options.chunk_size.max(1);
+            let (channels, height, width) = (format.channels(), format.height, format.width);
+
+            let data = group
+                .new_dataset::<KeysRecordConst>()
+                .chunk(chunk_size)
+                .shape(0..);
+            let images = group
+                .new_dataset::<u8>()
+                .chunk((chunk_size, channels, height, width))
+                .shape((0.., channels, height, width));
+            let (data, images) = match options.compression {
+                Compression::None => (data, images),
+                Compression::Deflate(level) => (data.deflate(level), images.deflate(level)),
+                #[cfg(feature = "blosc")]
+                Compression::BloscZstd(level) => {
+                    (data.blosc_zstd(level, true), images.blosc_zstd(level, true))
+                }
+            };
+            data.create(DATA_PATH)?;
+            images.create(IMAGES_PATH)?;
+
+            group
+                .new_dataset::<u64>()
+                .chunk(chunk_size)
+                .shape(0..)
+                .create(FRAMES_PATH)?;
+
+            write_format(&group, format)?;
+            write_aggregation(&group, options.aggregation)?;
+            write_version(&group)?;
+        }
+
+        let group = file.group("dir")?;
+        ensure_current(&group).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if !schema_version(&group)?.1 {
+            write_version(&group)?;
+        }
+
+        let stored = read_format(&group)?;
+        if stored != format {
+            return Err(format!(
+                "{}: формат кадров {:?}, записываются кадры {:?}",
+                path.display(),
+                stored,
+                format
+            )
+            .into());
+        }
+        let stored = read_aggregation(&group)?;
+        if stored != options.aggregation {
+            return Err(format!(
+                "{}: траектория мыши по {} отрезкам, записывается по {}",
+                path.display(),
+                stored.mouse_bins,
+                options.aggregation.mouse_bins
+            )
+            .into());
+        }
+
+        let data = group.dataset(DATA_PATH)?;
+        let images = group.dataset(IMAGES_PATH)?;
+        let frames = group.dataset(FRAMES_PATH)?;
+
+        let indices = frames.read_raw::<u64>()?;
+        let len = indices.len();
+        let ingested: BTreeSet<usize> = indices.into_iter().map(|index| index as usize).collect();
+
+        // Строки прерванной дозаписи без номеров кадров
+        let (channels, height, width) = (format.channels(), format.height, format.width);
+        let records = data.shape().first().copied().unwrap_or(0);
+        let image_records = images.shape().first().copied().unwrap_or(0);
+        if records < len || image_records < len {
+            return Err(format!(
+                "{}: записей {}, кадров {}, номеров {}",
+                path.display(),
+                records,
+                image_records,
+                len
+            )
+            .into());
+        }
+        if records > len {
+            data.resize(len)?;
+        }
+        if image_records > len {
+            images.resize((len, channels, height, width))?;
         }
 
-        // Запись файла
-        write_hdf5_file(&file_path, &array_data).unwrap();
+        Ok(Self {
+            format,
+            data,
+            images,
+            frames,
+            ingested,
+            len,
+        })
+    }
+
+    pub fn format(&self) -> FrameFormat {
+        self.format
+    }
+
+    /// Число записей в файле
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 
-        // Увеличиваем счетчик и выводим информацию о процессе
-        file_count += 1;
-        println!("Записан файл: {:?}", file_path);
+    /// Кадр с номером `index` уже записан
+    pub fn contains(&self, index: usize) -> bool {
+        self.ingested.contains(&index)
     }
 
-    // Выводим итоговое количество записанных файлов
-    println!("Итоговое количество записанных файлов: {}", file_count);
+    /// Последняя запись файла и номер её кадра
+    pub fn last(&self) -> Result<Option<(usize, MyConstData)>> {
+        let Some(row) = self.len.checked_sub(1) else {
+            return Ok(None);
+        };
+
+        let index = self.frames.read_slice_1d::<u64, _>(row..self.len)?[0] as usize;
+        let keys_record = self
+            .data
+            .read_slice_1d::<KeysRecordConst, _>(row..self.len)?[0]
+            .clone();
+        let pixels = self
+            .images
+            .read_slice::<u8, _, Ix4>(s![row..self.len, .., .., ..])?
+            .iter()
+            .copied()
+            .collect();
+        let image = MyImage {
+            format: self.format,
+            pixels,
+        };
+
+        Ok(Some((index, MyConstData { image, keys_record })))
+    }
+
+    /// Дозапись кадров `(номер кадра, запись)`, уже записанные кадры пропускаются.
+    /// Возвращает число добавленных записей.
+    pub fn append(&mut self, records: &[(usize, MyConstData)]) -> Result<usize> {
+        if let Some((index, record)) = records
+            .iter()
+            .find(|(_, record)| record.image.format != self.format)
+        {
+            return Err(format!(
+                "Кадр {}: формат {:?}, в файле {:?}",
+                index, record.image.format, self.format
+            )
+            .into());
+        }
+
+        let mut indices = Vec::new();
+        let mut data = Vec::new();
+        let mut pixels = Vec::new();
+
+        for (index, record) in records {
+            if self.ingested.insert(*index) {
+                indices.push(*index as u64);
+                data.push(record.keys_record.clone());
+                pixels.extend_from_slice(&record.image.pixels);
+            }
+        }
+
+        let count = data.len();
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let FrameFormat { width, height, .. } = self.format;
+        let channels = self.format.channels();
+        let images = Array::from_shape_vec((count, channels, height, width), pixels)
+            .map_err(|err| err.to_string())?;
+
+        let range = self.len..self.len + count;
+        self.data.resize(range.end)?;
+        self.data
+            .write_slice(&Array::from_vec(data), range.clone())?;
+        self.images.resize((range.end, channels, height, width))?;
+        self.images
+            .write_slice(&images, s![range.start..range.end, .., .., ..])?;
+        // Номера расширяются и пишутся последними: прерванная дозапись не отметит
+        // кадры записанными и не добавит записей, см. [`Hdf5Writer`]
+        self.frames.resize(range.end)?;
+        self.frames
+            .write_slice(&Array::from_vec(indices), range.clone())?;
+        self.len = range.end;
+
+        Ok(count)
+    }
 }
 
-pub fn read_all_hdf5_files(data_path: &PathBuf) -> io::Result<Vec<MyConstData>> {
-    let mut dataset = Vec::new();
+/// Дозапись кадров в [`DATASET_FILE`] в директории `data_path`.
+/// Возвращает число добавленных записей.
+pub fn append_to_dataset(
+    data_path: &Path,
+    records: &[(usize, MyConstData)],
+    format: FrameFormat,
+    options: WriterOptions,
+) -> io::Result<usize> {
+    fs::create_dir_all(data_path)?;
 
-    for entry in fs::read_dir(data_path)? {
-        let entry = entry?;
-        let path = entry.path();
+    let mut writer = Hdf5Writer::open(&data_path.join(DATASET_FILE), format, options)?;
 
-        if path.is_file() {
-            let file = File::open(path)?; // open for reading
-            let ds = file.dataset("dir/data")?; // open the dataset
+    Ok(writer.append(records)?)
+}
+
+/// Файлы датасета (`.h5`) в директории, включая поддиректории сессий, в порядке путей.
+/// Остальные файлы, например отчёты отбора кадров, пропускаются.
+/// Если `data_path` — файл, то только он.
+pub fn find_hdf5_files(data_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    if data_path.is_file() {
+        files.push(data_path.to_path_buf());
+        return Ok(files);
+    }
 
-            let my_data = ds.read_raw::<MyConstData>().unwrap();
+    let mut entries: Vec<PathBuf> = fs::read_dir(data_path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
 
-            dataset.extend(my_data);
+    for path in entries {
+        if path.is_dir() {
+            files.extend(find_hdf5_files(&path)?);
+        } else if path.is_file() && path.extension().is_some_and(|ext| ext == "h5") {
+            files.push(path);
         }
     }
 
-    Ok(dataset)
+    Ok(files)
+}
+
+/// Имя файла датасета в отчётах и разбиении на части: путь его директории
+/// относительно `data_path`, то есть номер сессии. Если файл лежит прямо
+/// в `data_path` или `data_path` и есть файл — путь к файлу.
+pub fn dataset_name(data_path: &Path, path: &Path) -> String {
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    match directory.strip_prefix(data_path) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+        _ => path.display().to_string(),
+    }
+}
+
+/// Чтение всех файлов в директории, включая поддиректории сессий
+pub fn read_all_hdf5_files(data_path: &Path) -> io::Result<Vec<MyConstData>> {
+    let reader = Hdf5Reader::open(data_path, 1)?;
+
+    (0..reader.len())
+        .filter_map(|index| reader.read(index).transpose())
+        .collect::<Result<_>>()
+        .map_err(io::Error::from)
+}
+
+/// Блоков в кэше [`Hdf5Reader`] по умолчанию
+pub const CACHED_BLOCKS: usize = 16;
+
+/// (номер файла, номер блока в файле)
+type BlockKey = (usize, usize);
+
+/// Недавно прочитанные блоки записей, вытесняется самый давний
+struct BlockCache {
+    capacity: usize,
+    /// Последний прочитанный блок в начале
+    blocks: VecDeque<(BlockKey, Arc<[MyConstData]>)>,
+}
+
+impl BlockCache {
+    fn get(&mut self, key: BlockKey) -> Option<Arc<[MyConstData]>> {
+        let position = self.blocks.iter().position(|(block, _)| *block == key)?;
+        let entry = self.blocks.remove(position)?;
+        let records = entry.1.clone();
+        self.blocks.push_front(entry);
+
+        Some(records)
+    }
+
+    fn insert(&mut self, key: BlockKey, records: Arc<[MyConstData]>) {
+        if self.blocks.iter().any(|(block, _)| *block == key) {
+            return;
+        }
+
+        self.blocks.push_front((key, records));
+        self.blocks.truncate(self.capacity);
+    }
+}
+
+/// Датасет на диске: записи всех файлов директории читаются по требованию.
+///
+/// При открытии строится только указатель номеров записей по файлам. Записи читаются
+/// блоками по [`RECORDS_PER_CHUNK`] (так они и хранятся), последние блоки держатся в кэше,
+/// поэтому объём датасета не ограничен памятью.
+pub struct Hdf5Reader {
+    format: FrameFormat,
+    aggregation: AggregationConfig,
+    /// Действия и кадры каждого файла
+    datasets: Vec<(Dataset, Dataset)>,
+    /// Номер первой записи каждого файла в общей нумерации
+    offsets: Vec<usize>,
+    /// Имена файлов, см. [`dataset_name`]
+    names: Vec<String>,
+    /// Номера исходных кадров всех записей
+    frames: Vec<u64>,
+    /// Участки кадров подряд: (номер первой записи, длина). Участок не выходит за
+    /// пределы файла, то есть сессии, и заканчивается на пропуске в номерах кадров.
+    runs: Vec<(usize, usize)>,
+    len: usize,
+    cache: Mutex<BlockCache>,
+}
+
+impl Hdf5Reader {
+    /// `data_path` — директория датасета или отдельный файл,
+    /// `cached_blocks` — сколько блоков держать в памяти.
+    /// Формат кадров и сведение действий всех файлов должны совпадать; если файлов нет,
+    /// они по умолчанию.
+    pub fn open(data_path: &Path, cached_blocks: usize) -> io::Result<Self> {
+        let mut format = None;
+        let mut aggregation = None;
+        let mut datasets = Vec::new();
+        let mut offsets = Vec::new();
+        let mut names = Vec::new();
+        let mut all_frames = Vec::new();
+        let mut runs = Vec::new();
+        let mut len = 0;
+
+        for path in find_hdf5_files(data_path)? {
+            let group = File::open(&path)
+                .and_then(|file| file.group("dir"))
+                .and_then(|group| ensure_current(&group).map(|()| group))
+                .at(&path)?;
+            let dataset = group.dataset(DATA_PATH).at(&path)?;
+            // Строки прерванной дозаписи без номеров кадров не читаются, см. [`Hdf5Writer`]
+            let frames = group
+                .dataset(FRAMES_PATH)
+                .and_then(|frames| frames.read_raw::<u64>())
+                .at(&path)?;
+            let records = frames.len();
+
+            if records == 0 {
+                continue;
+            }
+
+            let file_format = read_format(&group).at(&path)?;
+            if *format.get_or_insert(file_format) != file_format {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: формат кадров {:?} отличается от {:?} у остальных файлов",
+                        path.display(),
+                        file_format,
+                        format
+                    ),
+                ));
+            }
+            let file_aggregation = read_aggregation(&group).at(&path)?;
+            if *aggregation.get_or_insert(file_aggregation) != file_aggregation {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: траектория мыши по {} отрезкам, у остальных файлов по {}",
+                        path.display(),
+                        file_aggregation.mouse_bins,
+                        aggregation.unwrap_or_default().mouse_bins
+                    ),
+                ));
+            }
+
+            let images = group.dataset(IMAGES_PATH).at(&path)?;
+            let expected = [
+                records,
+                file_format.channels(),
+                file_format.height,
+                file_format.width,
+            ];
+            let image_shape = images.shape();
+            let data_records = dataset.shape().first().copied().unwrap_or(0);
+            if data_records < records
+                || image_shape.len() != 4
+                || image_shape[0] < records
+                || image_shape[1..] != expected[1..]
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: записей {}, кадры {:?}, ожидается не меньше {:?}",
+                        path.display(),
+                        data_records,
+                        image_shape,
+                        expected
+                    ),
+                ));
+            }
+
+            let mut run_start = 0;
+            for i in 1..=records {
+                if i == records || frames[i] != frames[i - 1] + 1 {
+                    runs.push((len + run_start, i - run_start));
+                    run_start = i;
+                }
+            }
+
+            offsets.push(len);
+            names.push(dataset_name(data_path, &path));
+            all_frames.extend(frames);
+            datasets.push((dataset, images));
+            len += records;
+        }
+
+        Ok(Self {
+            format: format.unwrap_or_default(),
+            aggregation: aggregation.unwrap_or_default(),
+            datasets,
+            offsets,
+            names,
+            frames: all_frames,
+            runs,
+            len,
+            cache: Mutex::new(BlockCache {
+                capacity: cached_blocks.max(1),
+                blocks: VecDeque::new(),
+            }),
+        })
+    }
+
+    pub fn format(&self) -> FrameFormat {
+        self.format
+    }
+
+    pub fn aggregation(&self) -> AggregationConfig {
+        self.aggregation
+    }
+
+    /// Число записей во всех файлах
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Участки кадров подряд: (номер первой записи, длина)
+    pub fn runs(&self) -> &[(usize, usize)] {
+        &self.runs
+    }
+
+    /// Откуда запись: имя файла (см. [`dataset_name`]) и номер исходного кадра
+    pub fn source(&self, index: usize) -> Option<(&str, u64)> {
+        let frame = *self.frames.get(index)?;
+        let file = self.offsets.partition_point(|offset| *offset <= index) - 1;
+
+        Some((&self.names[file], frame))
+    }
+
+    /// Проверка, что записи каждого файла читаются: читаются первый и последний блоки.
+    /// Ошибка указывает файл, см. [`dataset_name`].
+    pub fn validate(&self) -> io::Result<()> {
+        for (file, start) in self.offsets.iter().enumerate() {
+            let end = self.offsets.get(file + 1).copied().unwrap_or(self.len);
+
+            for index in [*start, end - 1] {
+                self.read(index).map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}: запись {} не читается: {}",
+                            self.names[file],
+                            index - start,
+                            err
+                        ),
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Номера первых записей всех окон из `length` кадров подряд одной сессии
+    pub fn windows(&self, length: usize) -> Vec<usize> {
+        let length = length.max(1);
+
+        self.runs
+            .iter()
+            .filter(|(_, run_len)| *run_len >= length)
+            .flat_map(|(start, run_len)| *start..=start + run_len - length)
+            .collect()
+    }
+
+    /// Запись с номером `index` в общей нумерации; `None`, если номер за пределами датасета
+    pub fn read(&self, index: usize) -> Result<Option<MyConstData>> {
+        if index >= self.len {
+            return Ok(None);
+        }
+
+        let file = self.offsets.partition_point(|offset| *offset <= index) - 1;
+        let local = index - self.offsets[file];
+        let key = (file, local / RECORDS_PER_CHUNK);
+
+        let cached = self.cache.lock().unwrap().get(key);
+        let records = match cached {
+            Some(records) => records,
+            None => {
+                let file_len =
+                    self.offsets.get(file + 1).map_or(self.len, |next| *next) - self.offsets[file];
+                let start = key.1 * RECORDS_PER_CHUNK;
+                let end = (start + RECORDS_PER_CHUNK).min(file_len);
+
+                // Чтение идёт без блокировки кэша: другие потоки в это время берут свои блоки
+                let (data, images) = &self.datasets[file];
+                let keys_records = data.read_slice_1d::<KeysRecordConst, _>(start..end)?;
+                let images = images.read_slice::<u8, _, Ix4>(s![start..end, .., .., ..])?;
+                let pixels: Vec<u8> = images.iter().copied().collect();
+
+                let records: Arc<[MyConstData]> = keys_records
+                    .into_iter()
+                    .zip(pixels.chunks_exact(self.format.pixel_count()))
+                    .map(|(keys_record, pixels)| MyConstData {
+                        image: MyImage {
+                            format: self.format,
+                            pixels: pixels.to_vec(),
+                        },
+                        keys_record,
+                    })
+                    .collect();
+                self.cache.lock().unwrap().insert(key, records.clone());
+
+                records
+            }
+        };
+
+        Ok(Some(records[local % RECORDS_PER_CHUNK].clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+    use common::frame::ChannelLayout;
+
+    fn format() -> FrameFormat {
+        FrameFormat::new(4, 3, ChannelLayout::Rgb).unwrap()
+    }
+
+    fn record(value: u8) -> MyConstData {
+        let mut image = MyImage::filled(format(), value);
+        *image.pixels.last_mut().unwrap() = value.wrapping_add(1);
+
+        MyConstData {
+            image,
+            keys_record: KeysRecordConst::from_slices(&[value], &[]),
+        }
+    }
+
+    /// Test records are appended across reopening and read back in order
+    #[test]
+    fn test_writer_appends_across_reopen() {
+        let dir = temp_dir("test_hdf5_writer_appends");
+        let path = dir.join(DATASET_FILE);
+        let options = WriterOptions {
+            chunk_size: 2,
+            compression: Compression::Deflate(4),
+            ..Default::default()
+        };
+
+        let mut writer = Hdf5Writer::open(&path, format(), options).unwrap();
+        assert!(writer.is_empty());
+        assert!(writer.last().unwrap().is_none());
+        assert_eq!(writer.append(&[(0, record(0)), (1, record(1))]).unwrap(), 2);
+        drop(writer);
+        let schema = read_schema(&path).unwrap();
+        assert_eq!((schema.version, schema.tagged), (SCHEMA_VERSION, true));
+
+        let mut writer = Hdf5Writer::open(&path, format(), options).unwrap();
+        assert_eq!(writer.len(), 2);
+        assert!(writer.contains(1));
+        let (index, last) = writer.last().unwrap().unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(last.image, record(1).image);
+        assert_eq!(last.keys_record.keys[0], 1);
+        assert_eq!(writer.append(&[(2, record(2))]).unwrap(), 1);
+        drop(writer);
+
+        let data = read_all_hdf5_files(&dir).unwrap();
+        let keys: Vec<u8> = data.iter().map(|data| data.keys_record.keys[0]).collect();
+        assert_eq!(keys, [0, 1, 2]);
+        for (value, data) in data.iter().enumerate() {
+            assert_eq!(data.image, record(value as u8).image);
+        }
+
+        // Кадры другого формата в тот же файл не пишутся
+        let other = FrameFormat::new(4, 3, ChannelLayout::Gray).unwrap();
+        assert!(Hdf5Writer::open(&path, other, options).is_err());
+        let mut writer = Hdf5Writer::open(&path, format(), options).unwrap();
+        let gray = MyConstData {
+            image: MyImage::filled(other, 0),
+            ..record(3)
+        };
+        assert!(writer.append(&[(3, gray)]).is_err());
+        assert_eq!(writer.len(), 3);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test the resize mode is stored with the frame format and checked on reopen
+    #[test]
+    fn test_writer_stores_resize() {
+        use common::frame::{Resize, ResizeFilter, ResizeMode};
+
+        let dir = temp_dir("test_hdf5_writer_resize");
+        let path = dir.join(DATASET_FILE);
+        let letterbox = format()
+            .with_resize(Resize {
+                mode: ResizeMode::Letterbox {
+                    color: [10, 20, 30, 255],
+                },
+                filter: ResizeFilter::CatmullRom,
+            })
+            .unwrap();
+
+        let mut writer = Hdf5Writer::open(&path, letterbox, WriterOptions::default()).unwrap();
+        let data = MyConstData {
+            image: MyImage::filled(letterbox, 1),
+            ..record(0)
+        };
+        writer.append(&[(0, data)]).unwrap();
+        drop(writer);
+
+        assert!(Hdf5Writer::open(&path, format(), WriterOptions::default()).is_err());
+        assert_eq!(Hdf5Reader::open(&dir, 1).unwrap().format(), letterbox);
+
+        for mode in [
+            ResizeMode::Stretch,
+            ResizeMode::Crop,
+            ResizeMode::Region {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 4,
+            },
+        ] {
+            let resize = Resize {
+                mode,
+                filter: ResizeFilter::Gaussian,
+            };
+            assert_eq!(decode_resize(encode_resize(resize)).unwrap(), resize);
+        }
+        assert!(decode_resize([9, 0, 0, 0, 0, 0]).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test the mouse trajectory length is stored apart from the format and must match on reopen
+    #[test]
+    fn test_writer_stores_mouse_bins() {
+        let dir = temp_dir("test_hdf5_writer_mouse_bins");
+        let path = dir.join(DATASET_FILE);
+        let coarse = WriterOptions {
+            aggregation: AggregationConfig::new(4).unwrap(),
+            ..Default::default()
+        };
+        assert!(AggregationConfig::new(0).is_err());
+        assert!(AggregationConfig::new(common::MOUSE_VECTOR_LENGTH + 1).is_err());
+
+        let mut writer = Hdf5Writer::open(&path, format(), coarse).unwrap();
+        writer.append(&[(0, record(0))]).unwrap();
+        drop(writer);
+
+        assert!(Hdf5Writer::open(&path, format(), WriterOptions::default()).is_err());
+        let reader = Hdf5Reader::open(&dir, 1).unwrap();
+        assert_eq!(reader.aggregation().mouse_bins, 4);
+        assert_eq!(reader.format(), format());
+        assert_eq!(read_schema(&path).unwrap().aggregation, coarse.aggregation);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test frames already ingested are not written twice
+    #[test]
+    fn test_writer_skips_ingested_frames() {
+        let dir = temp_dir("test_hdf5_writer_skips");
+
+        let records: Vec<_> = (0..3).map(|index| (index, record(index as u8))).collect();
+        assert_eq!(
+            append_to_dataset(&dir, &records[..2], format(), WriterOptions::default()).unwrap(),
+            2
+        );
+        assert_eq!(
+            append_to_dataset(&dir, &records, format(), WriterOptions::default()).unwrap(),
+            1
+        );
+        assert_eq!(
+            append_to_dataset(&dir, &records, format(), WriterOptions::default()).unwrap(),
+            0
+        );
+
+        assert_eq!(read_all_hdf5_files(&dir).unwrap().len(), 3);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test rows of an interrupted append without frame numbers are not read and are dropped on reopen
+    #[test]
+    fn test_writer_drops_interrupted_append() {
+        let dir = temp_dir("test_hdf5_writer_interrupted");
+        let path = dir.join(DATASET_FILE);
+
+        let mut writer = Hdf5Writer::open(&path, format(), WriterOptions::default()).unwrap();
+        writer.append(&[(5, record(5)), (6, record(6))]).unwrap();
+        drop(writer);
+
+        // Дозапись прервана после действий и кадров, до номеров
+        {
+            let group = File::open_rw(&path).unwrap().group("dir").unwrap();
+            group.dataset(DATA_PATH).unwrap().resize(3).unwrap();
+            let FrameFormat { width, height, .. } = format();
+            group
+                .dataset(IMAGES_PATH)
+                .unwrap()
+                .resize((3, format().channels(), height, width))
+                .unwrap();
+        }
+
+        let reader = Hdf5Reader::open(&dir, 1).unwrap();
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.read(1).unwrap().unwrap().keys_record.keys[0], 6);
+        drop(reader);
+
+        let mut writer = Hdf5Writer::open(&path, format(), WriterOptions::default()).unwrap();
+        assert_eq!(writer.len(), 2);
+        assert!(!writer.contains(0));
+        assert_eq!(writer.last().unwrap().unwrap().0, 6);
+        assert_eq!(writer.append(&[(7, record(7))]).unwrap(), 1);
+        drop(writer);
+
+        let schema = read_schema(&path).unwrap();
+        assert_eq!(schema.datasets[DATA_PATH][0], 3);
+        assert_eq!(schema.datasets[IMAGES_PATH][0], 3);
+        let keys: Vec<u8> = read_all_hdf5_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|data| data.keys_record.keys[0])
+            .collect();
+        assert_eq!(keys, [5, 6, 7]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test files with fewer actions or frames than frame numbers are refused
+    #[test]
+    fn test_reader_rejects_short_datasets() {
+        let dir = temp_dir("test_hdf5_reader_short");
+        let path = dir.join(DATASET_FILE);
+        let records: Vec<_> = (0..3).map(|index| (index, record(index as u8))).collect();
+        append_to_dataset(&dir, &records, format(), WriterOptions::default()).unwrap();
+
+        let reader = Hdf5Reader::open(&dir, 1).unwrap();
+        reader.validate().unwrap();
+        drop(reader);
+
+        {
+            let group = File::open_rw(&path).unwrap().group("dir").unwrap();
+            let FrameFormat { width, height, .. } = format();
+            group
+                .dataset(IMAGES_PATH)
+                .unwrap()
+                .resize((2, format().channels(), height, width))
+                .unwrap();
+        }
+        let Err(err) = Hdf5Reader::open(&dir, 1) else {
+            panic!("a file with missing frames should not be read");
+        };
+        assert!(err.to_string().contains(DATASET_FILE), "{err}");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test records are read on demand across files and blocks
+    #[test]
+    fn test_reader_reads_across_files() {
+        let dir = temp_dir("test_hdf5_reader");
+        let count = RECORDS_PER_CHUNK + 5;
+
+        let records: Vec<_> = (0..count)
+            .map(|index| (index, record(index as u8)))
+            .collect();
+        append_to_dataset(&dir.join("a"), &records, format(), WriterOptions::default()).unwrap();
+        append_to_dataset(
+            &dir.join("b"),
+            &records[..3],
+            format(),
+            WriterOptions::default(),
+        )
+        .unwrap();
+
+        let reader = Hdf5Reader::open(&dir, 1).unwrap();
+        assert_eq!(reader.len(), count + 3);
+        assert_eq!(reader.format(), format());
+
+        let key = |index| reader.read(index).unwrap().unwrap().keys_record.keys[0];
+        assert_eq!(key(RECORDS_PER_CHUNK + 1), (RECORDS_PER_CHUNK + 1) as u8);
+        assert_eq!(key(0), 0);
+        assert_eq!(key(count + 2), 2);
+        assert!(reader.read(count + 3).unwrap().is_none());
+
+        let all = read_all_hdf5_files(&dir).unwrap();
+        for (index, data) in all.iter().enumerate() {
+            assert_eq!(key(index), data.keys_record.keys[0]);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test windows stay inside one file and skip gaps in frame numbers
+    #[test]
+    fn test_reader_windows() {
+        let dir = temp_dir("test_hdf5_reader_windows");
+
+        // Кадр 3 потерян: участки 0..3 и 4..6
+        let records: Vec<_> = [0, 1, 2, 4, 5]
+            .into_iter()
+            .map(|index| (index, record(index as u8)))
+            .collect();
+        append_to_dataset(&dir.join("a"), &records, format(), WriterOptions::default()).unwrap();
+        append_to_dataset(
+            &dir.join("b"),
+            &records[..2],
+            format(),
+            WriterOptions::default(),
+        )
+        .unwrap();
+
+        let reader = Hdf5Reader::open(&dir, CACHED_BLOCKS).unwrap();
+
+        assert_eq!(reader.windows(1), (0..7).collect::<Vec<_>>());
+        assert_eq!(reader.windows(2), [0, 1, 3, 5]);
+        assert_eq!(reader.windows(3), [0]);
+        assert!(reader.windows(4).is_empty());
+
+        assert_eq!(reader.source(3), Some(("a", 4)));
+        assert_eq!(reader.source(6), Some(("b", 1)));
+        assert_eq!(reader.source(7), None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
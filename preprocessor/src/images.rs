@@ -1,67 +1,229 @@
 use std::{
     fmt, fs, io,
+    ops::Range,
     path::{Path, PathBuf},
 };
 
-use hdf5_metno::H5Type;
-use image::{DynamicImage, GenericImageView, RgbaImage};
+use common::frame::{ChannelLayout, FrameFormat, ResizeFilter, ResizeMode};
+use image::{
+    DynamicImage, GenericImageView, GrayImage, ImageFormat, RgbImage, Rgba, RgbaImage,
+    imageops::{self, FilterType},
+};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
+use crate::error::{ErrorPath, PreprocessError};
+
+#[derive(Debug)]
 pub struct ImageData {
     image_path: PathBuf,
 }
 
-fn load_image(image_data: &ImageData) -> DynamicImage {
-    image::open(&image_data.image_path).expect("Failed to open image")
+impl ImageData {
+    pub fn new(image_path: PathBuf) -> Self {
+        Self { image_path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.image_path
+    }
+}
+
+fn load_image(image_data: &ImageData) -> Result<DynamicImage, PreprocessError> {
+    image::open(&image_data.image_path).at(&image_data.image_path)
 }
 
-fn resize_image(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+pub fn resize_image(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
     image.resize_exact(width, height, image::imageops::FilterType::Lanczos3)
 }
 
-pub fn save_image(image: &DynamicImage, output_path: &PathBuf) {
-    image.save(output_path).expect("Failed to save image");
+fn filter_type(filter: ResizeFilter) -> FilterType {
+    match filter {
+        ResizeFilter::Nearest => FilterType::Nearest,
+        ResizeFilter::Triangle => FilterType::Triangle,
+        ResizeFilter::CatmullRom => FilterType::CatmullRom,
+        ResizeFilter::Gaussian => FilterType::Gaussian,
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+    }
 }
 
-pub fn load_images_from_directory(dir: &PathBuf) -> io::Result<Vec<ImageData>> {
-    let mut dataset = Vec::new();
+/// Приведение кадра к размеру формата способом из [`FrameFormat::resize`].
+/// Одно и то же преобразование применяют обработка, рекордер и инференс.
+/// Ошибка, если область кадра не попадает на кадр, см. [`FrameFormat::check_source`].
+pub fn transform_image(image: &DynamicImage, format: FrameFormat) -> io::Result<DynamicImage> {
+    let (width, height) = (format.width as u32, format.height as u32);
+    let filter = filter_type(format.resize.filter);
+    format.check_source(image.width(), image.height())?;
+
+    let image = match format.resize.mode {
+        ResizeMode::Stretch => image.resize_exact(width, height, filter),
+        ResizeMode::Crop => image.resize_to_fill(width, height, filter),
+        ResizeMode::Letterbox { color } => {
+            let fitted = image.resize(width, height, filter).to_rgba8();
+            let mut canvas = RgbaImage::from_pixel(width, height, Rgba(color));
+            let x = (width - fitted.width()) / 2;
+            let y = (height - fitted.height()) / 2;
+            imageops::overlay(&mut canvas, &fitted, x as i64, y as i64);
+
+            DynamicImage::ImageRgba8(canvas)
+        }
+        ResizeMode::Region {
+            x,
+            y,
+            width: region_width,
+            height: region_height,
+        } => {
+            // Область обрезается по краям кадра
+            image
+                .crop_imm(x, y, region_width, region_height)
+                .resize_exact(width, height, filter)
+        }
+    };
+
+    Ok(image)
+}
+
+pub fn save_image(image: &DynamicImage, output_path: &PathBuf) -> Result<(), PreprocessError> {
+    image.save(output_path).at(output_path)
+}
 
+/// Номер кадра — последнее число в имени файла: `image-10.png` -> 10,
+/// `1700000000123.png` (метка времени) -> 1700000000123
+pub fn frame_index(path: &Path) -> Option<usize> {
+    let stem = path.file_stem()?.to_str()?;
+    let end = stem.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = stem[..end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+
+    stem[start..end].parse().ok()
+}
+
+/// Кадры директории по возрастанию номеров и всё, что помешало их упорядочить
+#[derive(Debug, Default)]
+pub struct FrameScan {
+    /// Кадры по возрастанию номера
+    pub frames: Vec<(usize, ImageData)>,
+    /// Пропущенные номера между первым и последним кадром
+    pub missing: Vec<Range<usize>>,
+    /// Файлы с номером, который уже есть у другого кадра; в `frames` не входят
+    pub duplicates: Vec<PathBuf>,
+    /// Файлы, которые не являются изображениями или не содержат номера
+    pub skipped: Vec<PathBuf>,
+}
+
+impl FrameScan {
+    pub fn missing_count(&self) -> usize {
+        self.missing.iter().map(|range| range.len()).sum()
+    }
+
+    /// Номера идут подряд, лишних файлов нет
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.duplicates.is_empty() && self.skipped.is_empty()
+    }
+}
+
+impl fmt::Display for FrameScan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "кадров: {}, пропущено номеров: {}, повторов: {}, посторонних файлов: {}",
+            self.frames.len(),
+            self.missing_count(),
+            self.duplicates.len(),
+            self.skipped.len()
+        )?;
+
+        for range in &self.missing {
+            write!(f, "\n  пропуск: {}..{}", range.start, range.end)?;
+        }
+        for path in &self.duplicates {
+            write!(f, "\n  повтор: {}", path.display())?;
+        }
+        for path in &self.skipped {
+            write!(f, "\n  пропущен файл: {}", path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Кадры упорядочиваются по номеру из имени файла, см. [`frame_index`], а не по порядку
+/// `fs::read_dir`. Из файлов с одинаковым номером берётся первый по имени.
+pub fn scan_frames(dir: &Path) -> io::Result<FrameScan> {
+    let mut paths = Vec::new();
     for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
+        let path = entry?.path();
 
         if path.is_file() {
-            // Здесь вы можете определить, как извлекать метки из имени файла или структуры директорий
-            dataset.push(ImageData { image_path: path });
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut scan = FrameScan::default();
+    let mut numbered = Vec::new();
+    for path in paths {
+        match frame_index(&path) {
+            Some(index) if ImageFormat::from_path(&path).is_ok() => numbered.push((index, path)),
+            _ => scan.skipped.push(path),
         }
     }
+    // Сортировка устойчивая: при равных номерах остаётся порядок имён
+    numbered.sort_by_key(|(index, _)| *index);
+
+    for (index, path) in numbered {
+        let last = scan.frames.last().map(|(last, _)| *last);
+
+        if last == Some(index) {
+            scan.duplicates.push(path);
+            continue;
+        }
+        if let Some(last) = last
+            && index > last + 1
+        {
+            scan.missing.push(last + 1..index);
+        }
+
+        scan.frames.push((index, ImageData { image_path: path }));
+    }
 
-    Ok(dataset)
+    Ok(scan)
+}
+
+/// Кадры директории по возрастанию номеров, см. [`scan_frames`]
+pub fn load_images_from_directory(dir: &Path) -> io::Result<Vec<ImageData>> {
+    let scan = scan_frames(dir)?;
+
+    Ok(scan.frames.into_iter().map(|(_, data)| data).collect())
 }
 
 pub fn process_images(
-    input_dir: &PathBuf,
-    output_dir: &PathBuf,
-    width: u32,
-    height: u32,
-) -> io::Result<()> {
-    let dataset = load_images_from_directory(input_dir)?;
+    input_dir: &Path,
+    output_dir: &Path,
+    format: FrameFormat,
+) -> Result<(), PreprocessError> {
+    let scan = scan_frames(input_dir).at(input_dir)?;
+    if !scan.is_complete() {
+        println!("{}: {}", input_dir.display(), scan);
+    }
+    let dataset: Vec<ImageData> = scan.frames.into_iter().map(|(_, data)| data).collect();
 
     // Создаем выходную директорию, если она не существует
-    fs::create_dir_all(output_dir)?;
+    fs::create_dir_all(output_dir).at(output_dir)?;
 
     let finish = dataset.len();
     let counter = std::sync::Arc::new(std::sync::Mutex::new(0));
 
-    dataset.par_iter().for_each(|data| {
+    // Обработка останавливается на первом кадре, который не читается или не сохраняется
+    dataset.par_iter().try_for_each(|data| {
         // Создаем путь для сохранения измененного изображения
         let output_path = Path::new(output_dir).join(data.image_path.file_name().unwrap());
 
         if output_path.metadata().is_err() {
-            let image = load_image(&data);
-            let resized_image = resize_image(&DynamicImage::from(image), width, height); // Изменяем размер до заданных параметров
+            let image = load_image(data)?;
+            let resized_image = transform_image(&image, format).at(&data.image_path)?; // Изменяем размер до заданных параметров
 
-            save_image(&resized_image, &output_path);
+            save_image(&resized_image, &output_path)?;
 
             let mut count = counter.lock().unwrap();
             *count += 1;
@@ -73,7 +235,9 @@ pub fn process_images(
                 );
             }
         }
-    });
+
+        Ok(())
+    })?;
 
     let total_processed = *counter.lock().unwrap();
     println!("Было преобразвано {} изображений", total_processed);
@@ -81,89 +245,115 @@ pub fn process_images(
     Ok(())
 }
 
-#[derive(Clone, Copy, H5Type)]
-#[repr(C)]
-pub struct MyImage<const HEIGHT: usize, const WIDTH: usize, const CHANNELS: usize> {
-    pub pixels: [[[u8; HEIGHT]; WIDTH]; CHANNELS],
+/// Кадр датасета. Значения идут по каналам, затем по строкам и столбцам
+/// (`[канал][y][x]`) — в том порядке, в котором их принимает модель.
+#[derive(Clone, PartialEq)]
+pub struct MyImage {
+    pub format: FrameFormat,
+    pub pixels: Vec<u8>,
 }
 
-impl<const H: usize, const W: usize, const C: usize> fmt::Debug for MyImage<H, W, C> {
+impl fmt::Debug for MyImage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // Здесь вы можете настроить вывод по своему усмотрению
-        write!(f, "Image[{} x {}]({})", H, W, C)
+        write!(
+            f,
+            "Image[{} x {}]({})",
+            self.format.height,
+            self.format.width,
+            self.format.channels()
+        )
     }
 }
 
-impl<const H: usize, const W: usize, const C: usize> MyImage<H, W, C> {
-    // pub fn from_image(image: &DynamicImage) -> Self {
-    //     let image = image.to_rgba8();
-    //     let mut pixels: [[[u8; W]; H]; C] = [[[0; W]; H]; C];
-
-    //     for (i, pixel) in image.pixels().enumerate() {
-    //         let height = (i / C / W) % H;
-    //         let width = (i / C) % W;
-
-    //         for (color_index, color) in pixel.0.iter().enumerate() {
-    //             pixels[color_index][height][width] = *color;
-    //         }
-    //     }
+impl MyImage {
+    /// Кадр, заполненный одним значением
+    pub fn filled(format: FrameFormat, value: u8) -> Self {
+        Self {
+            format,
+            pixels: vec![value; format.pixel_count()],
+        }
+    }
 
-    //     MyImage { pixels }
-    // }
+    pub fn from_pixels(format: FrameFormat, pixels: Vec<u8>) -> io::Result<Self> {
+        if pixels.len() != format.pixel_count() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Кадр {:?}: ожидалось {} значений, получено {}",
+                    format,
+                    format.pixel_count(),
+                    pixels.len()
+                ),
+            ));
+        }
 
-    // pub fn to_image(&self) -> RgbaImage {
-    //     let mut img = RgbaImage::new(W as u32, H as u32);
+        Ok(Self { format, pixels })
+    }
 
-    //     for i in 0..H {
-    //         for j in 0..W {
-    //             let pixel = Rgb([
-    //                 self.pixels[0][i][j],
-    //                 self.pixels[1][i][j],
-    //                 self.pixels[2][i][j],
-    //             ]);
+    pub fn pixel(&self, channel: usize, y: usize, x: usize) -> u8 {
+        let FrameFormat { width, height, .. } = self.format;
 
-    //             img.put_pixel(j as u32, i as u32, pixel);
-    //         }
-    //     }
+        self.pixels[(channel * height + y) * width + x]
+    }
 
-    //     img
-    // }
+    /// Изображение другого размера сначала приводится к размеру формата, см. [`transform_image`]
+    pub fn from_image(image: &DynamicImage, format: FrameFormat) -> io::Result<Self> {
+        let resized;
+        let image = if image.dimensions() == (format.width as u32, format.height as u32) {
+            image
+        } else {
+            resized = transform_image(image, format)?;
+            &resized
+        };
 
-    pub fn from_image(image: &DynamicImage) -> Self {
-        let mut pixels = [[[0; H]; W]; C];
+        let interleaved = match format.layout {
+            ChannelLayout::Gray => image.to_luma8().into_raw(),
+            ChannelLayout::Rgb => image.to_rgb8().into_raw(),
+            ChannelLayout::Rgba => image.to_rgba8().into_raw(),
+        };
 
-        for (width, height, colors) in image.pixels() {
-            for (i, color) in colors.0.iter().enumerate() {
-                pixels[i][width as usize][height as usize] = *color;
-            }
+        let channels = format.channels();
+        let plane = format.width * format.height;
+        let mut pixels = vec![0; format.pixel_count()];
+        for (i, value) in interleaved.into_iter().enumerate() {
+            pixels[(i % channels) * plane + i / channels] = value;
         }
 
-        MyImage { pixels }
+        Ok(MyImage { format, pixels })
     }
 
     pub fn to_image(&self) -> DynamicImage {
-        let mut img = RgbaImage::new(W as u32, H as u32);
-
-        for i in 0..W {
-            for j in 0..H {
-                let pixel = image::Rgba([
-                    self.pixels[0][i][j],
-                    self.pixels[1][i][j],
-                    self.pixels[2][i][j],
-                    self.pixels[3][i][j],
-                ]);
-
-                img.put_pixel(i as u32, j as u32, pixel);
-            }
+        let FrameFormat { width, height, .. } = self.format;
+        let channels = self.format.channels();
+        let plane = width * height;
+
+        let mut interleaved = vec![0; self.pixels.len()];
+        for (i, value) in interleaved.iter_mut().enumerate() {
+            *value = self.pixels[(i % channels) * plane + i / channels];
         }
 
-        image::DynamicImage::ImageRgba8(img)
+        let (width, height) = (width as u32, height as u32);
+        match self.format.layout {
+            ChannelLayout::Gray => {
+                DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, interleaved).unwrap())
+            }
+            ChannelLayout::Rgb => {
+                DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, interleaved).unwrap())
+            }
+            ChannelLayout::Rgba => {
+                DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, interleaved).unwrap())
+            }
+        }
     }
 
-    pub fn from_image_data(image_data: &ImageData) -> MyImage<H, W, C> {
-        let image = load_image(image_data);
+    pub fn from_image_data(
+        image_data: &ImageData,
+        format: FrameFormat,
+    ) -> Result<MyImage, PreprocessError> {
+        let image = load_image(image_data)?;
 
-        MyImage::from_image(&image)
+        MyImage::from_image(&image, format).at(image_data.path())
     }
 }
 
@@ -188,14 +378,13 @@ impl<const H: usize, const W: usize, const C: usize> MyImage<H, W, C> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::temp_dir;
     use std::io::Write;
 
     /// Test load_images_from_directory with empty directory
     #[test]
     fn test_load_images_from_directory_empty() {
-        let temp_dir = std::env::temp_dir().join("test_empty_images");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&temp_dir).unwrap();
+        let temp_dir = temp_dir("test_empty_images");
 
         let result = load_images_from_directory(&temp_dir);
 
@@ -209,9 +398,7 @@ mod tests {
     /// Test load_images_from_directory ignores subdirectories
     #[test]
     fn test_load_images_from_directory_ignores_dirs() {
-        let temp_dir = std::env::temp_dir().join("test_dir_ignores");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&temp_dir).unwrap();
+        let temp_dir = temp_dir("test_dir_ignores");
 
         // Create a subdirectory with files (should be ignored)
         let subdir = temp_dir.join("subdir");
@@ -226,14 +413,72 @@ mod tests {
         let _ = fs::remove_dir_all(&temp_dir);
     }
 
+    /// Test frame numbers are parsed from the last number in the file name
+    #[test]
+    fn test_frame_index() {
+        assert_eq!(frame_index(Path::new("image-10.png")), Some(10));
+        assert_eq!(frame_index(Path::new("dir/frame_0007.png")), Some(7));
+        assert_eq!(
+            frame_index(Path::new("1700000000123.png")),
+            Some(1_700_000_000_123)
+        );
+        assert_eq!(frame_index(Path::new("shot-3-final.png")), Some(3));
+        assert_eq!(frame_index(Path::new("image.png")), None);
+        assert_eq!(frame_index(Path::new("notes.txt")), None);
+    }
+
+    /// Test frames are sorted numerically and gaps, duplicates and stray files are reported
+    #[test]
+    fn test_scan_frames_order_and_report() {
+        let temp_dir = temp_dir("test_scan_frames");
+
+        for name in [
+            "image-10.png",
+            "image-2.png",
+            "image-1.png",
+            "image-3.png",
+            "image-02.png",
+            "image-11.png",
+        ] {
+            DynamicImage::new_rgb8(2, 2)
+                .save(temp_dir.join(name))
+                .unwrap();
+        }
+        fs::File::create(temp_dir.join("notes.txt"))
+            .unwrap()
+            .write_all(b"1")
+            .unwrap();
+        fs::File::create(temp_dir.join("cover.png")).unwrap();
+
+        let scan = scan_frames(&temp_dir).unwrap();
+
+        let indices: Vec<usize> = scan.frames.iter().map(|(index, _)| *index).collect();
+        assert_eq!(indices, [1, 2, 3, 10, 11]);
+        assert_eq!(
+            scan.frames[1].1.image_path.file_name().unwrap(),
+            "image-02.png"
+        );
+        assert_eq!(scan.missing, vec![4..10]);
+        assert_eq!(scan.missing_count(), 6);
+        assert_eq!(scan.duplicates, [temp_dir.join("image-2.png")]);
+        assert_eq!(
+            scan.skipped,
+            [temp_dir.join("cover.png"), temp_dir.join("notes.txt")]
+        );
+        assert!(!scan.is_complete());
+        assert!(scan.to_string().contains("пропуск: 4..10"));
+
+        let loaded = load_images_from_directory(&temp_dir).unwrap();
+        assert_eq!(loaded.len(), 5);
+        assert_eq!(loaded[3].image_path.file_name().unwrap(), "image-10.png");
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
     /// Test MyImage::from_image_data with small test image
     #[test]
     fn test_my_image_debug_format() {
-        use common::{CHANNELS, HEIGHT, WIDTH};
-
-        let image: MyImage<HEIGHT, WIDTH, CHANNELS> = MyImage {
-            pixels: [[[0; HEIGHT]; WIDTH]; CHANNELS],
-        };
+        let image = MyImage::filled(FrameFormat::default(), 0);
 
         let debug_str = format!("{:?}", image);
         assert!(debug_str.contains("Image"));
@@ -242,16 +487,13 @@ mod tests {
     /// Test MyImage creation with custom dimensions
     #[test]
     fn test_my_image_custom_dimensions() {
-        const H: usize = 64;
-        const W: usize = 64;
-        const C: usize = 4;
+        let format = FrameFormat::new(64, 64, ChannelLayout::Rgba).unwrap();
 
-        let image: MyImage<H, W, C> = MyImage {
-            pixels: [[[1; H]; W]; C],
-        };
+        let image = MyImage::filled(format, 1);
 
         // Check first pixel channel value
-        assert_eq!(image.pixels[0][0][0], 1);
+        assert_eq!(image.pixel(0, 0, 0), 1);
+        assert_eq!(image.pixels.len(), 64 * 64 * 4);
 
         let debug_str = format!("{:?}", image);
         assert!(debug_str.contains("64"));
@@ -260,38 +502,85 @@ mod tests {
     /// Test MyImage pixel array bounds
     #[test]
     fn test_my_image_pixel_array_bounds() {
-        const H: usize = 32;
-        const W: usize = 32;
-        const C: usize = 4;
+        let format = FrameFormat::new(32, 16, ChannelLayout::Rgba).unwrap();
 
         // Initialize with zeros
-        let image: MyImage<H, W, C> = MyImage {
-            pixels: [[[0; H]; W]; C],
-        };
+        let mut image = MyImage::filled(format, 0);
 
-        // Set some values
-        let mut image_mut = image;
-        image_mut.pixels[0][0][0] = 255;
-        image_mut.pixels[3][31][31] = 128;
+        // Set first and last values
+        image.pixels[0] = 255;
+        *image.pixels.last_mut().unwrap() = 128;
 
         // Verify values were set correctly
-        assert_eq!(image_mut.pixels[0][0][0], 255);
-        assert_eq!(image_mut.pixels[3][31][31], 128);
+        assert_eq!(image.pixel(0, 0, 0), 255);
+        assert_eq!(image.pixel(3, 15, 31), 128);
         // Verify other values are still 0
-        assert_eq!(image_mut.pixels[1][0][0], 0);
+        assert_eq!(image.pixel(1, 0, 0), 0);
+
+        assert!(MyImage::from_pixels(format, vec![0; 10]).is_err());
+    }
+
+    /// Test conversion to and from DynamicImage for every channel layout
+    #[test]
+    fn test_my_image_layouts_round_trip() {
+        let mut source = RgbaImage::new(6, 4);
+        for (x, y, pixel) in source.enumerate_pixels_mut() {
+            *pixel = image::Rgba([x as u8 * 40, y as u8 * 60, 200, 255]);
+        }
+        let source = DynamicImage::ImageRgba8(source);
+
+        for layout in [ChannelLayout::Gray, ChannelLayout::Rgb, ChannelLayout::Rgba] {
+            let format = FrameFormat::new(6, 4, layout).unwrap();
+
+            let image = MyImage::from_image(&source, format).unwrap();
+            assert_eq!(image.pixels.len(), 6 * 4 * layout.channels());
+
+            let restored = MyImage::from_image(&image.to_image(), format).unwrap();
+            assert_eq!(restored, image);
+        }
+
+        let rgba = MyImage::from_image(
+            &source,
+            FrameFormat::new(6, 4, ChannelLayout::Rgba).unwrap(),
+        )
+        .unwrap();
+        // Канал красного — первая плоскость, значения идут по строкам
+        assert_eq!(rgba.pixel(0, 2, 3), 120);
+        assert_eq!(rgba.pixel(1, 2, 3), 120);
+        assert_eq!(rgba.pixel(2, 0, 0), 200);
+
+        // Изображение другого размера приводится к формату
+        let small =
+            MyImage::from_image(&source, FrameFormat::new(3, 2, ChannelLayout::Rgb).unwrap())
+                .unwrap();
+        assert_eq!(small.to_image().dimensions(), (3, 2));
+    }
+
+    /// Test a corrupt frame is an error naming the file instead of a panic
+    #[test]
+    fn test_from_image_data_corrupt_file() {
+        let temp_dir = temp_dir("test_corrupt_frame");
+
+        let path = temp_dir.join("image-0.png");
+        fs::write(&path, b"not a png").unwrap();
+
+        let err = MyImage::from_image_data(&ImageData::new(path.clone()), FrameFormat::default())
+            .unwrap_err();
+        assert!(matches!(err, PreprocessError::Image { .. }));
+        assert_eq!(err.path(), path);
+
+        let _ = fs::remove_dir_all(&temp_dir);
     }
 
     /// Test save_image creates file
     #[test]
     fn test_save_image_creates_file() {
-        let temp_dir = std::env::temp_dir().join("test_save_image");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&temp_dir).unwrap();
+        let temp_dir = temp_dir("test_save_image");
 
         let image = DynamicImage::new_rgb8(10, 10);
         let output_path = temp_dir.join("test.png");
 
-        save_image(&image, &output_path);
+        save_image(&image, &output_path).unwrap();
 
         assert!(output_path.exists());
 
@@ -329,4 +618,113 @@ mod tests {
         assert_eq!(resized_same.width(), 200);
         assert_eq!(resized_same.height(), 100);
     }
+
+    /// Test crop, letterbox and region modes keep the format size and pick the right pixels
+    #[test]
+    fn test_transform_image_modes() {
+        use common::frame::Resize;
+
+        // Wide 8x4 frame: left half red, right half blue
+        let mut source = RgbaImage::new(8, 4);
+        for (x, _, pixel) in source.enumerate_pixels_mut() {
+            *pixel = if x < 4 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            };
+        }
+        let source = DynamicImage::ImageRgba8(source);
+        let format = |mode| {
+            FrameFormat::new(4, 4, ChannelLayout::Rgba)
+                .unwrap()
+                .with_resize(Resize {
+                    mode,
+                    filter: ResizeFilter::Nearest,
+                })
+                .unwrap()
+        };
+
+        let crop = transform_image(&source, format(ResizeMode::Crop))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(crop.dimensions(), (4, 4));
+        assert_eq!(crop.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(crop.get_pixel(3, 3), &Rgba([0, 0, 255, 255]));
+
+        let color = [0, 255, 0, 255];
+        let letterbox = transform_image(&source, format(ResizeMode::Letterbox { color }))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(letterbox.dimensions(), (4, 4));
+        assert_eq!(letterbox.get_pixel(0, 0), &Rgba(color));
+        assert_eq!(letterbox.get_pixel(0, 3), &Rgba(color));
+        assert_eq!(letterbox.get_pixel(0, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(letterbox.get_pixel(3, 2), &Rgba([0, 0, 255, 255]));
+
+        let region = ResizeMode::Region {
+            x: 4,
+            y: 0,
+            width: 4,
+            height: 4,
+        };
+        let region = transform_image(&source, format(region)).unwrap().to_rgba8();
+        assert!(
+            region
+                .pixels()
+                .all(|pixel| pixel == &Rgba([0, 0, 255, 255]))
+        );
+
+        // Область, выступающая за край, обрезается по кадру
+        let partly = ResizeMode::Region {
+            x: 6,
+            y: 0,
+            width: 100,
+            height: 100,
+        };
+        let partly = transform_image(&source, format(partly)).unwrap().to_rgba8();
+        assert_eq!(partly.dimensions(), (4, 4));
+        assert_eq!(partly.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+
+        // Область за пределами кадра — ошибка, а не подмена всем кадром
+        let outside = ResizeMode::Region {
+            x: 100,
+            y: 100,
+            width: 4,
+            height: 4,
+        };
+        assert!(transform_image(&source, format(outside)).is_err());
+        assert!(format(outside).check_source(8, 4).is_err());
+        assert!(format(outside).check_source(101, 101).is_ok());
+        assert!(MyImage::from_image(&source, format(outside)).is_err());
+
+        let overflow = ResizeMode::Region {
+            x: u32::MAX,
+            y: 0,
+            width: 4,
+            height: 4,
+        };
+        assert!(
+            FrameFormat::default()
+                .with_resize(Resize {
+                    mode: overflow,
+                    filter: ResizeFilter::Nearest,
+                })
+                .is_err()
+        );
+
+        let empty = ResizeMode::Region {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 4,
+        };
+        assert!(
+            FrameFormat::default()
+                .with_resize(Resize {
+                    mode: empty,
+                    filter: ResizeFilter::Nearest,
+                })
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,427 @@
+//! Сводка по записанным датасетам: сколько кадров в каждой сессии, какие клавиши
+//! нажимались, как двигалась мышь, доля кадров без действий, яркость каналов
+//! и устройство файлов. Выводится текстом или в JSON, чтобы сравнивать датасеты
+//! перед обучением.
+
+use std::{collections::BTreeMap, fmt, io, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    filtering::is_idle,
+    hdf5_processing::{
+        CACHED_BLOCKS, DatasetSchema, Hdf5Reader, dataset_name, find_hdf5_files, read_schema,
+    },
+    types::MyConstData,
+    vocabulary::{KEY_PADDING, KeyVocabulary},
+};
+
+/// Нижние границы корзин гистограммы длины смещения мыши за кадр, в пикселях
+pub const DELTA_BINS: [u32; 10] = [0, 1, 2, 4, 8, 16, 32, 64, 128, 256];
+
+/// Среднее, стандартное отклонение и пределы значений
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub mean: f64,
+    pub std: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "среднее {:.2}, σ {:.2}, от {} до {}",
+            self.mean, self.std, self.min, self.max
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Moments {
+    count: u64,
+    sum: f64,
+    sum_sq: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for Moments {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            sum_sq: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Moments {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.sum_sq += value * value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn summary(&self) -> Summary {
+        if self.count == 0 {
+            return Summary::default();
+        }
+
+        let mean = self.sum / self.count as f64;
+        let variance = (self.sum_sq / self.count as f64 - mean * mean).max(0.0);
+
+        Summary {
+            mean,
+            std: variance.sqrt(),
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+/// Корзина гистограммы: значения от `from` до начала следующей корзины
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HistogramBin {
+    pub from: u32,
+    pub count: usize,
+}
+
+/// Статистика записей
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RecordStats {
+    pub records: usize,
+    /// Кадры без нажатий, удержаний и движения мыши, см. [`is_idle`]
+    pub idle: usize,
+    pub idle_fraction: f64,
+    /// В скольких кадрах была нажата или удерживалась клавиша, по именам из словаря
+    pub keys: BTreeMap<String, usize>,
+    /// Смещение мыши за кадр по осям
+    pub mouse_x: Summary,
+    pub mouse_y: Summary,
+    /// Длина смещения мыши за кадр, корзины [`DELTA_BINS`]
+    pub mouse_distance: Vec<HistogramBin>,
+    /// Значения пикселей (0–255) по каналам
+    pub channels: Vec<Summary>,
+}
+
+impl RecordStats {
+    /// Клавиши по убыванию числа кадров
+    pub fn top_keys(&self) -> Vec<(&str, usize)> {
+        let mut keys: Vec<(&str, usize)> = self
+            .keys
+            .iter()
+            .map(|(name, count)| (name.as_str(), *count))
+            .collect();
+        keys.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        keys
+    }
+}
+
+impl fmt::Display for RecordStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "  записей: {}", self.records)?;
+        writeln!(
+            f,
+            "  без действий: {} ({:.1}%)",
+            self.idle,
+            self.idle_fraction * 100.0
+        )?;
+        writeln!(f, "  мышь x: {}", self.mouse_x)?;
+        writeln!(f, "  мышь y: {}", self.mouse_y)?;
+
+        let distance: Vec<String> = self
+            .mouse_distance
+            .iter()
+            .map(|bin| format!("{}+: {}", bin.from, bin.count))
+            .collect();
+        writeln!(f, "  длина смещения: {}", distance.join(", "))?;
+
+        for (channel, summary) in self.channels.iter().enumerate() {
+            writeln!(f, "  канал {channel}: {summary}")?;
+        }
+
+        let keys: Vec<String> = self
+            .top_keys()
+            .into_iter()
+            .map(|(name, count)| format!("{name} {count}"))
+            .collect();
+        write!(f, "  клавиши: {}", keys.join(", "))
+    }
+}
+
+/// Накопление [`RecordStats`] по одной записи
+#[derive(Clone, Debug, Default)]
+struct StatsBuilder {
+    records: usize,
+    idle: usize,
+    keys: BTreeMap<u8, usize>,
+    mouse: [Moments; 2],
+    distance: [usize; DELTA_BINS.len()],
+    channels: Vec<Moments>,
+}
+
+impl StatsBuilder {
+    fn add(&mut self, record: &MyConstData) {
+        let keys_record = &record.keys_record;
+        self.records += 1;
+
+        if is_idle(keys_record) {
+            self.idle += 1;
+        }
+
+        let mut keys: Vec<u8> = keys_record
+            .keys
+            .iter()
+            .copied()
+            .filter(|key| *key != KEY_PADDING)
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        for key in keys {
+            *self.keys.entry(key).or_default() += 1;
+        }
+
+        let [x, y] = keys_record.delta.map(f64::from);
+        self.mouse[0].add(x);
+        self.mouse[1].add(y);
+        let distance = x.hypot(y);
+        let bin = DELTA_BINS.partition_point(|from| *from as f64 <= distance) - 1;
+        self.distance[bin] += 1;
+
+        let image = &record.image;
+        let channels = image.format.channels();
+        if self.channels.len() < channels {
+            self.channels.resize(channels, Moments::default());
+        }
+        let plane = image.format.width * image.format.height;
+        for (channel, moments) in self.channels.iter_mut().take(channels).enumerate() {
+            for value in &image.pixels[channel * plane..(channel + 1) * plane] {
+                moments.add(*value as f64);
+            }
+        }
+    }
+
+    fn finish(&self, vocabulary: &KeyVocabulary) -> RecordStats {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(id, count)| {
+                let name = vocabulary
+                    .num_to_key(*id)
+                    .map_or_else(|| format!("#{id}"), str::to_string);
+                (name, *count)
+            })
+            .collect();
+
+        RecordStats {
+            records: self.records,
+            idle: self.idle,
+            idle_fraction: if self.records == 0 {
+                0.0
+            } else {
+                self.idle as f64 / self.records as f64
+            },
+            keys,
+            mouse_x: self.mouse[0].summary(),
+            mouse_y: self.mouse[1].summary(),
+            mouse_distance: DELTA_BINS
+                .iter()
+                .zip(self.distance)
+                .map(|(from, count)| HistogramBin { from: *from, count })
+                .collect(),
+            channels: self.channels.iter().map(Moments::summary).collect(),
+        }
+    }
+}
+
+/// Статистика одного файла датасета, обычно одной сессии
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SessionStats {
+    /// Имя файла, обычно номер сессии, см. [`dataset_name`]
+    pub name: String,
+    pub schema: DatasetSchema,
+    /// Участков кадров без пропусков в номерах
+    pub runs: usize,
+    pub stats: RecordStats,
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = self.schema.format;
+        let version = if self.schema.tagged {
+            self.schema.version.to_string()
+        } else {
+            format!("{} (не записана в файле)", self.schema.version)
+        };
+        let datasets: Vec<String> = self
+            .schema
+            .datasets
+            .iter()
+            .map(|(name, shape)| format!("{name} {shape:?}"))
+            .collect();
+
+        writeln!(f, "{}", self.name)?;
+        writeln!(
+            f,
+            "  схема: версия {}, кадры {}×{} {:?}, {:?}, наборы: {}",
+            version,
+            format.width,
+            format.height,
+            format.layout,
+            format.resize.mode,
+            datasets.join(", ")
+        )?;
+        writeln!(f, "  участков без пропусков: {}", self.runs)?;
+        write!(f, "{}", self.stats)
+    }
+}
+
+/// Сводка по датасету: по файлам и по всем записям вместе
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DatasetStats {
+    pub sessions: Vec<SessionStats>,
+    pub total: RecordStats,
+}
+
+impl DatasetStats {
+    pub fn to_json(&self) -> io::Result<String> {
+        serde_json::to_string_pretty(self).map_err(io::Error::other)
+    }
+}
+
+impl fmt::Display for DatasetStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for session in &self.sessions {
+            writeln!(f, "{session}")?;
+            writeln!(f)?;
+        }
+
+        writeln!(f, "Всего файлов: {}", self.sessions.len())?;
+        write!(f, "{}", self.total)
+    }
+}
+
+/// Сводка по всем файлам датасета в `data_path` (директория или отдельный файл).
+/// Имена клавиш берутся из `vocabulary`.
+pub fn inspect_dataset(data_path: &Path, vocabulary: &KeyVocabulary) -> io::Result<DatasetStats> {
+    let mut sessions = Vec::new();
+    let mut total = StatsBuilder::default();
+
+    for path in find_hdf5_files(data_path)? {
+        let schema = read_schema(&path)?;
+        let reader = Hdf5Reader::open(&path, CACHED_BLOCKS)?;
+
+        let mut stats = StatsBuilder::default();
+        for index in 0..reader.len() {
+            if let Some(record) = reader.read(index)? {
+                stats.add(&record);
+                total.add(&record);
+            }
+        }
+
+        sessions.push(SessionStats {
+            name: dataset_name(data_path, &path),
+            schema,
+            runs: reader.runs().len(),
+            stats: stats.finish(vocabulary),
+        });
+    }
+
+    Ok(DatasetStats {
+        sessions,
+        total: total.finish(vocabulary),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        csv_processing::KeysRecordConst,
+        hdf5_processing::{DATASET_FILE, WriterOptions, append_to_dataset},
+        images::MyImage,
+        temp_dir,
+    };
+    use common::frame::{ChannelLayout, FrameFormat};
+    use std::fs;
+
+    fn record(keys: &[u8], delta: [i32; 2], value: u8) -> MyConstData {
+        let format = FrameFormat::new(2, 2, ChannelLayout::Rgb).unwrap();
+        MyConstData {
+            image: MyImage::filled(format, value),
+            keys_record: KeysRecordConst::from_slices(keys, &[delta]),
+        }
+    }
+
+    /// Test per-session and total statistics, text and JSON output
+    #[test]
+    fn test_inspect_dataset() {
+        let dir = temp_dir("test_inspect_dataset");
+        let format = FrameFormat::new(2, 2, ChannelLayout::Rgb).unwrap();
+        let vocabulary = KeyVocabulary::default();
+        let w = vocabulary.key_to_num("KeyW");
+
+        append_to_dataset(
+            &dir.join("a"),
+            &[
+                (0, record(&[w], [3, 4], 10)),
+                (1, record(&[], [0, 0], 30)),
+                (5, record(&[w], [0, 0], 20)),
+            ],
+            format,
+            WriterOptions::default(),
+        )
+        .unwrap();
+        append_to_dataset(
+            &dir.join("b"),
+            &[(0, record(&[], [0, 0], 0))],
+            format,
+            WriterOptions::default(),
+        )
+        .unwrap();
+        // Прочие файлы в директории датасета не читаются
+        fs::write(dir.join("a").join("filter_report.json"), "{}").unwrap();
+
+        let stats = inspect_dataset(&dir, &vocabulary).unwrap();
+
+        assert_eq!(stats.sessions.len(), 2);
+        let a = &stats.sessions[0];
+        assert_eq!(a.name, "a");
+        assert_eq!(a.runs, 2);
+        assert_eq!(a.schema.format, format);
+        assert_eq!(a.schema.datasets["images"], [3, 3, 2, 2]);
+        assert_eq!(a.stats.records, 3);
+        assert_eq!(a.stats.idle, 1);
+        assert_eq!(a.stats.keys["KeyW"], 2);
+        assert_eq!(a.stats.mouse_x.max, 3.0);
+        // Смещение (3, 4) длиной 5 попадает в корзину 4+
+        assert_eq!(a.stats.mouse_distance[0].count, 2);
+        assert_eq!(a.stats.mouse_distance[3].count, 1);
+        assert_eq!(a.stats.channels.len(), 3);
+        assert_eq!(a.stats.channels[0].mean, 20.0);
+
+        assert_eq!(stats.total.records, 4);
+        assert_eq!(stats.total.idle_fraction, 0.5);
+        assert_eq!(stats.total.channels[2].min, 0.0);
+
+        let text = stats.to_string();
+        assert!(text.contains("без действий: 2 (50.0%)"));
+        assert!(text.contains("клавиши: KeyW 2"));
+
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json().unwrap()).unwrap();
+        assert_eq!(json["total"]["records"], 4);
+        assert_eq!(json["sessions"][0]["stats"]["keys"]["KeyW"], 2);
+
+        let single = inspect_dataset(&dir.join("b").join(DATASET_FILE), &vocabulary).unwrap();
+        assert_eq!(single.total.records, 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,17 +1,42 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use csv_processing::load_records_from_directory;
-
-use common::*;
-use hdf5_processing::{read_all_hdf5_files, write_data_to_hdf5_files};
-use images::{MyImage, load_images_from_directory, process_images};
+use alignment::{FrameStamp, align_events};
+use common::{
+    aggregation::{AGGREGATION_CONFIG_FILE, AggregationConfig},
+    frame::{FRAME_FORMAT_FILE, FrameFormat},
+    *,
+};
+use error::{ErrorPath, PreprocessError};
+use event_log::{LEGACY_FRAME_INTERVAL_US, import_key_events_csv, load_event_log};
+use filtering::{FILTER_REPORT_FILE, FRAME_FILTER_FILE, FilterConfig, FilterReport, FrameFilter};
+use hdf5_processing::{
+    DATASET_FILE, Hdf5Writer, LEGACY_DATASET_DIR, WriterOptions, append_to_dataset,
+};
+use images::{MyImage, process_images, scan_frames};
+use inspection::inspect_dataset;
+use sessions::discover_sessions;
 use types::MyConstData;
+use vocabulary::{KEY_VOCABULARY_FILE, KeyVocabulary};
 // use videos::process_videos;
 
+pub mod aggregation;
+pub mod alignment;
 pub mod csv_processing;
+pub mod doctor;
+pub mod error;
+pub mod event_log;
+pub mod filtering;
 pub mod hdf5_processing;
 pub mod images;
+pub mod inspection;
+pub mod migration;
+pub mod portable;
+pub mod sessions;
 pub mod types;
+pub mod vocabulary;
 // mod videos;
 
 // pub fn process_my_videos() {
@@ -20,41 +45,225 @@ pub mod types;
 //     process_videos("data/videos/video.mp4", "data/images/raw/");
 // }
 
-pub fn process_my_images() {
-    let input_dir = &PathBuf::from_str("data/images/raw").unwrap(); // Путь к входной папке с изображениями
+/// Прерванные сессии приводятся в порядок до обработки.
+/// Вызывать, только когда запись не идёт: активная сессия тоже выглядит прерванной.
+fn recover_interrupted_sessions(data_path: &Path) -> Result<(), PreprocessError> {
+    for mut session in discover_sessions(data_path).at(data_path)? {
+        if session.is_interrupted() {
+            let report = session.recover().at(&session.dir)?;
+            println!("Сессия {} восстановлена: {:?}", session.id(), report);
+        }
+    }
+
+    Ok(())
+}
+
+/// Кадры уменьшаются до размера и способом из каталога данных,
+/// см. [`FrameFormat::load_or_default`]. Уже уменьшенные кадры не пересчитываются,
+/// после смены формата их нужно удалить.
+pub fn process_my_images() -> Result<(), PreprocessError> {
+    let data_path = PathBuf::from_str(DATA_DIR).unwrap();
+    let format = FrameFormat::load_or_default(&data_path).at(&data_path.join(FRAME_FORMAT_FILE))?;
+    recover_interrupted_sessions(&data_path)?;
+
+    for session in discover_sessions(&data_path).at(&data_path)? {
+        // Кадры уже уменьшены при захвате, полноразмерных может не быть
+        if !session.frames_dir().exists() {
+            continue;
+        }
+
+        println!("Сессия {}", session.id());
+        process_images(&session.frames_dir(), &session.resized_dir(), format)?;
+    }
+
+    // Старый формат записи без сессий
+    let input_dir = &data_path.join("images/raw"); // Путь к входной папке с изображениями
+    if input_dir.exists() {
+        let output_dir = &data_path.join("images/resized_images"); // Путь к выходной папке для сохранения измененных изображений
 
-    std::fs::create_dir_all("data/images/resized_images").unwrap();
-    let output_dir = &PathBuf::from_str("data/images/resized_images").unwrap(); // Путь к выходной папке для сохранения измененных изображений
+        process_images(input_dir, output_dir, format)?;
+    }
 
-    process_images(input_dir, output_dir, WIDTH as u32, HEIGHT as u32).unwrap();
+    Ok(())
 }
 
-pub fn write_my_data() {
-    let data_path = PathBuf::from_str("data").unwrap();
+/// Каждая сессия записывается в свою директорию `data/hdf5_files/<session_id>/`.
+/// Повторный запуск дописывает только новые кадры, см. [`Hdf5Writer`].
+/// Клавиши кодируются словарём из каталога данных, см. [`KeyVocabulary::load_or_default`],
+/// кадры приводятся к формату оттуда же, см. [`FrameFormat::load_or_default`], действия
+/// сводятся по настройкам оттуда же, см. [`AggregationConfig::load_or_default`].
+/// Если в каталоге данных есть настройки отбора кадров ([`FilterConfig::load_or_default`]),
+/// кадры перед записью проходят отбор, итоги сохраняются рядом с датасетом.
+/// Датасеты, записанные при захвате, отбор не проходят.
+pub fn write_my_data() -> Result<(), PreprocessError> {
+    let data_path = PathBuf::from_str(DATA_DIR).unwrap();
+    let hdf5_path = data_path.join("hdf5_files");
+    let vocabulary =
+        KeyVocabulary::load_or_default(&data_path).at(&data_path.join(KEY_VOCABULARY_FILE))?;
+    let format = FrameFormat::load_or_default(&data_path).at(&data_path.join(FRAME_FORMAT_FILE))?;
+    let aggregation = AggregationConfig::load_or_default(&data_path)
+        .at(&data_path.join(AGGREGATION_CONFIG_FILE))?;
+    let options = WriterOptions {
+        aggregation,
+        ..Default::default()
+    };
+    let filter_config =
+        FilterConfig::load_or_default(&data_path).at(&data_path.join(FRAME_FILTER_FILE))?;
+    let filtering = !filter_config.keeps_all();
+    recover_interrupted_sessions(&data_path)?;
+
+    for session in discover_sessions(&data_path).at(&data_path)? {
+        if session.manifest.dataset_written {
+            println!("Сессия {}: датасет записан при захвате", session.id());
+
+            // Записанный при захвате датасет уже закодирован словарём того времени
+            if session.manifest.key_vocabulary_version != vocabulary.version() {
+                println!(
+                    "Сессия {}: словарь клавиш версии {}, текущий — версии {}",
+                    session.id(),
+                    session.manifest.key_vocabulary_version,
+                    vocabulary.version()
+                );
+            }
+            continue;
+        }
 
-    let keys_records = load_records_from_directory(&data_path.join("keys")).unwrap();
-    let images = load_images_from_directory(&data_path.join("images/resized_images")).unwrap();
+        let dataset_path = hdf5_path.join(session.id());
+        std::fs::create_dir_all(&dataset_path).at(&dataset_path)?;
 
-    if images.len() == 0 {
-        panic!("Отсутствуют изображения для обработки")
+        // Записываются только кадры, которых ещё нет в датасете
+        let dataset_file = dataset_path.join(DATASET_FILE);
+        let mut writer = match Hdf5Writer::open(&dataset_file, format, options) {
+            Ok(writer) => writer,
+            Err(err) => {
+                // Например, датасет записан в другом формате кадров или сведении действий
+                println!("Сессия {}: {}", session.id(), err);
+                continue;
+            }
+        };
+        // Удалённые отбором кадры при повторном запуске не проверяются заново
+        let report_path = dataset_path.join(FILTER_REPORT_FILE);
+        let mut report = FilterReport::load_or_default(&report_path).at(&report_path)?;
+        let mut my_data = session.align_except(&vocabulary, format, aggregation, |index| {
+            writer.contains(index) || (filtering && report.dropped.contains_key(&index))
+        })?;
+
+        if filtering && !my_data.is_empty() {
+            // Отбор продолжается с последнего проверенного кадра и серий прошлого запуска,
+            // в отчётах без номера такого кадра — с последнего записанного
+            let previous = match report.last_checked {
+                Some(index) => session.load_resized(index, format)?,
+                None => writer.last().at(&dataset_file)?.map(|(_, data)| data.image),
+            };
+            my_data = FrameFilter::resume(filter_config.clone(), previous, &report)
+                .apply(my_data, &mut report);
+            report.save(&report_path).at(&report_path)?;
+            println!("Сессия {}: отбор кадров: {}", session.id(), report);
+        }
+
+        if my_data.is_empty() {
+            if writer.is_empty() {
+                println!("Сессия {}: нет обработанных кадров", session.id());
+            }
+            continue;
+        }
+
+        let added = writer.append(&my_data).at(&dataset_file)?;
+        println!("Сессия {}: добавлено записей: {}", session.id(), added);
     }
 
-    let my_data: Vec<MyConstData> = keys_records
+    let csv_path = data_path.join("keys/key_events.csv");
+    if csv_path.exists() {
+        // Старый журнал один раз переводится в двоичный формат
+        let log_path = data_path.join("keys/key_events.bin");
+        if !log_path.exists() {
+            import_key_events_csv(&csv_path, &log_path, LEGACY_FRAME_INTERVAL_US).at(&csv_path)?;
+        }
+
+        let legacy_path = hdf5_path.join(LEGACY_DATASET_DIR);
+        let mut my_data = zip_my_data(&data_path, &log_path, &vocabulary, format, aggregation)?;
+
+        // Кадры старого формата каждый раз отбираются заново, отчёт перезаписывается
+        let mut report = FilterReport::default();
+        if filtering {
+            my_data = FrameFilter::new(filter_config).apply(my_data, &mut report);
+            println!("Старый формат: отбор кадров: {}", report);
+        }
+
+        append_to_dataset(&legacy_path, &my_data, format, options).at(&legacy_path)?;
+        if filtering {
+            let report_path = legacy_path.join(FILTER_REPORT_FILE);
+            report.save(&report_path).at(&report_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Старый формат записи без меток времени: кадр с номером `n` из имени файла
+/// сопоставляется со строкой журнала `n - первый номер`, поэтому пропущенные кадры
+/// не сдвигают действия остальных
+fn zip_my_data(
+    data_path: &Path,
+    log_path: &Path,
+    vocabulary: &KeyVocabulary,
+    format: FrameFormat,
+    aggregation: AggregationConfig,
+) -> Result<Vec<(usize, MyConstData)>, PreprocessError> {
+    let events = load_event_log(log_path).at(log_path)?;
+    let images_path = data_path.join("images/resized_images");
+    let scan = scan_frames(&images_path).at(&images_path)?;
+
+    if scan.frames.is_empty() {
+        return Err(PreprocessError::invalid_data(
+            &images_path,
+            "отсутствуют изображения для обработки",
+        ));
+    }
+    if !scan.is_complete() {
+        println!("Старый формат: {}", scan);
+    }
+
+    let first = scan.frames[0].0;
+    let frames: Vec<FrameStamp> = scan
+        .frames
         .iter()
-        .zip(images.iter())
-        .map(|(keys_record, image_data)| MyConstData {
-            image: MyImage::from_image_data(image_data),
-            keys_record: keys_record.clone(),
+        .map(|(index, _)| FrameStamp {
+            index: *index,
+            file: String::new(),
+            timestamp_us: (index - first) as u64 * LEGACY_FRAME_INTERVAL_US,
         })
         .collect();
 
-    write_data_to_hdf5_files(&data_path.join("hdf5_files"), &my_data);
+    align_events(&frames, &events, vocabulary, aggregation.mouse_bins)
+        .into_iter()
+        .zip(scan.frames.iter())
+        .map(|(keys_record, (index, image_data))| {
+            let image = MyImage::from_image_data(image_data, format)?;
+
+            Ok((*index, MyConstData { image, keys_record }))
+        })
+        .collect()
 }
 
-pub fn read_my_data() {
-    let data_path = PathBuf::from_str("data").unwrap();
-    let data_path = &data_path.join("hdf5_files");
+/// Сводка по датасету, см. [`inspect_dataset`]
+pub fn read_my_data() -> Result<(), PreprocessError> {
+    let data_path = PathBuf::from_str(DATA_DIR).unwrap();
+    let hdf5_path = data_path.join("hdf5_files");
+    let vocabulary =
+        KeyVocabulary::load_or_default(&data_path).at(&data_path.join(KEY_VOCABULARY_FILE))?;
+
+    let stats = inspect_dataset(&hdf5_path, &vocabulary).at(&hdf5_path)?;
+    println!("{stats}");
+
+    Ok(())
+}
 
-    let a = read_all_hdf5_files(data_path).unwrap();
-    println!("{:?}\n\n", a.len());
+/// Пустая временная директория для тестов
+#[cfg(test)]
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
 }
@@ -0,0 +1,171 @@
+//! Обслуживание датасетов из командной строки.
+//!
+//! `preprocessor inspect [--json] [путь]` — сводка по датасету, см.
+//! [`preprocessor::inspection`]. Путь — директория датасета или отдельный файл,
+//! по умолчанию `data/hdf5_files`. Имена клавиш берутся из словаря каталога данных.
+//!
+//! `preprocessor doctor [--json] [каталог]` — проверка данных, см.
+//! [`preprocessor::doctor`]. Каталог по умолчанию `data/`. Если найдены ошибки,
+//! команда завершается с кодом 1.
+//!
+//! `preprocessor export <npz|safetensors> <каталог> [путь]` — выгрузка сессий
+//! датасета в файлы обмена, см. [`preprocessor::portable`]. Путь по умолчанию
+//! `data/hdf5_files`.
+//!
+//! `preprocessor import <файл|каталог> [путь]` — загрузка файлов обмена в датасет,
+//! по умолчанию в `data/hdf5_files`. Клавиши кодируются словарём каталога данных.
+//!
+//! `preprocessor migrate [--dry-run] [путь]` — обновление файлов датасета до текущей
+//! версии схемы, см. [`preprocessor::migration`]. С `--dry-run` только показывает,
+//! что будет сделано. Путь по умолчанию `data/hdf5_files`.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use common::DATA_DIR;
+use preprocessor::{
+    doctor::diagnose,
+    inspection::inspect_dataset,
+    migration::migrate_dataset,
+    portable::{PortableFormat, export_dataset, import_files},
+    vocabulary::KeyVocabulary,
+};
+
+const USAGE: &str = "Использование: preprocessor inspect [--json] [путь]
+               preprocessor doctor [--json] [каталог]
+               preprocessor export <npz|safetensors> <каталог> [путь]
+               preprocessor import <файл|каталог> [путь]
+               preprocessor migrate [--dry-run] [путь]";
+
+/// Необязательные флаг `flag` и путь
+fn parse_args(args: &[String], flag: Option<&str>) -> Result<(bool, Option<PathBuf>), String> {
+    let mut found = false;
+    let mut path = None;
+
+    for arg in args {
+        match arg.as_str() {
+            arg if Some(arg) == flag => found = true,
+            flag if flag.starts_with("--") => return Err(format!("неизвестный флаг {flag}")),
+            _ if path.is_some() => return Err(format!("лишний аргумент {arg}")),
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    Ok((found, path))
+}
+
+fn inspect(args: &[String]) -> Result<(), String> {
+    let (json, path) = parse_args(args, Some("--json"))?;
+
+    let data_path = Path::new(DATA_DIR);
+    let path = path.unwrap_or_else(|| data_path.join("hdf5_files"));
+    let vocabulary = KeyVocabulary::load_or_default(data_path).map_err(|err| err.to_string())?;
+
+    let stats = inspect_dataset(&path, &vocabulary)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    if json {
+        println!("{}", stats.to_json().map_err(|err| err.to_string())?);
+    } else {
+        println!("{stats}");
+    }
+
+    Ok(())
+}
+
+/// `Ok(false)`, если найдены ошибки
+fn doctor(args: &[String]) -> Result<bool, String> {
+    let (json, path) = parse_args(args, Some("--json"))?;
+
+    let report = diagnose(&path.unwrap_or_else(|| PathBuf::from(DATA_DIR)));
+
+    if json {
+        println!("{}", report.to_json().map_err(|err| err.to_string())?);
+    } else {
+        println!("{report}");
+    }
+
+    Ok(report.is_healthy())
+}
+
+fn export(args: &[String]) -> Result<(), String> {
+    let [format, out_dir, rest @ ..] = args else {
+        return Err("не указаны формат и каталог".to_string());
+    };
+    let format: PortableFormat = format.parse()?;
+    let (_, path) = parse_args(rest, None)?;
+
+    let data_path = Path::new(DATA_DIR);
+    let path = path.unwrap_or_else(|| data_path.join("hdf5_files"));
+    let vocabulary = KeyVocabulary::load_or_default(data_path).map_err(|err| err.to_string())?;
+
+    let files = export_dataset(&path, Path::new(out_dir), format, &vocabulary)
+        .map_err(|err| err.to_string())?;
+    for file in files {
+        println!("{}", file.display());
+    }
+
+    Ok(())
+}
+
+fn import(args: &[String]) -> Result<(), String> {
+    let [source, rest @ ..] = args else {
+        return Err("не указан файл или каталог".to_string());
+    };
+    let (_, path) = parse_args(rest, None)?;
+
+    let data_path = Path::new(DATA_DIR);
+    let path = path.unwrap_or_else(|| data_path.join("hdf5_files"));
+    let vocabulary = KeyVocabulary::load_or_default(data_path).map_err(|err| err.to_string())?;
+
+    let imported =
+        import_files(Path::new(source), &path, &vocabulary).map_err(|err| err.to_string())?;
+    for (session, added) in imported {
+        println!("Сессия {session}: добавлено записей: {added}");
+    }
+
+    Ok(())
+}
+
+fn migrate(args: &[String]) -> Result<(), String> {
+    let (dry_run, path) = parse_args(args, Some("--dry-run"))?;
+    let path = path.unwrap_or_else(|| Path::new(DATA_DIR).join("hdf5_files"));
+
+    let migrations = migrate_dataset(&path, dry_run).map_err(|err| err.to_string())?;
+    if dry_run {
+        println!("Проверка без изменений");
+    }
+    for migration in migrations {
+        println!("{migration}");
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("inspect") => inspect(&args[1..]).map(|()| true),
+        Some("doctor") => doctor(&args[1..]),
+        Some("export") => export(&args[1..]).map(|()| true),
+        Some("import") => import(&args[1..]).map(|()| true),
+        Some("migrate") => migrate(&args[1..]).map(|()| true),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{err}");
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
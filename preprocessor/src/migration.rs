@@ -0,0 +1,514 @@
+//! Обновление файлов датасета до текущей схемы, [`SCHEMA_VERSION`].
+//!
+//! Файлы в текущем устройстве без записанной версии только помечаются ею.
+//! Файлы версии 1 переписываются: записи с кадрами внутри разделяются на действия
+//! и кадры. Новый файл пишется рядом и заменяет старый только после полной записи,
+//! поэтому прерванное обновление не портит данные. Сжатие при этом не сохраняется.
+//!
+//! Файлы версии 0 (`my_data_{i}.h5`, по 100 записей) переносятся в датасет
+//! [`LEGACY_DATASET_DIR`] рядом с ними. Номеров кадров в них нет, записи нумеруются
+//! по порядку: `100 * i + номер записи в файле`. Файл удаляется только после того,
+//! как все его записи перенесены; повторный запуск уже перенесённые кадры не дублирует.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use common::{CHANNELS, HEIGHT, WIDTH, frame::FrameFormat};
+use hdf5_metno::{File, H5Type};
+use serde::Serialize;
+
+use crate::{
+    csv_processing::KeysRecordConst,
+    error::{ErrorPath, PreprocessError},
+    hdf5_processing::{
+        DATA_PATH, DATASET_FILE, FRAMES_PATH, Hdf5Writer, LEGACY_DATASET_DIR, RECORDS_PER_CHUNK,
+        SCHEMA_VERSION, WriterOptions, find_hdf5_files, schema_version, write_version,
+    },
+    images::MyImage,
+    types::MyConstData,
+};
+
+/// Кадр записи версии 1: 40×40 RGBA, значения `[канал][x][y]`
+#[derive(Clone, Copy, H5Type)]
+#[repr(C)]
+struct ImageV1 {
+    pixels: [[[u8; HEIGHT]; WIDTH]; CHANNELS],
+}
+
+impl ImageV1 {
+    /// Кадр текущего формата со значениями `[канал][y][x]`
+    fn to_image(self) -> MyImage {
+        let format = FrameFormat::default();
+        let mut image = MyImage::filled(format, 0);
+
+        for (channel, columns) in self.pixels.iter().enumerate() {
+            for (x, column) in columns.iter().enumerate() {
+                for (y, value) in column.iter().enumerate() {
+                    image.pixels[(channel * HEIGHT + y) * WIDTH + x] = *value;
+                }
+            }
+        }
+
+        image
+    }
+}
+
+/// Действия версии 0: номера клавиш и движения мыши по порядку, пустые ячейки нулевые
+#[derive(Clone, Copy, H5Type)]
+#[repr(C)]
+struct KeysRecordV0 {
+    keys: [u8; 200],
+    mouse: [[i32; 2]; 200],
+}
+
+impl KeysRecordV0 {
+    /// Действия текущего формата. Нули в конце считаются пустыми ячейками:
+    /// клавиша с номером 0 в конце списка от них не отличается и отбрасывается
+    fn to_keys_record(self) -> KeysRecordConst {
+        let keys = self.keys.len() - self.keys.iter().rev().take_while(|key| **key == 0).count();
+        let moves = self.mouse.len()
+            - self
+                .mouse
+                .iter()
+                .rev()
+                .take_while(|delta| **delta == [0, 0])
+                .count();
+
+        KeysRecordConst::from_slices(&self.keys[..keys], &self.mouse[..moves])
+    }
+}
+
+/// Запись версии 0: кадр как в версии 1, действия без меток времени
+#[derive(Clone, Copy, H5Type)]
+#[repr(C)]
+struct RecordV0 {
+    image: ImageV1,
+    keys_record: KeysRecordV0,
+}
+
+/// Записей в одном файле версии 0
+const V0_RECORDS_PER_FILE: usize = 100;
+
+/// Запись версии 1: действия и кадр одним составным типом
+#[derive(Clone, H5Type)]
+#[repr(C)]
+struct RecordV1 {
+    image: ImageV1,
+    keys_record: KeysRecordConst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationAction {
+    /// Файл уже в текущей схеме
+    Current,
+    /// Дописана версия схемы
+    Tagged,
+    /// Файл переписан в текущее устройство
+    Rewritten { records: usize },
+    /// Записи перенесены в датасет [`LEGACY_DATASET_DIR`], файл удалён, см. [`v0_target`]
+    Merged { records: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Migration {
+    pub path: PathBuf,
+    /// Версия схемы до обновления
+    pub from: u32,
+    pub action: MigrationAction,
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.action {
+            MigrationAction::Current => "обновление не нужно".to_string(),
+            MigrationAction::Tagged => "дописывается номер версии".to_string(),
+            MigrationAction::Rewritten { records } => {
+                format!("переписывается в версию {SCHEMA_VERSION}, записей: {records}")
+            }
+            MigrationAction::Merged { records } => format!(
+                "переносится в {} с номерами кадров по порядку, записей: {records}",
+                v0_target(&self.path).display()
+            ),
+        };
+
+        write!(
+            f,
+            "{}: версия {}, {}",
+            self.path.display(),
+            self.from,
+            action
+        )
+    }
+}
+
+/// Запись версии 1 в файл текущей схемы рядом, затем замена исходного файла
+fn rewrite_v1(path: &Path) -> Result<usize, PreprocessError> {
+    let temp = path.with_extension("h5.migrating");
+    if temp.exists() {
+        fs::remove_file(&temp).at(&temp)?;
+    }
+
+    let mut writer =
+        Hdf5Writer::open(&temp, FrameFormat::default(), WriterOptions::default()).at(&temp)?;
+    {
+        let group = File::open(path)
+            .and_then(|file| file.group("dir"))
+            .at(path)?;
+        let data = group.dataset(DATA_PATH).at(path)?;
+        let frames = group
+            .dataset(FRAMES_PATH)
+            .and_then(|frames| frames.read_raw::<u64>())
+            .at(path)?;
+
+        for start in (0..frames.len()).step_by(RECORDS_PER_CHUNK) {
+            let end = (start + RECORDS_PER_CHUNK).min(frames.len());
+            let records: Vec<(usize, MyConstData)> = data
+                .read_slice_1d::<RecordV1, _>(start..end)
+                .at(path)?
+                .into_iter()
+                .zip(&frames[start..end])
+                .map(|(record, frame)| {
+                    let data = MyConstData {
+                        image: record.image.to_image(),
+                        keys_record: record.keys_record,
+                    };
+                    (*frame as usize, data)
+                })
+                .collect();
+
+            writer.append(&records).at(&temp)?;
+        }
+    }
+
+    let records = writer.len();
+    drop(writer);
+    fs::rename(&temp, path).at(path)?;
+
+    Ok(records)
+}
+
+/// Датасет, в который переносится файл версии 0
+pub fn v0_target(path: &Path) -> PathBuf {
+    path.parent()
+        .unwrap_or(Path::new(""))
+        .join(LEGACY_DATASET_DIR)
+        .join(DATASET_FILE)
+}
+
+/// Перенос записей файла версии 0 `my_data_{i}.h5` в [`v0_target`], затем удаление файла
+fn merge_v0(path: &Path) -> Result<usize, PreprocessError> {
+    let file_index = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.strip_prefix("my_data_"))
+        .and_then(|index| index.parse::<usize>().ok())
+        .ok_or_else(|| {
+            PreprocessError::invalid_data(path, "файл версии 0 должен называться my_data_{i}.h5")
+        })?;
+
+    let target = v0_target(path);
+    let dir = target.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(dir).at(dir)?;
+    let mut writer =
+        Hdf5Writer::open(&target, FrameFormat::default(), WriterOptions::default()).at(&target)?;
+
+    let records: Vec<(usize, MyConstData)> = File::open(path)
+        .and_then(|file| file.dataset("dir/data"))
+        .and_then(|data| data.read_raw::<RecordV0>())
+        .at(path)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| {
+            let data = MyConstData {
+                image: record.image.to_image(),
+                keys_record: record.keys_record.to_keys_record(),
+            };
+            (file_index * V0_RECORDS_PER_FILE + i, data)
+        })
+        .collect();
+    writer.append(&records).at(&target)?;
+    drop(writer);
+
+    fs::remove_file(path).at(path)?;
+
+    Ok(records.len())
+}
+
+/// Обновление одного файла; при `dry_run` файл не меняется, а только
+/// определяется, что с ним будет сделано
+pub fn migrate_file(path: &Path, dry_run: bool) -> Result<Migration, PreprocessError> {
+    let (from, tagged, records) = {
+        let group = File::open(path)
+            .and_then(|file| file.group("dir"))
+            .at(path)?;
+        let (version, tagged) = schema_version(&group).at(path)?;
+        let records = group
+            .dataset(DATA_PATH)
+            .at(path)?
+            .shape()
+            .first()
+            .copied()
+            .unwrap_or(0);
+
+        (version, tagged, records)
+    };
+
+    let action = match from {
+        SCHEMA_VERSION if tagged => MigrationAction::Current,
+        SCHEMA_VERSION => {
+            if !dry_run {
+                File::open_rw(path)
+                    .and_then(|file| write_version(&file.group("dir")?))
+                    .at(path)?;
+            }
+            MigrationAction::Tagged
+        }
+        0 => MigrationAction::Merged {
+            records: if dry_run { records } else { merge_v0(path)? },
+        },
+        1 => MigrationAction::Rewritten {
+            records: if dry_run { records } else { rewrite_v1(path)? },
+        },
+        _ => {
+            return Err(PreprocessError::invalid_data(
+                path,
+                format!("схема версии {from} новее поддерживаемой версии {SCHEMA_VERSION}"),
+            ));
+        }
+    };
+
+    Ok(Migration {
+        path: path.to_path_buf(),
+        from,
+        action,
+    })
+}
+
+/// Обновление всех файлов датасета в `data_path` (директория или отдельный файл)
+pub fn migrate_dataset(data_path: &Path, dry_run: bool) -> Result<Vec<Migration>, PreprocessError> {
+    find_hdf5_files(data_path)
+        .at(data_path)?
+        .iter()
+        .map(|path| migrate_file(path, dry_run))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hdf5_processing::{Hdf5Reader, IMAGES_PATH, read_schema, write_format},
+        temp_dir,
+        vocabulary::KEY_PADDING,
+    };
+    use ndarray::Array;
+
+    /// Файл версии 1: записи с кадрами внутри и номера кадров
+    fn write_v1(path: &Path, frames: &[u64]) {
+        let group = File::create(path).unwrap().create_group("dir").unwrap();
+
+        let records: Vec<RecordV1> = frames
+            .iter()
+            .map(|frame| {
+                let mut pixels = [[[0; HEIGHT]; WIDTH]; CHANNELS];
+                // Отличимые значения: канал, столбец и строка
+                pixels[1][2][3] = *frame as u8 + 1;
+                RecordV1 {
+                    image: ImageV1 { pixels },
+                    keys_record: KeysRecordConst::from_slices(&[*frame as u8], &[]),
+                }
+            })
+            .collect();
+        group
+            .new_dataset_builder()
+            .with_data(&Array::from_vec(records))
+            .create(DATA_PATH)
+            .unwrap();
+        group
+            .new_dataset_builder()
+            .with_data(&Array::from_vec(frames.to_vec()))
+            .create(FRAMES_PATH)
+            .unwrap();
+    }
+
+    /// Файл версии 0 `my_data_{index}.h5`: `count` записей, клавиша записи — её номер
+    fn write_v0(dir: &Path, index: usize, count: usize) -> PathBuf {
+        let path = dir.join(format!("my_data_{index}.h5"));
+        let records: Vec<RecordV0> = (0..count)
+            .map(|i| {
+                let mut pixels = [[[0; HEIGHT]; WIDTH]; CHANNELS];
+                pixels[0][1][2] = i as u8 + 1;
+                let mut keys = [0; 200];
+                keys[0] = 52;
+                keys[1] = i as u8;
+                let mut mouse = [[0; 2]; 200];
+                mouse[0] = [3, -1];
+                mouse[1] = [2, 0];
+                RecordV0 {
+                    image: ImageV1 { pixels },
+                    keys_record: KeysRecordV0 { keys, mouse },
+                }
+            })
+            .collect();
+        File::create(&path)
+            .unwrap()
+            .create_group("dir")
+            .unwrap()
+            .new_dataset_builder()
+            .with_data(&Array::from_vec(records))
+            .create(DATA_PATH)
+            .unwrap();
+
+        path
+    }
+
+    /// Test version 0 files are merged into the legacy dataset with sequential frame numbers
+    #[test]
+    fn test_migrate_v0() {
+        let dir = temp_dir("test_migrate_v0");
+        let first = write_v0(&dir, 0, 3);
+        let second = write_v0(&dir, 1, 2);
+        let target = dir.join(LEGACY_DATASET_DIR).join(DATASET_FILE);
+
+        let schema = read_schema(&first).unwrap();
+        assert_eq!((schema.version, schema.tagged), (0, false));
+
+        let planned = migrate_dataset(&dir, true).unwrap();
+        assert_eq!(planned[0].from, 0);
+        assert_eq!(planned[0].action, MigrationAction::Merged { records: 3 });
+        assert_eq!(planned[1].action, MigrationAction::Merged { records: 2 });
+        assert!(planned[0].to_string().contains(LEGACY_DATASET_DIR));
+        assert!(first.exists() && !target.exists());
+
+        let done = migrate_dataset(&dir, false).unwrap();
+        assert_eq!(done, planned);
+        assert!(!first.exists() && !second.exists());
+
+        let reader = Hdf5Reader::open(&dir, 1).unwrap();
+        assert_eq!(reader.len(), 5);
+        assert_eq!(reader.source(2), Some((LEGACY_DATASET_DIR, 2)));
+        assert_eq!(reader.source(4), Some((LEGACY_DATASET_DIR, 101)));
+        let record = reader.read(4).unwrap().unwrap();
+        assert_eq!(record.image.pixel(0, 2, 1), 2);
+        // Нулевая клавиша в конце считается пустой ячейкой
+        let record = reader.read(0).unwrap().unwrap();
+        assert_eq!(&record.keys_record.keys[..2], &[52, KEY_PADDING]);
+        assert_eq!(record.keys_record.delta, [5, -1]);
+        let record = reader.read(1).unwrap().unwrap();
+        assert_eq!(&record.keys_record.keys[..3], &[52, 1, KEY_PADDING]);
+
+        // Прерванный перенос: файл остался, уже перенесённые кадры не дублируются
+        write_v0(&dir, 1, 2);
+        migrate_dataset(&dir, false).unwrap();
+        assert_eq!(Hdf5Reader::open(&dir, 1).unwrap().len(), 5);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test version 1 files are rewritten into the current layout and readers refuse them before that
+    #[test]
+    fn test_migrate_v1() {
+        let dir = temp_dir("test_migrate_v1");
+        let path = dir.join("session").join(DATASET_FILE);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_v1(&path, &[4, 5, 7]);
+
+        let schema = read_schema(&path).unwrap();
+        assert_eq!((schema.version, schema.tagged), (1, false));
+        let Err(err) = Hdf5Reader::open(&dir, 1) else {
+            panic!("version 1 files should not be read");
+        };
+        assert!(err.to_string().contains("preprocessor migrate"), "{err}");
+
+        let planned = migrate_dataset(&dir, true).unwrap();
+        assert_eq!(planned[0].action, MigrationAction::Rewritten { records: 3 });
+        assert_eq!(read_schema(&path).unwrap().version, 1);
+
+        let done = migrate_dataset(&dir, false).unwrap();
+        assert_eq!(done, planned);
+        let schema = read_schema(&path).unwrap();
+        assert_eq!((schema.version, schema.tagged), (SCHEMA_VERSION, true));
+        assert!(!path.with_extension("h5.migrating").exists());
+
+        let reader = Hdf5Reader::open(&dir, 1).unwrap();
+        assert_eq!(reader.len(), 3);
+        assert_eq!(reader.source(2), Some(("session", 7)));
+        let record = reader.read(1).unwrap().unwrap();
+        assert_eq!(record.image.format, FrameFormat::default());
+        assert_eq!(record.image.pixel(1, 3, 2), 6);
+        assert_eq!(record.image.pixel(1, 2, 3), 0);
+        assert_eq!(record.keys_record.keys[0], 5);
+
+        // Повторный запуск ничего не меняет
+        assert_eq!(
+            migrate_dataset(&dir, false).unwrap()[0].action,
+            MigrationAction::Current
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test untagged current files are only tagged and newer ones refused
+    #[test]
+    fn test_migrate_tags_and_skips() {
+        let dir = temp_dir("test_migrate_tags");
+        let path = dir.join("session").join(DATASET_FILE);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        // Файл прежних версий программы: текущее устройство без атрибута версии
+        let format = FrameFormat::default();
+        let group = File::create(&path).unwrap().create_group("dir").unwrap();
+        let keys_record = KeysRecordConst::from_slices(&[], &[]);
+        group
+            .new_dataset_builder()
+            .with_data(&Array::from_vec(vec![keys_record]))
+            .create(DATA_PATH)
+            .unwrap();
+        let shape = (1, format.channels(), format.height, format.width);
+        group
+            .new_dataset_builder()
+            .with_data(&Array::from_elem(shape, 1u8))
+            .create(IMAGES_PATH)
+            .unwrap();
+        group
+            .new_dataset_builder()
+            .with_data(&Array::from_vec(vec![0u64]))
+            .create(FRAMES_PATH)
+            .unwrap();
+        write_format(&group, format).unwrap();
+        drop(group);
+        assert!(!read_schema(&path).unwrap().tagged);
+
+        assert_eq!(
+            migrate_file(&path, true).unwrap().action,
+            MigrationAction::Tagged
+        );
+        assert!(!read_schema(&path).unwrap().tagged);
+        assert_eq!(
+            migrate_file(&path, false).unwrap().action,
+            MigrationAction::Tagged
+        );
+        assert!(read_schema(&path).unwrap().tagged);
+        assert_eq!(Hdf5Reader::open(&path, 1).unwrap().len(), 1);
+
+        assert_eq!(
+            migrate_dataset(&dir, false).unwrap()[0].action,
+            MigrationAction::Current
+        );
+
+        let group = File::open_rw(&path).unwrap().group("dir").unwrap();
+        group
+            .attr("version")
+            .unwrap()
+            .write_scalar(&(SCHEMA_VERSION + 1))
+            .unwrap();
+        drop(group);
+        assert!(migrate_file(&path, false).is_err());
+        assert!(Hdf5Reader::open(&path, 1).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1047 @@
+//! Обмен датасетами с другими программами: NumPy `.npz` и safetensors.
+//!
+//! Сессия выгружается в один файл с тензорами по записям:
+//! - `frames` — u8 `[запись, канал, y, x]`;
+//! - `frame_index` — i64, номера исходных кадров;
+//! - `keys` — u8 `[запись, клавиша]`: 1 — клавиша нажата, столбцы — номера словаря;
+//! - `key_hold` — f32 `[запись, клавиша]`: доля кадра, в течение которой клавиша удерживалась;
+//! - `mouse` — i32 `[запись, отрезок, 2]`: траектория мыши за кадр;
+//! - `position` — f32 `[запись, 2]`; `delta`, `wheel` — i32 `[запись, 2]`;
+//! - `buttons` — u8 `[запись, кнопка]`; `key_events`, `button_events` — u16 `[запись, 2]`.
+//!
+//! Описание файла ([`PortableMetadata`]) в JSON лежит в `.npz` отдельным файлом
+//! `metadata.json`, в safetensors — в заголовке под ключом `metadata`.
+//!
+//! При загрузке обязателен только `frames`: недостающие действия считаются пустыми,
+//! кадры — идущими подряд. Без описания формат кадров определяется по размерам
+//! `frames`, а столбцы `keys` считаются номерами текущего словаря.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::{BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use common::{MOUSE_BUTTONS, aggregation::AggregationConfig, frame::FrameFormat};
+use safetensors::{Dtype, SafeTensors, serialize_to_file, tensor::TensorView};
+use serde::{Deserialize, Serialize};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    csv_processing::KeysRecordConst,
+    error::{ErrorPath, PreprocessError},
+    hdf5_processing::{
+        Hdf5Reader, WriterOptions, append_to_dataset, dataset_name, find_hdf5_files,
+    },
+    images::MyImage,
+    types::MyConstData,
+    vocabulary::{KEY_PADDING, KeyVocabulary},
+};
+
+/// Версия устройства файлов обмена
+pub const PORTABLE_VERSION: u32 = 1;
+
+/// Описание внутри `.npz`
+const METADATA_FILE: &str = "metadata.json";
+/// Ключ описания в заголовке safetensors
+const METADATA_KEY: &str = "metadata";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortableFormat {
+    Npz,
+    Safetensors,
+}
+
+impl PortableFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Npz => "npz",
+            Self::Safetensors => "safetensors",
+        }
+    }
+
+    /// Формат по расширению файла
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for PortableFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "npz" => Ok(Self::Npz),
+            "safetensors" => Ok(Self::Safetensors),
+            _ => Err(format!(
+                "неизвестный формат {value}, ожидается npz или safetensors"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PortableFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// Порядок осей `frames`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameAxes {
+    /// `[запись, канал, y, x]`, как в датасете
+    #[default]
+    Nchw,
+    /// `[запись, y, x, канал]`, как обычно хранят изображения
+    Nhwc,
+}
+
+/// Описание файла обмена. Все поля необязательны для файлов, подготовленных вне проекта.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PortableMetadata {
+    #[serde(default)]
+    pub version: u32,
+    /// Имя сессии, см. [`dataset_name`]; без него — имя файла
+    #[serde(default)]
+    pub session: Option<String>,
+    /// Без формата кадры считаются растянутыми до размеров `frames`
+    #[serde(default)]
+    pub format: Option<FrameFormat>,
+    /// Без сведения действий длина траектории берётся из размеров `mouse`
+    #[serde(default)]
+    pub aggregation: Option<AggregationConfig>,
+    #[serde(default)]
+    pub axes: FrameAxes,
+    /// Имена клавиш по столбцам `keys`; без них столбцы — номера текущего словаря
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+/// Записи сессии из файла обмена
+#[derive(Clone, Debug)]
+pub struct PortableSession {
+    pub session: String,
+    pub format: FrameFormat,
+    pub aggregation: AggregationConfig,
+    /// Номер исходного кадра и запись
+    pub records: Vec<(usize, MyConstData)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Element {
+    U8,
+    U16,
+    I32,
+    I64,
+    F32,
+}
+
+impl Element {
+    fn size(self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::U16 => 2,
+            Self::I32 | Self::F32 => 4,
+            Self::I64 => 8,
+        }
+    }
+
+    fn descr(self) -> &'static str {
+        match self {
+            Self::U8 => "|u1",
+            Self::U16 => "<u2",
+            Self::I32 => "<i4",
+            Self::I64 => "<i8",
+            Self::F32 => "<f4",
+        }
+    }
+
+    /// Тип `.npy`; логические значения читаются как u8
+    fn from_descr(descr: &str) -> Option<Self> {
+        match descr {
+            "|u1" | "<u1" | "u1" | "|b1" | "<b1" | "b1" => Some(Self::U8),
+            "<u2" => Some(Self::U16),
+            "<i4" => Some(Self::I32),
+            "<i8" => Some(Self::I64),
+            "<f4" => Some(Self::F32),
+            _ => None,
+        }
+    }
+
+    fn dtype(self) -> Dtype {
+        match self {
+            Self::U8 => Dtype::U8,
+            Self::U16 => Dtype::U16,
+            Self::I32 => Dtype::I32,
+            Self::I64 => Dtype::I64,
+            Self::F32 => Dtype::F32,
+        }
+    }
+
+    fn from_dtype(dtype: Dtype) -> Option<Self> {
+        match dtype {
+            Dtype::U8 | Dtype::BOOL => Some(Self::U8),
+            Dtype::U16 => Some(Self::U16),
+            Dtype::I32 => Some(Self::I32),
+            Dtype::I64 => Some(Self::I64),
+            Dtype::F32 => Some(Self::F32),
+            _ => None,
+        }
+    }
+}
+
+/// Значение тензора в порядке байтов little-endian
+trait Scalar: Copy + Default {
+    const ELEMENT: Element;
+
+    fn write(self, bytes: &mut Vec<u8>);
+    fn read(bytes: &[u8]) -> Self;
+}
+
+macro_rules! scalar {
+    ($type:ty, $element:ident) => {
+        impl Scalar for $type {
+            const ELEMENT: Element = Element::$element;
+
+            fn write(self, bytes: &mut Vec<u8>) {
+                bytes.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn read(bytes: &[u8]) -> Self {
+                Self::from_le_bytes(bytes.try_into().unwrap())
+            }
+        }
+    };
+}
+
+scalar!(u8, U8);
+scalar!(u16, U16);
+scalar!(i32, I32);
+scalar!(i64, I64);
+scalar!(f32, F32);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Tensor {
+    element: Element,
+    shape: Vec<usize>,
+    data: Vec<u8>,
+}
+
+impl Tensor {
+    fn new<T: Scalar>(shape: Vec<usize>, values: impl IntoIterator<Item = T>) -> Self {
+        let mut data = Vec::with_capacity(shape.iter().product::<usize>() * T::ELEMENT.size());
+        for value in values {
+            value.write(&mut data);
+        }
+
+        Self {
+            element: T::ELEMENT,
+            shape,
+            data,
+        }
+    }
+
+    fn values<T: Scalar>(&self) -> Vec<T> {
+        self.data
+            .chunks_exact(T::ELEMENT.size())
+            .map(T::read)
+            .collect()
+    }
+
+    /// Файл `.npy` версии 1.0
+    fn to_npy(&self) -> Vec<u8> {
+        let dims: Vec<String> = self.shape.iter().map(ToString::to_string).collect();
+        let shape = match dims.as_slice() {
+            [single] => format!("({single},)"),
+            dims => format!("({})", dims.join(", ")),
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            self.element.descr(),
+            shape
+        );
+        // Начало файла с заголовком выравнивается на 64 байта,
+        // заголовок кончается переводом строки
+        let length = (10 + header.len() + 1).next_multiple_of(64) - 10;
+        header.extend(std::iter::repeat_n(' ', length - header.len() - 1));
+        header.push('\n');
+
+        let mut bytes = Vec::with_capacity(10 + length + self.data.len());
+        bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+        bytes.extend_from_slice(&(length as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&self.data);
+
+        bytes
+    }
+
+    fn from_npy(bytes: &[u8]) -> Result<Self, String> {
+        let (length, start) = match bytes {
+            [0x93, b'N', b'U', b'M', b'P', b'Y', 1, _, a, b, ..] => {
+                (u16::from_le_bytes([*a, *b]) as usize, 10)
+            }
+            [0x93, b'N', b'U', b'M', b'P', b'Y', 2 | 3, _, a, b, c, d, ..] => {
+                (u32::from_le_bytes([*a, *b, *c, *d]) as usize, 12)
+            }
+            _ => return Err("не файл .npy".to_string()),
+        };
+        let header = bytes
+            .get(start..start + length)
+            .and_then(|header| std::str::from_utf8(header).ok())
+            .ok_or("повреждён заголовок .npy")?;
+
+        let field = |name: &str| {
+            let key = format!("'{name}':");
+            header
+                .find(&key)
+                .map(|position| header[position + key.len()..].trim_start())
+        };
+
+        let descr = field("descr")
+            .and_then(|value| value.strip_prefix('\'')?.split('\'').next())
+            .ok_or("в заголовке .npy нет типа")?;
+        let element = Element::from_descr(descr).ok_or(format!("тип {descr} не поддерживается"))?;
+        if field("fortran_order").is_some_and(|value| value.starts_with("True")) {
+            return Err("порядок Fortran не поддерживается".to_string());
+        }
+        let shape = field("shape")
+            .and_then(|value| value.strip_prefix('(')?.split(')').next())
+            .ok_or("в заголовке .npy нет размеров")?
+            .split(',')
+            .map(str::trim)
+            .filter(|dim| !dim.is_empty())
+            .map(|dim| {
+                dim.parse::<usize>()
+                    .map_err(|err| format!("размер {dim}: {err}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_raw(element, shape, bytes[start + length..].to_vec())
+    }
+
+    fn from_raw(element: Element, shape: Vec<usize>, data: Vec<u8>) -> Result<Self, String> {
+        let expected = shape.iter().product::<usize>() * element.size();
+        if data.len() != expected {
+            return Err(format!(
+                "размеры {shape:?}: ожидалось {expected} байт, получено {}",
+                data.len()
+            ));
+        }
+
+        Ok(Self {
+            element,
+            shape,
+            data,
+        })
+    }
+}
+
+/// Тензоры записей сессии; клавиши — по столбцам словаря из `key_count` клавиш,
+/// траектория мыши — по `aggregation.mouse_bins` отрезкам
+fn to_tensors(
+    records: &[(u64, MyConstData)],
+    key_count: usize,
+    aggregation: AggregationConfig,
+) -> BTreeMap<&'static str, Tensor> {
+    let n = records.len();
+    let format = records
+        .first()
+        .map_or_else(FrameFormat::default, |(_, data)| data.image.format);
+    let actions = || records.iter().map(|(_, data)| &data.keys_record);
+
+    let mut keys = vec![0u8; n * key_count];
+    let mut hold = vec![0f32; n * key_count];
+    for (row, record) in actions().enumerate() {
+        for (&key, &fraction) in record.keys.iter().zip(&record.hold) {
+            if key == KEY_PADDING || key as usize >= key_count {
+                continue;
+            }
+
+            let cell = row * key_count + key as usize;
+            keys[cell] = 1;
+            hold[cell] = hold[cell].max(fraction);
+        }
+    }
+
+    BTreeMap::from([
+        (
+            "frames",
+            Tensor::new(
+                vec![n, format.channels(), format.height, format.width],
+                records
+                    .iter()
+                    .flat_map(|(_, data)| data.image.pixels.iter().copied()),
+            ),
+        ),
+        (
+            "frame_index",
+            Tensor::new(vec![n], records.iter().map(|(frame, _)| *frame as i64)),
+        ),
+        ("keys", Tensor::new(vec![n, key_count], keys)),
+        ("key_hold", Tensor::new(vec![n, key_count], hold)),
+        (
+            "key_events",
+            Tensor::new(vec![n, 2], actions().flat_map(|record| record.key_events)),
+        ),
+        (
+            "mouse",
+            Tensor::new(
+                vec![n, aggregation.mouse_bins, 2],
+                actions().flat_map(|record| {
+                    record
+                        .mouse
+                        .into_iter()
+                        .take(aggregation.mouse_bins)
+                        .flatten()
+                }),
+            ),
+        ),
+        (
+            "position",
+            Tensor::new(vec![n, 2], actions().flat_map(|record| record.position)),
+        ),
+        (
+            "delta",
+            Tensor::new(vec![n, 2], actions().flat_map(|record| record.delta)),
+        ),
+        (
+            "buttons",
+            Tensor::new(
+                vec![n, MOUSE_BUTTONS],
+                actions().flat_map(|record| record.buttons),
+            ),
+        ),
+        (
+            "button_events",
+            Tensor::new(
+                vec![n, 2],
+                actions().flat_map(|record| record.button_events),
+            ),
+        ),
+        (
+            "wheel",
+            Tensor::new(vec![n, 2], actions().flat_map(|record| record.wheel)),
+        ),
+    ])
+}
+
+/// Значения тензора `name` по записям, `None`, если тензора нет
+fn column<T: Scalar>(
+    tensors: &HashMap<String, Tensor>,
+    name: &str,
+    shape: &[usize],
+) -> Result<Option<Vec<T>>, String> {
+    let Some(tensor) = tensors.get(name) else {
+        return Ok(None);
+    };
+
+    if tensor.element != T::ELEMENT {
+        return Err(format!(
+            "{name}: тип {:?}, ожидался {:?}",
+            tensor.element,
+            T::ELEMENT
+        ));
+    }
+    if tensor.shape != shape {
+        return Err(format!(
+            "{name}: размеры {:?}, ожидались {:?}",
+            tensor.shape, shape
+        ));
+    }
+
+    Ok(Some(tensor.values()))
+}
+
+/// Сведение действий из описания, без него — по размерам тензора мыши
+fn aggregation(
+    tensors: &HashMap<String, Tensor>,
+    metadata: &PortableMetadata,
+) -> Result<AggregationConfig, String> {
+    match (metadata.aggregation, tensors.get("mouse")) {
+        (Some(aggregation), _) => Ok(aggregation),
+        (None, Some(mouse)) => match mouse.shape[..] {
+            [_, mouse_bins, 2] => {
+                AggregationConfig::new(mouse_bins).map_err(|err| format!("mouse: {err}"))
+            }
+            _ => Err(format!("mouse: размеры {:?}, ожидались 3", mouse.shape)),
+        },
+        (None, None) => Ok(AggregationConfig::default()),
+    }
+}
+
+/// Записи из тензоров. Клавиши переводятся в номера `vocabulary` по именам из описания,
+/// траектория мыши читается по `aggregation.mouse_bins` отрезкам.
+fn from_tensors(
+    tensors: &HashMap<String, Tensor>,
+    metadata: &PortableMetadata,
+    aggregation: AggregationConfig,
+    vocabulary: &KeyVocabulary,
+) -> Result<(FrameFormat, Vec<(usize, MyConstData)>), String> {
+    let frames = tensors.get("frames").ok_or("нет тензора frames")?;
+    let [n, a, b, c] = frames.shape[..] else {
+        return Err(format!("frames: размеры {:?}, ожидались 4", frames.shape));
+    };
+    let (channels, height, width) = match metadata.axes {
+        FrameAxes::Nchw => (a, b, c),
+        FrameAxes::Nhwc => (c, a, b),
+    };
+    let format = match metadata.format {
+        Some(format) => format,
+        None => {
+            FrameFormat::from_channels(width, height, channels).map_err(|err| err.to_string())?
+        }
+    };
+    if (format.channels(), format.height, format.width) != (channels, height, width) {
+        return Err(format!(
+            "frames: размеры {:?} не совпадают с форматом {format:?}",
+            frames.shape
+        ));
+    }
+    let pixels = column::<u8>(tensors, "frames", &frames.shape)?.unwrap_or_default();
+
+    let frame_index = match column::<i64>(tensors, "frame_index", &[n])? {
+        Some(index) => index
+            .into_iter()
+            .map(|frame| usize::try_from(frame).map_err(|_| format!("frame_index: {frame}")))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => (0..n).collect(),
+    };
+
+    let key_count = match tensors.get("keys").map(|keys| &keys.shape[..]) {
+        Some(&[_, key_count]) => key_count,
+        Some(shape) => return Err(format!("keys: размеры {shape:?}, ожидались 2")),
+        None => 0,
+    };
+    let keys = column::<u8>(tensors, "keys", &[n, key_count])?;
+    let hold = column::<f32>(tensors, "key_hold", &[n, key_count])?;
+    let key_ids: Vec<u8> = (0..key_count)
+        .map(|column| match metadata.keys.get(column) {
+            Some(name) => vocabulary.key_to_num(name),
+            None if metadata.keys.is_empty() && column < vocabulary.size() => column as u8,
+            None => vocabulary.unknown_id(),
+        })
+        .collect();
+
+    let key_events = column::<u16>(tensors, "key_events", &[n, 2])?;
+    let mouse = column::<i32>(tensors, "mouse", &[n, aggregation.mouse_bins, 2])?;
+    let position = column::<f32>(tensors, "position", &[n, 2])?;
+    let delta = column::<i32>(tensors, "delta", &[n, 2])?;
+    let buttons = column::<u8>(tensors, "buttons", &[n, MOUSE_BUTTONS])?;
+    let button_events = column::<u16>(tensors, "button_events", &[n, 2])?;
+    let wheel = column::<i32>(tensors, "wheel", &[n, 2])?;
+
+    /// Строка `row` столбца как массив; пустой, если столбца нет
+    fn row<T: Scalar, const N: usize>(values: &Option<Vec<T>>, row: usize) -> [T; N] {
+        values.as_ref().map_or([T::default(); N], |values| {
+            values[row * N..(row + 1) * N].try_into().unwrap()
+        })
+    }
+
+    let mut records = Vec::with_capacity(n);
+    for (index, (frame, pixels)) in frame_index
+        .into_iter()
+        .zip(pixels.chunks_exact(format.pixel_count()))
+        .enumerate()
+    {
+        let pixels = match metadata.axes {
+            FrameAxes::Nchw => pixels.to_vec(),
+            FrameAxes::Nhwc => {
+                let plane = height * width;
+                let mut planar = vec![0; pixels.len()];
+                for (i, value) in pixels.iter().enumerate() {
+                    planar[(i % channels) * plane + i / channels] = *value;
+                }
+                planar
+            }
+        };
+
+        let mut keys_record = KeysRecordConst::from_slices(&[], &[]);
+        let mut slot = 0;
+        for (column, &id) in key_ids.iter().enumerate() {
+            let cell = index * key_count + column;
+            let pressed = keys.as_ref().is_some_and(|keys| keys[cell] != 0);
+            // Несколько столбцов могут попасть в одну клавишу словаря, например неизвестную
+            if !pressed || keys_record.keys[..slot].contains(&id) {
+                continue;
+            }
+            if slot == keys_record.keys.len() {
+                break;
+            }
+
+            keys_record.keys[slot] = id;
+            keys_record.hold[slot] = hold.as_ref().map_or(1.0, |hold| hold[cell]);
+            slot += 1;
+        }
+
+        if let Some(mouse) = &mouse {
+            let bins = aggregation.mouse_bins;
+            let trajectory = mouse[index * bins * 2..(index + 1) * bins * 2].chunks_exact(2);
+            for (point, value) in keys_record.mouse.iter_mut().zip(trajectory) {
+                *point = [value[0], value[1]];
+            }
+        }
+        keys_record.key_events = row(&key_events, index);
+        keys_record.position = row(&position, index);
+        keys_record.delta = row(&delta, index);
+        keys_record.buttons = row(&buttons, index);
+        keys_record.button_events = row(&button_events, index);
+        keys_record.wheel = row(&wheel, index);
+
+        let image = MyImage::from_pixels(format, pixels).map_err(|err| err.to_string())?;
+        records.push((frame, MyConstData { image, keys_record }));
+    }
+
+    Ok((format, records))
+}
+
+fn write_npz(
+    path: &Path,
+    tensors: &BTreeMap<&str, Tensor>,
+    metadata: &str,
+) -> Result<(), PreprocessError> {
+    let file = fs::File::create(path).at(path)?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+
+    let entries = tensors
+        .iter()
+        .map(|(name, tensor)| (format!("{name}.npy"), tensor.to_npy()))
+        .chain([(METADATA_FILE.to_string(), metadata.as_bytes().to_vec())]);
+    for (name, bytes) in entries {
+        // Как `numpy.savez`: без сжатия, большие файлы в формате zip64
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(bytes.len() as u64 >= u32::MAX as u64);
+        zip.start_file(name, options).at(path)?;
+        zip.write_all(&bytes).at(path)?;
+    }
+
+    zip.finish().at(path)?.flush().at(path)
+}
+
+fn read_npz(path: &Path) -> Result<(HashMap<String, Tensor>, Option<String>), PreprocessError> {
+    let mut zip = ZipArchive::new(fs::File::open(path).at(path)?).at(path)?;
+    let mut tensors = HashMap::new();
+    let mut metadata = None;
+
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index).at(path)?;
+        let name = entry.name().to_string();
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut bytes).at(path)?;
+
+        if name == METADATA_FILE {
+            metadata = Some(String::from_utf8(bytes).map_err(|err| {
+                PreprocessError::invalid_data(path, format!("{METADATA_FILE}: {err}"))
+            })?);
+        } else if let Some(name) = name.strip_suffix(".npy") {
+            let tensor = Tensor::from_npy(&bytes)
+                .map_err(|err| PreprocessError::invalid_data(path, format!("{name}: {err}")))?;
+            tensors.insert(name.to_string(), tensor);
+        }
+    }
+
+    Ok((tensors, metadata))
+}
+
+fn write_safetensors(
+    path: &Path,
+    tensors: &BTreeMap<&str, Tensor>,
+    metadata: &str,
+) -> Result<(), PreprocessError> {
+    let views = tensors
+        .iter()
+        .map(|(name, tensor)| {
+            TensorView::new(tensor.element.dtype(), tensor.shape.clone(), &tensor.data)
+                .map(|view| (*name, view))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .at(path)?;
+    let info = HashMap::from([(METADATA_KEY.to_string(), metadata.to_string())]);
+
+    serialize_to_file(views, Some(info), path).at(path)
+}
+
+fn read_safetensors(
+    path: &Path,
+) -> Result<(HashMap<String, Tensor>, Option<String>), PreprocessError> {
+    let bytes = fs::read(path).at(path)?;
+    let (_, header) = SafeTensors::read_metadata(&bytes).at(path)?;
+    let metadata = header
+        .metadata()
+        .as_ref()
+        .and_then(|info| info.get(METADATA_KEY).cloned());
+
+    let mut tensors = HashMap::new();
+    for (name, view) in SafeTensors::deserialize(&bytes).at(path)?.tensors() {
+        let tensor = Element::from_dtype(view.dtype())
+            .ok_or(format!("тип {:?} не поддерживается", view.dtype()))
+            .and_then(|element| {
+                Tensor::from_raw(element, view.shape().to_vec(), view.data().to_vec())
+            })
+            .map_err(|err| PreprocessError::invalid_data(path, format!("{name}: {err}")))?;
+        tensors.insert(name, tensor);
+    }
+
+    Ok((tensors, metadata))
+}
+
+/// Выгрузка датасета `data_path` (директория или отдельный файл) в `out_dir`,
+/// по файлу на сессию. Клавиши — по столбцам `vocabulary`, которым закодирован датасет.
+/// Возвращает пути записанных файлов.
+pub fn export_dataset(
+    data_path: &Path,
+    out_dir: &Path,
+    format: PortableFormat,
+    vocabulary: &KeyVocabulary,
+) -> Result<Vec<PathBuf>, PreprocessError> {
+    // Для отдельного файла имя сессии — его директория
+    let root = if data_path.is_file() {
+        data_path
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""))
+    } else {
+        data_path
+    };
+    let key_names: Vec<String> = (0..vocabulary.size())
+        .map(|id| {
+            vocabulary
+                .num_to_key(id as u8)
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect();
+
+    let files = find_hdf5_files(data_path).at(data_path)?;
+    fs::create_dir_all(out_dir).at(out_dir)?;
+    let mut written = Vec::new();
+
+    for file in files {
+        let reader = Hdf5Reader::open(&file, 1).at(&file)?;
+        let records = (0..reader.len())
+            .map(|index| {
+                let data = reader.read(index).at(&file)?.unwrap();
+                let (_, frame) = reader.source(index).unwrap();
+
+                Ok((frame, data))
+            })
+            .collect::<Result<Vec<_>, PreprocessError>>()?;
+
+        let session = dataset_name(root, &file);
+        let metadata = PortableMetadata {
+            version: PORTABLE_VERSION,
+            session: Some(session.clone()),
+            format: Some(reader.format()),
+            aggregation: Some(reader.aggregation()),
+            axes: FrameAxes::Nchw,
+            keys: key_names.clone(),
+        };
+        let metadata = serde_json::to_string_pretty(&metadata)
+            .map_err(|err| PreprocessError::invalid_data(&file, err.to_string()))?;
+        let tensors = to_tensors(&records, vocabulary.size(), reader.aggregation());
+
+        let name = session.replace(['/', '\\'], "_");
+        let path = out_dir.join(format!("{name}.{}", format.extension()));
+        match format {
+            PortableFormat::Npz => write_npz(&path, &tensors, &metadata)?,
+            PortableFormat::Safetensors => write_safetensors(&path, &tensors, &metadata)?,
+        }
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Чтение файла обмена; клавиши кодируются `vocabulary`
+pub fn read_portable(
+    path: &Path,
+    vocabulary: &KeyVocabulary,
+) -> Result<PortableSession, PreprocessError> {
+    let (tensors, metadata) = match PortableFormat::from_path(path) {
+        Some(PortableFormat::Npz) => read_npz(path)?,
+        Some(PortableFormat::Safetensors) => read_safetensors(path)?,
+        None => {
+            return Err(PreprocessError::invalid_data(
+                path,
+                "ожидается файл .npz или .safetensors",
+            ));
+        }
+    };
+
+    let metadata: PortableMetadata = match metadata {
+        Some(json) => serde_json::from_str(&json)
+            .map_err(|err| PreprocessError::invalid_data(path, format!("описание: {err}")))?,
+        None => PortableMetadata::default(),
+    };
+    if metadata.version > PORTABLE_VERSION {
+        return Err(PreprocessError::invalid_data(
+            path,
+            format!("версия файла {} новее поддерживаемой", metadata.version),
+        ));
+    }
+
+    let session = match &metadata.session {
+        Some(session) => session.clone(),
+        None => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    let nested = Path::new(&session).components().collect::<Vec<_>>();
+    if nested.is_empty()
+        || !nested
+            .iter()
+            .all(|part| matches!(part, Component::Normal(_)))
+    {
+        return Err(PreprocessError::invalid_data(
+            path,
+            format!("недопустимое имя сессии {session:?}"),
+        ));
+    }
+
+    let aggregation = aggregation(&tensors, &metadata)
+        .map_err(|message| PreprocessError::invalid_data(path, message))?;
+    let (format, records) = from_tensors(&tensors, &metadata, aggregation, vocabulary)
+        .map_err(|message| PreprocessError::invalid_data(path, message))?;
+
+    Ok(PortableSession {
+        session,
+        format,
+        aggregation,
+        records,
+    })
+}
+
+/// Загрузка файла обмена или всех таких файлов директории в датасет
+/// `data_path/<сессия>/`. Уже записанные кадры сессии пропускаются, поэтому
+/// повторная загрузка ничего не добавляет. Возвращает сессии и число добавленных записей.
+pub fn import_files(
+    path: &Path,
+    data_path: &Path,
+    vocabulary: &KeyVocabulary,
+) -> Result<Vec<(String, usize)>, PreprocessError> {
+    let files = if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)
+            .at(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()
+            .at(path)?;
+        files.retain(|file| PortableFormat::from_path(file).is_some());
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut imported = Vec::new();
+    for file in files {
+        let PortableSession {
+            session,
+            format,
+            aggregation,
+            records,
+        } = read_portable(&file, vocabulary)?;
+
+        let dataset_path = data_path.join(&session);
+        let options = WriterOptions {
+            aggregation,
+            ..Default::default()
+        };
+        let added =
+            append_to_dataset(&dataset_path, &records, format, options).at(&dataset_path)?;
+        imported.push((session, added));
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hdf5_processing::DATASET_FILE, temp_dir};
+    use common::frame::ChannelLayout;
+
+    fn record(value: u8) -> MyConstData {
+        let format = FrameFormat::new(3, 2, ChannelLayout::Rgb).unwrap();
+        let pixels = (0..format.pixel_count() as u8)
+            .map(|i| i.wrapping_add(value))
+            .collect();
+
+        let mut keys_record = KeysRecordConst::from_slices(&[1, value], &[[value as i32, -1]]);
+        keys_record.hold[1] = 0.5;
+        keys_record.position = [value as f32, 0.25];
+        keys_record.buttons = [1, 0, value % 2];
+        keys_record.wheel = [0, -(value as i32)];
+        keys_record.key_events = [2, 1];
+
+        MyConstData {
+            image: MyImage::from_pixels(format, pixels).unwrap(),
+            keys_record,
+        }
+    }
+
+    fn assert_same(left: &MyConstData, right: &MyConstData) {
+        assert_eq!(left.image, right.image);
+        let (left, right) = (&left.keys_record, &right.keys_record);
+        assert_eq!(left.keys, right.keys);
+        assert_eq!(left.hold, right.hold);
+        assert_eq!(left.mouse, right.mouse);
+        assert_eq!(left.position, right.position);
+        assert_eq!(left.delta, right.delta);
+        assert_eq!(left.buttons, right.buttons);
+        assert_eq!(left.wheel, right.wheel);
+        assert_eq!(left.key_events, right.key_events);
+    }
+
+    /// Test sessions exported to npz and safetensors are imported back unchanged and only once
+    #[test]
+    fn test_export_import_round_trip() {
+        let dir = temp_dir("test_portable_round_trip");
+        let vocabulary = KeyVocabulary::default();
+        let format = record(0).image.format;
+
+        let first: Vec<_> = [0, 1, 3]
+            .map(|index| (index, record(index as u8 + 2)))
+            .into();
+        let second: Vec<_> = vec![(5, record(7))];
+        let source = dir.join("source");
+        let options = WriterOptions {
+            aggregation: AggregationConfig::new(2).unwrap(),
+            ..Default::default()
+        };
+        append_to_dataset(&source.join("a"), &first, format, options).unwrap();
+        append_to_dataset(&source.join("b"), &second, format, options).unwrap();
+
+        for portable in [PortableFormat::Npz, PortableFormat::Safetensors] {
+            let out = dir.join(portable.extension());
+            let files = export_dataset(&source, &out, portable, &vocabulary).unwrap();
+            assert_eq!(files.len(), 2);
+            assert_eq!(PortableFormat::from_path(&files[0]), Some(portable));
+
+            let target = dir.join(format!("{portable}_dataset"));
+            let imported = import_files(&out, &target, &vocabulary).unwrap();
+            assert_eq!(imported, [("a".to_string(), 3), ("b".to_string(), 1)]);
+            assert_eq!(
+                import_files(&files[0], &target, &vocabulary).unwrap(),
+                [("a".to_string(), 0)]
+            );
+
+            let reader = Hdf5Reader::open(&target.join("a").join(DATASET_FILE), 1).unwrap();
+            assert_eq!(reader.format(), format);
+            assert_eq!(reader.aggregation(), options.aggregation);
+            for (index, (frame, data)) in first.iter().enumerate() {
+                assert_eq!(reader.source(index).unwrap().1, *frame as u64);
+                assert_same(&reader.read(index).unwrap().unwrap(), data);
+            }
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test files prepared elsewhere load with defaults, channel-last frames and named keys
+    #[test]
+    fn test_read_external_files() {
+        let dir = temp_dir("test_portable_external");
+        let vocabulary = KeyVocabulary::default();
+
+        // Только кадры и клавиши, без описания: формат по размерам, столбцы — номера словаря
+        let frames = Tensor::new(vec![2, 1, 2, 3], 0..12u8);
+        let keys = Tensor::new(vec![2, 4], [0, 1, 0, 0, 0, 0, 0, 1u8]);
+        let path = dir.join("episode.safetensors");
+        let views = [("frames", &frames), ("keys", &keys)].map(|(name, tensor)| {
+            let view = TensorView::new(tensor.element.dtype(), tensor.shape.clone(), &tensor.data);
+            (name, view.unwrap())
+        });
+        serialize_to_file(views, None, &path).unwrap();
+
+        let session = read_portable(&path, &vocabulary).unwrap();
+        assert_eq!(session.session, "episode");
+        assert_eq!(
+            session.format,
+            FrameFormat::new(3, 2, ChannelLayout::Gray).unwrap()
+        );
+        assert_eq!(session.aggregation, AggregationConfig::default());
+        let (frame, data) = &session.records[1];
+        assert_eq!(*frame, 1);
+        assert_eq!(data.image.pixels, (6..12).collect::<Vec<u8>>());
+        assert_eq!(data.keys_record.keys[..2], [3, KEY_PADDING]);
+        assert_eq!(data.keys_record.hold[0], 1.0);
+
+        // Кадры `[запись, y, x, канал]` и клавиши по именам
+        let frames = Tensor::new(vec![1, 1, 2, 3], [1, 2, 3, 4, 5, 6u8]);
+        let keys = Tensor::new(vec![1, 2], [1, 1u8]);
+        let metadata = PortableMetadata {
+            session: Some("external".to_string()),
+            axes: FrameAxes::Nhwc,
+            keys: vec!["KeyW".to_string(), "no such key".to_string()],
+            ..PortableMetadata::default()
+        };
+        let path = dir.join("episode.npz");
+        let tensors = BTreeMap::from([("frames", frames), ("keys", keys)]);
+        write_npz(&path, &tensors, &serde_json::to_string(&metadata).unwrap()).unwrap();
+
+        let session = read_portable(&path, &vocabulary).unwrap();
+        assert_eq!(session.session, "external");
+        assert_eq!(
+            session.format,
+            FrameFormat::new(2, 1, ChannelLayout::Rgb).unwrap()
+        );
+        let data = &session.records[0].1;
+        assert_eq!(data.image.pixels, [1, 4, 2, 5, 3, 6]);
+        assert_eq!(
+            data.keys_record.keys[..3],
+            [
+                vocabulary.key_to_num("KeyW"),
+                vocabulary.unknown_id(),
+                KEY_PADDING
+            ]
+        );
+
+        // Клавиши — только таблица `[запись, столбец]`
+        for shape in [vec![], vec![1], vec![1, 1, 1]] {
+            let keys = Tensor::new(shape.clone(), vec![1u8; shape.iter().product()]);
+            let path = dir.join("keys.safetensors");
+            let views = [("frames", &tensors["frames"]), ("keys", &keys)].map(|(name, tensor)| {
+                let view =
+                    TensorView::new(tensor.element.dtype(), tensor.shape.clone(), &tensor.data);
+                (name, view.unwrap())
+            });
+            serialize_to_file(views, None, &path).unwrap();
+            assert!(read_portable(&path, &vocabulary).is_err(), "{shape:?}");
+        }
+
+        // Имя сессии не должно выводить за пределы датасета
+        let metadata = PortableMetadata {
+            session: Some("../outside".to_string()),
+            ..PortableMetadata::default()
+        };
+        write_npz(&path, &tensors, &serde_json::to_string(&metadata).unwrap()).unwrap();
+        assert!(read_portable(&path, &vocabulary).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Test npy headers are aligned and parsed back, including numpy's own spelling
+    #[test]
+    fn test_npy_round_trip() {
+        let tensor = Tensor::new(vec![2, 3], [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let bytes = tensor.to_npy();
+        assert!((bytes.len() - tensor.data.len()).is_multiple_of(64));
+        assert_eq!(Tensor::from_npy(&bytes).unwrap(), tensor);
+
+        let header = "{'descr': '<i8', 'fortran_order': False, 'shape': (2,), }";
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend([7i64, -1].iter().flat_map(|value| value.to_le_bytes()));
+        let tensor = Tensor::from_npy(&bytes).unwrap();
+        assert_eq!(tensor.shape, [2]);
+        assert_eq!(tensor.values::<i64>(), [7, -1]);
+
+        bytes.pop();
+        assert!(Tensor::from_npy(&bytes).is_err());
+    }
+}
@@ -0,0 +1,494 @@
+//! Поиск и обработка записанных сессий `data/sessions/<session_id>/`.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use common::{
+    aggregation::AggregationConfig,
+    frame::FrameFormat,
+    session::{
+        CHECKPOINT_FILE, EVENTS_FILE, FRAMES_DIR, FRAMES_INDEX_FILE, LEGACY_EVENTS_FILE,
+        MANIFEST_FILE, RESIZED_DIR, SESSIONS_DIR, SessionManifest, TEMP_SUFFIX, temp_path,
+        write_atomic,
+    },
+};
+
+use crate::{
+    alignment::{FrameStamp, align_events, load_frame_stamps},
+    csv_processing::load_events,
+    error::{ErrorPath, PreprocessError},
+    event_log::{EventLogWriter, EventRecord, InputEvent, load_event_log},
+    images::{ImageData, MyImage},
+    types::MyConstData,
+    vocabulary::KeyVocabulary,
+};
+
+/// Что было исправлено при восстановлении прерванной сессии
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecoveryReport {
+    pub frames_kept: usize,
+    /// Строки индекса без целого файла кадра
+    pub frames_removed: usize,
+    /// Файлы кадров, которых нет в индексе
+    pub orphan_files_removed: usize,
+    pub temp_files_removed: usize,
+    pub events_kept: usize,
+    /// Клавиши и кнопки, которые остались нажатыми к моменту обрыва
+    pub keys_released: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub dir: PathBuf,
+    pub manifest: SessionManifest,
+}
+
+impl Session {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let manifest = SessionManifest::load(&dir.join(MANIFEST_FILE))?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            manifest,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.manifest.session_id
+    }
+
+    pub fn frames_dir(&self) -> PathBuf {
+        self.dir.join(FRAMES_DIR)
+    }
+
+    pub fn frames_index(&self) -> PathBuf {
+        self.dir.join(FRAMES_INDEX_FILE)
+    }
+
+    pub fn events_path(&self) -> PathBuf {
+        self.dir.join(EVENTS_FILE)
+    }
+
+    /// События сессии: двоичный журнал, а у сессий, записанных до него, — `events.csv`
+    pub fn load_events(&self) -> io::Result<Vec<EventRecord>> {
+        let legacy_path = self.dir.join(LEGACY_EVENTS_FILE);
+
+        if !self.events_path().exists() && legacy_path.exists() {
+            return load_events(&legacy_path);
+        }
+
+        load_event_log(&self.events_path())
+    }
+
+    pub fn resized_dir(&self) -> PathBuf {
+        self.dir.join(RESIZED_DIR)
+    }
+
+    /// Кадры сессии вместе с действиями, выровненными по меткам времени, и номерами кадров.
+    /// Кадры, для которых нет уменьшенного изображения, пропускаются; остальные
+    /// приводятся к формату `format`, действия сводятся по `aggregation`.
+    pub fn align(
+        &self,
+        vocabulary: &KeyVocabulary,
+        format: FrameFormat,
+        aggregation: AggregationConfig,
+    ) -> Result<Vec<(usize, MyConstData)>, PreprocessError> {
+        self.align_except(vocabulary, format, aggregation, |_| false)
+    }
+
+    /// Уменьшенный кадр с номером `index`, приведённый к формату `format`;
+    /// `None`, если такого кадра нет в индексе или уменьшенного изображения нет на диске
+    pub fn load_resized(
+        &self,
+        index: usize,
+        format: FrameFormat,
+    ) -> Result<Option<MyImage>, PreprocessError> {
+        let frames = load_frame_stamps(&self.frames_index()).at(&self.frames_index())?;
+        let Some(frame) = frames.iter().find(|frame| frame.index == index) else {
+            return Ok(None);
+        };
+
+        let image_path = self.resized_dir().join(&frame.file);
+        if !image_path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(MyImage::from_image_data(
+            &ImageData::new(image_path),
+            format,
+        )?))
+    }
+
+    /// То же, что [`Session::align`], но без кадров, для которых `skip` вернул `true`.
+    /// Действия выравниваются по всем кадрам, изображения загружаются только для нужных.
+    pub fn align_except(
+        &self,
+        vocabulary: &KeyVocabulary,
+        format: FrameFormat,
+        aggregation: AggregationConfig,
+        skip: impl Fn(usize) -> bool,
+    ) -> Result<Vec<(usize, MyConstData)>, PreprocessError> {
+        let frames = load_frame_stamps(&self.frames_index()).at(&self.frames_index())?;
+        let events = self.load_events().at(&self.events_path())?;
+
+        let resized_dir = self.resized_dir();
+        let keys_records = align_events(&frames, &events, vocabulary, aggregation.mouse_bins);
+
+        frames
+            .iter()
+            .zip(keys_records)
+            .filter(|(frame, _)| !skip(frame.index))
+            .filter_map(|(frame, keys_record)| {
+                let image_path = resized_dir.join(&frame.file);
+
+                image_path.exists().then(|| {
+                    let image = MyImage::from_image_data(&ImageData::new(image_path), format)?;
+
+                    Ok((frame.index, MyConstData { image, keys_record }))
+                })
+            })
+            .collect()
+    }
+
+    /// Метка незавершённой записи остаётся на диске, только если запись не завершилась штатно
+    pub fn is_interrupted(&self) -> bool {
+        self.dir.join(CHECKPOINT_FILE).exists()
+    }
+
+    /// Приведение прерванной сессии к согласованному состоянию, после которого её можно
+    /// обработать или продолжить запись:
+    /// - удаляются временные файлы;
+    /// - из индекса убираются оборванная последняя строка и кадры без целого файла,
+    ///   а файлы кадров вне индекса удаляются;
+    /// - из журнала событий убирается неполный хвост, удерживаемые клавиши отпускаются;
+    /// - датасет, записанный на лету, удаляется: он пересобирается из кадров;
+    /// - манифест обновляется, метка незавершённой записи удаляется.
+    pub fn recover(&mut self) -> io::Result<RecoveryReport> {
+        let mut report = RecoveryReport::default();
+
+        let image_dirs: Vec<PathBuf> = [self.frames_dir(), self.resized_dir()]
+            .into_iter()
+            .filter(|dir| dir.exists())
+            .collect();
+
+        for dir in image_dirs.iter().chain([&self.dir]) {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+
+                if path.to_string_lossy().ends_with(TEMP_SUFFIX) {
+                    fs::remove_file(&path)?;
+                    report.temp_files_removed += 1;
+                }
+            }
+        }
+
+        let frames = load_complete_frame_stamps(&self.frames_index())?;
+        let total = frames.len();
+        let frames: Vec<FrameStamp> = frames
+            .into_iter()
+            .filter(|frame| {
+                !image_dirs.is_empty()
+                    && image_dirs
+                        .iter()
+                        .all(|dir| image::image_dimensions(dir.join(&frame.file)).is_ok())
+            })
+            .collect();
+        report.frames_kept = frames.len();
+        report.frames_removed = total - frames.len();
+        write_frame_stamps(&self.frames_index(), &frames)?;
+
+        let files: HashSet<&str> = frames.iter().map(|frame| frame.file.as_str()).collect();
+        for dir in &image_dirs {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+
+                if !files.contains(entry.file_name().to_string_lossy().as_ref()) {
+                    fs::remove_file(entry.path())?;
+                    report.orphan_files_removed += 1;
+                }
+            }
+        }
+
+        if self.events_path().exists() {
+            let mut events = load_event_log(&self.events_path())?;
+            report.events_kept = events.len();
+
+            let end_us = events
+                .last()
+                .into_iter()
+                .map(|event| event.timestamp_us)
+                .chain(frames.last().map(|frame| frame.timestamp_us))
+                .max()
+                .unwrap_or(0);
+            let releases = held_at_end(&events);
+            report.keys_released = releases.len();
+
+            events.extend(releases.into_iter().map(|event| EventRecord {
+                timestamp_us: end_us,
+                event,
+            }));
+
+            let temp = temp_path(&self.events_path());
+            let mut writer = EventLogWriter::create(&temp)?;
+            for event in &events {
+                writer.write(event)?;
+            }
+            writer.flush()?;
+            fs::rename(&temp, self.events_path())?;
+        }
+
+        if let Some(data_dir) = self.dir.parent().and_then(Path::parent) {
+            let dataset_dir = data_dir.join("hdf5_files").join(self.id());
+
+            if dataset_dir.exists() {
+                fs::remove_dir_all(dataset_dir)?;
+            }
+        }
+
+        if self.manifest.native_resolution == [0, 0]
+            && let Some(frame) = frames.first()
+            && let Ok((width, height)) =
+                image::image_dimensions(self.frames_dir().join(&frame.file))
+        {
+            self.manifest.native_resolution = [width, height];
+        }
+        self.manifest.frame_count = frames.len();
+        self.manifest.dataset_written = false;
+        self.manifest.save(&self.dir.join(MANIFEST_FILE))?;
+
+        fs::remove_file(self.dir.join(CHECKPOINT_FILE))?;
+
+        Ok(report)
+    }
+}
+
+/// Индекс кадров без последней строки, если она оборвана на середине
+fn load_complete_frame_stamps(path: &Path) -> io::Result<Vec<FrameStamp>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut contents = fs::read(path)?;
+    let complete = contents
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |i| i + 1);
+    contents.truncate(complete);
+
+    let mut frames = Vec::new();
+
+    let mut reader = csv::Reader::from_reader(contents.as_slice());
+    for result in reader.deserialize() {
+        let record: FrameStamp = result?;
+        frames.push(record);
+    }
+
+    Ok(frames)
+}
+
+fn write_frame_stamps(path: &Path, frames: &[FrameStamp]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for frame in frames {
+        writer.serialize(frame)?;
+    }
+
+    let contents = writer.into_inner().map_err(|error| error.into_error())?;
+
+    write_atomic(path, &contents)
+}
+
+/// Отпускания для клавиш и кнопок, которые остались нажатыми в конце журнала
+fn held_at_end(events: &[EventRecord]) -> Vec<InputEvent> {
+    let mut keys = BTreeSet::new();
+    let mut buttons = BTreeSet::new();
+
+    for event in events {
+        match &event.event {
+            InputEvent::KeyPress(key) => {
+                keys.insert(key.clone());
+            }
+            InputEvent::KeyRelease(key) => {
+                keys.remove(key);
+            }
+            InputEvent::ButtonPress(button) => {
+                buttons.insert(button.clone());
+            }
+            InputEvent::ButtonRelease(button) => {
+                buttons.remove(button);
+            }
+            _ => {}
+        }
+    }
+
+    keys.into_iter()
+        .map(InputEvent::KeyRelease)
+        .chain(buttons.into_iter().map(InputEvent::ButtonRelease))
+        .collect()
+}
+
+/// Все сессии в `data_dir/sessions`, от старых к новым.
+/// Директории без манифеста (например, прерванная запись) пропускаются с предупреждением.
+pub fn discover_sessions(data_dir: &Path) -> io::Result<Vec<Session>> {
+    let sessions_dir = data_dir.join(SESSIONS_DIR);
+
+    if !sessions_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut sessions = Vec::new();
+
+    for entry in fs::read_dir(&sessions_dir)? {
+        let path = entry?.path();
+
+        if !path.is_dir() {
+            continue;
+        }
+
+        match Session::open(&path) {
+            Ok(session) => sessions.push(session),
+            Err(err) => println!("Пропущена сессия {:?}: {}", path, err),
+        }
+    }
+
+    sessions.sort_by(|a, b| {
+        (a.manifest.started_at_unix_ms, a.id()).cmp(&(b.manifest.started_at_unix_ms, b.id()))
+    });
+
+    Ok(sessions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+
+    fn manifest(session_id: &str, started_at_unix_ms: u64) -> SessionManifest {
+        SessionManifest {
+            session_id: session_id.to_string(),
+            started_at_unix_ms,
+            monitor_name: "test".to_string(),
+            capture_target: Some("primary".to_string()),
+            native_resolution: [8, 8],
+            target_fps: 20.0,
+            key_vocabulary_version: common::session::KEY_VOCABULARY_VERSION,
+            frame_count: 0,
+            tags: vec!["test".to_string()],
+            dataset_written: false,
+        }
+    }
+
+    #[test]
+    fn test_discover_sessions_sorted_by_start() {
+        let data_dir = temp_dir("test_discover_sessions_sorted");
+
+        for (id, started_at) in [("session-b", 200), ("session-a", 100)] {
+            let dir = data_dir.join(SESSIONS_DIR).join(id);
+            fs::create_dir_all(&dir).unwrap();
+            manifest(id, started_at)
+                .save(&dir.join(MANIFEST_FILE))
+                .unwrap();
+        }
+        // Прерванная запись без манифеста
+        fs::create_dir_all(data_dir.join(SESSIONS_DIR).join("broken")).unwrap();
+
+        let sessions = discover_sessions(&data_dir).unwrap();
+        let ids: Vec<&str> = sessions.iter().map(|s| s.id()).collect();
+
+        assert_eq!(ids, vec!["session-a", "session-b"]);
+        assert_eq!(sessions[0].manifest, manifest("session-a", 100));
+
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    /// Test an interrupted session is cut back to complete frames and events
+    #[test]
+    fn test_recover_interrupted_session() {
+        let data_dir = temp_dir("test_recover_interrupted_session");
+
+        let dir = data_dir.join(SESSIONS_DIR).join("session-a");
+        let frames_dir = dir.join(FRAMES_DIR);
+        fs::create_dir_all(&frames_dir).unwrap();
+        manifest("session-a", 100)
+            .save(&dir.join(MANIFEST_FILE))
+            .unwrap();
+        fs::write(dir.join(CHECKPOINT_FILE), "{}").unwrap();
+
+        // Кадр 2 не успел сохраниться, строка кадра 3 оборвана, кадр 4 есть только на диске
+        for i in [0, 1, 4] {
+            image::RgbaImage::new(8, 6)
+                .save(frames_dir.join(format!("image-{i}.png")))
+                .unwrap();
+        }
+        fs::write(frames_dir.join("image-2.png.tmp"), "partial").unwrap();
+        fs::write(
+            dir.join(FRAMES_INDEX_FILE),
+            "index,file,timestamp_us\n0,image-0.png,0\n1,image-1.png,50000\n2,image-2.png,100000\n3,image-3.pn",
+        )
+        .unwrap();
+
+        let mut writer = EventLogWriter::create(&dir.join(EVENTS_FILE)).unwrap();
+        for (timestamp_us, event) in [
+            (10_000, InputEvent::KeyPress("KeyW".to_string())),
+            (20_000, InputEvent::ButtonPress("Left".to_string())),
+            (30_000, InputEvent::ButtonRelease("Left".to_string())),
+        ] {
+            writer
+                .write(&EventRecord {
+                    timestamp_us,
+                    event,
+                })
+                .unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut bytes = fs::read(dir.join(EVENTS_FILE)).unwrap();
+        bytes.extend([0, 1, 2]); // Оборванное событие
+        fs::write(dir.join(EVENTS_FILE), bytes).unwrap();
+
+        let mut session = Session::open(&dir).unwrap();
+        assert!(session.is_interrupted());
+
+        let report = session.recover().unwrap();
+
+        assert_eq!(
+            report,
+            RecoveryReport {
+                frames_kept: 2,
+                frames_removed: 1,
+                orphan_files_removed: 1,
+                temp_files_removed: 1,
+                events_kept: 3,
+                keys_released: 1,
+            }
+        );
+        assert!(!session.is_interrupted());
+        assert_eq!(Session::open(&dir).unwrap().manifest.frame_count, 2);
+
+        let frames = load_frame_stamps(&session.frames_index()).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(fs::read_dir(&frames_dir).unwrap().count(), 2);
+
+        let events = session.load_events().unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[3],
+            EventRecord {
+                timestamp_us: 50_000,
+                event: InputEvent::KeyRelease("KeyW".to_string()),
+            }
+        );
+
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn test_discover_sessions_without_sessions_dir() {
+        let data_dir = temp_dir("test_discover_sessions_missing");
+
+        assert!(discover_sessions(&data_dir).unwrap().is_empty());
+    }
+}
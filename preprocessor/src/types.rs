@@ -1,11 +1,9 @@
-use common::*;
-use hdf5_metno::H5Type;
-
 use crate::{csv_processing::KeysRecordConst, images::MyImage};
 
-#[derive(Clone, Debug, H5Type)]
-#[repr(C)]
+/// Запись датасета. В файле hdf5 действия и кадры лежат в разных наборах данных:
+/// размер кадра задаётся форматом файла, см. [`crate::hdf5_processing`]
+#[derive(Clone, Debug)]
 pub struct MyConstData {
-    pub image: MyImage<HEIGHT, WIDTH, CHANNELS>,
+    pub image: MyImage,
     pub keys_record: KeysRecordConst,
 }
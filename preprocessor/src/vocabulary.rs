@@ -0,0 +1,359 @@
+//! Словарь клавиш: соответствие имён клавиш номерам в [`KeysRecordConst::keys`].
+//!
+//! Словарь хранится в JSON-файле [`KEY_VOCABULARY_FILE`] в каталоге данных:
+//!
+//! ```json
+//! { "version": 1, "keys": [["KeyW", "w"], ["KeyA", "a"], ["KeyS", "s"], ["KeyD", "d"]] }
+//! ```
+//!
+//! Номер клавиши — её позиция в `keys`, первое имя — основное, остальные — синонимы.
+//! Имена сравниваются без учёта регистра. Все клавиши не из словаря попадают в
+//! отдельную корзину неизвестных с последним номером, поэтому размер словаря на
+//! единицу больше числа клавиш. Без файла используется словарь по умолчанию.
+//!
+//! [`KeysRecordConst::keys`]: crate::csv_processing::KeysRecordConst::keys
+
+use std::{collections::HashMap, fs, io, path::Path, sync::LazyLock};
+
+use common::session::{KEY_VOCABULARY_VERSION, write_atomic};
+use serde::{Deserialize, Serialize};
+
+/// Файл словаря в каталоге данных и в каталоге артефактов модели
+pub const KEY_VOCABULARY_FILE: &str = "key_vocabulary.json";
+
+/// Заполнитель свободных ячеек в [`KeysRecordConst::keys`], не является номером клавиши
+///
+/// [`KeysRecordConst::keys`]: crate::csv_processing::KeysRecordConst::keys
+pub const KEY_PADDING: u8 = u8::MAX;
+
+/// Имя корзины неизвестных клавиш в [`KeyVocabulary::num_to_key`]
+pub const UNKNOWN_KEY: &str = "<unknown>";
+
+/// Клавиши словаря по умолчанию в порядке номеров, как их называет `rdev`, с синонимами
+const DEFAULT_KEYS: &[&[&str]] = &[
+    &["Alt"],
+    &["AltGr"],
+    &["Backspace"],
+    &["CapsLock"],
+    &["ControlLeft", "Control"],
+    &["ControlRight"],
+    &["Delete"],
+    &["DownArrow"],
+    &["End"],
+    &["Escape"],
+    &["F1"],
+    &["F10"],
+    &["F11"],
+    &["F12"],
+    &["F2"],
+    &["F3"],
+    &["F4"],
+    &["F5"],
+    &["F6"],
+    &["F7"],
+    &["F8"],
+    &["F9"],
+    &["Home"],
+    &["LeftArrow"],
+    &["MetaLeft"],
+    &["MetaRight"],
+    &["PageDown"],
+    &["PageUp"],
+    &["Return"],
+    &["RightArrow"],
+    &["ShiftLeft", "Shift"],
+    &["ShiftRight"],
+    &["Space"],
+    &["Tab"],
+    &["UpArrow"],
+    &["PrintScreen"],
+    &["ScrollLock"],
+    &["Pause"],
+    &["NumLock"],
+    &["BackQuote"],
+    &["Num1", "1"],
+    &["Num2", "2"],
+    &["Num3", "3"],
+    &["Num4", "4"],
+    &["Num5", "5"],
+    &["Num6", "6"],
+    &["Num7", "7"],
+    &["Num8", "8"],
+    &["Num9", "9"],
+    &["Num0", "0"],
+    &["Minus", "-"],
+    &["Equal", "="],
+    &["KeyQ", "q"],
+    &["KeyW", "w"],
+    &["KeyE", "e"],
+    &["KeyR", "r"],
+    &["KeyT", "t"],
+    &["KeyY", "y"],
+    &["KeyU", "u"],
+    &["KeyI", "i"],
+    &["KeyO", "o"],
+    &["KeyP", "p"],
+    &["LeftBracket"],
+    &["RightBracket"],
+    &["KeyA", "a"],
+    &["KeyS", "s"],
+    &["KeyD", "d"],
+    &["KeyF", "f"],
+    &["KeyG", "g"],
+    &["KeyH", "h"],
+    &["KeyJ", "j"],
+    &["KeyK", "k"],
+    &["KeyL", "l"],
+    &["SemiColon"],
+    &["Quote"],
+    &["BackSlash"],
+    &["IntlBackslash"],
+    &["KeyZ", "z"],
+    &["KeyX", "x"],
+    &["KeyC", "c"],
+    &["KeyV", "v"],
+    &["KeyB", "b"],
+    &["KeyN", "n"],
+    &["KeyM", "m"],
+    &["Comma", ","],
+    &["Dot", "."],
+    &["Slash", "/"],
+    &["Insert"],
+    &["KpReturn"],
+    &["KpMinus"],
+    &["KpPlus"],
+    &["KpMultiply"],
+    &["KpDivide"],
+    &["Kp0"],
+    &["Kp1"],
+    &["Kp2"],
+    &["Kp3"],
+    &["Kp4"],
+    &["Kp5"],
+    &["Kp6"],
+    &["Kp7"],
+    &["Kp8"],
+    &["Kp9"],
+    &["KpDelete"],
+    &["Function"],
+];
+
+/// Размер словаря по умолчанию вместе с корзиной неизвестных клавиш
+pub const DEFAULT_KEY_COUNT: usize = DEFAULT_KEYS.len() + 1;
+
+static DEFAULT_VOCABULARY: LazyLock<KeyVocabulary> = LazyLock::new(KeyVocabulary::default);
+
+/// Словарь в том виде, в котором он лежит в файле
+#[derive(Serialize, Deserialize)]
+struct KeyVocabularyFile {
+    version: u32,
+    keys: Vec<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyVocabulary {
+    version: u32,
+    /// Имена клавиш по номерам, первое имя основное
+    keys: Vec<Vec<String>>,
+    /// Имя в нижнем регистре -> номер
+    ids: HashMap<String, u8>,
+}
+
+impl KeyVocabulary {
+    /// Словарь из списка клавиш по номерам.
+    /// Пустые записи и повторяющиеся имена — ошибка, как и клавиши, номера которых
+    /// не помещаются в `u8` вместе с корзиной неизвестных и [`KEY_PADDING`].
+    pub fn new(version: u32, keys: Vec<Vec<String>>) -> io::Result<Self> {
+        if keys.len() >= KEY_PADDING as usize {
+            return Err(invalid_data(format!(
+                "в словаре {} клавиш, допустимо не больше {}",
+                keys.len(),
+                KEY_PADDING - 1
+            )));
+        }
+
+        let mut ids = HashMap::new();
+
+        for (id, names) in keys.iter().enumerate() {
+            if names.is_empty() {
+                return Err(invalid_data(format!("у клавиши {id} нет имени")));
+            }
+
+            for name in names {
+                if ids.insert(name.to_lowercase(), id as u8).is_some() {
+                    return Err(invalid_data(format!("клавиша {name} встречается дважды")));
+                }
+            }
+        }
+
+        Ok(Self { version, keys, ids })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        let file: KeyVocabularyFile = serde_json::from_reader(file).map_err(invalid_data)?;
+
+        Self::new(file.version, file.keys)
+    }
+
+    /// Словарь из [`KEY_VOCABULARY_FILE`] в каталоге `dir`, если файла нет — словарь по умолчанию
+    pub fn load_or_default(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(KEY_VOCABULARY_FILE);
+
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = KeyVocabularyFile {
+            version: self.version,
+            keys: self.keys.clone(),
+        };
+        let json = serde_json::to_vec_pretty(&file).map_err(io::Error::other)?;
+
+        write_atomic(path, &json)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Размер вектора клавиш: все клавиши словаря и корзина неизвестных
+    pub fn size(&self) -> usize {
+        self.keys.len() + 1
+    }
+
+    /// Номер корзины неизвестных клавиш, всегда последний
+    pub fn unknown_id(&self) -> u8 {
+        self.keys.len() as u8
+    }
+
+    /// Номер клавиши по имени без учёта регистра, для клавиш не из словаря — [`Self::unknown_id`]
+    pub fn key_to_num(&self, key: &str) -> u8 {
+        self.ids
+            .get(&key.to_lowercase())
+            .copied()
+            .unwrap_or_else(|| self.unknown_id())
+    }
+
+    /// Основное имя клавиши по номеру, для корзины неизвестных — [`UNKNOWN_KEY`].
+    /// `None` для [`KEY_PADDING`] и номеров вне словаря.
+    pub fn num_to_key(&self, id: u8) -> Option<&str> {
+        if id == self.unknown_id() {
+            return Some(UNKNOWN_KEY);
+        }
+
+        self.keys.get(id as usize).map(|names| names[0].as_str())
+    }
+}
+
+impl Default for KeyVocabulary {
+    fn default() -> Self {
+        let keys = DEFAULT_KEYS
+            .iter()
+            .map(|names| names.iter().map(|name| name.to_string()).collect())
+            .collect();
+
+        Self::new(KEY_VOCABULARY_VERSION, keys).unwrap()
+    }
+}
+
+/// Общий экземпляр словаря по умолчанию
+pub fn default_vocabulary() -> &'static KeyVocabulary {
+    &DEFAULT_VOCABULARY
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir;
+
+    /// Test the default vocabulary keeps the ids of the old hardcoded mapping
+    #[test]
+    fn test_default_vocabulary_ids() {
+        let vocabulary = KeyVocabulary::default();
+
+        assert_eq!(vocabulary.key_to_num("Alt"), 0);
+        assert_eq!(vocabulary.key_to_num("KeyW"), 53);
+        assert_eq!(vocabulary.key_to_num("w"), 53);
+        assert_eq!(vocabulary.key_to_num("Function"), 104);
+        assert_eq!(vocabulary.unknown_id(), 105);
+        assert_eq!(vocabulary.size(), DEFAULT_KEY_COUNT);
+    }
+
+    /// Test num_to_key returns the primary name for every id
+    #[test]
+    fn test_num_to_key_roundtrip() {
+        let vocabulary = KeyVocabulary::default();
+
+        for id in 0..vocabulary.unknown_id() {
+            let key = vocabulary.num_to_key(id).unwrap();
+            assert_eq!(vocabulary.key_to_num(key), id);
+        }
+
+        assert_eq!(
+            vocabulary.num_to_key(vocabulary.unknown_id()),
+            Some(UNKNOWN_KEY)
+        );
+        assert_eq!(vocabulary.num_to_key(KEY_PADDING), None);
+    }
+
+    /// Test a restricted vocabulary sends other keys to its unknown bucket
+    #[test]
+    fn test_restricted_vocabulary_unknown_bucket() {
+        let keys = ["KeyW", "KeyA", "KeyS", "KeyD"]
+            .iter()
+            .map(|key| vec![key.to_string()])
+            .collect();
+        let vocabulary = KeyVocabulary::new(7, keys).unwrap();
+
+        assert_eq!(vocabulary.size(), 5);
+        assert_eq!(vocabulary.key_to_num("keyd"), 3);
+        assert_eq!(vocabulary.key_to_num("Space"), 4);
+        assert_eq!(vocabulary.key_to_num("Unknown(42)"), 4);
+    }
+
+    /// Test duplicate names and empty entries are rejected
+    #[test]
+    fn test_vocabulary_rejects_invalid() {
+        let duplicate = vec![vec!["KeyW".to_string()], vec!["keyw".to_string()]];
+        assert!(KeyVocabulary::new(1, duplicate).is_err());
+
+        let empty = vec![vec!["KeyW".to_string()], vec![]];
+        assert!(KeyVocabulary::new(1, empty).is_err());
+    }
+
+    /// Test save and load_or_default
+    #[test]
+    fn test_vocabulary_save_load() {
+        let temp_dir = temp_dir("test_key_vocabulary");
+
+        assert_eq!(
+            KeyVocabulary::load_or_default(&temp_dir).unwrap(),
+            KeyVocabulary::default()
+        );
+
+        let keys = vec![
+            vec!["KeyW".to_string(), "w".to_string()],
+            vec!["Space".to_string()],
+        ];
+        let vocabulary = KeyVocabulary::new(3, keys).unwrap();
+        vocabulary
+            .save(&temp_dir.join(KEY_VOCABULARY_FILE))
+            .unwrap();
+
+        let loaded = KeyVocabulary::load_or_default(&temp_dir).unwrap();
+        assert_eq!(loaded, vocabulary);
+        assert_eq!(loaded.version(), 3);
+
+        // Cleanup
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+}
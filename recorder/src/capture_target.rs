@@ -0,0 +1,98 @@
+use std::{fmt, str::FromStr};
+
+/// Прямоугольник в координатах рабочего стола
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Что именно записывать.
+///
+/// Строковая форма используется в интерфейсе и в манифесте сессии:
+/// - `primary` — основной монитор;
+/// - `monitor:1` или `monitor:HDMI-1` — монитор по номеру или имени;
+/// - `region:100,200,800x600` — прямоугольник `x,y,ширинаxвысота`;
+/// - `window:Doom` — окно, в заголовке которого есть подстрока (без учёта регистра).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum CaptureTarget {
+    #[default]
+    Primary,
+    MonitorIndex(usize),
+    MonitorName(String),
+    Region(Region),
+    Window(String),
+}
+
+impl FromStr for CaptureTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.is_empty() || s == "primary" {
+            return Ok(Self::Primary);
+        }
+
+        let Some((kind, value)) = s.split_once(':') else {
+            return Err(format!("Неизвестная цель захвата: {}", s));
+        };
+        let value = value.trim();
+
+        if value.is_empty() {
+            return Err(format!("Не указано значение цели захвата: {}", s));
+        }
+
+        match kind.trim() {
+            "monitor" => Ok(match value.parse() {
+                Ok(index) => Self::MonitorIndex(index),
+                Err(_) => Self::MonitorName(value.to_string()),
+            }),
+            "region" => parse_region(value).map(Self::Region),
+            "window" => Ok(Self::Window(value.to_string())),
+            other => Err(format!("Неизвестный тип цели захвата: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for CaptureTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Primary => write!(f, "primary"),
+            Self::MonitorIndex(index) => write!(f, "monitor:{}", index),
+            Self::MonitorName(name) => write!(f, "monitor:{}", name),
+            Self::Region(region) => write!(
+                f,
+                "region:{},{},{}x{}",
+                region.x, region.y, region.width, region.height
+            ),
+            Self::Window(title) => write!(f, "window:{}", title),
+        }
+    }
+}
+
+/// `x,y,ширинаxвысота`
+fn parse_region(value: &str) -> Result<Region, String> {
+    let error = || format!("Ожидается region:x,y,ширинаxвысота, получено {}", value);
+
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    let [x, y, size] = parts[..] else {
+        return Err(error());
+    };
+    let (width, height) = size.split_once('x').ok_or_else(error)?;
+
+    let region = Region {
+        x: x.parse().map_err(|_| error())?,
+        y: y.parse().map_err(|_| error())?,
+        width: width.trim().parse().map_err(|_| error())?,
+        height: height.trim().parse().map_err(|_| error())?,
+    };
+
+    if region.width == 0 || region.height == 0 {
+        return Err(error());
+    }
+
+    Ok(region)
+}
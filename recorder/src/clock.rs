@@ -0,0 +1,31 @@
+use std::time::Instant;
+
+/// Монотонные часы сессии записи.
+///
+/// Одни и те же часы используются для кадров и для событий клавиатуры/мыши,
+/// поэтому их метки времени можно напрямую сравнивать при выравнивании.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionClock {
+    start: Instant,
+    offset_us: u64,
+}
+
+impl SessionClock {
+    pub fn start() -> Self {
+        Self::resume_from(0)
+    }
+
+    /// Часы продолженной сессии: отсчёт идёт с `offset_us`, чтобы новые метки
+    /// были позже уже записанных
+    pub fn resume_from(offset_us: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            offset_us,
+        }
+    }
+
+    /// Микросекунды, прошедшие с начала сессии
+    pub fn now_us(&self) -> u64 {
+        self.offset_us + self.start.elapsed().as_micros() as u64
+    }
+}
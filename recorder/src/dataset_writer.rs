@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use common::aggregation::AggregationConfig;
+use common::frame::FrameFormat;
+use preprocessor::alignment::Aligner;
+use preprocessor::event_log::EventRecord;
+use preprocessor::hdf5_processing::{DATASET_FILE, Hdf5Writer, RECORDS_PER_CHUNK, WriterOptions};
+use preprocessor::images::MyImage;
+use preprocessor::types::MyConstData;
+use preprocessor::vocabulary::KeyVocabulary;
+
+use crate::clock::SessionClock;
+use crate::session::SessionShared;
+
+/// Запас времени перед закрытием кадра: событие с меткой раньше следующего кадра
+/// могло ещё не дойти до потока записи датасета.
+const SETTLE_US: u64 = 200_000;
+
+pub(crate) enum DatasetMessage {
+    Event(EventRecord),
+    /// Кадр поставлен в очередь кодирования
+    Stamp {
+        index: usize,
+        timestamp_us: u64,
+    },
+    /// Уменьшенный кадр; `None`, если кодирование не удалось
+    Image {
+        index: usize,
+        image: Option<MyImage>,
+    },
+    /// Захват завершён, все кадры уже отправлены
+    Finish,
+}
+
+/// Запись датасета во время захвата: уменьшенные кадры выравниваются с событиями
+/// тем же [`Aligner`], что и в `preprocessor`, и дописываются в [`DATASET_FILE`]
+/// блоками по [`RECORDS_PER_CHUNK`] записей, как после обычной постобработки.
+pub(crate) struct DatasetWriter {
+    output_dir: PathBuf,
+    clock: SessionClock,
+    format: FrameFormat,
+    aggregation: AggregationConfig,
+    aligner: Aligner,
+    stamps: BTreeMap<usize, u64>,
+    images: BTreeMap<usize, Option<MyImage>>,
+    next_frame: usize,
+    buffer: Vec<(usize, MyConstData)>,
+    /// Открывается при первой записи: у сессии без кадров датасета нет
+    writer: Option<Hdf5Writer>,
+}
+
+impl DatasetWriter {
+    pub fn new(
+        output_dir: PathBuf,
+        clock: SessionClock,
+        vocabulary: KeyVocabulary,
+        format: FrameFormat,
+        aggregation: AggregationConfig,
+    ) -> Self {
+        Self {
+            output_dir,
+            clock,
+            format,
+            aggregation,
+            aligner: Aligner::new(vocabulary).with_mouse_bins(aggregation.mouse_bins),
+            stamps: BTreeMap::new(),
+            images: BTreeMap::new(),
+            next_frame: 0,
+            buffer: Vec::with_capacity(RECORDS_PER_CHUNK),
+            writer: None,
+        }
+    }
+
+    pub fn start(
+        mut self,
+        receiver: Receiver<DatasetMessage>,
+        session: Arc<SessionShared>,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            if let Err(err) = self.run(receiver) {
+                session.fail(err);
+                return;
+            }
+
+            session.dataset_written();
+
+            let records = self.writer.as_ref().map_or(0, Hdf5Writer::len);
+            println!("Dataset recorded: {} records", records);
+        })
+    }
+
+    fn run(&mut self, receiver: Receiver<DatasetMessage>) -> io::Result<()> {
+        loop {
+            let finished = match receiver.recv_timeout(Duration::from_millis(50)) {
+                Ok(DatasetMessage::Finish) | Err(RecvTimeoutError::Disconnected) => true,
+                Ok(message) => {
+                    self.handle(message);
+                    false
+                }
+                Err(RecvTimeoutError::Timeout) => false,
+            };
+
+            self.close_ready_frames(finished)?;
+
+            if finished {
+                break;
+            }
+        }
+
+        if !self.buffer.is_empty() {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, message: DatasetMessage) {
+        match message {
+            DatasetMessage::Event(event) => self.aligner.push_event(event),
+            DatasetMessage::Stamp {
+                index,
+                timestamp_us,
+            } => {
+                self.stamps.insert(index, timestamp_us);
+            }
+            DatasetMessage::Image { index, image } => {
+                self.images.insert(index, image);
+            }
+            DatasetMessage::Finish => {}
+        }
+    }
+
+    /// Кадр закрывается, когда известны его изображение и начало следующего кадра.
+    /// После завершения захвата закрываются все оставшиеся кадры.
+    fn close_ready_frames(&mut self, finished: bool) -> io::Result<()> {
+        while let Some(&start) = self.stamps.get(&self.next_frame) {
+            let end = match self.stamps.get(&(self.next_frame + 1)) {
+                Some(&end) if finished || self.clock.now_us() >= end + SETTLE_US => end,
+                None if finished => u64::MAX,
+                _ => break,
+            };
+
+            let image = match self.images.remove(&self.next_frame) {
+                Some(image) => image,
+                // Все кодировщики уже завершены, кадр потерян
+                None if finished => None,
+                None => break,
+            };
+
+            let index = self.next_frame;
+            self.stamps.remove(&index);
+            self.next_frame += 1;
+
+            let keys_record = self.aligner.align_frame(start, end);
+
+            if let Some(image) = image {
+                self.buffer
+                    .push((index, MyConstData { image, keys_record }));
+
+                if self.buffer.len() == RECORDS_PER_CHUNK {
+                    self.flush()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => {
+                fs::create_dir_all(&self.output_dir)?;
+                Hdf5Writer::open(
+                    &self.output_dir.join(DATASET_FILE),
+                    self.format,
+                    WriterOptions {
+                        aggregation: self.aggregation,
+                        ..Default::default()
+                    },
+                )
+                .map_err(io::Error::other)?
+            }
+        };
+
+        self.writer
+            .insert(writer)
+            .append(&self.buffer)
+            .map_err(io::Error::other)?;
+        self.buffer.clear();
+
+        Ok(())
+    }
+}
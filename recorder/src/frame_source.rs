@@ -0,0 +1,223 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use image::{RgbaImage, imageops};
+use preprocessor::images::frame_index;
+use xcap::{Monitor, Window};
+
+use crate::capture_target::{CaptureTarget, Region};
+
+/// Источник кадров для записи.
+pub trait FrameSource: Send {
+    /// Захват следующего кадра. `Ok(None)` означает, что источник исчерпан и запись завершается.
+    fn capture(&mut self) -> io::Result<Option<RgbaImage>>;
+
+    /// Название источника для манифеста сессии
+    fn name(&self) -> String {
+        "unknown".to_string()
+    }
+
+    /// Цель захвата экрана, если источник — экран
+    fn target(&self) -> Option<CaptureTarget> {
+        None
+    }
+}
+
+/// Захват экрана через `xcap` (источник по умолчанию).
+///
+/// Монитор или окно ищется при первом захвате и запоминается. Если захват не удался
+/// (монитор отключён, окно закрыто), цель ищется заново: так переживаем переподключение
+/// мониторов и перезапуск окна.
+pub struct ScreenSource {
+    target: CaptureTarget,
+    resolved: Option<Resolved>,
+}
+
+/// Найденный монитор или окно
+enum Resolved {
+    Monitor(Monitor),
+    Window(Window),
+}
+
+// SAFETY: в Windows объекты `xcap` хранят дескрипторы HMONITOR и HWND, которые
+// действуют во всём процессе, а не только в создавшем их потоке; на остальных
+// платформах это числовые идентификаторы.
+unsafe impl Send for Resolved {}
+
+impl Resolved {
+    fn monitor_name(&self) -> String {
+        match self {
+            Resolved::Monitor(monitor) => monitor.name().to_string(),
+            Resolved::Window(window) => window.current_monitor().name().to_string(),
+        }
+    }
+}
+
+impl ScreenSource {
+    pub fn new(target: CaptureTarget) -> Self {
+        Self {
+            target,
+            resolved: None,
+        }
+    }
+
+    pub fn primary() -> Self {
+        Self::new(CaptureTarget::Primary)
+    }
+
+    fn resolve(&self) -> io::Result<Resolved> {
+        match &self.target {
+            CaptureTarget::Window(title) => Self::window(title).map(Resolved::Window),
+            _ => self.monitor().map(Resolved::Monitor),
+        }
+    }
+
+    fn monitor(&self) -> io::Result<Monitor> {
+        let monitors = Monitor::all().map_err(io::Error::other)?;
+
+        let monitor = match &self.target {
+            CaptureTarget::Primary | CaptureTarget::Window(_) => monitors
+                .iter()
+                .find(|m| m.is_primary())
+                .or(monitors.first()),
+            CaptureTarget::MonitorIndex(index) => monitors.get(*index),
+            CaptureTarget::MonitorName(name) => monitors.iter().find(|m| m.name() == name),
+            CaptureTarget::Region(region) => monitors.iter().find(|m| {
+                (m.x()..m.x() + m.width() as i32).contains(&region.x)
+                    && (m.y()..m.y() + m.height() as i32).contains(&region.y)
+            }),
+        };
+
+        monitor
+            .cloned()
+            .ok_or_else(|| not_found(format!("Монитор для {} не найден", self.target)))
+    }
+
+    fn window(title: &str) -> io::Result<Window> {
+        let needle = title.to_lowercase();
+
+        Window::all()
+            .map_err(io::Error::other)?
+            .into_iter()
+            .find(|w| !w.is_minimized() && w.title().to_lowercase().contains(&needle))
+            .ok_or_else(|| not_found(format!("Окно \"{}\" не найдено", title)))
+    }
+
+    fn capture_resolved(&self, resolved: &Resolved) -> io::Result<RgbaImage> {
+        match (resolved, &self.target) {
+            (Resolved::Window(window), _) => window.capture_image().map_err(io::Error::other),
+            (Resolved::Monitor(monitor), CaptureTarget::Region(region)) => {
+                let image = monitor.capture_image().map_err(io::Error::other)?;
+
+                Ok(crop_region(&image, monitor, region))
+            }
+            (Resolved::Monitor(monitor), _) => monitor.capture_image().map_err(io::Error::other),
+        }
+    }
+}
+
+impl FrameSource for ScreenSource {
+    fn capture(&mut self) -> io::Result<Option<RgbaImage>> {
+        if let Some(resolved) = &self.resolved {
+            match self.capture_resolved(resolved) {
+                Ok(image) => return Ok(Some(image)),
+                // Цель ищется заново ниже
+                Err(_) => self.resolved = None,
+            }
+        }
+
+        let resolved = self.resolve()?;
+        let image = self.capture_resolved(&resolved)?;
+        self.resolved = Some(resolved);
+
+        Ok(Some(image))
+    }
+
+    fn name(&self) -> String {
+        match &self.resolved {
+            Some(resolved) => resolved.monitor_name(),
+            None => self
+                .resolve()
+                .map(|resolved| resolved.monitor_name())
+                .unwrap_or_else(|_| self.target.to_string()),
+        }
+    }
+
+    fn target(&self) -> Option<CaptureTarget> {
+        Some(self.target.clone())
+    }
+}
+
+/// Вырезание прямоугольника из снимка монитора.
+/// Снимок может быть в физических пикселях (HiDPI), поэтому координаты масштабируются.
+fn crop_region(image: &RgbaImage, monitor: &Monitor, region: &Region) -> RgbaImage {
+    let scale = image.width() as f64 / monitor.width().max(1) as f64;
+
+    let x = ((region.x - monitor.x()) as f64 * scale) as u32;
+    let y = ((region.y - monitor.y()) as f64 * scale) as u32;
+    let width = (region.width as f64 * scale) as u32;
+    let height = (region.height as f64 * scale) as u32;
+
+    // crop_imm сам обрезает прямоугольник по границам снимка
+    imageops::crop_imm(image, x, y, width, height).to_image()
+}
+
+fn not_found(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, message)
+}
+
+/// Воспроизведение заранее сохранённых кадров из директории.
+///
+/// Кадры отдаются по возрастанию номера в имени файла (`image-2.png` раньше `image-10.png`,
+/// см. [`frame_index`]), после последнего кадра источник исчерпан.
+pub struct DirectoryFrameSource {
+    dir: PathBuf,
+    frames: Vec<PathBuf>,
+    position: usize,
+}
+
+impl DirectoryFrameSource {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        let mut frames: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .filter(|path| image::ImageFormat::from_path(path).is_ok())
+            .collect();
+
+        frames.sort_by_key(|path| (frame_index(path), path.clone()));
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            frames,
+            position: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl FrameSource for DirectoryFrameSource {
+    fn capture(&mut self) -> io::Result<Option<RgbaImage>> {
+        let Some(path) = self.frames.get(self.position) else {
+            return Ok(None);
+        };
+        self.position += 1;
+
+        let image = image::open(path).map_err(io::Error::other)?;
+
+        Ok(Some(image.to_rgba8()))
+    }
+
+    fn name(&self) -> String {
+        format!("directory:{}", self.dir.display())
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    sync::mpsc::{Receiver, channel},
+    thread,
+    time::Duration,
+};
+
+use rdev::EventType;
+use serde::{Deserialize, Serialize};
+
+use crate::listener;
+
+/// Источник событий клавиатуры и мыши для записи.
+pub trait InputSource: Send {
+    /// Запуск источника. Когда события заканчиваются, отправитель удаляется и канал закрывается.
+    fn start(self: Box<Self>) -> Receiver<EventType>;
+}
+
+/// Глобальные события ОС через `rdev` (источник по умолчанию)
+pub struct RdevInputSource;
+
+impl InputSource for RdevInputSource {
+    fn start(self: Box<Self>) -> Receiver<EventType> {
+        listener::subscribe()
+    }
+}
+
+/// Одно событие сценария: задержка после предыдущего события и само событие.
+///
+/// Сценарий хранится в формате JSON Lines, например:
+/// `{"delay_ms": 20, "event": {"KeyPress": "KeyW"}}`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptedEvent {
+    pub delay_ms: u64,
+    pub event: EventType,
+}
+
+/// Воспроизведение событий из файла сценария с исходными задержками
+pub struct ScriptedInputSource {
+    events: Vec<ScriptedEvent>,
+}
+
+impl ScriptedInputSource {
+    pub fn new(events: Vec<ScriptedEvent>) -> Self {
+        Self { events }
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let mut events = Vec::new();
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let event: ScriptedEvent = serde_json::from_str(&line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            events.push(event);
+        }
+
+        Ok(Self::new(events))
+    }
+}
+
+impl InputSource for ScriptedInputSource {
+    fn start(self: Box<Self>) -> Receiver<EventType> {
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || {
+            for scripted in self.events {
+                thread::sleep(Duration::from_millis(scripted.delay_ms));
+
+                if sender.send(scripted.event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        receiver
+    }
+}
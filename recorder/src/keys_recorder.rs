@@ -1,85 +1,75 @@
-use csv::Writer;
-use rdev::{Event, EventType};
-use std::collections::HashSet;
+use preprocessor::event_log::{EventLogWriter, EventRecord, InputEvent};
+use rdev::EventType;
 use std::fs::File;
+use std::io::{self, BufWriter};
+use std::sync::mpsc::Sender;
 
-use serde::Serialize;
-
-#[derive(Debug, Serialize)]
-struct CsvRecord {
-    keys: String,
-    mouse: String,
-}
+use crate::clock::SessionClock;
+use crate::dataset_writer::DatasetMessage;
 
+/// Журнал событий: каждое событие клавиатуры или мыши сохраняется
+/// отдельной записью [`EventRecord`] со своей меткой времени.
 pub struct KeysRecorder {
-    key_states: HashSet<String>,
-    mouse_states: HashSet<String>,
+    writer: EventLogWriter<BufWriter<File>>,
+    clock: SessionClock,
+    /// Копии событий для записи датасета на лету
+    dataset: Option<Sender<DatasetMessage>>,
 }
 
 impl KeysRecorder {
-    pub fn new() -> Self {
+    pub fn new(writer: EventLogWriter<BufWriter<File>>, clock: SessionClock) -> Self {
         Self {
-            key_states: HashSet::new(),
-            mouse_states: HashSet::new(),
+            writer,
+            clock,
+            dataset: None,
         }
     }
 
-    pub fn insert_key(&mut self, event: &Event) {
-        match event.event_type {
-            EventType::KeyPress(key) => {
-                self.key_states.insert(format!("{:?}", key)); // Сохраняем нажатие клавиши
-            }
-            EventType::KeyRelease(key) => {
-                self.key_states.remove(&format!("{:?}", key)); // Удаляем отпускание клавиши
-            }
-            EventType::MouseMove { x, y } => {
-                self.mouse_states.insert(format!("{},{}", x, y)); // Сохраняем координаты мыши
-            }
-            EventType::ButtonPress(button) => {
-                self.key_states.insert(format!("{:?}", button)); // Сохраняем нажатие клавиши
-            }
-            EventType::ButtonRelease(button) => {
-                self.key_states.remove(&format!("{:?}", button)); // Удаляем отпускание клавиши
-            }
-            EventType::Wheel { delta_x, delta_y } => {
-                self.mouse_states.insert(format!("{},{}", delta_x, delta_y)); // Удаляем отпускание клавиши
-            }
-        }
+    pub fn with_dataset(mut self, dataset: Sender<DatasetMessage>) -> Self {
+        self.dataset = Some(dataset);
+        self
     }
 
-    pub fn write_keys(&mut self, writer: &mut Writer<File>) {
-        if !self.key_states.is_empty() || !self.mouse_states.is_empty() {
-            let mut record = CsvRecord {
-                keys: "".to_string(),
-                mouse: "".to_string(),
-            };
+    pub fn insert_key(&mut self, event: &EventType) -> io::Result<()> {
+        let timestamp_us = self.clock.now_us();
+
+        let event = match *event {
+            EventType::KeyPress(key) => InputEvent::KeyPress(format!("{:?}", key)),
+            EventType::KeyRelease(key) => InputEvent::KeyRelease(format!("{:?}", key)),
+            EventType::ButtonPress(button) => InputEvent::ButtonPress(format!("{:?}", button)),
+            EventType::ButtonRelease(button) => InputEvent::ButtonRelease(format!("{:?}", button)),
+            EventType::MouseMove { x, y } => InputEvent::MouseMove { x, y }, // Координаты мыши
+            EventType::Wheel { delta_x, delta_y } => InputEvent::Wheel { delta_x, delta_y }, // Прокрутка колёсика
+        };
+
+        self.write(EventRecord {
+            timestamp_us,
+            event,
+        })
+    }
 
-            if !self.key_states.is_empty() {
-                // Объединяем все нажатия клавиш в одну строку
-                let keys_pressed = self
-                    .key_states
-                    .iter()
-                    .cloned()
-                    .collect::<Vec<String>>()
-                    .join(", "); // Объединяем нажатия в одну строку
-                record.keys = keys_pressed;
-            }
+    /// Служебная метка в журнале ([`InputEvent::Pause`], [`InputEvent::Resume`])
+    pub fn insert_marker(&mut self, marker: InputEvent) -> io::Result<()> {
+        let timestamp_us = self.clock.now_us();
 
-            if !self.mouse_states.is_empty() {
-                // Объединяем все нажатия клавиш в одну строку
-                let mouse_action = self
-                    .mouse_states
-                    .iter()
-                    .cloned()
-                    .collect::<Vec<String>>()
-                    .join(", "); // Объединяем нажатия в одну строку
-                record.mouse = mouse_action;
+        self.write(EventRecord {
+            timestamp_us,
+            event: marker,
+        })
+    }
 
-                self.mouse_states.clear();
-            }
+    fn write(&mut self, record: EventRecord) -> io::Result<()> {
+        self.writer.write(&record)?;
 
-            writer.serialize(record).unwrap();
-            writer.flush().unwrap();
+        if let Some(dataset) = &self.dataset {
+            // Запись датасета могла уже завершиться
+            let _ = dataset.send(DatasetMessage::Event(record));
         }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
     }
 }
@@ -0,0 +1,39 @@
+use std::{
+    sync::{
+        Mutex, Once,
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread,
+};
+
+use rdev::{EventType, listen};
+
+/// `rdev::listen` нельзя остановить, поэтому слушатель запускается один раз на процесс
+/// и пересылает события текущей сессии записи.
+static LISTENER: Once = Once::new();
+static SUBSCRIBER: Mutex<Option<Sender<EventType>>> = Mutex::new(None);
+
+/// Подписка на глобальные события клавиатуры и мыши.
+/// Новая подписка заменяет предыдущую.
+pub fn subscribe() -> Receiver<EventType> {
+    LISTENER.call_once(|| {
+        thread::spawn(|| {
+            listen(|event| {
+                let mut subscriber = SUBSCRIBER.lock().unwrap();
+
+                if let Some(sender) = subscriber.as_ref()
+                    && sender.send(event.event_type).is_err()
+                {
+                    // Сессия завершилась, получатель удалён
+                    *subscriber = None;
+                }
+            })
+            .unwrap();
+        });
+    });
+
+    let (sender, receiver) = channel();
+    *SUBSCRIBER.lock().unwrap() = Some(sender);
+
+    receiver
+}
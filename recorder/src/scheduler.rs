@@ -0,0 +1,125 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::session::CaptureStats;
+
+/// Планировщик захвата по дедлайнам.
+///
+/// Такты отсчитываются от первого кадра, а не от конца предыдущего захвата,
+/// поэтому время захвата не накапливает сдвиг. Если захват опоздал больше чем на такт,
+/// пропущенные такты не догоняются, а учитываются как потерянные кадры.
+pub struct Scheduler {
+    interval: Duration,
+    next_deadline: Option<Instant>,
+}
+
+impl Scheduler {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_deadline: None,
+        }
+    }
+
+    /// Ожидание следующего такта. Возвращает количество пропущенных тактов.
+    pub fn wait(&mut self) -> usize {
+        let now = Instant::now();
+
+        let Some(deadline) = self.next_deadline else {
+            self.next_deadline = Some(now + self.interval);
+            return 0;
+        };
+
+        let missed = if now < deadline {
+            thread::sleep(deadline - now);
+            0
+        } else {
+            ((now - deadline).as_nanos() / self.interval.as_nanos()) as usize
+        };
+
+        self.next_deadline = Some(deadline + self.interval * (missed as u32 + 1));
+
+        missed
+    }
+
+    /// После паузы отсчёт тактов начинается заново
+    pub fn reset(&mut self) {
+        self.next_deadline = None;
+    }
+}
+
+/// Накопление статистики захвата по ходу записи
+pub struct StatsAccumulator {
+    target_interval: Duration,
+    last_capture: Option<Instant>,
+    intervals: usize,
+    interval_sum: Duration,
+    deviation_sum: Duration,
+    max_interval: Duration,
+    pub missed_deadlines: usize,
+    pub queue_full: usize,
+    pub capture_errors: usize,
+}
+
+impl StatsAccumulator {
+    pub fn new(target_interval: Duration) -> Self {
+        Self {
+            target_interval,
+            last_capture: None,
+            intervals: 0,
+            interval_sum: Duration::ZERO,
+            deviation_sum: Duration::ZERO,
+            max_interval: Duration::ZERO,
+            missed_deadlines: 0,
+            queue_full: 0,
+            capture_errors: 0,
+        }
+    }
+
+    pub fn frame_captured(&mut self, at: Instant) {
+        if let Some(last) = self.last_capture {
+            let interval = at - last;
+
+            self.intervals += 1;
+            self.interval_sum += interval;
+            self.deviation_sum += interval.abs_diff(self.target_interval);
+            self.max_interval = self.max_interval.max(interval);
+        }
+
+        self.last_capture = Some(at);
+    }
+
+    /// Интервал через паузу не учитывается
+    pub fn reset(&mut self) {
+        self.last_capture = None;
+    }
+
+    pub fn finish(&self, frames: usize, encode_errors: usize) -> CaptureStats {
+        let (achieved_fps, jitter_ms) = if self.intervals > 0 {
+            (
+                self.intervals as f64 / self.interval_sum.as_secs_f64(),
+                self.deviation_sum.as_secs_f64() * 1000.0 / self.intervals as f64,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        CaptureStats {
+            target_fps: 1.0 / self.target_interval.as_secs_f64(),
+            achieved_fps,
+            jitter_ms,
+            max_interval_ms: self.max_interval.as_secs_f64() * 1000.0,
+            frames,
+            dropped_frames: self.missed_deadlines
+                + self.queue_full
+                + self.capture_errors
+                + encode_errors,
+            missed_deadlines: self.missed_deadlines,
+            queue_full: self.queue_full,
+            capture_errors: self.capture_errors,
+            encode_errors,
+        }
+    }
+}
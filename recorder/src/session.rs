@@ -0,0 +1,628 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{RecvTimeoutError, channel},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use common::aggregation::AggregationConfig;
+use common::frame::FrameFormat;
+use common::session::{
+    CAPTURE_STATS_FILE, CHECKPOINT_FILE, EVENTS_FILE, FRAMES_DIR, FRAMES_INDEX_FILE,
+    LEGACY_EVENTS_FILE, MANIFEST_FILE, RESIZED_DIR, SESSIONS_DIR, SessionManifest, write_atomic,
+};
+use preprocessor::{
+    alignment::load_frame_stamps,
+    event_log::{EventLogWriter, InputEvent},
+    sessions::Session,
+    vocabulary::KeyVocabulary,
+};
+use rdev::{EventType, Key};
+
+use crate::{
+    capture_target::CaptureTarget,
+    clock::SessionClock,
+    dataset_writer::DatasetWriter,
+    frame_source::{FrameSource, ScreenSource},
+    input_source::{InputSource, RdevInputSource},
+    keys_recorder::KeysRecorder,
+    video_recorder::VideoRecorder,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingState {
+    Recording,
+    Paused,
+    Stopped,
+}
+
+/// Снимок состояния сессии для отображения в интерфейсе
+#[derive(Clone, Copy, Debug)]
+pub struct RecordingStatus {
+    pub state: RecordingState,
+    /// Количество сохранённых кадров
+    pub frames: usize,
+    /// Время записи без учёта пауз
+    pub elapsed: Duration,
+    /// Кадры, которые не удалось захватить
+    pub dropped_frames: usize,
+}
+
+/// Горячие клавиши управления записью.
+/// Нажатия горячих клавиш не попадают в журнал событий.
+#[derive(Clone, Debug)]
+pub struct Hotkeys {
+    pub toggle_pause: Option<Key>,
+    pub stop: Option<Key>,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Self {
+            toggle_pause: Some(Key::F9),
+            stop: Some(Key::F10),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecorderConfig {
+    pub hotkeys: Hotkeys,
+    /// Монитор, область или окно для записи
+    pub capture_target: CaptureTarget,
+    /// Желаемая частота кадров
+    pub target_fps: f64,
+    /// Потоки кодирования PNG
+    pub encoder_threads: usize,
+    /// Кадры, ожидающие кодирования; при переполнении новые кадры отбрасываются
+    pub queue_capacity: usize,
+    /// Уменьшать кадры до `frame_format` при захвате и сразу писать датасет в `hdf5_files`
+    pub downscale_at_capture: bool,
+    /// Сохранять полноразмерные кадры (в режиме уменьшения при захвате — по желанию)
+    pub keep_full_resolution: bool,
+    /// Пользовательские метки сессии (игра, сценарий и т.п.), сохраняются в манифесте
+    pub tags: Vec<String>,
+    /// Словарь для датасета, который пишется при захвате; версия сохраняется в манифесте
+    pub key_vocabulary: KeyVocabulary,
+    /// Разрешение и каналы кадров датасета, который пишется при захвате
+    pub frame_format: FrameFormat,
+    /// Сведение действий за кадр в датасете, который пишется при захвате
+    pub aggregation: AggregationConfig,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            hotkeys: Hotkeys::default(),
+            capture_target: CaptureTarget::default(),
+            target_fps: 20.0,
+            encoder_threads: 2,
+            queue_capacity: 16,
+            downscale_at_capture: false,
+            keep_full_resolution: true,
+            tags: Vec::new(),
+            key_vocabulary: KeyVocabulary::default(),
+            frame_format: FrameFormat::default(),
+            aggregation: AggregationConfig::default(),
+        }
+    }
+}
+
+impl RecorderConfig {
+    /// Промежуток между кадрами; частота должна быть положительной и конечной
+    pub fn frame_interval(&self) -> io::Result<Duration> {
+        match Duration::try_from_secs_f64(1.0 / self.target_fps) {
+            Ok(interval) if !interval.is_zero() => Ok(interval),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Недопустимая частота кадров: {}", self.target_fps),
+            )),
+        }
+    }
+}
+
+struct Timing {
+    state: RecordingState,
+    started_at: Instant,
+    paused_since: Option<Instant>,
+    paused_total: Duration,
+}
+
+/// Состояние, общее для потоков записи и управляющего кода
+pub(crate) struct SessionShared {
+    timing: Mutex<Timing>,
+    frames: AtomicUsize,
+    dropped_frames: AtomicUsize,
+    keys_recorder: Mutex<KeysRecorder>,
+    manifest: Mutex<SessionManifest>,
+    manifest_path: PathBuf,
+    checkpoint_path: PathBuf,
+    /// Первая ошибка записи в фоновых потоках, см. [`SessionShared::fail`]
+    error: Mutex<Option<io::Error>>,
+}
+
+impl SessionShared {
+    pub(crate) fn state(&self) -> RecordingState {
+        self.timing.lock().unwrap().state
+    }
+
+    /// Кадр закодирован и сохранён на диск
+    pub(crate) fn frame_saved(&self) {
+        self.frames.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn frame_dropped(&self) {
+        self.frames_dropped(1);
+    }
+
+    pub(crate) fn frames_dropped(&self, count: usize) {
+        self.dropped_frames.fetch_add(count, Ordering::SeqCst);
+    }
+
+    /// Разрешение исходных кадров известно только после первого захвата
+    pub(crate) fn set_native_resolution(&self, width: u32, height: u32) {
+        self.manifest.lock().unwrap().native_resolution = [width, height];
+    }
+
+    pub(crate) fn dataset_written(&self) {
+        self.manifest.lock().unwrap().dataset_written = true;
+    }
+
+    /// Запись манифеста с актуальным количеством кадров
+    pub(crate) fn save_manifest(&self) -> io::Result<()> {
+        let mut manifest = self.manifest.lock().unwrap();
+        manifest.frame_count = self.frames.load(Ordering::SeqCst);
+        manifest.save(&self.manifest_path)
+    }
+
+    /// Контрольная точка: события и манифест сбрасываются на диск, чтобы после сбоя
+    /// потерялось не больше одного интервала между точками
+    pub(crate) fn checkpoint(&self) -> io::Result<()> {
+        self.keys_recorder.lock().unwrap().flush()?;
+        self.save_manifest()
+    }
+
+    /// Штатное завершение записи: метка незавершённой записи больше не нужна.
+    /// Её может и не быть, если её удалили вручную.
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        self.keys_recorder.lock().unwrap().flush()?;
+        self.save_manifest()?;
+
+        match fs::remove_file(&self.checkpoint_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ошибка записи в фоновом потоке: запись останавливается, а ошибка возвращается
+    /// из [`RecordingSession::stop`]. Сохраняется только первая ошибка.
+    pub(crate) fn fail(&self, err: io::Error) {
+        eprintln!("Recording failed: {err}");
+
+        let mut error = self.error.lock().unwrap();
+        if error.is_none() {
+            *error = Some(err);
+        }
+        drop(error);
+
+        self.stop();
+    }
+
+    pub(crate) fn failed(&self) -> bool {
+        self.error.lock().unwrap().is_some()
+    }
+
+    fn set_paused(&self, paused: bool) {
+        let mut timing = self.timing.lock().unwrap();
+
+        let written = match (timing.state, paused) {
+            (RecordingState::Recording, true) => {
+                timing.state = RecordingState::Paused;
+                timing.paused_since = Some(Instant::now());
+                // Метка паузы в журнале: кадр перед паузой не получит события, произошедшие во время неё
+                self.keys_recorder
+                    .lock()
+                    .unwrap()
+                    .insert_marker(InputEvent::Pause)
+            }
+            (RecordingState::Paused, false) => {
+                timing.state = RecordingState::Recording;
+                if let Some(since) = timing.paused_since.take() {
+                    timing.paused_total += since.elapsed();
+                }
+                self.keys_recorder
+                    .lock()
+                    .unwrap()
+                    .insert_marker(InputEvent::Resume)
+            }
+            _ => Ok(()),
+        };
+        drop(timing);
+
+        if let Err(err) = written {
+            self.fail(err);
+        }
+    }
+
+    fn toggle_pause(&self) {
+        let paused = self.state() == RecordingState::Paused;
+        self.set_paused(!paused);
+    }
+
+    pub(crate) fn stop(&self) {
+        let mut timing = self.timing.lock().unwrap();
+
+        if let Some(since) = timing.paused_since.take() {
+            timing.paused_total += since.elapsed();
+        }
+        timing.state = RecordingState::Stopped;
+    }
+
+    pub(crate) fn status(&self) -> RecordingStatus {
+        let timing = self.timing.lock().unwrap();
+
+        let paused = timing.paused_total
+            + timing
+                .paused_since
+                .map_or(Duration::ZERO, |since| since.elapsed());
+
+        RecordingStatus {
+            state: timing.state,
+            frames: self.frames.load(Ordering::SeqCst),
+            elapsed: timing.started_at.elapsed().saturating_sub(paused),
+            dropped_frames: self.dropped_frames.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Всё, что нужно потокам записи новой или продолженной сессии
+struct Launch {
+    session_dir: PathBuf,
+    manifest: SessionManifest,
+    clock: SessionClock,
+    events: EventLogWriter<BufWriter<File>>,
+    /// Номер следующего кадра
+    first_index: usize,
+    /// Куда писать датасет на лету, если он нужен
+    dataset_dir: Option<PathBuf>,
+}
+
+/// Управляемая сессия записи кадров и событий ввода.
+///
+/// Запись идёт в фоновых потоках; сессию можно приостановить, продолжить и остановить
+/// как из интерфейса, так и горячими клавишами из [`Hotkeys`].
+pub struct RecordingSession {
+    shared: Arc<SessionShared>,
+    session_dir: PathBuf,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl RecordingSession {
+    /// Запись экрана (цель из [`RecorderConfig::capture_target`]) и глобальных событий ввода
+    pub fn start(config: RecorderConfig, data_dir: PathBuf) -> io::Result<Self> {
+        let frame_source = ScreenSource::new(config.capture_target.clone());
+
+        Self::start_with_sources(
+            config,
+            data_dir,
+            Box::new(frame_source),
+            Box::new(RdevInputSource),
+        )
+    }
+
+    /// Запись из произвольных источников кадров и событий.
+    /// Сессия завершается сама, когда исчерпан источник кадров.
+    ///
+    /// Каждая сессия пишется в собственную директорию `data_dir/sessions/<session_id>/`,
+    /// старые сессии не затрагиваются.
+    pub fn start_with_sources(
+        config: RecorderConfig,
+        data_dir: PathBuf,
+        frame_source: Box<dyn FrameSource>,
+        input_source: Box<dyn InputSource>,
+    ) -> io::Result<Self> {
+        config.frame_interval()?;
+
+        let started_at_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?
+            .as_millis() as u64;
+        let session_id = format!("session-{}", started_at_unix_ms);
+
+        let session_dir = data_dir.join(SESSIONS_DIR).join(&session_id);
+        fs::create_dir_all(&session_dir)?;
+
+        let manifest = SessionManifest {
+            session_id,
+            started_at_unix_ms,
+            monitor_name: frame_source.name(),
+            capture_target: frame_source.target().map(|target| target.to_string()),
+            native_resolution: [0, 0],
+            target_fps: config.target_fps,
+            key_vocabulary_version: config.key_vocabulary.version(),
+            frame_count: 0,
+            tags: config.tags.clone(),
+            dataset_written: false,
+        };
+
+        // Общие часы для кадров и событий
+        let clock = SessionClock::start();
+
+        let dataset_dir = config
+            .downscale_at_capture
+            .then(|| data_dir.join("hdf5_files").join(&manifest.session_id));
+
+        Self::launch(
+            config,
+            Launch {
+                events: EventLogWriter::create(&session_dir.join(EVENTS_FILE))?,
+                session_dir,
+                manifest,
+                clock,
+                first_index: 0,
+                dataset_dir,
+            },
+            frame_source,
+            input_source,
+        )
+    }
+
+    /// Продолжение прерванной записи экрана, см. [`RecordingSession::continue_with_sources`]
+    pub fn continue_session(config: RecorderConfig, session_dir: &Path) -> io::Result<Self> {
+        let frame_source = ScreenSource::new(config.capture_target.clone());
+
+        Self::continue_with_sources(
+            config,
+            session_dir,
+            Box::new(frame_source),
+            Box::new(RdevInputSource),
+        )
+    }
+
+    /// Продолжение прерванной записи в той же директории.
+    ///
+    /// Сессия сначала восстанавливается ([`Session::recover`]), затем кадры и события
+    /// дописываются: нумерация кадров и часы продолжаются с места обрыва, частота кадров
+    /// берётся из манифеста. Датасет на лету при продолжении не пишется, его собирает
+    /// предобработка из кадров.
+    pub fn continue_with_sources(
+        mut config: RecorderConfig,
+        session_dir: &Path,
+        frame_source: Box<dyn FrameSource>,
+        input_source: Box<dyn InputSource>,
+    ) -> io::Result<Self> {
+        let mut session = Session::open(session_dir)?;
+
+        if !session.is_interrupted() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Сессия завершена штатно, продолжать нечего",
+            ));
+        }
+        if session_dir.join(LEGACY_EVENTS_FILE).exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Сессия записана со старым журналом событий",
+            ));
+        }
+
+        // Частота проверяется до восстановления, чтобы не менять сессию понапрасну
+        config.target_fps = session.manifest.target_fps;
+        let frame_interval_us = config.frame_interval()?.as_micros() as u64;
+
+        session.recover()?;
+
+        let frames = load_frame_stamps(&session.frames_index())?;
+        let events = session.load_events()?;
+        let last_timestamp_us = frames
+            .iter()
+            .map(|frame| frame.timestamp_us)
+            .chain(events.iter().map(|event| event.timestamp_us))
+            .max();
+
+        let events_path = session.events_path();
+        let events = if events_path.exists() {
+            EventLogWriter::append(&events_path)?
+        } else {
+            EventLogWriter::create(&events_path)?
+        };
+
+        Self::launch(
+            config,
+            Launch {
+                session_dir: session.dir,
+                manifest: session.manifest,
+                clock: SessionClock::resume_from(
+                    last_timestamp_us.map_or(0, |last| last + frame_interval_us),
+                ),
+                events,
+                first_index: frames
+                    .iter()
+                    .map(|frame| frame.index + 1)
+                    .max()
+                    .unwrap_or(0),
+                dataset_dir: None,
+            },
+            frame_source,
+            input_source,
+        )
+    }
+
+    fn launch(
+        config: RecorderConfig,
+        launch: Launch,
+        frame_source: Box<dyn FrameSource>,
+        input_source: Box<dyn InputSource>,
+    ) -> io::Result<Self> {
+        let Launch {
+            session_dir,
+            manifest,
+            clock,
+            events,
+            first_index,
+            dataset_dir,
+        } = launch;
+
+        let mut keys_recorder = KeysRecorder::new(events, clock);
+
+        let mut video_recorder = VideoRecorder::new(
+            (!config.downscale_at_capture || config.keep_full_resolution)
+                .then(|| session_dir.join(FRAMES_DIR)),
+            config
+                .downscale_at_capture
+                .then(|| session_dir.join(RESIZED_DIR)),
+            session_dir.join(FRAMES_INDEX_FILE),
+            session_dir.join(CAPTURE_STATS_FILE),
+            &config,
+            clock,
+        )?
+        .continue_from(first_index);
+
+        if let Some(dataset_dir) = dataset_dir {
+            let (sender, receiver) = channel();
+
+            keys_recorder = keys_recorder.with_dataset(sender.clone());
+            video_recorder = video_recorder.with_dataset(
+                DatasetWriter::new(
+                    dataset_dir,
+                    clock,
+                    config.key_vocabulary.clone(),
+                    config.frame_format,
+                    config.aggregation,
+                ),
+                receiver,
+                sender,
+            );
+        }
+
+        let shared = Arc::new(SessionShared {
+            timing: Mutex::new(Timing {
+                state: RecordingState::Recording,
+                started_at: Instant::now(),
+                paused_since: None,
+                paused_total: Duration::ZERO,
+            }),
+            frames: AtomicUsize::new(manifest.frame_count),
+            dropped_frames: AtomicUsize::new(0),
+            keys_recorder: Mutex::new(keys_recorder),
+            manifest: Mutex::new(manifest),
+            manifest_path: session_dir.join(MANIFEST_FILE),
+            checkpoint_path: session_dir.join(CHECKPOINT_FILE),
+            error: Mutex::new(None),
+        });
+
+        // Пока метка на диске, сессия считается незавершённой
+        shared.checkpoint()?;
+        write_atomic(&shared.checkpoint_path, b"{}")?;
+
+        let video_handle = video_recorder.start(frame_source, shared.clone())?;
+
+        let input_handle = Self::start_input(input_source, shared.clone(), config.hotkeys);
+
+        println!("Record started: {}", session_dir.display());
+
+        Ok(Self {
+            shared,
+            session_dir,
+            threads: Mutex::new(vec![video_handle, input_handle]),
+        })
+    }
+
+    fn start_input(
+        input_source: Box<dyn InputSource>,
+        shared: Arc<SessionShared>,
+        hotkeys: Hotkeys,
+    ) -> JoinHandle<()> {
+        let receiver = input_source.start();
+
+        thread::spawn(move || {
+            while shared.state() != RecordingState::Stopped {
+                let event = match receiver.recv_timeout(Duration::from_millis(50)) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                match event {
+                    EventType::KeyPress(key) if Some(key) == hotkeys.stop => shared.stop(),
+                    EventType::KeyPress(key) if Some(key) == hotkeys.toggle_pause => {
+                        shared.toggle_pause()
+                    }
+                    // Отпускание горячих клавиш тоже не записываем
+                    EventType::KeyRelease(key)
+                        if Some(key) == hotkeys.stop || Some(key) == hotkeys.toggle_pause => {}
+                    _ => {
+                        let written = shared.keys_recorder.lock().unwrap().insert_key(&event);
+                        if let Err(err) = written {
+                            shared.fail(err);
+                        }
+                    }
+                }
+            }
+
+            let flushed = shared.keys_recorder.lock().unwrap().flush();
+            if let Err(err) = flushed {
+                shared.fail(err);
+            }
+        })
+    }
+
+    pub fn pause(&self) {
+        self.shared.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.shared.set_paused(false);
+    }
+
+    /// Остановка записи и ожидание завершения фоновых потоков.
+    /// Возвращает ошибку, из-за которой запись могла остановиться раньше.
+    pub fn stop(&self) -> io::Result<()> {
+        self.shared.stop();
+
+        for handle in self.threads.lock().unwrap().drain(..) {
+            if handle.join().is_err() {
+                self.shared
+                    .fail(io::Error::other("Поток записи завершился аварийно"));
+            }
+        }
+
+        match self.shared.error.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Блокирует поток до остановки сессии (например, горячей клавишей)
+    pub fn wait(&self) -> io::Result<()> {
+        while !self.is_finished() {
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        self.stop()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.shared.state() == RecordingState::Stopped
+    }
+
+    pub fn status(&self) -> RecordingStatus {
+        self.shared.status()
+    }
+
+    /// Директория этой сессии
+    pub fn session_dir(&self) -> &Path {
+        &self.session_dir
+    }
+
+    /// Текущее состояние манифеста; на диск он окончательно записывается при остановке
+    pub fn manifest(&self) -> SessionManifest {
+        self.shared.manifest.lock().unwrap().clone()
+    }
+}
@@ -1,66 +1,426 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender, TrySendError, sync_channel};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use common::frame::FrameFormat;
+use common::session::write_atomic;
+use csv::{Writer, WriterBuilder};
 use fs_extra::dir;
-use rdev::{Event, EventType};
-use xcap::Monitor;
+use image::{DynamicImage, ImageFormat, ImageResult, RgbaImage};
+use preprocessor::images::{MyImage, transform_image};
+use serde::Serialize;
 
+use crate::clock::SessionClock;
+use crate::dataset_writer::{DatasetMessage, DatasetWriter};
+use crate::frame_source::FrameSource;
+use crate::scheduler::{Scheduler, StatsAccumulator};
+use crate::session::{RecorderConfig, RecordingState, SessionShared};
+
+/// Как часто сохраняется контрольная точка записи
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Строка индекса кадров: имя файла и момент захвата по часам сессии
+#[derive(Debug, Serialize)]
+struct FrameRecord {
+    index: usize,
+    file: String,
+    timestamp_us: u64,
+}
+
+/// Захваченный кадр в очереди на кодирование
+struct EncodeJob {
+    index: usize,
+    file: String,
+    timestamp_us: u64,
+    image: RgbaImage,
+}
+
+/// Индекс кадров. Строка пишется, только когда файл кадра уже на месте, и строки идут
+/// по порядку номеров, даже если кодировщики закончили кадры не по порядку.
+struct FrameIndex {
+    writer: Writer<File>,
+    /// Номер кадра, строка которого пишется следующей
+    next: usize,
+    /// Закодированные кадры с номерами после `next`; `None` — кадр не сохранён
+    done: BTreeMap<usize, Option<FrameRecord>>,
+}
+
+impl FrameIndex {
+    fn new(writer: Writer<File>, first_index: usize) -> Self {
+        Self {
+            writer,
+            next: first_index,
+            done: BTreeMap::new(),
+        }
+    }
+
+    fn complete(&mut self, index: usize, record: Option<FrameRecord>) -> io::Result<()> {
+        self.done.insert(index, record);
+
+        while let Some(record) = self.done.remove(&self.next) {
+            if let Some(record) = record {
+                self.writer.serialize(record)?;
+            }
+            self.next += 1;
+        }
+
+        self.writer.flush()
+    }
+}
+
+/// Куда кодировщики сохраняют кадр
 #[derive(Clone)]
+struct EncoderOutput {
+    /// Полноразмерные кадры
+    frames_dir: Option<PathBuf>,
+    /// Кадры, уменьшенные до `format`
+    resized_dir: Option<PathBuf>,
+    format: FrameFormat,
+    dataset: Option<Sender<DatasetMessage>>,
+    index: Arc<Mutex<FrameIndex>>,
+}
+
+/// Запись датасета на лету, см. [`DatasetWriter`]
+struct DatasetSink {
+    writer: DatasetWriter,
+    receiver: Receiver<DatasetMessage>,
+    sender: Sender<DatasetMessage>,
+}
+
 pub struct VideoRecorder {
-    should_stop: Arc<Mutex<bool>>,
-    path_to_images: PathBuf,
+    path_to_images: Option<PathBuf>,
+    path_to_resized: Option<PathBuf>,
+    path_to_index: PathBuf,
+    path_to_stats: PathBuf,
+    frame_interval: Duration,
+    encoder_threads: usize,
+    queue_capacity: usize,
+    /// Формат кадров датасета
+    format: FrameFormat,
+    clock: SessionClock,
+    dataset: Option<DatasetSink>,
+    /// Номер первого кадра; больше нуля, если сессия продолжается
+    first_index: usize,
 }
 
 impl VideoRecorder {
-    pub fn new(path_to_images: PathBuf) -> Self {
-        Self {
-            should_stop: Arc::new(Mutex::new(false)),
+    /// `path_to_images` — полноразмерные кадры, `path_to_resized` — уменьшенные;
+    /// хотя бы одна из директорий должна быть задана.
+    pub fn new(
+        path_to_images: Option<PathBuf>,
+        path_to_resized: Option<PathBuf>,
+        path_to_index: PathBuf,
+        path_to_stats: PathBuf,
+        config: &RecorderConfig,
+        clock: SessionClock,
+    ) -> io::Result<Self> {
+        Ok(Self {
             path_to_images,
-        }
+            path_to_resized,
+            path_to_index,
+            path_to_stats,
+            frame_interval: config.frame_interval()?,
+            encoder_threads: config.encoder_threads.max(1),
+            queue_capacity: config.queue_capacity.max(1),
+            format: config.frame_format,
+            clock,
+            dataset: None,
+            first_index: 0,
+        })
+    }
+
+    /// Продолжение прерванной сессии: нумерация кадров продолжается с `first_index`,
+    /// индекс кадров дописывается
+    pub(crate) fn continue_from(mut self, first_index: usize) -> Self {
+        self.first_index = first_index;
+        self
     }
 
-    pub fn start(&self) -> JoinHandle<()> {
-        let path_to_images = self.path_to_images.clone();
-        let should_stop = self.should_stop.clone();
+    /// Уменьшенные кадры вместе с событиями из `receiver` сразу пишутся в датасет
+    pub(crate) fn with_dataset(
+        mut self,
+        writer: DatasetWriter,
+        receiver: Receiver<DatasetMessage>,
+        sender: Sender<DatasetMessage>,
+    ) -> Self {
+        self.dataset = Some(DatasetSink {
+            writer,
+            receiver,
+            sender,
+        });
+        self
+    }
+
+    /// Захват идёт в отдельном потоке по расписанию [`Scheduler`], кодирование PNG — в пуле
+    /// потоков. Если пул не успевает и очередь заполнена, кадр отбрасывается, а не задерживает захват.
+    /// Ошибки записи в потоках останавливают сессию, см. [`SessionShared::fail`].
+    pub(crate) fn start(
+        self,
+        mut frame_source: Box<dyn FrameSource>,
+        session: Arc<SessionShared>,
+    ) -> io::Result<JoinHandle<()>> {
+        let path_to_stats = self.path_to_stats.clone();
+        let clock = self.clock;
+        let frame_interval = self.frame_interval;
+
+        for path in [&self.path_to_images, &self.path_to_resized]
+            .into_iter()
+            .flatten()
+        {
+            dir::create_all(path, false).map_err(io::Error::other)?;
+        }
+
+        let index_writer = if self.first_index > 0 {
+            let file = OpenOptions::new().append(true).open(&self.path_to_index)?;
+            WriterBuilder::new().has_headers(false).from_writer(file)
+        } else {
+            Writer::from_writer(File::create(&self.path_to_index)?)
+        };
+        let first_index = self.first_index;
+        let frame_index = Arc::new(Mutex::new(FrameIndex::new(index_writer, first_index)));
 
-        dir::create_all(path_to_images.clone(), true).unwrap();
+        let (dataset_sender, dataset_handle) = match self.dataset {
+            Some(sink) => (
+                Some(sink.sender),
+                Some(sink.writer.start(sink.receiver, session.clone())),
+            ),
+            None => (None, None),
+        };
+
+        // Формат уменьшенных кадров сверяется с размером захвата на первом кадре
+        let resized_format = self.path_to_resized.is_some().then_some(self.format);
+        let output = EncoderOutput {
+            frames_dir: self.path_to_images,
+            resized_dir: self.path_to_resized,
+            format: self.format,
+            dataset: dataset_sender.clone(),
+            index: frame_index.clone(),
+        };
+
+        let (sender, receiver) = sync_channel::<EncodeJob>(self.queue_capacity);
+        let encode_errors = Arc::new(AtomicUsize::new(0));
+        let encoders = spawn_encoders(
+            self.encoder_threads,
+            receiver,
+            output,
+            session.clone(),
+            encode_errors.clone(),
+        );
 
         // Запускаем поток для захвата изображений
-        thread::spawn(move || {
-            let monitors = Monitor::all().unwrap();
-            let monitor = &monitors[0];
+        Ok(thread::spawn(move || {
+            let mut scheduler = Scheduler::new(frame_interval);
+            let mut stats = StatsAccumulator::new(frame_interval);
+            let mut index = first_index;
+            let mut last_checkpoint = Instant::now();
+
+            loop {
+                match session.state() {
+                    RecordingState::Stopped => break,
+                    RecordingState::Paused => {
+                        scheduler.reset();
+                        stats.reset();
+                        thread::sleep(Duration::from_millis(10));
+                        continue;
+                    }
+                    RecordingState::Recording => {}
+                }
 
-            let mut count = 0;
+                let missed = scheduler.wait();
+                if missed > 0 {
+                    stats.missed_deadlines += missed;
+                    session.frames_dropped(missed);
+                }
+
+                // Время берём до захвата: кадр соответствует состоянию экрана на этот момент
+                let captured_at = Instant::now();
+                let timestamp_us = clock.now_us();
+
+                match frame_source.capture() {
+                    Ok(Some(image)) => {
+                        stats.frame_captured(captured_at);
+                        if index == first_index {
+                            session.set_native_resolution(image.width(), image.height());
+
+                            // Иначе каждый кадр отбрасывался бы при кодировании
+                            if let Some(format) = resized_format
+                                && let Err(err) = format.check_source(image.width(), image.height())
+                            {
+                                session.fail(err);
+                                break;
+                            }
+                        }
+
+                        let job = EncodeJob {
+                            index,
+                            file: format!("image-{}.png", index),
+                            timestamp_us,
+                            image,
+                        };
 
-            while !*should_stop.lock().unwrap() {
-                let image = monitor.capture_image().unwrap();
-                let file_path = path_to_images.join(format!("image-{}.png", count));
-                image.save(file_path).unwrap();
-                count += 1;
+                        match sender.try_send(job) {
+                            Ok(()) => {
+                                if let Some(dataset) = &dataset_sender {
+                                    let _ = dataset.send(DatasetMessage::Stamp {
+                                        index,
+                                        timestamp_us,
+                                    });
+                                }
 
-                // Ждем 1/20 секунды
-                thread::sleep(Duration::from_millis(50));
+                                index += 1;
+
+                                if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                                    if let Err(err) = session.checkpoint() {
+                                        session.fail(err);
+                                    }
+                                    last_checkpoint = Instant::now();
+                                }
+                            }
+                            Err(TrySendError::Full(_)) => {
+                                stats.queue_full += 1;
+                                session.frame_dropped();
+                            }
+                            Err(TrySendError::Disconnected(_)) => break,
+                        }
+                    }
+                    // Источник исчерпан
+                    Ok(None) => {
+                        session.stop();
+                        break;
+                    }
+                    Err(_) => {
+                        stats.capture_errors += 1;
+                        session.frame_dropped();
+                    }
+                }
             }
-        })
-    }
 
-    pub fn check_keys(&mut self, event: &Event) {
-        match event.event_type {
-            EventType::KeyPress(key) => match key {
-                rdev::Key::KeyQ => {
-                    println!("Video recorded");
+            // Дожидаемся кодирования кадров, оставшихся в очереди
+            drop(sender);
+            for encoder in encoders {
+                if encoder.join().is_err() {
+                    session.fail(io::Error::other("Кодировщик кадров завершился аварийно"));
+                }
+            }
 
-                    *self.should_stop.lock().unwrap() = true;
+            if let (Some(dataset), Some(handle)) = (dataset_sender, dataset_handle) {
+                let _ = dataset.send(DatasetMessage::Finish);
+                if handle.join().is_err() {
+                    session.fail(io::Error::other("Запись датасета завершилась аварийно"));
                 }
-                _ => {}
-            },
-            _ => {}
-        }
+            }
+
+            let saved = stats
+                .finish(
+                    session.status().frames,
+                    encode_errors.load(Ordering::SeqCst),
+                )
+                .save(&path_to_stats);
+            if let Err(err) = saved {
+                session.fail(err);
+            }
+
+            // После ошибки метка незавершённой записи остаётся: сессию нужно восстановить
+            if session.failed() {
+                return;
+            }
+            if let Err(err) = session.finish() {
+                session.fail(err);
+                return;
+            }
+
+            println!("Video recorded");
+        }))
     }
+}
+
+fn spawn_encoders(
+    count: usize,
+    receiver: Receiver<EncodeJob>,
+    output: EncoderOutput,
+    session: Arc<SessionShared>,
+    encode_errors: Arc<AtomicUsize>,
+) -> Vec<JoinHandle<()>> {
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    (0..count)
+        .map(|_| {
+            let receiver = receiver.clone();
+            let output = output.clone();
+            let session = session.clone();
+            let encode_errors = encode_errors.clone();
+
+            thread::spawn(move || {
+                loop {
+                    // Блокировка снимается сразу после получения задачи
+                    let job = receiver.lock().unwrap().recv();
+                    let Ok(job) = job else {
+                        break;
+                    };
+                    let index = job.index;
+                    let record = FrameRecord {
+                        index,
+                        file: job.file.clone(),
+                        timestamp_us: job.timestamp_us,
+                    };
+
+                    let (image, record) = match encode(job, &output) {
+                        Ok(resized) => {
+                            session.frame_saved();
+                            (resized, Some(record))
+                        }
+                        Err(_) => {
+                            encode_errors.fetch_add(1, Ordering::SeqCst);
+                            session.frame_dropped();
+                            (None, None)
+                        }
+                    };
+
+                    // Строка индекса появляется только после переименования файла кадра
+                    let indexed = output.index.lock().unwrap().complete(index, record);
+                    if let Err(err) = indexed {
+                        session.fail(err);
+                    }
+
+                    if let Some(dataset) = &output.dataset {
+                        let _ = dataset.send(DatasetMessage::Image { index, image });
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+/// Сохранение кадра; возвращает уменьшенный кадр, если он нужен
+fn encode(job: EncodeJob, output: &EncoderOutput) -> ImageResult<Option<MyImage>> {
+    let image = DynamicImage::ImageRgba8(job.image);
 
-    pub fn is_finished(&self) -> bool {
-        *self.should_stop.lock().unwrap()
+    if let Some(frames_dir) = &output.frames_dir {
+        save_png(&image, &frames_dir.join(&job.file))?;
     }
+
+    let Some(resized_dir) = &output.resized_dir else {
+        return Ok(None);
+    };
+
+    let resized = transform_image(&image, output.format)?;
+    save_png(&resized, &resized_dir.join(&job.file))?;
+
+    Ok(Some(MyImage::from_image(&resized, output.format)?))
+}
+
+/// После сбоя на диске не остаётся недописанного PNG: только целый файл или `*.tmp`
+fn save_png(image: &DynamicImage, path: &Path) -> ImageResult<()> {
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageFormat::Png)?;
+
+    write_atomic(path, png.get_ref())?;
+
+    Ok(())
 }
@@ -0,0 +1,65 @@
+//! Parsing of capture targets as typed in the UI and stored in session manifests.
+//! Run: cargo test -p recorder --test capture_target_test
+
+use recorder::{CaptureTarget, Region};
+
+#[test]
+fn test_parse_capture_targets() {
+    assert_eq!("".parse(), Ok(CaptureTarget::Primary));
+    assert_eq!("primary".parse(), Ok(CaptureTarget::Primary));
+    assert_eq!("monitor:1".parse(), Ok(CaptureTarget::MonitorIndex(1)));
+    assert_eq!(
+        "monitor:HDMI-1".parse(),
+        Ok(CaptureTarget::MonitorName("HDMI-1".to_string()))
+    );
+    assert_eq!(
+        "region:-10, 20, 800x600".parse(),
+        Ok(CaptureTarget::Region(Region {
+            x: -10,
+            y: 20,
+            width: 800,
+            height: 600
+        }))
+    );
+    assert_eq!(
+        "window:Doom Eternal".parse(),
+        Ok(CaptureTarget::Window("Doom Eternal".to_string()))
+    );
+}
+
+#[test]
+fn test_parse_invalid_capture_targets() {
+    for invalid in [
+        "screen",
+        "monitor:",
+        "region:0,0",
+        "region:0,0,800",
+        "region:0,0,0x600",
+        "tab:1",
+    ] {
+        assert!(
+            invalid.parse::<CaptureTarget>().is_err(),
+            "{} should not parse",
+            invalid
+        );
+    }
+}
+
+/// The string form round-trips, so manifests can be parsed back
+#[test]
+fn test_capture_target_display_round_trip() {
+    for target in [
+        CaptureTarget::Primary,
+        CaptureTarget::MonitorIndex(2),
+        CaptureTarget::MonitorName("DP-1".to_string()),
+        CaptureTarget::Region(Region {
+            x: 0,
+            y: 0,
+            width: 640,
+            height: 480,
+        }),
+        CaptureTarget::Window("Quake".to_string()),
+    ] {
+        assert_eq!(target.to_string().parse(), Ok(target));
+    }
+}
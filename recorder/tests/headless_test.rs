@@ -0,0 +1,451 @@
+//! Headless recording tests: frames are replayed from a directory and input from a script,
+//! so no display or input devices are required.
+//! Run: cargo test -p recorder --test headless_test
+
+use std::fs;
+
+use common::{
+    MOUSE_VECTOR_LENGTH,
+    aggregation::AggregationConfig,
+    frame::{ChannelLayout, FrameFormat, Resize, ResizeFilter, ResizeMode},
+};
+use preprocessor::{
+    alignment::{align_events, load_frame_stamps},
+    csv_processing::key_to_num,
+    event_log::load_event_log,
+    hdf5_processing::read_all_hdf5_files,
+    sessions::Session,
+    vocabulary::default_vocabulary,
+};
+use recorder::{
+    CaptureStats, DirectoryFrameSource, RecorderConfig, RecordingSession, RecordingState,
+    ScriptedInputSource, SessionManifest,
+};
+
+const FRAMES: usize = 5;
+
+fn write_frames(dir: &std::path::Path) {
+    fs::create_dir_all(dir).unwrap();
+
+    for i in 0..FRAMES {
+        let image = image::RgbaImage::from_pixel(8, 8, image::Rgba([i as u8 * 40, 0, 0, 255]));
+        image.save(dir.join(format!("frame-{}.png", i))).unwrap();
+    }
+}
+
+/// Record -> align: every replayed frame is stored and scripted events end up in its actions
+#[test]
+fn test_headless_recording() {
+    let root = std::env::temp_dir().join("test_headless_recording");
+    let _ = fs::remove_dir_all(&root);
+
+    let frames_dir = root.join("frames");
+    write_frames(&frames_dir);
+
+    let script_path = root.join("input.jsonl");
+    fs::write(
+        &script_path,
+        r#"{"delay_ms": 10, "event": {"KeyPress": "KeyW"}}
+{"delay_ms": 10, "event": {"MouseMove": {"x": 100.0, "y": 100.0}}}
+{"delay_ms": 10, "event": {"MouseMove": {"x": 110.0, "y": 100.0}}}
+{"delay_ms": 100, "event": {"KeyRelease": "KeyW"}}
+"#,
+    )
+    .unwrap();
+
+    let data_dir = root.join("data");
+    let config = RecorderConfig {
+        tags: vec!["headless".to_string()],
+        ..RecorderConfig::default()
+    };
+    let session = RecordingSession::start_with_sources(
+        config,
+        data_dir.clone(),
+        Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+        Box::new(ScriptedInputSource::from_file(&script_path).unwrap()),
+    )
+    .unwrap();
+
+    // The session stops by itself once the frame source is exhausted
+    session.wait().unwrap();
+
+    let status = session.status();
+    assert_eq!(status.state, RecordingState::Stopped);
+    assert_eq!(status.frames, FRAMES);
+    assert_eq!(status.dropped_frames, 0);
+
+    let session_dir = session.session_dir();
+    assert!(session_dir.starts_with(data_dir.join("sessions")));
+
+    let raw_frames = fs::read_dir(session_dir.join("frames")).unwrap().count();
+    assert_eq!(raw_frames, FRAMES);
+
+    let manifest = SessionManifest::load(&session_dir.join("manifest.json")).unwrap();
+    assert_eq!(manifest, session.manifest());
+    assert_eq!(manifest.frame_count, FRAMES);
+    assert_eq!(manifest.native_resolution, [8, 8]);
+    assert_eq!(manifest.tags, vec!["headless"]);
+    assert!(manifest.monitor_name.starts_with("directory:"));
+    assert_eq!(manifest.capture_target, None);
+    // Finished cleanly: no checkpoint is left behind
+    assert!(!session_dir.join("checkpoint.json").exists());
+
+    let stats = CaptureStats::load(&session_dir.join("capture_stats.json")).unwrap();
+    assert_eq!(stats.frames, FRAMES);
+    assert_eq!(stats.dropped_frames, 0);
+    assert_eq!(stats.target_fps, 20.0);
+    assert!(stats.achieved_fps > 0.0);
+
+    let frames = load_frame_stamps(&session_dir.join("frames.csv")).unwrap();
+    let events = load_event_log(&session_dir.join("events.bin")).unwrap();
+    assert_eq!(frames.len(), FRAMES);
+    assert_eq!(events.len(), 4);
+
+    let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+    assert_eq!(records.len(), FRAMES);
+    assert!(
+        records
+            .iter()
+            .any(|record| record.keys.contains(&key_to_num("KeyW")))
+    );
+
+    // Cleanup
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// Capture runs on a fixed schedule: five frames at 50 fps take about 80ms, not 5x the encode time
+#[test]
+fn test_headless_fixed_rate() {
+    let root = std::env::temp_dir().join("test_headless_fixed_rate");
+    let _ = fs::remove_dir_all(&root);
+
+    let frames_dir = root.join("frames");
+    write_frames(&frames_dir);
+
+    let config = RecorderConfig {
+        target_fps: 50.0,
+        ..RecorderConfig::default()
+    };
+    let session = RecordingSession::start_with_sources(
+        config,
+        root.join("data"),
+        Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+        Box::new(ScriptedInputSource::new(vec![])),
+    )
+    .unwrap();
+    session.wait().unwrap();
+
+    let stats = CaptureStats::load(&session.session_dir().join("capture_stats.json")).unwrap();
+    assert_eq!(stats.frames, FRAMES);
+    assert!(
+        (stats.achieved_fps - 50.0).abs() < 10.0,
+        "achieved {} fps",
+        stats.achieved_fps
+    );
+
+    // Cleanup
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// A zero, negative or non-finite frame rate is an error, not a panic
+#[test]
+fn test_headless_invalid_fps() {
+    let root = std::env::temp_dir().join("test_headless_invalid_fps");
+    let _ = fs::remove_dir_all(&root);
+
+    let frames_dir = root.join("frames");
+    write_frames(&frames_dir);
+
+    for target_fps in [0.0, -20.0, f64::NAN, f64::INFINITY] {
+        let config = RecorderConfig {
+            target_fps,
+            ..RecorderConfig::default()
+        };
+        assert!(config.frame_interval().is_err());
+
+        let started = RecordingSession::start_with_sources(
+            config,
+            root.join("data"),
+            Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+            Box::new(ScriptedInputSource::new(vec![])),
+        );
+        assert!(started.is_err(), "{target_fps} fps");
+    }
+    assert!(!root.join("data").exists());
+
+    // Cleanup
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// A session whose checkpoint is already gone still finishes cleanly
+#[test]
+fn test_headless_missing_checkpoint() {
+    let root = std::env::temp_dir().join("test_headless_missing_checkpoint");
+    let _ = fs::remove_dir_all(&root);
+
+    let frames_dir = root.join("frames");
+    write_frames(&frames_dir);
+
+    let session = RecordingSession::start_with_sources(
+        RecorderConfig::default(),
+        root.join("data"),
+        Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+        Box::new(ScriptedInputSource::new(vec![])),
+    )
+    .unwrap();
+    fs::remove_file(session.session_dir().join("checkpoint.json")).unwrap();
+    session.wait().unwrap();
+
+    let manifest = SessionManifest::load(&session.session_dir().join("manifest.json")).unwrap();
+    assert_eq!(manifest.frame_count, FRAMES);
+
+    // Cleanup
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// Downscale-at-capture writes a trainable dataset that matches offline preprocessing
+#[test]
+fn test_headless_downscale_at_capture() {
+    let root = std::env::temp_dir().join("test_headless_downscale_at_capture");
+    let _ = fs::remove_dir_all(&root);
+
+    let frames_dir = root.join("frames");
+    write_frames(&frames_dir);
+
+    let format = FrameFormat::new(16, 12, ChannelLayout::Rgb).unwrap();
+    let aggregation = AggregationConfig::new(4).unwrap();
+    let config = RecorderConfig {
+        downscale_at_capture: true,
+        keep_full_resolution: false,
+        frame_format: format,
+        aggregation,
+        ..RecorderConfig::default()
+    };
+    let data_dir = root.join("data");
+    let session = RecordingSession::start_with_sources(
+        config,
+        data_dir.clone(),
+        Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+        Box::new(ScriptedInputSource::new(vec![
+            recorder::ScriptedEvent {
+                delay_ms: 10,
+                event: recorder::EventType::KeyPress(recorder::Key::KeyW),
+            },
+            recorder::ScriptedEvent {
+                delay_ms: 100,
+                event: recorder::EventType::KeyRelease(recorder::Key::KeyW),
+            },
+        ])),
+    )
+    .unwrap();
+    session.wait().unwrap();
+
+    let session_dir = session.session_dir();
+    assert!(!session_dir.join("frames").exists());
+
+    let resized: Vec<_> = fs::read_dir(session_dir.join("resized"))
+        .unwrap()
+        .map(|entry| image::open(entry.unwrap().path()).unwrap())
+        .collect();
+    assert_eq!(resized.len(), FRAMES);
+    assert!(
+        resized
+            .iter()
+            .all(|image| (image.width(), image.height()) == (16, 12))
+    );
+
+    let manifest = session.manifest();
+    assert!(manifest.dataset_written);
+
+    let dataset = read_all_hdf5_files(&data_dir.join("hdf5_files")).unwrap();
+    assert_eq!(dataset.len(), FRAMES);
+
+    // Same actions as offline alignment of the recorded logs
+    let offline = Session::open(session_dir)
+        .unwrap()
+        .align(default_vocabulary(), format, aggregation)
+        .unwrap();
+    for (online, (_, offline)) in dataset.iter().zip(&offline) {
+        assert_eq!(online.keys_record.keys, offline.keys_record.keys);
+        assert_eq!(online.keys_record.mouse, offline.keys_record.mouse);
+        assert_eq!(online.image.format, format);
+        assert_eq!(online.image.pixels, offline.image.pixels);
+    }
+    assert!(
+        dataset
+            .iter()
+            .any(|record| record.keys_record.keys.contains(&key_to_num("KeyW")))
+    );
+
+    // Cleanup
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// A frame region outside the captured frames stops the recording with an error
+#[test]
+fn test_headless_region_outside_capture() {
+    let root = std::env::temp_dir().join("test_headless_region_outside_capture");
+    let _ = fs::remove_dir_all(&root);
+
+    let frames_dir = root.join("frames");
+    write_frames(&frames_dir);
+
+    let region = Resize {
+        mode: ResizeMode::Region {
+            x: 100,
+            y: 100,
+            width: 4,
+            height: 4,
+        },
+        filter: ResizeFilter::Nearest,
+    };
+    let config = RecorderConfig {
+        downscale_at_capture: true,
+        frame_format: FrameFormat::new(4, 4, ChannelLayout::Rgb)
+            .unwrap()
+            .with_resize(region)
+            .unwrap(),
+        ..RecorderConfig::default()
+    };
+    let session = RecordingSession::start_with_sources(
+        config,
+        root.join("data"),
+        Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+        Box::new(ScriptedInputSource::new(vec![])),
+    )
+    .unwrap();
+
+    let err = session.wait().unwrap_err();
+    assert!(err.to_string().contains("за пределами кадра"), "{err}");
+
+    // Cleanup
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// Pausing from code stops frame capture until the session is resumed
+#[test]
+fn test_headless_pause_resume() {
+    let root = std::env::temp_dir().join("test_headless_pause_resume");
+    let _ = fs::remove_dir_all(&root);
+
+    let frames_dir = root.join("frames");
+    write_frames(&frames_dir);
+
+    let session = RecordingSession::start_with_sources(
+        RecorderConfig::default(),
+        root.join("data"),
+        Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+        Box::new(ScriptedInputSource::new(vec![])),
+    )
+    .unwrap();
+
+    session.pause();
+    // Let a capture that was already in flight finish
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let paused_frames = session.status().frames;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    assert_eq!(session.status().state, RecordingState::Paused);
+    assert_eq!(session.status().frames, paused_frames);
+
+    session.resume();
+    session.wait().unwrap();
+
+    assert_eq!(session.status().frames, FRAMES);
+
+    // Cleanup
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// A crashed session is recovered and recording continues where it stopped
+#[test]
+fn test_headless_continue_interrupted() {
+    let root = std::env::temp_dir().join("test_headless_continue_interrupted");
+    let _ = fs::remove_dir_all(&root);
+
+    let frames_dir = root.join("frames");
+    write_frames(&frames_dir);
+
+    let script_path = root.join("input.jsonl");
+    fs::write(
+        &script_path,
+        r#"{"delay_ms": 10, "event": {"KeyPress": "KeyW"}}
+"#,
+    )
+    .unwrap();
+
+    let session = RecordingSession::start_with_sources(
+        RecorderConfig::default(),
+        root.join("data"),
+        Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+        Box::new(ScriptedInputSource::from_file(&script_path).unwrap()),
+    )
+    .unwrap();
+    session.wait().unwrap();
+    let session_dir = session.session_dir().to_path_buf();
+
+    // Simulate a crash: the checkpoint is still there, the last frame never made it to disk
+    // and a temporary file was left mid-write
+    fs::write(session_dir.join("checkpoint.json"), "{}").unwrap();
+    fs::remove_file(session_dir.join("frames/image-4.png")).unwrap();
+    fs::write(session_dir.join("frames/image-5.png.tmp"), "partial").unwrap();
+
+    assert!(Session::open(&session_dir).unwrap().is_interrupted());
+
+    let continued = RecordingSession::continue_with_sources(
+        RecorderConfig::default(),
+        &session_dir,
+        Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+        Box::new(ScriptedInputSource::new(vec![])),
+    )
+    .unwrap();
+    continued.wait().unwrap();
+
+    assert_eq!(continued.session_dir(), session_dir);
+    assert!(!session_dir.join("checkpoint.json").exists());
+    assert!(!session_dir.join("frames/image-5.png.tmp").exists());
+
+    let manifest = SessionManifest::load(&session_dir.join("manifest.json")).unwrap();
+    assert_eq!(manifest.frame_count, FRAMES - 1 + FRAMES);
+
+    // Numbering and timestamps carry on after the last complete frame
+    let frames = load_frame_stamps(&session_dir.join("frames.csv")).unwrap();
+    let indices: Vec<usize> = frames.iter().map(|frame| frame.index).collect();
+    assert_eq!(
+        indices,
+        (0..FRAMES - 1).chain(4..4 + FRAMES).collect::<Vec<_>>()
+    );
+    assert!(
+        frames
+            .windows(2)
+            .all(|pair| pair[0].timestamp_us < pair[1].timestamp_us)
+    );
+    assert_eq!(
+        fs::read_dir(session_dir.join("frames")).unwrap().count(),
+        frames.len()
+    );
+
+    // The key held at the crash was released, so it does not stick to the continued frames
+    let events = load_event_log(&session_dir.join("events.bin")).unwrap();
+    let records = align_events(&frames, &events, default_vocabulary(), MOUSE_VECTOR_LENGTH);
+    assert!(
+        records
+            .iter()
+            .any(|record| record.keys.contains(&key_to_num("KeyW")))
+    );
+    assert!(!records.last().unwrap().keys.contains(&key_to_num("KeyW")));
+
+    // A cleanly finished session cannot be continued
+    assert!(
+        RecordingSession::continue_with_sources(
+            RecorderConfig::default(),
+            &session_dir,
+            Box::new(DirectoryFrameSource::new(&frames_dir).unwrap()),
+            Box::new(ScriptedInputSource::new(vec![])),
+        )
+        .is_err()
+    );
+
+    // Cleanup
+    let _ = fs::remove_dir_all(&root);
+}
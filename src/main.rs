@@ -1,9 +1,15 @@
-use std::thread;
+use std::{path::PathBuf, str::FromStr, thread};
 
-use iced::keyboard::{on_key_press, Key, Modifiers};
-use iced::widget::{button, column, container, image as iced_image, mouse_area, row, text};
+use common::aggregation::AggregationConfig;
+use common::frame::FrameFormat;
+use iced::keyboard::{Key, Modifiers, on_key_press};
+use iced::widget::{
+    button, checkbox, column, container, image as iced_image, mouse_area, row, text, text_input,
+};
 use iced::{Alignment, Element, Length, Size, Subscription, Theme};
 use image::DynamicImage;
+use preprocessor::vocabulary::KeyVocabulary;
+use recorder::{CaptureTarget, RecorderConfig, RecordingSession};
 
 mod utils;
 
@@ -14,7 +20,13 @@ enum Message {
     GenerateImage,
     ReloadImage,
     ModelTraining,
+    Tags(String),
+    CaptureTarget(String),
+    DownscaleAtCapture(bool),
     Record,
+    ContinueRecord,
+    PauseRecord,
+    ResumeRecord,
     StopRecord,
     Postprocess,
     CheckData,
@@ -28,6 +40,10 @@ pub struct State {
     pub current_image: Option<DynamicImage>,
     pub initial_image: Option<DynamicImage>,
     pub image_handle: Option<iced::widget::image::Handle>,
+    pub recording: Option<RecordingSession>,
+    pub tags: String,
+    pub capture_target: String,
+    pub downscale_at_capture: bool,
 }
 
 impl Default for State {
@@ -41,6 +57,10 @@ impl Default for State {
             current_image: initial.clone(),
             initial_image: initial,
             image_handle: handle,
+            recording: None,
+            tags: String::new(),
+            capture_target: String::new(),
+            downscale_at_capture: false,
         }
     }
 }
@@ -78,20 +98,136 @@ fn update(state: &mut State, message: Message) {
         }
         Message::ModelTraining => {
             state.message_to_user = "Starting training...".to_string();
-            thread::spawn(|| model_training::training::run());
+            thread::spawn(model_training::training::run);
         }
+        Message::Tags(tags) => state.tags = tags,
+        Message::CaptureTarget(target) => state.capture_target = target,
+        Message::DownscaleAtCapture(enabled) => state.downscale_at_capture = enabled,
         Message::Record => {
-            state.message_to_user = "Starting recording...".to_string();
-            thread::spawn(|| recorder::run());
+            if state.recording.as_ref().is_some_and(|s| !s.is_finished()) {
+                state.message_to_user = "Recording is already running".to_string();
+            } else {
+                let capture_target = match state.capture_target.parse::<CaptureTarget>() {
+                    Ok(target) => target,
+                    Err(err) => {
+                        state.message_to_user = err;
+                        return;
+                    }
+                };
+                let data_dir = PathBuf::from_str(common::DATA_DIR).unwrap();
+                let key_vocabulary = match KeyVocabulary::load_or_default(&data_dir) {
+                    Ok(vocabulary) => vocabulary,
+                    Err(err) => {
+                        state.message_to_user = format!("Key vocabulary: {err}");
+                        return;
+                    }
+                };
+                let frame_format = match FrameFormat::load_or_default(&data_dir) {
+                    Ok(format) => format,
+                    Err(err) => {
+                        state.message_to_user = format!("Frame format: {err}");
+                        return;
+                    }
+                };
+                let aggregation = match AggregationConfig::load_or_default(&data_dir) {
+                    Ok(aggregation) => aggregation,
+                    Err(err) => {
+                        state.message_to_user = format!("Aggregation: {err}");
+                        return;
+                    }
+                };
+                let config = RecorderConfig {
+                    capture_target,
+                    tags: utils::parse_tags(&state.tags),
+                    downscale_at_capture: state.downscale_at_capture,
+                    key_vocabulary,
+                    frame_format,
+                    aggregation,
+                    ..RecorderConfig::default()
+                };
+                match RecordingSession::start(config, data_dir) {
+                    Ok(session) => {
+                        state.message_to_user = format!(
+                            "Recording {} started (F9 pause, F10 stop)",
+                            session.manifest().session_id
+                        );
+                        state.recording = Some(session);
+                    }
+                    Err(err) => state.message_to_user = format!("Recording failed: {err}"),
+                }
+            }
+        }
+        Message::ContinueRecord => {
+            if state.recording.as_ref().is_some_and(|s| !s.is_finished()) {
+                state.message_to_user = "Recording is already running".to_string();
+                return;
+            }
+            let Some(session_dir) = utils::latest_interrupted_session() else {
+                state.message_to_user = "No interrupted recordings".to_string();
+                return;
+            };
+            let capture_target = match state.capture_target.parse::<CaptureTarget>() {
+                Ok(target) => target,
+                Err(err) => {
+                    state.message_to_user = err;
+                    return;
+                }
+            };
+            let config = RecorderConfig {
+                capture_target,
+                ..RecorderConfig::default()
+            };
+            match RecordingSession::continue_session(config, &session_dir) {
+                Ok(session) => {
+                    state.message_to_user = format!(
+                        "Recording {} continued (F9 pause, F10 stop)",
+                        session.manifest().session_id
+                    );
+                    state.recording = Some(session);
+                }
+                Err(err) => state.message_to_user = err.to_string(),
+            }
+        }
+        Message::PauseRecord => {
+            if let Some(ref session) = state.recording {
+                session.pause();
+                state.message_to_user = "Recording paused".to_string();
+            }
+        }
+        Message::ResumeRecord => {
+            if let Some(ref session) = state.recording {
+                session.resume();
+                state.message_to_user = "Recording resumed".to_string();
+            }
         }
         Message::StopRecord => {
-            state.message_to_user = "Stop recording clicked".to_string();
+            if let Some(session) = state.recording.take() {
+                let stopped = session.stop();
+                let status = session.status();
+                state.message_to_user = match stopped {
+                    Ok(()) => format!(
+                        "Recording stopped: {} frames, {} dropped",
+                        status.frames, status.dropped_frames
+                    ),
+                    Err(err) => format!("Recording failed after {} frames: {err}", status.frames),
+                };
+            } else {
+                state.message_to_user = "Nothing is being recorded".to_string();
+            }
         }
         Message::Postprocess => {
+            // Незавершённая сессия была бы принята за прерванную и обрезана
+            if state.recording.as_ref().is_some_and(|s| !s.is_finished()) {
+                state.message_to_user = "Stop recording before postprocessing".to_string();
+                return;
+            }
             state.message_to_user = "Postprocessing...".to_string();
             thread::spawn(|| {
-                preprocessor::process_my_images();
-                preprocessor::write_my_data();
+                let result =
+                    preprocessor::process_my_images().and_then(|()| preprocessor::write_my_data());
+                if let Err(err) = result {
+                    eprintln!("Postprocessing failed: {err}");
+                }
             });
         }
         Message::CheckData => {
@@ -118,6 +254,11 @@ fn view(state: &State) -> Element<'_, Message> {
                 )),
                 text(format!("hdf5 файлы: {}", state.data_status.hdf5_files)),
                 text(format!("Keys: {}", state.data_status.keys)),
+                text(format!(
+                    "Сессии: {} (прерванных: {})",
+                    state.data_status.sessions, state.data_status.interrupted_sessions
+                )),
+                text(utils::recording_status(state.recording.as_ref())),
             ]
             .spacing(10),
             column![text("Log"), text(&state.message_to_user),].spacing(10)
@@ -147,10 +288,26 @@ fn view(state: &State) -> Element<'_, Message> {
                 button(text("Перезагрузить статус")).on_press(Message::CheckData),
             ],
             row![
+                text_input(
+                    "primary | monitor:1 | region:x,y,wxh | window:title",
+                    &state.capture_target
+                )
+                .on_input(Message::CaptureTarget)
+                .width(Length::Fixed(200.0)),
+                text_input("Теги через запятую", &state.tags)
+                    .on_input(Message::Tags)
+                    .width(Length::Fixed(200.0)),
                 button(text("Запись")).on_press(Message::Record),
+                button(text("Продолжить прерванную")).on_press(Message::ContinueRecord),
+                button(text("Пауза")).on_press(Message::PauseRecord),
+                button(text("Продолжить")).on_press(Message::ResumeRecord),
                 button(text("Стоп запись")).on_press(Message::StopRecord),
                 button(text("Постобработка")).on_press(Message::Postprocess),
             ],
+            row![
+                checkbox("Уменьшать кадры при записи", state.downscale_at_capture)
+                    .on_toggle(Message::DownscaleAtCapture),
+            ],
             row![button(text("Тренировка")).on_press(Message::ModelTraining),]
         ]
         .spacing(10)
@@ -164,7 +321,7 @@ fn view(state: &State) -> Element<'_, Message> {
             .center_x(Length::Fill)
             .center_y(Length::Fill),
     )
-    .on_move(|point| Message::Mouse(point))
+    .on_move(Message::Mouse)
     .into()
 }
 
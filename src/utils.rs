@@ -1,6 +1,12 @@
-use std::{fs, path::PathBuf, str::FromStr};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use image::DynamicImage;
+use preprocessor::sessions::{Session, discover_sessions};
+use recorder::{RecordingSession, RecordingState};
 
 use crate::State;
 
@@ -47,17 +53,50 @@ pub struct DataStatus {
     pub resized_images: bool,
     pub hdf5_files: bool,
     pub keys: bool,
+    pub sessions: usize,
+    /// Сессии, запись которых оборвалась
+    pub interrupted_sessions: usize,
 }
 
 pub fn check_data(state: &mut State) {
-    let data_path = PathBuf::from_str("data").unwrap();
+    let data_path = PathBuf::from_str(common::DATA_DIR).unwrap();
+    let sessions = discover_sessions(&data_path).unwrap_or_default();
+    let hdf5_path = data_path.join("hdf5_files");
 
-    state.data_status.hdf5_files = check_dir_not_empty(&data_path.join("hdf5_files"));
-    state.data_status.keys = check_dir_not_empty(&data_path.join("keys"));
+    state.data_status.sessions = sessions.len();
+    state.data_status.interrupted_sessions = sessions.iter().filter(|s| s.is_interrupted()).count();
+    state.data_status.hdf5_files = check_dir_not_empty(&hdf5_path)
+        || sessions.iter().any(|s| has_files(&hdf5_path.join(s.id())));
+    state.data_status.keys = check_dir_not_empty(&data_path.join("keys"))
+        || sessions.iter().any(|s| s.events_path().exists());
 
     let images_path = data_path.join("images");
-    state.data_status.images_from_frames = check_dir_not_empty(&images_path.join("raw"));
-    state.data_status.resized_images = check_dir_not_empty(&images_path.join("resized_images"));
+    state.data_status.images_from_frames = check_dir_not_empty(&images_path.join("raw"))
+        || sessions.iter().any(|s| has_files(&s.frames_dir()));
+    state.data_status.resized_images = check_dir_not_empty(&images_path.join("resized_images"))
+        || sessions.iter().any(|s| has_files(&s.resized_dir()));
+}
+
+/// Human-readable state of the current recording session
+pub fn recording_status(session: Option<&RecordingSession>) -> String {
+    let Some(session) = session else {
+        return "Запись: нет".to_string();
+    };
+
+    let status = session.status();
+    let state = match status.state {
+        RecordingState::Recording => "идёт",
+        RecordingState::Paused => "пауза",
+        RecordingState::Stopped => "остановлена",
+    };
+
+    format!(
+        "Запись: {} | кадры: {} | {:.1} c | пропущено: {}",
+        state,
+        status.frames,
+        status.elapsed.as_secs_f64(),
+        status.dropped_frames
+    )
 }
 
 /// Convert a DynamicImage to an iced image Handle (RGBA8)
@@ -67,9 +106,27 @@ pub fn dynamic_image_to_handle(img: &DynamicImage) -> iced::widget::image::Handl
     iced::widget::image::Handle::from_rgba(w, h, rgba.into_raw())
 }
 
-/// Load the first image from data/images/resized_images/ as the initial image
+/// The latest interrupted session, which can be continued
+pub fn latest_interrupted_session() -> Option<PathBuf> {
+    let data_path = PathBuf::from_str(common::DATA_DIR).unwrap();
+
+    discover_sessions(&data_path)
+        .unwrap_or_default()
+        .into_iter()
+        .rev()
+        .find(|session| session.is_interrupted())
+        .map(|session| session.dir)
+}
+
+/// Load the first resized image of the latest session
+/// (or data/images/resized_images/ for old recordings) as the initial image
 pub fn load_initial_image() -> (Option<DynamicImage>, Option<iced::widget::image::Handle>) {
-    let image_dir = PathBuf::from_str("data/images/resized_images").unwrap();
+    let data_path = PathBuf::from_str(common::DATA_DIR).unwrap();
+    let image_dir = discover_sessions(&data_path)
+        .unwrap_or_default()
+        .last()
+        .map(Session::resized_dir)
+        .unwrap_or_else(|| data_path.join("images/resized_images"));
     match get_first_file_in_directory(&image_dir) {
         Some(path) => match image::open(&path) {
             Ok(img) => {
@@ -97,13 +154,24 @@ fn get_first_file_in_directory(dir: &PathBuf) -> Option<PathBuf> {
 fn check_dir_not_empty(dir: &PathBuf) -> bool {
     fs::create_dir_all(dir).unwrap();
 
+    has_files(dir)
+}
+
+/// Parse comma separated session tags, skipping empty ones
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn has_files(dir: &Path) -> bool {
     match fs::read_dir(dir) {
         Ok(entries) => {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
-                        return true;
-                    }
+            for entry in entries.flatten() {
+                if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
+                    return true;
                 }
             }
             false
@@ -208,6 +276,13 @@ mod tests {
         assert!(!status.resized_images);
         assert!(!status.hdf5_files);
         assert!(!status.keys);
+        assert_eq!(status.sessions, 0);
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(parse_tags(" doom, e1m1 ,,"), vec!["doom", "e1m1"]);
+        assert!(parse_tags("").is_empty());
     }
 
     /// Test check_dir_not_empty with empty directory